@@ -257,6 +257,8 @@ rec {
         export TOMMATHSRC=${nixpkgs.sources.libtommath}
         export MUSLSRC=${nixpkgs.sources.musl-wasi}/libc-top-half/musl
         export MUSL_WASI_SYSROOT=${musl-wasi-sysroot}
+        # Test vectors for the Candid subtype check in `motoko-rts-tests`
+        export CANDID_TESTS=${nixpkgs.sources.candid}/test
       '';
 
       doCheck = true;
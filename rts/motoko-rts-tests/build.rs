@@ -1,4 +1,10 @@
+#[path = "build/candid_test_suite.rs"]
+mod candid_test_suite;
+
 fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    candid_test_suite::generate(std::path::Path::new(&out_dir));
+
     let target = std::env::var("TARGET").unwrap();
 
     match target.as_str() {
@@ -0,0 +1,822 @@
+//! Generates conformance cases for `idl_sub` from the test vectors of the Candid test suite
+//! (`$CANDID_TESTS/*.test.did`), such that the tests run offline, also on Wasm targets.
+//!
+//! The test suite encodes subtyping tests as function and service references: decoding a
+//! reference succeeds exactly if the type in the message is a subtype of the expected type.
+//! Each such assertion becomes a case of two message headers (the type of the message and
+//! the expected type) and the expected outcome. Assertions on other values, and references
+//! that are rejected for other reasons than subtyping (invalid type tables or values), are
+//! skipped.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::path::Path;
+
+const TEST_FILES: &[&str] = &["subtypes.test.did", "reference.test.did"];
+
+const MAX_PRINCIPAL_LENGTH: usize = 29;
+
+const IDL_CON_OPT: i64 = -18;
+const IDL_CON_VEC: i64 = -19;
+const IDL_CON_RECORD: i64 = -20;
+const IDL_CON_VARIANT: i64 = -21;
+const IDL_CON_FUNC: i64 = -22;
+const IDL_CON_SERVICE: i64 = -23;
+
+const IDL_PRIM_NULL: i64 = -1;
+const IDL_PRIM_NAT8: i64 = -5;
+const IDL_PRIM_EMPTY: i64 = -17;
+const IDL_PRIM_PRINCIPAL: i64 = -24;
+
+const FUNC_ANNOTATION_ONEWAY: u8 = 2;
+
+/// Writes `candid_test_suite.rs` to `out_dir`, defining
+/// `CANDID_TEST_SUITE: Option<&[(&str, &[u8], &[u8], bool)]>`, which is `None` if the
+/// test suite is not available (`CANDID_TESTS` unset).
+pub fn generate(out_dir: &Path) {
+    println!("cargo:rerun-if-env-changed=CANDID_TESTS");
+
+    let mut output = String::new();
+    match std::env::var("CANDID_TESTS") {
+        Ok(directory) => {
+            output.push_str(
+                "pub const CANDID_TEST_SUITE: Option<&[(&str, &[u8], &[u8], bool)]> = Some(&[\n",
+            );
+            for file in TEST_FILES {
+                let path = Path::new(&directory).join(file);
+                println!("cargo:rerun-if-changed={}", path.display());
+                let source = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|error| panic!("cannot read {}: {}", path.display(), error));
+                for case in parse_test_file(file, &source) {
+                    writeln!(
+                        output,
+                        "    ({:?}, {}, {}, {}),",
+                        case.description,
+                        byte_string(&case.message),
+                        byte_string(&case.expected_type),
+                        case.expected
+                    )
+                    .unwrap();
+                }
+            }
+            output.push_str("]);\n");
+        }
+        Err(_) => {
+            output.push_str(
+                "pub const CANDID_TEST_SUITE: Option<&[(&str, &[u8], &[u8], bool)]> = None;\n",
+            );
+        }
+    }
+    std::fs::write(out_dir.join("candid_test_suite.rs"), output).unwrap();
+}
+
+fn byte_string(bytes: &[u8]) -> String {
+    let mut result = String::from("b\"");
+    for byte in bytes {
+        if byte.is_ascii_alphanumeric() {
+            result.push(*byte as char);
+        } else {
+            write!(result, "\\x{:02x}", byte).unwrap();
+        }
+    }
+    result.push('"');
+    result
+}
+
+struct Case {
+    description: String,
+    message: Vec<u8>,
+    expected_type: Vec<u8>,
+    expected: bool,
+}
+
+// -- Lexer
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Id(String),
+    Number(u64),
+    Text(Vec<u8>),
+    Symbol(&'static str),
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    const SYMBOLS: &[&str] = &[
+        "!:", "==", "!=", "->", "(", ")", "{", "}", ";", ":", ",", "=",
+    ];
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    'next: while i < bytes.len() {
+        let c = bytes[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else if bytes[i..].starts_with(b"/*") {
+            let mut depth = 0;
+            while i < bytes.len() {
+                if bytes[i..].starts_with(b"/*") {
+                    depth += 1;
+                    i += 2;
+                } else if bytes[i..].starts_with(b"*/") {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if c == b'"' {
+            let (text, end) = text_literal(bytes, i + 1);
+            tokens.push(Token::Text(text));
+            i = end;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'_') {
+                i += 1;
+            }
+            let digits: String = source[start..i].chars().filter(|c| *c != '_').collect();
+            tokens.push(Token::Number(digits.parse().unwrap()));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            let start = i;
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push(Token::Id(source[start..i].to_string()));
+        } else {
+            for symbol in SYMBOLS {
+                if bytes[i..].starts_with(symbol.as_bytes()) {
+                    tokens.push(Token::Symbol(symbol));
+                    i += symbol.len();
+                    continue 'next;
+                }
+            }
+            panic!("unexpected character {:?} in Candid test file", c as char);
+        }
+    }
+    tokens
+}
+
+// Returns the bytes of a text literal starting after the opening quote, and the position after
+// the closing quote. Besides the usual escapes, `\HH` denotes a byte in hexadecimal.
+fn text_literal(bytes: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut text = vec![];
+    while bytes[i] != b'"' {
+        if bytes[i] == b'\\' {
+            let escape = bytes[i + 1];
+            i += 2;
+            match escape {
+                b'n' => text.push(b'\n'),
+                b'r' => text.push(b'\r'),
+                b't' => text.push(b'\t'),
+                b'\\' | b'"' | b'\'' => text.push(escape),
+                b'u' => {
+                    let end = i + bytes[i..].iter().position(|b| *b == b'}').unwrap();
+                    let hex = std::str::from_utf8(&bytes[i + 1..end]).unwrap();
+                    let c = char::from_u32(u32::from_str_radix(hex, 16).unwrap()).unwrap();
+                    text.extend_from_slice(c.to_string().as_bytes());
+                    i = end + 1;
+                }
+                _ => {
+                    let hex = std::str::from_utf8(&bytes[i - 1..i + 1]).unwrap();
+                    text.push(u8::from_str_radix(hex, 16).unwrap());
+                    i += 1;
+                }
+            }
+        } else {
+            text.push(bytes[i]);
+            i += 1;
+        }
+    }
+    (text, i + 1)
+}
+
+// -- Parser of test files
+
+#[derive(Clone, Debug)]
+enum Type {
+    Prim(i64),
+    Var(String),
+    Opt(Box<Type>),
+    Vec(Box<Type>),
+    Record(Vec<(u32, Type)>),
+    Variant(Vec<(u32, Type)>),
+    Func(Vec<Type>, Vec<Type>, Vec<u8>),
+    Service(Vec<(Vec<u8>, Type)>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.position + offset)
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        self.position += 1;
+        token
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Id(id)) if id == keyword)
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) {
+        match self.next() {
+            Token::Symbol(s) if s == symbol => {}
+            other => panic!("expected `{}`, found {:?}", symbol, other),
+        }
+    }
+
+    fn expect_id(&mut self) -> String {
+        match self.next() {
+            Token::Id(id) => id,
+            other => panic!("expected identifier, found {:?}", other),
+        }
+    }
+
+    fn skip_to_semicolon(&mut self) {
+        while !self.is_symbol(";") {
+            self.next();
+        }
+        self.next();
+    }
+
+    fn data_type(&mut self) -> Type {
+        let id = self.expect_id();
+        match id.as_str() {
+            "opt" => Type::Opt(Box::new(self.data_type())),
+            "vec" => Type::Vec(Box::new(self.data_type())),
+            "blob" => Type::Vec(Box::new(Type::Prim(IDL_PRIM_NAT8))),
+            "record" => Type::Record(self.fields(false)),
+            "variant" => Type::Variant(self.fields(true)),
+            "func" => self.func_type(),
+            "service" => self.service_type(),
+            _ => match primitive_type(&id) {
+                Some(code) => Type::Prim(code),
+                None => Type::Var(id),
+            },
+        }
+    }
+
+    fn fields(&mut self, variant: bool) -> Vec<(u32, Type)> {
+        self.expect_symbol("{");
+        let mut fields = vec![];
+        let mut next_index = 0;
+        while !self.is_symbol("}") {
+            let labelled = matches!(self.peek_at(1), Some(Token::Symbol(":")));
+            let label = match self.peek() {
+                Some(Token::Number(n)) if labelled || variant => Some(*n as u32),
+                Some(Token::Id(id)) if labelled || (variant && primitive_type(id).is_none()) => {
+                    Some(idl_hash(id.as_bytes()))
+                }
+                Some(Token::Text(text)) => Some(idl_hash(text)),
+                _ => None,
+            };
+            let field = match label {
+                Some(label) => {
+                    self.next();
+                    if self.is_symbol(":") {
+                        self.next();
+                        (label, self.data_type())
+                    } else {
+                        (label, Type::Prim(IDL_PRIM_NULL))
+                    }
+                }
+                None => (next_index, self.data_type()),
+            };
+            next_index = field.0.wrapping_add(1);
+            fields.push(field);
+            if self.is_symbol(";") {
+                self.next();
+            }
+        }
+        self.next();
+        fields
+    }
+
+    fn arguments(&mut self) -> Vec<Type> {
+        self.expect_symbol("(");
+        let mut arguments = vec![];
+        while !self.is_symbol(")") {
+            // Argument names are irrelevant.
+            if matches!(self.peek_at(1), Some(Token::Symbol(":"))) {
+                self.next();
+                self.next();
+            }
+            arguments.push(self.data_type());
+            if self.is_symbol(",") {
+                self.next();
+            }
+        }
+        self.next();
+        arguments
+    }
+
+    fn func_type(&mut self) -> Type {
+        let arguments = self.arguments();
+        self.expect_symbol("->");
+        let results = self.arguments();
+        let mut annotations = vec![];
+        loop {
+            let annotation = if self.is_keyword("query") {
+                1
+            } else if self.is_keyword("oneway") {
+                2
+            } else if self.is_keyword("composite_query") {
+                3
+            } else {
+                break;
+            };
+            self.next();
+            annotations.push(annotation);
+        }
+        Type::Func(arguments, results, annotations)
+    }
+
+    fn service_type(&mut self) -> Type {
+        self.expect_symbol("{");
+        let mut methods = vec![];
+        while !self.is_symbol("}") {
+            let name = match self.next() {
+                Token::Id(id) => id.into_bytes(),
+                Token::Text(text) => text,
+                other => panic!("expected method name, found {:?}", other),
+            };
+            self.expect_symbol(":");
+            let method = if self.is_symbol("(") {
+                self.func_type()
+            } else {
+                self.data_type()
+            };
+            methods.push((name, method));
+            if self.is_symbol(";") {
+                self.next();
+            }
+        }
+        self.next();
+        Type::Service(methods)
+    }
+}
+
+fn primitive_type(name: &str) -> Option<i64> {
+    let code = match name {
+        "null" => -1,
+        "bool" => -2,
+        "nat" => -3,
+        "int" => -4,
+        "nat8" => -5,
+        "nat16" => -6,
+        "nat32" => -7,
+        "nat64" => -8,
+        "int8" => -9,
+        "int16" => -10,
+        "int32" => -11,
+        "int64" => -12,
+        "float32" => -13,
+        "float64" => -14,
+        "text" => -15,
+        "reserved" => -16,
+        "empty" => -17,
+        "principal" => -24,
+        _ => return None,
+    };
+    Some(code)
+}
+
+fn idl_hash(name: &[u8]) -> u32 {
+    name.iter().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(223).wrapping_add(*byte as u32)
+    })
+}
+
+fn parse_test_file(file: &str, source: &str) -> Vec<Case> {
+    let mut parser = Parser {
+        tokens: tokenize(source),
+        position: 0,
+    };
+    let mut definitions = HashMap::new();
+    let mut cases = vec![];
+    let mut count = 0;
+    while parser.peek().is_some() {
+        match parser.expect_id().as_str() {
+            "type" => {
+                let name = parser.expect_id();
+                parser.expect_symbol("=");
+                let definition = parser.data_type();
+                parser.expect_symbol(";");
+                definitions.insert(name, definition);
+            }
+            "assert" => {
+                count += 1;
+                let is_blob = parser.is_keyword("blob");
+                if is_blob {
+                    parser.next();
+                }
+                let input = match parser.next() {
+                    Token::Text(text) => text,
+                    other => panic!("expected test input, found {:?}", other),
+                };
+                let expected = if parser.is_symbol(":") {
+                    true
+                } else if parser.is_symbol("!:") {
+                    false
+                } else {
+                    // Equality tests between values are irrelevant for subtyping.
+                    parser.skip_to_semicolon();
+                    continue;
+                };
+                parser.next();
+                let arguments = parser.arguments();
+                let description = match parser.peek() {
+                    Some(Token::Text(text)) => {
+                        let text = String::from_utf8_lossy(text).into_owned();
+                        parser.next();
+                        text
+                    }
+                    _ => format!("assertion {}", count),
+                };
+                parser.expect_symbol(";");
+                if !is_blob || arguments.len() != 1 {
+                    continue;
+                }
+                if let Some((message, expected_type)) =
+                    reference_case(&input, &arguments[0], &definitions)
+                {
+                    cases.push(Case {
+                        description: format!("{}: {}", file, description),
+                        message,
+                        expected_type,
+                        expected,
+                    });
+                }
+            }
+            _ => parser.skip_to_semicolon(),
+        }
+    }
+    cases
+}
+
+// -- Encoding of the expected type
+
+struct Encoder<'a> {
+    definitions: &'a HashMap<String, Type>,
+    entries: Vec<Vec<u8>>,
+    indices: HashMap<String, i64>,
+}
+
+impl<'a> Encoder<'a> {
+    fn resolve(&self, mut t: &'a Type) -> Option<&'a Type> {
+        let mut steps = 0;
+        while let Type::Var(name) = t {
+            t = self.definitions.get(name)?;
+            steps += 1;
+            if steps > self.definitions.len() {
+                return None;
+            }
+        }
+        Some(t)
+    }
+
+    fn reference(&mut self, t: &'a Type) -> Option<i64> {
+        if let Type::Var(name) = t {
+            if let Some(index) = self.indices.get(name) {
+                return Some(*index);
+            }
+            return match self.resolve(t)? {
+                Type::Prim(code) => Some(*code),
+                definition => {
+                    let index = self.entries.len() as i64;
+                    self.entries.push(vec![]);
+                    self.indices.insert(name.clone(), index);
+                    let entry = self.entry(definition)?;
+                    self.entries[index as usize] = entry;
+                    Some(index)
+                }
+            };
+        }
+        if let Type::Prim(code) = t {
+            return Some(*code);
+        }
+        let index = self.entries.len();
+        self.entries.push(vec![]);
+        let entry = self.entry(t)?;
+        self.entries[index] = entry;
+        Some(index as i64)
+    }
+
+    fn entry(&mut self, t: &'a Type) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        match t {
+            Type::Opt(t1) | Type::Vec(t1) => {
+                let code = if matches!(t, Type::Opt(_)) {
+                    IDL_CON_OPT
+                } else {
+                    IDL_CON_VEC
+                };
+                sleb128(&mut bytes, code);
+                let t1 = self.reference(t1)?;
+                sleb128(&mut bytes, t1);
+            }
+            Type::Record(fields) | Type::Variant(fields) => {
+                let code = if matches!(t, Type::Record(_)) {
+                    IDL_CON_RECORD
+                } else {
+                    IDL_CON_VARIANT
+                };
+                let mut fields: Vec<&(u32, Type)> = fields.iter().collect();
+                fields.sort_by_key(|(label, _)| *label);
+                if fields.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                    return None;
+                }
+                sleb128(&mut bytes, code);
+                leb128(&mut bytes, fields.len() as u64);
+                for (label, field_type) in fields {
+                    leb128(&mut bytes, *label as u64);
+                    let field_type = self.reference(field_type)?;
+                    sleb128(&mut bytes, field_type);
+                }
+            }
+            Type::Func(arguments, results, annotations) => {
+                sleb128(&mut bytes, IDL_CON_FUNC);
+                for list in [arguments, results] {
+                    leb128(&mut bytes, list.len() as u64);
+                    for t1 in list {
+                        let t1 = self.reference(t1)?;
+                        sleb128(&mut bytes, t1);
+                    }
+                }
+                leb128(&mut bytes, annotations.len() as u64);
+                bytes.extend_from_slice(annotations);
+            }
+            Type::Service(methods) => {
+                let mut methods: Vec<&(Vec<u8>, Type)> = methods.iter().collect();
+                methods.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+                if methods.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+                    return None;
+                }
+                sleb128(&mut bytes, IDL_CON_SERVICE);
+                leb128(&mut bytes, methods.len() as u64);
+                for (name, method) in methods {
+                    if !matches!(self.resolve(method)?, Type::Func(..)) {
+                        return None;
+                    }
+                    leb128(&mut bytes, name.len() as u64);
+                    bytes.extend_from_slice(name);
+                    let method = self.reference(method)?;
+                    sleb128(&mut bytes, method);
+                }
+            }
+            Type::Prim(_) | Type::Var(_) => unreachable!(),
+        }
+        Some(bytes)
+    }
+}
+
+fn leb128(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn sleb128(bytes: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// -- Validation of the message
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn take(&mut self, length: u64) -> Option<&'a [u8]> {
+        let end = self.position.checked_add(usize::try_from(length).ok()?)?;
+        let bytes = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(bytes)
+    }
+
+    fn leb128(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn sleb128(&mut self) -> Option<i64> {
+        let mut value = 0i64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as i64) << shift;
+            if byte & 0x80 == 0 {
+                if shift + 7 < 64 && byte & 0x40 != 0 {
+                    value |= -1 << (shift + 7);
+                }
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+fn valid_type(t: i64, table_size: usize) -> bool {
+    (t >= 0 && (t as usize) < table_size)
+        || (IDL_PRIM_EMPTY..=IDL_PRIM_NULL).contains(&t)
+        || t == IDL_PRIM_PRINCIPAL
+}
+
+// Checks the type table of the message and returns its opcodes, or `None` if it is invalid.
+fn type_table_opcodes(reader: &mut Reader) -> Option<Vec<i64>> {
+    let size = reader.leb128()? as usize;
+    if size > reader.bytes.len() {
+        return None;
+    }
+    let mut opcodes = vec![];
+    let mut method_types = vec![];
+    for _ in 0..size {
+        let opcode = reader.sleb128()?;
+        opcodes.push(opcode);
+        match opcode {
+            IDL_CON_OPT | IDL_CON_VEC => {
+                if !valid_type(reader.sleb128()?, size) {
+                    return None;
+                }
+            }
+            IDL_CON_RECORD | IDL_CON_VARIANT => {
+                let mut previous: Option<u64> = None;
+                for _ in 0..reader.leb128()? {
+                    let label = reader.leb128()?;
+                    if label > u32::MAX as u64 || previous.map_or(false, |p| p >= label) {
+                        return None;
+                    }
+                    previous = Some(label);
+                    if !valid_type(reader.sleb128()?, size) {
+                        return None;
+                    }
+                }
+            }
+            IDL_CON_FUNC => {
+                for _ in 0..reader.leb128()? {
+                    if !valid_type(reader.sleb128()?, size) {
+                        return None;
+                    }
+                }
+                let results = reader.leb128()?;
+                for _ in 0..results {
+                    if !valid_type(reader.sleb128()?, size) {
+                        return None;
+                    }
+                }
+                let annotations = reader.leb128()?;
+                let annotations = reader.take(annotations)?;
+                if annotations.len() > 1
+                    || annotations.iter().any(|a| !(1..=3).contains(a))
+                    || (annotations.contains(&FUNC_ANNOTATION_ONEWAY) && results > 0)
+                {
+                    return None;
+                }
+            }
+            IDL_CON_SERVICE => {
+                let mut previous: Option<&[u8]> = None;
+                for _ in 0..reader.leb128()? {
+                    let length = reader.leb128()?;
+                    let name = reader.take(length)?;
+                    if std::str::from_utf8(name).is_err() || previous.map_or(false, |p| p >= name) {
+                        return None;
+                    }
+                    previous = Some(name);
+                    let method = reader.sleb128()?;
+                    if method < 0 || method as usize >= size {
+                        return None;
+                    }
+                    method_types.push(method as usize);
+                }
+            }
+            _ => return None,
+        }
+    }
+    if method_types.iter().any(|t| opcodes[*t] != IDL_CON_FUNC) {
+        return None;
+    }
+    Some(opcodes)
+}
+
+// A public service reference, or the service of a public function reference.
+fn principal(reader: &mut Reader) -> Option<()> {
+    if reader.byte()? != 1 {
+        return None;
+    }
+    let length = reader.leb128()?;
+    if length as usize > MAX_PRINCIPAL_LENGTH {
+        return None;
+    }
+    reader.take(length).map(|_| ())
+}
+
+// Returns the header of the message and the header of the expected type, if the message is a
+// valid reference and the expected type is a reference type.
+fn reference_case(
+    input: &[u8],
+    expected_type: &Type,
+    definitions: &HashMap<String, Type>,
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    if !input.starts_with(b"DIDL") {
+        return None;
+    }
+    let mut reader = Reader {
+        bytes: input,
+        position: 4,
+    };
+    let opcodes = type_table_opcodes(&mut reader)?;
+    if reader.leb128()? != 1 {
+        return None;
+    }
+    let main_type = reader.sleb128()?;
+    if main_type < 0 || main_type as usize >= opcodes.len() {
+        return None;
+    }
+    let header_end = reader.position;
+    match opcodes[main_type as usize] {
+        IDL_CON_FUNC => {
+            // Opaque references are not supported.
+            if reader.byte()? != 1 {
+                return None;
+            }
+            principal(&mut reader)?;
+            let length = reader.leb128()?;
+            std::str::from_utf8(reader.take(length)?).ok()?;
+        }
+        IDL_CON_SERVICE => principal(&mut reader)?,
+        _ => return None,
+    }
+    if reader.position != input.len() {
+        return None;
+    }
+
+    let mut encoder = Encoder {
+        definitions,
+        entries: vec![],
+        indices: HashMap::new(),
+    };
+    if !matches!(
+        encoder.resolve(expected_type)?,
+        Type::Func(..) | Type::Service(..)
+    ) {
+        return None;
+    }
+    let main = encoder.reference(expected_type)?;
+    let mut header = b"DIDL".to_vec();
+    leb128(&mut header, encoder.entries.len() as u64);
+    for entry in &encoder.entries {
+        header.extend_from_slice(entry);
+    }
+    leb128(&mut header, 1);
+    sleb128(&mut header, main);
+
+    Some((input[..header_end].to_vec(), header))
+}
@@ -0,0 +1,173 @@
+use motoko_rts::compatibility::offline::{
+    check_compatibility, EncodedTypeDescriptor, InvalidTypeDescriptor, TypeDescriptorBytes,
+};
+
+// Stable actor types `{ x : opt T }` (type 0) with `opt T` (type 1), field hash of `x` = 120.
+const NAT_FIELD: &[u8] = b"\x6c\x01\x78\x01\x6e\x7d";
+const INT_FIELD: &[u8] = b"\x6c\x01\x78\x01\x6e\x7c";
+const TEXT_FIELD: &[u8] = b"\x6c\x01\x78\x01\x6e\x71";
+
+// `{ x : opt nat; y : opt [var nat] }`, field hash of `y` = 121.
+const TWO_FIELDS: &[u8] = b"\x6c\x02\x78\x01\x79\x02\x6e\x7d\x6e\x03\x01\x04\x6d\x7d";
+const TWO_FIELDS_OFFSETS: &[u64] = &[0, 6, 8, 10, 12];
+
+// `{ x : opt text; y : opt [var int] }`
+const TWO_CHANGED_FIELDS: &[u8] = b"\x6c\x02\x78\x01\x79\x02\x6e\x71\x6e\x03\x01\x04\x6d\x7c";
+
+const FIELD_NAMES: &[u8] = b"\x01x";
+const TWO_FIELD_NAMES: &[u8] = b"\x01x\x01y";
+
+pub unsafe fn test() {
+    println!("Testing offline compatibility check ...");
+
+    let offsets = type_offsets(&[0, 4]);
+    let nat_field = TypeDescriptorBytes::new(NAT_FIELD, &offsets).unwrap();
+    let int_field = TypeDescriptorBytes::new(INT_FIELD, &offsets).unwrap();
+    let text_field = TypeDescriptorBytes::new(TEXT_FIELD, &offsets).unwrap();
+
+    let two_offsets = type_offsets(TWO_FIELDS_OFFSETS);
+    let two_fields = TypeDescriptorBytes::new(TWO_FIELDS, &two_offsets).unwrap();
+    let two_changed_fields = TypeDescriptorBytes::new(TWO_CHANGED_FIELDS, &two_offsets).unwrap();
+
+    assert_compatible(&nat_field, &nat_field);
+    assert_compatible(&nat_field, &int_field);
+    // New stable variables can be added and existing ones dropped.
+    assert_compatible(&nat_field, &two_fields);
+    assert_compatible(&two_fields, &nat_field);
+
+    assert_incompatible(
+        &int_field,
+        &nat_field,
+        FIELD_NAMES,
+        "x: int -> nat (covariant)",
+    );
+    assert_incompatible(
+        &nat_field,
+        &text_field,
+        FIELD_NAMES,
+        "x: nat -> text (covariant)",
+    );
+    assert_incompatible(
+        &nat_field,
+        &text_field,
+        b"",
+        "_120_: nat -> text (covariant)",
+    );
+    assert_incompatible(
+        &two_fields,
+        &two_changed_fields,
+        TWO_FIELD_NAMES,
+        "x: nat -> text (covariant); y[]: nat -> int (invariant)",
+    );
+
+    test_invalid_descriptors();
+    test_encoded_descriptors();
+}
+
+fn test_invalid_descriptors() {
+    let invalid = |candid_data: &[u8], offsets: &[u8]| {
+        TypeDescriptorBytes::new(candid_data, offsets)
+            .err()
+            .unwrap()
+    };
+
+    assert_eq!(
+        invalid(NAT_FIELD, &[]),
+        InvalidTypeDescriptor::InvalidOffsets
+    );
+    assert_eq!(
+        invalid(NAT_FIELD, &[0, 0, 0, 0]),
+        InvalidTypeDescriptor::InvalidOffsets
+    );
+    assert_eq!(
+        invalid(NAT_FIELD, &type_offsets(&[0, 6])),
+        InvalidTypeDescriptor::OffsetOutOfBounds(1)
+    );
+    // Reference to a missing type.
+    assert_eq!(
+        invalid(NAT_FIELD, &type_offsets(&[0])),
+        InvalidTypeDescriptor::InvalidType(0)
+    );
+    // Truncated record.
+    assert_eq!(
+        invalid(&NAT_FIELD[..3], &type_offsets(&[0])),
+        InvalidTypeDescriptor::InvalidType(0)
+    );
+    // Unknown type.
+    assert_eq!(
+        invalid(b"\x6c\x01\x78\x01\x6e\x60", &type_offsets(&[0, 4])),
+        InvalidTypeDescriptor::InvalidType(1)
+    );
+}
+
+fn test_encoded_descriptors() {
+    let offsets = type_offsets(&[0, 4]);
+    let descriptor = EncodedTypeDescriptor {
+        candid_data: NAT_FIELD,
+        type_offsets: &offsets,
+        field_names: FIELD_NAMES,
+    };
+    let bytes = descriptor.encode();
+    assert_eq!(
+        bytes.len(),
+        3 * 8 + NAT_FIELD.len() + offsets.len() + FIELD_NAMES.len()
+    );
+    assert_eq!(&bytes[..8], &(NAT_FIELD.len() as u64).to_le_bytes());
+
+    let decoded = EncodedTypeDescriptor::decode(&bytes).unwrap();
+    assert_eq!(decoded.candid_data, NAT_FIELD);
+    assert_eq!(decoded.type_offsets, offsets.as_slice());
+    assert_eq!(decoded.field_names, FIELD_NAMES);
+
+    let empty_names = EncodedTypeDescriptor {
+        field_names: b"",
+        ..descriptor
+    }
+    .encode();
+    assert_eq!(
+        EncodedTypeDescriptor::decode(&empty_names)
+            .unwrap()
+            .field_names,
+        b""
+    );
+
+    let invalid = |bytes: &[u8]| EncodedTypeDescriptor::decode(bytes).err().unwrap();
+    assert_eq!(invalid(b""), InvalidTypeDescriptor::InvalidEncoding);
+    // Missing part.
+    assert_eq!(
+        invalid(&bytes[..bytes.len() - FIELD_NAMES.len() - 8]),
+        InvalidTypeDescriptor::InvalidEncoding
+    );
+    // Truncated part.
+    assert_eq!(
+        invalid(&bytes[..bytes.len() - 1]),
+        InvalidTypeDescriptor::InvalidEncoding
+    );
+    // Trailing bytes.
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(invalid(&trailing), InvalidTypeDescriptor::InvalidEncoding);
+}
+
+fn type_offsets(offsets: &[u64]) -> Vec<u8> {
+    offsets
+        .iter()
+        .flat_map(|offset| offset.to_le_bytes())
+        .collect()
+}
+
+fn assert_compatible(old_type: &TypeDescriptorBytes, new_type: &TypeDescriptorBytes) {
+    let check = check_compatibility(old_type, new_type, b"");
+    assert!(check.is_compatible(), "{}", check);
+}
+
+fn assert_incompatible(
+    old_type: &TypeDescriptorBytes,
+    new_type: &TypeDescriptorBytes,
+    new_field_names: &[u8],
+    expected: &str,
+) {
+    let check = check_compatibility(old_type, new_type, new_field_names);
+    assert!(!check.is_compatible());
+    assert_eq!(check.to_string(), expected);
+}
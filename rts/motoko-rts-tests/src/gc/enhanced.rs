@@ -25,6 +25,8 @@ impl GC {
                     unused_root,
                     unused_root,
                     unused_root,
+                    unused_root,
+                    unused_root,
                 ];
                 IncrementalGC::instance(heap, get_incremental_gc_state())
                     .empty_call_stack_increment(roots);
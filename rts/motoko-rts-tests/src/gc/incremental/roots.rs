@@ -136,6 +136,8 @@ unsafe fn get_roots(heap: &MotokoHeap) -> Roots {
         unused_root,
         unused_root,
         unused_root,
+        unused_root,
+        unused_root,
     ]
 }
 
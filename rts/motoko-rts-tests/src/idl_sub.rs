@@ -0,0 +1,458 @@
+// Conformance cases for the subtyping rules of the Candid specification. Like the test vectors
+// of the Candid test suite, the types are given as Candid message headers (`DIDL`, type table,
+// one argument type), such that the cases run offline.
+//
+// In addition, the reference tests of the Candid test suite are run, as generated by `build.rs`
+// from `$CANDID_TESTS` (see `build/candid_test_suite.rs`).
+
+use motoko_rts::buf::Buf;
+use motoko_rts::idl_sub::{idl_sub, idl_sub_buf_init, idl_sub_buf_words};
+use motoko_rts::leb128::{leb128_decode, sleb128_decode};
+
+include!(concat!(env!("OUT_DIR"), "/candid_test_suite.rs"));
+
+// (description, subtype candidate, supertype candidate, expected result)
+const CASES: &[(&str, &[u8], &[u8], bool)] = &[
+    // Primitive types
+    ("nat <: int", b"DIDL\x00\x01\x7d", b"DIDL\x00\x01\x7c", true),
+    (
+        "int </: nat",
+        b"DIDL\x00\x01\x7c",
+        b"DIDL\x00\x01\x7d",
+        false,
+    ),
+    (
+        "nat8 </: nat",
+        b"DIDL\x00\x01\x7b",
+        b"DIDL\x00\x01\x7d",
+        false,
+    ),
+    (
+        "float32 <: float32",
+        b"DIDL\x00\x01\x73",
+        b"DIDL\x00\x01\x73",
+        true,
+    ),
+    (
+        "float32 </: float64",
+        b"DIDL\x00\x01\x73",
+        b"DIDL\x00\x01\x72",
+        false,
+    ),
+    (
+        "float64 </: float32",
+        b"DIDL\x00\x01\x72",
+        b"DIDL\x00\x01\x73",
+        false,
+    ),
+    (
+        "empty <: float32",
+        b"DIDL\x00\x01\x6f",
+        b"DIDL\x00\x01\x73",
+        true,
+    ),
+    (
+        "float32 <: reserved",
+        b"DIDL\x00\x01\x73",
+        b"DIDL\x00\x01\x70",
+        true,
+    ),
+    (
+        "reserved </: nat",
+        b"DIDL\x00\x01\x70",
+        b"DIDL\x00\x01\x7d",
+        false,
+    ),
+    (
+        "principal <: principal",
+        b"DIDL\x00\x01\x68",
+        b"DIDL\x00\x01\x68",
+        true,
+    ),
+    (
+        "text </: principal",
+        b"DIDL\x00\x01\x71",
+        b"DIDL\x00\x01\x68",
+        false,
+    ),
+    // Options
+    (
+        "null <: opt nat",
+        b"DIDL\x00\x01\x7f",
+        b"DIDL\x01\x6e\x7d\x01\x00",
+        true,
+    ),
+    (
+        "nat <: opt int",
+        b"DIDL\x00\x01\x7d",
+        b"DIDL\x01\x6e\x7c\x01\x00",
+        true,
+    ),
+    (
+        "text <: opt nat",
+        b"DIDL\x00\x01\x71",
+        b"DIDL\x01\x6e\x7d\x01\x00",
+        true,
+    ),
+    (
+        "opt text <: opt nat",
+        b"DIDL\x01\x6e\x71\x01\x00",
+        b"DIDL\x01\x6e\x7d\x01\x00",
+        true,
+    ),
+    (
+        "opt nat </: nat",
+        b"DIDL\x01\x6e\x7d\x01\x00",
+        b"DIDL\x00\x01\x7d",
+        false,
+    ),
+    (
+        "vec opt nat <: vec opt text",
+        b"DIDL\x02\x6d\x01\x6e\x7d\x01\x00",
+        b"DIDL\x02\x6d\x01\x6e\x71\x01\x00",
+        true,
+    ),
+    (
+        "opt opt nat <: opt opt opt text",
+        b"DIDL\x02\x6e\x01\x6e\x7d\x01\x00",
+        b"DIDL\x03\x6e\x01\x6e\x02\x6e\x71\x01\x00",
+        true,
+    ),
+    // Vectors
+    (
+        "vec nat <: vec int",
+        b"DIDL\x01\x6d\x7d\x01\x00",
+        b"DIDL\x01\x6d\x7c\x01\x00",
+        true,
+    ),
+    (
+        "vec int </: vec nat",
+        b"DIDL\x01\x6d\x7c\x01\x00",
+        b"DIDL\x01\x6d\x7d\x01\x00",
+        false,
+    ),
+    // Records
+    (
+        "record { 1 : nat; 2 : text } <: record { 1 : int }",
+        b"DIDL\x01\x6c\x02\x01\x7d\x02\x71\x01\x00",
+        b"DIDL\x01\x6c\x01\x01\x7c\x01\x00",
+        true,
+    ),
+    (
+        "record { 2 : nat } <: record { 1 : opt nat; 2 : nat }",
+        b"DIDL\x01\x6c\x01\x02\x7d\x01\x00",
+        b"DIDL\x02\x6c\x02\x01\x01\x02\x7d\x6e\x7d\x01\x00",
+        true,
+    ),
+    (
+        "record { 1 : nat; 3 : nat } <: record { 2 : opt nat; 3 : int }",
+        b"DIDL\x01\x6c\x02\x01\x7d\x03\x7d\x01\x00",
+        b"DIDL\x02\x6c\x02\x02\x01\x03\x7c\x6e\x7d\x01\x00",
+        true,
+    ),
+    (
+        "record {} <: record { 1 : reserved; 2 : null }",
+        b"DIDL\x01\x6c\x00\x01\x00",
+        b"DIDL\x01\x6c\x02\x01\x70\x02\x7f\x01\x00",
+        true,
+    ),
+    (
+        "record { 1 : nat } </: record { 2 : nat }",
+        b"DIDL\x01\x6c\x01\x01\x7d\x01\x00",
+        b"DIDL\x01\x6c\x01\x02\x7d\x01\x00",
+        false,
+    ),
+    (
+        "record {} </: record { 1 : nat }",
+        b"DIDL\x01\x6c\x00\x01\x00",
+        b"DIDL\x01\x6c\x01\x01\x7d\x01\x00",
+        false,
+    ),
+    (
+        "record { 1 : nat; 2 : nat } </: record { 1 : nat; 2 : text }",
+        b"DIDL\x01\x6c\x02\x01\x7d\x02\x7d\x01\x00",
+        b"DIDL\x01\x6c\x02\x01\x7d\x02\x71\x01\x00",
+        false,
+    ),
+    // Variants
+    (
+        "variant { 1 : nat } <: variant { 1 : int; 2 : text }",
+        b"DIDL\x01\x6b\x01\x01\x7d\x01\x00",
+        b"DIDL\x01\x6b\x02\x01\x7c\x02\x71\x01\x00",
+        true,
+    ),
+    (
+        "variant { 1 : nat; 2 : text } </: variant { 1 : nat }",
+        b"DIDL\x01\x6b\x02\x01\x7d\x02\x71\x01\x00",
+        b"DIDL\x01\x6b\x01\x01\x7d\x01\x00",
+        false,
+    ),
+    (
+        "variant { 2 : nat } </: variant { 1 : nat; 3 : nat }",
+        b"DIDL\x01\x6b\x01\x02\x7d\x01\x00",
+        b"DIDL\x01\x6b\x02\x01\x7d\x03\x7d\x01\x00",
+        false,
+    ),
+    // Functions
+    (
+        "func (int) -> (nat) <: func (nat) -> (int)",
+        b"DIDL\x01\x6a\x01\x7c\x01\x7d\x00\x01\x00",
+        b"DIDL\x01\x6a\x01\x7d\x01\x7c\x00\x01\x00",
+        true,
+    ),
+    (
+        "func (nat) -> (int) </: func (int) -> (nat)",
+        b"DIDL\x01\x6a\x01\x7d\x01\x7c\x00\x01\x00",
+        b"DIDL\x01\x6a\x01\x7c\x01\x7d\x00\x01\x00",
+        false,
+    ),
+    (
+        "func (float32) -> (float32) </: func (float64) -> (float64)",
+        b"DIDL\x01\x6a\x01\x73\x01\x73\x00\x01\x00",
+        b"DIDL\x01\x6a\x01\x72\x01\x72\x00\x01\x00",
+        false,
+    ),
+    (
+        "func (nat) -> () </: func () -> ()",
+        b"DIDL\x01\x6a\x01\x7d\x00\x00\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        false,
+    ),
+    (
+        "func (opt nat) -> () <: func () -> ()",
+        b"DIDL\x02\x6a\x01\x01\x00\x00\x6e\x7d\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        true,
+    ),
+    (
+        "func () -> () <: func (nat) -> ()",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x01\x6a\x01\x7d\x00\x00\x01\x00",
+        true,
+    ),
+    (
+        "func () -> (nat) <: func () -> ()",
+        b"DIDL\x01\x6a\x00\x01\x7d\x00\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        true,
+    ),
+    (
+        "func () -> () <: func () -> (opt nat)",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x02\x6a\x00\x01\x01\x00\x6e\x7d\x01\x00",
+        true,
+    ),
+    (
+        "func () -> () </: func () -> (nat)",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x01\x6a\x00\x01\x7d\x00\x01\x00",
+        false,
+    ),
+    // Function annotations must agree
+    (
+        "func () -> () query <: func () -> () query",
+        b"DIDL\x01\x6a\x00\x00\x01\x01\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x01\x01\x01\x00",
+        true,
+    ),
+    (
+        "func () -> () query </: func () -> ()",
+        b"DIDL\x01\x6a\x00\x00\x01\x01\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        false,
+    ),
+    (
+        "func () -> () </: func () -> () query",
+        b"DIDL\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x01\x01\x01\x00",
+        false,
+    ),
+    (
+        "func () -> () oneway <: func () -> () oneway",
+        b"DIDL\x01\x6a\x00\x00\x01\x02\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x01\x02\x01\x00",
+        true,
+    ),
+    (
+        "func () -> () composite_query <: func () -> () composite_query",
+        b"DIDL\x01\x6a\x00\x00\x01\x03\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x01\x03\x01\x00",
+        true,
+    ),
+    (
+        "func () -> () composite_query </: func () -> () query",
+        b"DIDL\x01\x6a\x00\x00\x01\x03\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x01\x01\x01\x00",
+        false,
+    ),
+    (
+        "func () -> () query </: func () -> () composite_query",
+        b"DIDL\x01\x6a\x00\x00\x01\x01\x01\x00",
+        b"DIDL\x01\x6a\x00\x00\x01\x03\x01\x00",
+        false,
+    ),
+    // Services
+    (
+        "service { a : func () -> (); b : func () -> () } <: service { b : func () -> () }",
+        b"DIDL\x02\x69\x02\x01a\x01\x01b\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x02\x69\x01\x01b\x01\x6a\x00\x00\x00\x01\x00",
+        true,
+    ),
+    (
+        "service { b : func () -> () } </: service { a : func () -> (); b : func () -> () }",
+        b"DIDL\x02\x69\x01\x01b\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x02\x69\x02\x01a\x01\x01b\x01\x6a\x00\x00\x00\x01\x00",
+        false,
+    ),
+    (
+        "service { a : func () -> (); ab : func () -> () } <: service { ab : func () -> () }",
+        b"DIDL\x02\x69\x02\x01a\x01\x02ab\x01\x6a\x00\x00\x00\x01\x00",
+        b"DIDL\x02\x69\x01\x02ab\x01\x6a\x00\x00\x00\x01\x00",
+        true,
+    ),
+    (
+        "service { a : func () -> () query } </: service { a : func () -> () }",
+        b"DIDL\x02\x69\x01\x01a\x01\x6a\x00\x00\x01\x01\x01\x00",
+        b"DIDL\x02\x69\x01\x01a\x01\x6a\x00\x00\x00\x01\x00",
+        false,
+    ),
+    (
+        "service { a : func () -> (nat) } <: service { a : func () -> (int) }",
+        b"DIDL\x02\x69\x01\x01a\x01\x6a\x00\x01\x7d\x00\x01\x00",
+        b"DIDL\x02\x69\x01\x01a\x01\x6a\x00\x01\x7c\x00\x01\x00",
+        true,
+    ),
+    // Recursive types
+    (
+        "μlist. opt record { 0 : nat; 1 : list } <: μlist. opt record { 0 : int; 1 : list }",
+        b"DIDL\x02\x6e\x01\x6c\x02\x00\x7d\x01\x00\x01\x00",
+        b"DIDL\x02\x6e\x01\x6c\x02\x00\x7c\x01\x00\x01\x00",
+        true,
+    ),
+    (
+        "μs. service { m : func () -> (s) } <: μs. service { m : func () -> (s) }",
+        b"DIDL\x02\x69\x01\x01m\x01\x6a\x00\x01\x00\x00\x01\x00",
+        b"DIDL\x02\x69\x01\x01m\x01\x6a\x00\x01\x00\x00\x01\x00",
+        true,
+    ),
+    (
+        "μs. service { m : func (s) -> () } </: μs. service { m : func (s) -> () query }",
+        b"DIDL\x02\x69\x01\x01m\x01\x6a\x01\x00\x00\x00\x01\x00",
+        b"DIDL\x02\x69\x01\x01m\x01\x6a\x01\x00\x00\x01\x01\x01\x00",
+        false,
+    ),
+];
+
+pub unsafe fn test() {
+    println!("Testing Candid subtyping ...");
+
+    check_cases(CASES);
+
+    match CANDID_TEST_SUITE {
+        Some(cases) => {
+            println!("  Testing {} cases of the Candid test suite", cases.len());
+            check_cases(cases);
+        }
+        None => println!("  Skipping the Candid test suite (CANDID_TESTS not set)"),
+    }
+}
+
+unsafe fn check_cases(cases: &[(&str, &[u8], &[u8], bool)]) {
+    for (description, message1, message2, expected) in cases {
+        assert_eq!(
+            is_subtype(message1, message2),
+            *expected,
+            "unexpected result for {}",
+            description
+        );
+    }
+}
+
+struct TypeTable {
+    /// Owns the bytes that the entries point into
+    message: Vec<u8>,
+    entries: Vec<*mut u8>,
+    end: *mut u8,
+    main_type: i32,
+}
+
+/// Parses a (trusted) Candid message header with a single argument type.
+unsafe fn parse_header(message: &[u8]) -> TypeTable {
+    assert_eq!(&message[..4], b"DIDL");
+    let mut table = TypeTable {
+        message: message.to_vec(),
+        entries: vec![],
+        end: core::ptr::null_mut(),
+        main_type: 0,
+    };
+    let start = table.message.as_mut_ptr();
+    let mut buf = Buf {
+        ptr: start.add(4),
+        end: start.add(message.len()),
+    };
+    for _ in 0..leb128_decode(&mut buf) {
+        table.entries.push(buf.ptr);
+        skip_entry(&mut buf);
+    }
+    table.end = buf.ptr;
+    assert_eq!(leb128_decode(&mut buf), 1);
+    table.main_type = sleb128_decode(&mut buf) as i32;
+    assert!(buf.ptr == buf.end);
+    table
+}
+
+unsafe fn skip_entry(buf: &mut Buf) {
+    match sleb128_decode(buf) {
+        // opt, vec
+        -18 | -19 => {
+            sleb128_decode(buf);
+        }
+        // record, variant
+        -20 | -21 => {
+            for _ in 0..leb128_decode(buf) {
+                leb128_decode(buf);
+                sleb128_decode(buf);
+            }
+        }
+        // func
+        -22 => {
+            for _ in 0..leb128_decode(buf) {
+                sleb128_decode(buf);
+            }
+            for _ in 0..leb128_decode(buf) {
+                sleb128_decode(buf);
+            }
+            let annotations = leb128_decode(buf);
+            buf.ptr = buf.ptr.add(annotations);
+        }
+        // service
+        -23 => {
+            for _ in 0..leb128_decode(buf) {
+                let length = leb128_decode(buf);
+                buf.ptr = buf.ptr.add(length);
+                sleb128_decode(buf);
+            }
+        }
+        other => panic!("unexpected type constructor {}", other),
+    }
+}
+
+unsafe fn is_subtype(message1: &[u8], message2: &[u8]) -> bool {
+    let mut table1 = parse_header(message1);
+    let mut table2 = parse_header(message2);
+    let size1 = table1.entries.len();
+    let size2 = table2.entries.len();
+    let mut rel_buf = vec![0usize; idl_sub_buf_words(size1, size2)];
+    idl_sub_buf_init(rel_buf.as_mut_ptr(), size1, size2);
+    idl_sub(
+        rel_buf.as_mut_ptr(),
+        table1.entries.as_mut_ptr(),
+        table2.entries.as_mut_ptr(),
+        table1.end,
+        table2.end,
+        size1,
+        size2,
+        table1.main_type,
+        table2.main_type,
+    )
+}
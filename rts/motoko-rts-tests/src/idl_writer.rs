@@ -0,0 +1,49 @@
+use crate::memory::{initialize_test_memory, reset_test_memory};
+
+use motoko_rts::idl_writer::{idl_hash, IdlWriter};
+use motoko_rts::types::Value;
+
+pub unsafe fn test() {
+    println!("Testing IDL writer ...");
+
+    assert_eq!(idl_hash(""), 0);
+    assert_eq!(idl_hash("a"), 97);
+    assert_eq!(idl_hash("foo"), 5097222);
+    assert_eq!(idl_hash("region"), 9224436);
+
+    let mut heap = initialize_test_memory();
+
+    let writer = IdlWriter::new(&mut heap);
+    assert_eq!(blob_bytes(writer.finish()), b"DIDL");
+
+    let mut writer = IdlWriter::new(&mut heap);
+    writer.write_leb128(&mut heap, 0);
+    writer.write_leb128(&mut heap, 624485);
+    writer.write_sleb128(&mut heap, -1);
+    writer.write_sleb128(&mut heap, 63);
+    writer.write_sleb128(&mut heap, -123456);
+    writer.write_bool(&mut heap, true);
+    writer.write_u16(&mut heap, 0x0102);
+    writer.write_u32(&mut heap, 0x01020304);
+    assert_eq!(writer.length(), 20);
+    assert_eq!(
+        blob_bytes(writer.finish()),
+        b"DIDL\x00\xe5\x8e\x26\x7f\x3f\xc0\xbb\x78\x01\x02\x01\x04\x03\x02\x01"
+    );
+
+    // Growing beyond the initial capacity
+    let mut writer = IdlWriter::new(&mut heap);
+    let payload = [0xab; 1000];
+    writer.write_blob(&mut heap, &payload);
+    let bytes = blob_bytes(writer.finish());
+    assert_eq!(bytes.len(), 4 + 2 + payload.len());
+    assert_eq!(&bytes[4..6], b"\xe8\x07");
+    assert_eq!(&bytes[6..], &payload[..]);
+
+    reset_test_memory();
+}
+
+unsafe fn blob_bytes(blob: Value) -> Vec<u8> {
+    let blob = blob.as_blob();
+    std::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize()).to_vec()
+}
@@ -7,9 +7,13 @@ mod print;
 
 mod bigint;
 mod bitrel;
+#[enhanced_orthogonal_persistence]
+mod compatibility;
 mod continuation_table;
 mod crc32;
 mod gc;
+mod idl_sub;
+mod idl_writer;
 mod leb128;
 mod memory;
 mod principal_id;
@@ -18,6 +22,7 @@ mod principal_id;
 mod stabilization;
 mod stable_option;
 mod text;
+mod upgrade_history;
 mod utf8;
 
 fn main() {
@@ -29,11 +34,14 @@ fn main() {
         continuation_table::test();
         crc32::test();
         gc::test();
+        idl_sub::test();
+        idl_writer::test();
         leb128::test();
         principal_id::test();
         persistence_test();
         stable_option::test();
         text::test();
+        upgrade_history::test();
         utf8::test();
     }
 }
@@ -59,6 +67,7 @@ fn check_architecture() {
 #[enhanced_orthogonal_persistence]
 fn persistence_test() {
     unsafe {
+        compatibility::test();
         stabilization::test();
     }
 }
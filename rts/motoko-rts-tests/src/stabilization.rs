@@ -1,3 +1,6 @@
+mod checksum;
+mod deduplication;
+mod inspector;
 mod layout;
 mod reader_writer;
 mod stable_bigints;
@@ -12,11 +15,11 @@ use crate::{
     stabilization::stable_memory::clear_stable_memory,
 };
 use motoko_rts::{
-    memory::{alloc_array, Memory},
+    memory::{alloc_array, alloc_blob, Memory},
     stabilization::{
         deserialization::Deserialization, graph_copy::GraphCopy, serialization::Serialization,
     },
-    types::{Value, Words, TAG_ARRAY_M},
+    types::{Bytes, Value, Words, TAG_ARRAY_M, TAG_BLOB_B},
 };
 use oorandom::Rand32;
 
@@ -25,6 +28,9 @@ pub unsafe fn test() {
     layout::test();
     stable_bigints::test();
     reader_writer::test();
+    checksum::test();
+    deduplication::test();
+    inspector::test();
     test_stabilization();
     reset_memory();
 }
@@ -156,26 +162,68 @@ fn test_serialization_deserialization(random: &mut Rand32, max_objects: usize, s
     let mut heap = random_heap(random, max_objects);
     let old_stable_root = heap.old_stable_root();
 
-    let stable_size = serialize(old_stable_root, stable_start);
+    // The serialization allocates the chunk checksums, which requires a GC state.
+    reset_main_memory();
+    let serialized_data = serialize(old_stable_root, stable_start);
 
     heap.clear();
 
-    let stable_root = deserialize(&mut heap.memory, stable_start, stable_size);
+    let stable_root = deserialize(&mut heap.memory, stable_start, &serialized_data);
 
     heap.set_new_root(stable_root);
     heap.check_heap();
 }
 
-fn serialize(old_stable_root: Value, stable_start: u64) -> u64 {
-    let mut memory = TestMemory::new(Words(0));
-    let mut serialization = Serialization::start(&mut memory, old_stable_root, stable_start);
+struct SerializedData {
+    length: u64,
+    chunk_checksums: Vec<u8>,
+}
+
+fn serialize(old_stable_root: Value, stable_start: u64) -> SerializedData {
+    serialize_with_options(old_stable_root, stable_start, false)
+}
+
+fn serialize_with_options(
+    old_stable_root: Value,
+    stable_start: u64,
+    deduplication: bool,
+) -> SerializedData {
+    // Memory for the deduplication table and the chunk checksums.
+    let mut memory = TestMemory::new(Words(64 * 1024));
+    let mut serialization =
+        Serialization::start(&mut memory, old_stable_root, stable_start, deduplication);
     serialization.copy_increment(&mut memory);
     assert!(serialization.is_completed());
-    serialization.serialized_data_length()
+    let chunk_checksums = unsafe {
+        let blob = serialization.chunk_checksums().as_blob();
+        std::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize()).to_vec()
+    };
+    SerializedData {
+        length: serialization.serialized_data_length(),
+        chunk_checksums,
+    }
 }
 
-fn deserialize<M: Memory>(mem: &mut M, stable_start: u64, stable_size: u64) -> Value {
-    let mut deserialization = Deserialization::start(mem, stable_start, stable_size);
+fn deserialize<M: Memory>(
+    mem: &mut M,
+    stable_start: u64,
+    serialized_data: &SerializedData,
+) -> Value {
+    // Separate memory for the chunk checksums, as only the deserialized objects are checked in `mem`.
+    let mut checksum_memory = TestMemory::new(Words(64 * 1024));
+    let chunk_checksums = unsafe {
+        let length = serialized_data.chunk_checksums.len();
+        let blob = alloc_blob(&mut checksum_memory, TAG_BLOB_B, Bytes(length));
+        let payload = blob.as_blob_mut().payload_addr();
+        std::ptr::copy_nonoverlapping(serialized_data.chunk_checksums.as_ptr(), payload, length);
+        blob
+    };
+    let mut deserialization = Deserialization::start(
+        mem,
+        stable_start,
+        serialized_data.length,
+        Some(chunk_checksums),
+    );
     deserialization.copy_increment(mem);
     assert!(deserialization.is_completed());
     deserialization.get_stable_root()
@@ -0,0 +1,66 @@
+use motoko_rts::{
+    memory::alloc_blob,
+    stabilization::checksum::{ChunkChecksums, CHUNK_SIZE},
+    types::{Bytes, Value, TAG_BLOB_B},
+};
+
+use crate::{
+    memory::{initialize_test_memory, reset_test_memory},
+    stabilization::stable_memory::{
+        clear_stable_memory, ic0_stable64_grow, ic0_stable64_read, ic0_stable64_write,
+    },
+};
+
+const DATA_START: u64 = 1000;
+const DATA_LENGTH: u64 = 3 * CHUNK_SIZE + 42;
+
+pub unsafe fn test() {
+    println!("  Testing stable memory checksums ...");
+
+    clear_stable_memory();
+    ic0_stable64_grow(4);
+    let data: Vec<u8> = (0..DATA_LENGTH).map(|index| (index % 251) as u8).collect();
+    ic0_stable64_write(DATA_START, data.as_ptr() as u64, DATA_LENGTH);
+
+    let mut memory = initialize_test_memory();
+    let mut computation = ChunkChecksums::allocate(&mut memory, DATA_START, DATA_LENGTH);
+    while !computation.is_completed() {
+        computation.compute_next();
+    }
+    let table = computation.table();
+    assert_eq!(table.as_blob().len(), Bytes(4 * 4));
+
+    assert_eq!(verify(table), Ok(()));
+    for offset in [0, CHUNK_SIZE + 17, DATA_LENGTH - 1] {
+        flip_byte(DATA_START + offset);
+        let chunk_start = DATA_START + offset / CHUNK_SIZE * CHUNK_SIZE;
+        assert_eq!(verify(table), Err(chunk_start));
+        flip_byte(DATA_START + offset);
+    }
+    assert_eq!(verify(table), Ok(()));
+
+    // Loading an equal table from elsewhere.
+    let copy = alloc_blob(&mut memory, TAG_BLOB_B, table.as_blob().len());
+    for index in 0..table.as_blob().len().as_usize() {
+        copy.as_blob_mut().set(index, table.as_blob().get(index));
+    }
+    assert_eq!(verify(copy), Ok(()));
+
+    reset_test_memory();
+    clear_stable_memory();
+}
+
+unsafe fn verify(table: Value) -> Result<(), u64> {
+    let mut verification = ChunkChecksums::load(table, DATA_START, DATA_LENGTH);
+    while !verification.is_completed() {
+        verification.verify_next()?;
+    }
+    Ok(())
+}
+
+fn flip_byte(address: u64) {
+    let mut byte = 0u8;
+    ic0_stable64_read(&mut byte as *mut u8 as u64, address, 1);
+    byte ^= 0xff;
+    ic0_stable64_write(address, &byte as *const u8 as u64, 1);
+}
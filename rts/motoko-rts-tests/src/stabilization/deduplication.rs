@@ -0,0 +1,74 @@
+use motoko_rts::{
+    memory::{alloc_array, alloc_blob, Memory},
+    text::text_of_str,
+    types::{Bytes, Value, TAG_ARRAY_I, TAG_BLOB_B, TAG_BLOB_T},
+};
+
+use crate::{
+    memory::{initialize_test_memory, reset_test_memory},
+    stabilization::{deserialize, serialize_with_options, stable_memory::clear_stable_memory},
+};
+
+const KEYS: usize = 10;
+const REPETITIONS: usize = 100;
+
+pub unsafe fn test() {
+    println!("  Testing stable blob deduplication ...");
+
+    let plain_size = test_serialization(false);
+    let deduplicated_size = test_serialization(true);
+    assert!(deduplicated_size < plain_size);
+
+    clear_stable_memory();
+}
+
+/// Serializes an array of repeated texts, followed by a blob with the same content as the first
+/// text, and returns the size of the serialized data.
+unsafe fn test_serialization(deduplication: bool) -> u64 {
+    let mut memory = initialize_test_memory();
+    let length = KEYS * REPETITIONS + 1;
+    let array = alloc_array(&mut memory, TAG_ARRAY_I, length);
+    for index in 0..KEYS * REPETITIONS {
+        let text = text_of_str(&mut memory, &key(index % KEYS));
+        array.as_array().initialize(index, text, &mut memory);
+    }
+    let blob = blob_of_str(&mut memory, &key(0));
+    array.as_array().initialize(length - 1, blob, &mut memory);
+
+    let serialized_data = serialize_with_options(array, 0, deduplication);
+
+    let output = deserialize(&mut memory, 0, &serialized_data).as_array();
+    assert_eq!(output.len(), length);
+    for index in 0..KEYS * REPETITIONS {
+        let text = output.get(index);
+        assert_eq!(text.tag(), TAG_BLOB_T);
+        assert_eq!(payload(text), key(index % KEYS).as_bytes());
+        if index >= KEYS {
+            let shared = output.get(index % KEYS) == text;
+            assert_eq!(shared, deduplication);
+        }
+    }
+    let blob = output.get(length - 1);
+    assert_eq!(blob.tag(), TAG_BLOB_B);
+    assert_eq!(payload(blob), key(0).as_bytes());
+    assert!(blob != output.get(0));
+
+    reset_test_memory();
+    serialized_data.length
+}
+
+fn key(index: usize) -> String {
+    format!("key{index}")
+}
+
+unsafe fn blob_of_str<M: Memory>(mem: &mut M, content: &str) -> Value {
+    let blob = alloc_blob(mem, TAG_BLOB_B, Bytes(content.len()));
+    let payload = blob.as_blob_mut().payload_addr();
+    std::ptr::copy_nonoverlapping(content.as_ptr(), payload, content.len());
+    blob
+}
+
+unsafe fn payload(blob: Value) -> &'static [u8] {
+    let blob = blob.as_blob();
+    std::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize())
+}
@@ -0,0 +1,202 @@
+use std::convert::TryInto;
+
+use motoko_rts::{
+    memory::{alloc_array, alloc_blob, Memory},
+    stabilization::checksum::blobs_checksum,
+    text::text_of_str,
+    types::{Bytes, Value, Words, TAG_ARRAY_I, TAG_ARRAY_T, TAG_BLOB_B},
+};
+use motoko_stable_format::{
+    metadata::{
+        LastPageRecord, UpgradeStatistics, CHECKSUM_MARKER, LAST_PAGE_RECORD_SIZE,
+        VERSION_GRAPH_COPY_NO_REGIONS,
+    },
+    upgrade_history::{PersistenceMode, UpgradeHistory, UPGRADE_HISTORY_MARKER},
+};
+use motoko_stable_inspector::{
+    export::{export_json, ExportOptions},
+    graph::{StableGraph, StableObjectKind},
+    image::{verify_chunks, Checksums, StableImage, PAGE_SIZE},
+    statistics::{KindStatistics, Statistics},
+    types::TypeTable,
+    InspectionError,
+};
+
+use crate::{
+    memory::{initialize_test_memory, reset_test_memory, TestMemory},
+    stabilization::{
+        serialize,
+        stable_memory::{clear_stable_memory, ic0_stable64_read},
+    },
+};
+
+/// Type table of `(Text, [Nat], Blob, Text)`, with the tuple as first type.
+const CANDID_DATA: &[u8] = &[
+    b'D', b'I', b'D', b'L', 0x02, // Header and type count
+    0xfe, 0x7e, 0x04, 0x00, 0x71, 0x01, 0x01, 0x02, 0xff, 0x7e, 0x03, 0x71, // Tuple
+    0x6d, 0x7d, // Vector of `Nat`
+];
+const TYPE_OFFSETS: &[u64] = &[5, 17];
+
+/// LEB128-length-prefixed field names, as generated by the compiler.
+const FIELD_NAMES: &[u8] = b"\x05hello\x05world";
+
+pub unsafe fn test() {
+    println!("  Testing stable image inspection ...");
+
+    let mut memory = initialize_test_memory();
+    let root = create_tuple(&mut memory);
+    let serialized_data = serialize(root, 0);
+    let mut data = vec![0u8; serialized_data.length as usize];
+    ic0_stable64_read(data.as_mut_ptr() as u64, 0, serialized_data.length);
+    reset_test_memory();
+    clear_stable_memory();
+
+    assert!(verify_chunks(&data, &serialized_data.chunk_checksums).is_ok());
+    test_image(&data, &serialized_data.chunk_checksums);
+    let graph = StableGraph::new(&data);
+    test_statistics(&graph);
+    test_export(&graph);
+
+    data[8] ^= 1;
+    assert_eq!(
+        verify_chunks(&data, &serialized_data.chunk_checksums),
+        Err(0)
+    );
+}
+
+/// The inspector reads a stable memory image as stored by the RTS, with the metadata checksum
+/// computed by the RTS.
+unsafe fn test_image(data: &[u8], chunk_checksums: &[u8]) {
+    let type_offsets = type_offsets();
+    let page_size = PAGE_SIZE as usize;
+    let type_descriptor_address = (data.len() + page_size - 1) / page_size * page_size;
+    let mut memory = data.to_vec();
+    memory.resize(type_descriptor_address, 0);
+    let parts = [CANDID_DATA, &type_offsets, FIELD_NAMES, chunk_checksums];
+    for part in &parts {
+        memory.extend_from_slice(&(part.len() as u64).to_le_bytes());
+        memory.extend_from_slice(part);
+    }
+    let field_names_address =
+        type_descriptor_address + 3 * 8 + CANDID_DATA.len() + type_offsets.len();
+    memory.resize((memory.len() / page_size + 2) * page_size, 0);
+
+    let mut heap = TestMemory::new(Words(1024));
+    let blobs: Vec<Value> = parts
+        .iter()
+        .map(|part| create_blob(&mut heap, part))
+        .collect();
+    let metadata_checksum = blobs_checksum(&blobs);
+
+    let mut upgrade_history = UpgradeHistory::default();
+    upgrade_history.start(1000);
+    upgrade_history.complete(42, PersistenceMode::GraphCopyStabilization, 1, 2, 3000);
+    let first_word_backup = u32::from_le_bytes(memory[..4].try_into().unwrap());
+    memory[..4].fill(0);
+    let record = LastPageRecord {
+        metadata_checksum: metadata_checksum as u64,
+        checksum_marker: CHECKSUM_MARKER,
+        upgrade_history,
+        upgrade_history_marker: UPGRADE_HISTORY_MARKER,
+        statistics: UpgradeStatistics {
+            stabilization_instructions: 123,
+        },
+        serialized_data_address: 0,
+        serialized_data_length: data.len() as u64,
+        type_descriptor_address: type_descriptor_address as u64,
+        first_word_backup,
+        version: VERSION_GRAPH_COPY_NO_REGIONS,
+    };
+    let record_start = memory.len() - LAST_PAGE_RECORD_SIZE;
+    memory[record_start..].copy_from_slice(std::slice::from_raw_parts(
+        &record as *const LastPageRecord as *const u8,
+        LAST_PAGE_RECORD_SIZE,
+    ));
+
+    let image = StableImage::parse(memory.clone()).unwrap();
+    assert_eq!(image.serialized_data(), data);
+    assert_eq!(image.stabilization_instructions, 123);
+    assert_eq!(image.verify_checksums(), Ok(Checksums::Verified));
+    assert_eq!(image.labels(), Ok(vec!["hello", "world"]));
+    assert_eq!(image.upgrade_history.len(), 1);
+    assert_eq!(image.upgrade_history[0].heap_size_before, 1000);
+    assert_eq!(image.upgrade_history[0].heap_size_after, 3000);
+
+    // The field names are protected by the metadata checksum.
+    memory[field_names_address + 8] ^= 1;
+    let image = StableImage::parse(memory).unwrap();
+    assert_eq!(
+        image.verify_checksums(),
+        Err(InspectionError::ChecksumMismatch(
+            "type descriptor",
+            type_descriptor_address as u64
+        ))
+    );
+}
+
+unsafe fn create_blob<M: Memory>(mem: &mut M, bytes: &[u8]) -> Value {
+    let blob = alloc_blob(mem, TAG_BLOB_B, Bytes(bytes.len()));
+    let payload = blob.as_blob_mut().payload_addr();
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), payload, bytes.len());
+    blob
+}
+
+fn type_offsets() -> Vec<u8> {
+    TYPE_OFFSETS
+        .iter()
+        .flat_map(|offset| offset.to_le_bytes())
+        .collect()
+}
+
+/// Creates the tuple `("hello", [1, 2, 3], "\ca\fe", "hello")`, sharing the text.
+unsafe fn create_tuple<M: Memory>(mem: &mut M) -> Value {
+    let text = text_of_str(mem, "hello");
+    let numbers = alloc_array(mem, TAG_ARRAY_I, 3);
+    for index in 0..3 {
+        let number = Value::from_scalar(index + 1);
+        numbers.as_array().initialize(index, number, mem);
+    }
+    let blob = alloc_blob(mem, TAG_BLOB_B, Bytes(2));
+    let payload = blob.as_blob_mut().payload_addr();
+    *payload = 0xca;
+    *payload.add(1) = 0xfe;
+    let tuple = alloc_array(mem, TAG_ARRAY_T, 4);
+    for (index, element) in [text, numbers, blob, text].iter().enumerate() {
+        tuple.as_array().initialize(index, *element, mem);
+    }
+    tuple
+}
+
+fn test_statistics(graph: &StableGraph) {
+    let statistics = Statistics::collect(graph).unwrap();
+    assert_eq!(statistics.objects, 4);
+    assert_eq!(statistics.bytes, graph.length());
+    assert_eq!(statistics.shared_objects, 1);
+    for (kind, count, bytes) in [
+        (StableObjectKind::ArrayTuple, 1, 48),
+        (StableObjectKind::BlobText, 1, 24),
+        (StableObjectKind::ArrayImmutable, 1, 40),
+        (StableObjectKind::BlobBytes, 1, 24),
+    ] {
+        assert_eq!(statistics.kinds[&kind], KindStatistics { count, bytes });
+    }
+    assert_eq!(
+        statistics.largest_object,
+        Some((0, 48, StableObjectKind::ArrayTuple))
+    );
+}
+
+fn test_export(graph: &StableGraph) {
+    let type_offsets = type_offsets();
+    let types = TypeTable::new(CANDID_DATA, &type_offsets).unwrap();
+    let options = ExportOptions::default();
+    assert_eq!(
+        export_json(graph, Some(&types), &options).unwrap(),
+        r#"["hello",[1,2,3],"cafe","hello"]"#
+    );
+    assert_eq!(
+        export_json(graph, None, &options).unwrap(),
+        r#"["hello",[{"$scalar":"0x2"},{"$scalar":"0x4"},{"$scalar":"0x6"}],"cafe","hello"]"#
+    );
+}
@@ -1,4 +1,4 @@
-use motoko_rts::stabilization::layout::StableObjectKind;
+use motoko_rts::stabilization::layout::{StableObjectKind, StableTag};
 
 pub unsafe fn test() {
     println!("  Testing layout ...");
@@ -27,6 +27,6 @@ fn test_stable_tags() {
         StableObjectKind::BigInt,
         StableObjectKind::Some,
     ] {
-        assert!(object_kind.encode().decode() == object_kind);
+        assert!(StableTag::from(object_kind).decode() == object_kind);
     }
 }
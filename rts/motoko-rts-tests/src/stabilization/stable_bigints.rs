@@ -76,9 +76,9 @@ unsafe fn test_bigint<F: FnMut() -> Value>(mut generate_bigint: F) {
     // Clone the input bigint object, because it is destructed on serialization.
     let clone = bigint_add(input, bigint_of_word64(0));
     assert!(bigint_eq(clone, input));
-    let stable_size = serialize(clone, 0);
+    let serialized_data = serialize(clone, 0);
     // Note: `clone` is no longer a valid bigint because it has been replaced by a forwarding object.
-    let output = deserialize(&mut memory, 0, stable_size);
+    let output = deserialize(&mut memory, 0, &serialized_data);
     assert!(bigint_eq(output, input));
     set_bigint_heap(null_mut());
     reset_test_memory();
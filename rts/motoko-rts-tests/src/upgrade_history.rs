@@ -0,0 +1,184 @@
+use crate::memory::{initialize_test_memory, reset_test_memory};
+
+use std::convert::TryInto;
+
+use motoko_rts::types::Value;
+use motoko_rts::upgrade_history::{
+    to_candid, PersistenceMode, UpgradeHistory, UpgradeRecord, UPGRADE_HISTORY_LENGTH,
+    UPGRADE_HISTORY_MARKER,
+};
+
+pub unsafe fn test() {
+    println!("Testing upgrade history ...");
+
+    test_recording();
+    test_wraparound();
+    test_gc_completion();
+    test_stored_history();
+    test_candid_report();
+}
+
+fn record_upgrade(history: &mut UpgradeHistory, number: u64) {
+    history.start(number * 100);
+    history.add_compatibility_check(number);
+    history.complete(
+        number * 1000,
+        PersistenceMode::EnhancedOrthogonalPersistence,
+        number * 10,
+        number * 20,
+        number * 200,
+    );
+}
+
+fn expected_record(number: u64) -> UpgradeRecord {
+    UpgradeRecord {
+        timestamp: number * 1000,
+        persistence_mode: PersistenceMode::EnhancedOrthogonalPersistence as u64,
+        stabilization_instructions: number * 10,
+        destabilization_instructions: number * 20 - number,
+        compatibility_check_instructions: number,
+        gc_completion_instructions: 0,
+        heap_size_before: number * 100,
+        heap_size_after: number * 200,
+    }
+}
+
+fn test_recording() {
+    println!("  Testing recording ...");
+
+    let mut history = UpgradeHistory::default();
+    assert_eq!(history.count(), 0);
+    assert_eq!(history.length(), 0);
+
+    for number in 1..=3 {
+        record_upgrade(&mut history, number);
+    }
+    assert_eq!(history.count(), 3);
+    assert_eq!(history.length(), 3);
+    for n in 0..3 {
+        assert_eq!(*history.recent(n), expected_record(3 - n as u64));
+    }
+
+    // A new upgrade does not inherit the compatibility check of the previous one.
+    history.start(0);
+    history.complete(0, PersistenceMode::GraphCopyStabilization, 0, 0, 0);
+    assert_eq!(history.recent(0).compatibility_check_instructions, 0);
+    assert_eq!(
+        history.recent(0).persistence_mode,
+        PersistenceMode::GraphCopyStabilization as u64
+    );
+}
+
+fn test_wraparound() {
+    println!("  Testing wraparound ...");
+
+    let mut history = UpgradeHistory::default();
+    let total = 2 * UPGRADE_HISTORY_LENGTH as u64 + 3;
+    for number in 1..=total {
+        record_upgrade(&mut history, number);
+        assert_eq!(history.count(), number);
+        assert_eq!(
+            history.length(),
+            core::cmp::min(number as usize, UPGRADE_HISTORY_LENGTH)
+        );
+        assert_eq!(*history.recent(0), expected_record(number));
+    }
+    for n in 0..UPGRADE_HISTORY_LENGTH {
+        assert_eq!(*history.recent(n), expected_record(total - n as u64));
+    }
+}
+
+fn test_gc_completion() {
+    println!("  Testing GC completion accounting ...");
+
+    let mut history = UpgradeHistory::default();
+    assert!(!history.is_gc_completion_pending());
+
+    record_upgrade(&mut history, 1);
+    assert!(history.is_gc_completion_pending());
+    history.add_gc_increment(5, false);
+    history.add_gc_increment(7, true);
+    assert!(!history.is_gc_completion_pending());
+    assert_eq!(history.recent(0).gc_completion_instructions, 12);
+
+    // The increments are only accounted to the last upgrade.
+    record_upgrade(&mut history, 2);
+    history.add_gc_increment(3, true);
+    assert_eq!(history.recent(0).gc_completion_instructions, 3);
+    assert_eq!(history.recent(1).gc_completion_instructions, 12);
+}
+
+fn test_stored_history() {
+    println!("  Testing stored history ...");
+
+    let mut stored = UpgradeHistory::default();
+    for number in 1..=UPGRADE_HISTORY_LENGTH as u64 + 1 {
+        record_upgrade(&mut stored, number);
+    }
+
+    // Carried over through the stable memory metadata of graph-copy-based stabilization.
+    let restored = UpgradeHistory::from_stored(UPGRADE_HISTORY_MARKER, stored);
+    assert_eq!(restored.count(), stored.count());
+    for n in 0..UPGRADE_HISTORY_LENGTH {
+        assert_eq!(restored.recent(n), stored.recent(n));
+    }
+
+    // Stored by an older program version without upgrade history.
+    for marker in [0, u64::MAX, UPGRADE_HISTORY_MARKER.swap_bytes()] {
+        let restored = UpgradeHistory::from_stored(marker, stored);
+        assert_eq!(restored.count(), 0);
+        assert_eq!(restored.length(), 0);
+        assert!(!restored.is_gc_completion_pending());
+    }
+}
+
+unsafe fn test_candid_report() {
+    println!("  Testing Candid report ...");
+
+    let mut heap = initialize_test_memory();
+
+    let empty = blob_bytes(to_candid(&mut heap, &UpgradeHistory::default()));
+    assert!(empty.starts_with(b"DIDL\x02\x6d\x01\x6c\x08"));
+    assert_eq!(empty.last(), Some(&0));
+    // Type table and argument types, followed by the vector length.
+    let header_length = empty.len() - 1;
+
+    let mut history = UpgradeHistory::default();
+    let total = UPGRADE_HISTORY_LENGTH as u64 + 2;
+    for number in 1..=total {
+        record_upgrade(&mut history, number);
+    }
+    let report = blob_bytes(to_candid(&mut heap, &history));
+    const RECORD_SIZE: usize = 8 * 8;
+    assert_eq!(&report[..header_length], &empty[..header_length]);
+    assert_eq!(report[header_length] as usize, UPGRADE_HISTORY_LENGTH);
+    assert_eq!(
+        report.len(),
+        header_length + 1 + UPGRADE_HISTORY_LENGTH * RECORD_SIZE
+    );
+
+    for n in 0..UPGRADE_HISTORY_LENGTH {
+        let start = header_length + 1 + n * RECORD_SIZE;
+        let field = |index: usize| {
+            let offset = start + index * 8;
+            u64::from_le_bytes(report[offset..offset + 8].try_into().unwrap())
+        };
+        let expected = expected_record(total - n as u64);
+        // Fields in the order of their Candid hashes.
+        assert_eq!(field(0), expected.heap_size_after);
+        assert_eq!(field(1), expected.destabilization_instructions);
+        assert_eq!(field(2), expected.persistence_mode);
+        assert_eq!(field(3), expected.heap_size_before);
+        assert_eq!(field(4), expected.gc_completion_instructions);
+        assert_eq!(field(5), expected.timestamp);
+        assert_eq!(field(6), expected.stabilization_instructions);
+        assert_eq!(field(7), expected.compatibility_check_instructions);
+    }
+
+    reset_test_memory();
+}
+
+unsafe fn blob_bytes(blob: Value) -> Vec<u8> {
+    let blob = blob.as_blob();
+    std::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize()).to_vec()
+}
@@ -11,7 +11,6 @@ pub struct Buf {
 }
 
 impl Buf {
-    #[cfg(feature = "ic")]
     pub(crate) unsafe fn advance(self: *mut Self, n: usize) {
         advance(self, n)
     }
@@ -29,27 +28,6 @@ pub(crate) unsafe fn read_byte(buf: *mut Buf) -> u8 {
     byte
 }
 
-#[cfg(feature = "ic")]
-/// Read a little-endian word
-pub(crate) unsafe fn read_word(buf: *mut Buf) -> u32 {
-    // IDL buffer is still 32-bit-based.
-    const WORD_SIZE: usize = core::mem::size_of::<u32>();
-
-    if (*buf).ptr.add(WORD_SIZE - 1) >= (*buf).end {
-        idl_trap_with("word read out of buffer");
-    }
-
-    let p = (*buf).ptr;
-
-    let bytes: [u8; WORD_SIZE] = core::array::from_fn(|count| *p.add(count));
-    let word = u32::from_le_bytes(bytes);
-
-    (*buf).ptr = (*buf).ptr.add(WORD_SIZE);
-
-    word
-}
-
-#[cfg(feature = "ic")]
 unsafe fn advance(buf: *mut Buf, n: usize) {
     if (*buf).ptr.add(n) > (*buf).end {
         idl_trap_with("advance out of buffer");
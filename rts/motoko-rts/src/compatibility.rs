@@ -1,12 +1,18 @@
 //! Persistent type compatibility check.
 //! Determines whether a new actor type is compatible with the existing persistent state.
 //! Engages the existing IDL subtype check functionality.
+//! The check is also available outside of a canister, see `offline`.
 
+mod check;
+pub mod offline;
+pub mod report;
+
+use self::check::TypeVariance;
+use self::report::CompatibilityReport;
 use crate::{
     barriers::write_with_barrier,
     bitrel::BitRel,
     constants::WORD_SIZE,
-    idl::TypeVariance,
     memory::{alloc_blob, Memory},
     types::{Value, Words, TAG_BLOB_B},
 };
@@ -141,14 +147,21 @@ unsafe fn create_type_check_cache<M: Memory>(
 }
 
 // Fix main actor type index, see `compile.ml`.
-const MAIN_ACTOR_TYPE_INDEX: i32 = 0;
+pub(crate) const MAIN_ACTOR_TYPE_INDEX: i32 = 0;
 
 /// Test whether the new stable type complies with the existing old stable type.
 /// This uses the existing IDL subtype test.
+/// The incompatibilities are collected in the `report`, naming the fields by the
+/// `new_field_names` of the new stable type and the `old_field_names` of the old stable type
+/// (see `CompatibilityReport`). The old field names are `DEFAULT_VALUE` if the old stable type
+/// was persisted without them.
 pub unsafe fn memory_compatible<M: Memory>(
     mem: &mut M,
     old_type: &mut TypeDescriptor,
     new_type: &mut TypeDescriptor,
+    old_field_names: Value,
+    new_field_names: Value,
+    report: &mut CompatibilityReport,
 ) -> bool {
     let cache = create_type_check_cache(mem, old_type, new_type);
 
@@ -157,9 +170,32 @@ pub unsafe fn memory_compatible<M: Memory>(
 
     let new_type_table = new_type.build_type_table(mem);
     let new_table_end = new_type.type_table_end();
+    let new_field_names = new_field_names.as_blob();
+    report.set_new_field_names(
+        new_type_table,
+        new_table_end,
+        new_type.type_count(),
+        core::slice::from_raw_parts(
+            new_field_names.payload_const(),
+            new_field_names.len().as_usize(),
+        ),
+    );
+    if old_field_names != DEFAULT_VALUE {
+        let old_field_names = old_field_names.as_blob();
+        report.set_old_field_names(
+            old_type_table,
+            old_table_end,
+            old_type.type_count(),
+            core::slice::from_raw_parts(
+                old_field_names.payload_const(),
+                old_field_names.len().as_usize(),
+            ),
+        );
+    }
 
-    crate::idl::memory_compatible(
+    check::memory_compatible(
         &cache,
+        report,
         TypeVariance::Covariance,
         old_type_table,
         new_type_table,
@@ -0,0 +1,698 @@
+//! Memory compatibility check of two type tables, see `memory_compatible`.
+
+#![allow(non_upper_case_globals)]
+
+use super::report::{CompatibilityReport, Constructor, PathElement};
+use crate::bitrel::BitRel;
+use crate::buf::{read_byte, Buf};
+use crate::idl_sub::utf8_cmp;
+use crate::idl_types::*;
+
+use core::cmp::min;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) enum TypeVariance {
+    Covariance,
+    Contravariance,
+    Invariance,
+}
+
+impl TypeVariance {
+    fn invert(self) -> TypeVariance {
+        match self {
+            TypeVariance::Covariance => TypeVariance::Contravariance,
+            TypeVariance::Contravariance => TypeVariance::Covariance,
+            TypeVariance::Invariance => TypeVariance::Invariance,
+        }
+    }
+}
+
+unsafe fn recurring_memory_check(
+    cache: &BitRel,
+    variance: TypeVariance,
+    t1: usize,
+    t2: usize,
+) -> bool {
+    match variance {
+        TypeVariance::Covariance => cache.visited(true, t1, t2),
+        TypeVariance::Contravariance => cache.visited(false, t1, t2),
+        TypeVariance::Invariance => cache.visited(true, t1, t2) && cache.visited(false, t2, t1),
+    }
+}
+
+unsafe fn remember_memory_check(cache: &BitRel, variance: TypeVariance, t1: usize, t2: usize) {
+    match variance {
+        TypeVariance::Covariance => cache.visit(true, t1, t2),
+        TypeVariance::Contravariance => cache.visit(false, t1, t2),
+        TypeVariance::Invariance => {
+            cache.visit(true, t1, t2);
+            cache.visit(false, t2, t1);
+        }
+    }
+}
+
+/// Memory compatibility check for orthogonal persistence (with or without graph copying).
+/// Checks whether the new type (`typetbl2`) is compatible to the old type (`typetbl1`).
+/// The implementation is similar to the Candid sub-type test `sub()` below, however,
+/// with some relevant differences w.r.t. the permitted type relations:
+/// * Support of variable (MutBox) with type invariance.
+/// * Types cannot be made optional (no insertion of Option).
+/// * Same arity for function parameters and function return types.
+/// * Records cannot introduce additional optional fields.
+/// * Same arity for tuple types.
+/// * Records and tuples are distinct.
+/// Incompatibilities are recorded in the `report`. For this purpose, the check of the main actor
+/// type continues after an incompatible field, such that further stable variables are reported.
+pub(crate) unsafe fn memory_compatible(
+    rel: &BitRel,
+    report: &mut CompatibilityReport,
+    variance: TypeVariance,
+    typtbl1: *mut *mut u8,
+    typtbl2: *mut *mut u8,
+    end1: *mut u8,
+    end2: *mut u8,
+    t1: i32,
+    t2: i32,
+    main_actor: bool,
+) -> bool {
+    // Do not use the cache for the main actor sub-type relation, as it does not follow the ordinary sub-type rules,
+    // i.e. new actor fields can be inserted in new program versions.
+    // The `main_actor` flag only occurs non-recursively at the top level of the memory compatibility check.
+    if !main_actor && t1 >= 0 && t2 >= 0 {
+        let t1 = t1 as usize;
+        let t2 = t2 as usize;
+        if recurring_memory_check(rel, variance, t1, t2) {
+            return true;
+        };
+        remember_memory_check(rel, variance, t1, t2);
+    };
+
+    /* primitives reflexive */
+    if is_primitive_type(CompatibilityMode::MemoryCompatibility, t1)
+        && is_primitive_type(CompatibilityMode::MemoryCompatibility, t2)
+        && t1 == t2
+    {
+        return true;
+    }
+
+    // unfold t1, if necessary
+    let mut tb1 = Buf {
+        ptr: if t1 < 0 {
+            end1
+        } else {
+            *typtbl1.add(t1 as usize)
+        },
+        end: end1,
+    };
+
+    let u1 = if t1 >= 0 {
+        sleb128_decode(&mut tb1)
+    } else {
+        t1
+    };
+
+    // unfold t2, if necessary
+    let mut tb2 = Buf {
+        ptr: if t2 < 0 {
+            end2
+        } else {
+            *typtbl2.add(t2 as usize)
+        },
+        end: end2,
+    };
+
+    let u2 = if t2 >= 0 {
+        sleb128_decode(&mut tb2)
+    } else {
+        t2
+    };
+
+    match (u1, u2) {
+        (IDL_CON_alias, IDL_CON_alias) => {
+            let t11 = sleb128_decode(&mut tb1);
+            let t21 = sleb128_decode(&mut tb2);
+            // invariance
+            memory_compatible(
+                rel,
+                report,
+                TypeVariance::Invariance,
+                typtbl1,
+                typtbl2,
+                end1,
+                end2,
+                t11,
+                t21,
+                false,
+            )
+        }
+        (IDL_PRIM_reserved, IDL_PRIM_reserved) | (IDL_PRIM_empty, IDL_PRIM_empty) => true,
+        (_, IDL_PRIM_reserved) | (IDL_PRIM_empty, _) | (IDL_PRIM_nat, IDL_PRIM_int) => {
+            variance != TypeVariance::Invariance || report_mismatch(report, u1, u2, variance)
+        }
+        (_, IDL_CON_alias) | (IDL_CON_alias, _) => report_mismatch(report, u1, u2, variance),
+        (IDL_CON_opt, IDL_CON_opt) => {
+            let t11 = sleb128_decode(&mut tb1);
+            let t21 = sleb128_decode(&mut tb2);
+            memory_compatible_at(
+                rel,
+                report,
+                PathElement::Option,
+                variance,
+                typtbl1,
+                typtbl2,
+                end1,
+                end2,
+                t11,
+                t21,
+            )
+        }
+        (_, IDL_CON_opt) => report_mismatch(report, u1, u2, variance),
+        (IDL_CON_vec, IDL_CON_vec) => {
+            let t11 = sleb128_decode(&mut tb1);
+            let t21 = sleb128_decode(&mut tb2);
+            memory_compatible_at(
+                rel,
+                report,
+                PathElement::Element,
+                variance,
+                typtbl1,
+                typtbl2,
+                end1,
+                end2,
+                t11,
+                t21,
+            )
+        }
+        (IDL_CON_func, IDL_CON_func) => {
+            // contra in domain
+            let in1 = leb128_decode(&mut tb1);
+            let in2 = leb128_decode(&mut tb2);
+            if in1 != in2 {
+                report_arity_mismatch(
+                    report,
+                    PathElement::Argument,
+                    variance.invert(),
+                    typtbl1,
+                    typtbl2,
+                    &mut tb1,
+                    &mut tb2,
+                    in1,
+                    in2,
+                    false,
+                );
+                return false;
+            }
+            for index in 0..in1 {
+                let t11 = sleb128_decode(&mut tb1);
+                let t21 = sleb128_decode(&mut tb2);
+                // NB: invert p and args!
+                report.swap();
+                let compatible = memory_compatible_at(
+                    rel,
+                    report,
+                    PathElement::Argument(index),
+                    variance.invert(),
+                    typtbl2,
+                    typtbl1,
+                    end2,
+                    end1,
+                    t21,
+                    t11,
+                );
+                report.swap();
+                if !compatible {
+                    return false;
+                }
+            }
+            // co in range
+            let out1 = leb128_decode(&mut tb1);
+            let out2 = leb128_decode(&mut tb2);
+            if out1 != out2 {
+                report_arity_mismatch(
+                    report,
+                    PathElement::Result,
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    &mut tb1,
+                    &mut tb2,
+                    out1,
+                    out2,
+                    false,
+                );
+                return false;
+            }
+            for index in 0..out2 {
+                let t21 = sleb128_decode(&mut tb2);
+                let t11 = sleb128_decode(&mut tb1);
+                if !memory_compatible_at(
+                    rel,
+                    report,
+                    PathElement::Result(index),
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    end1,
+                    end2,
+                    t11,
+                    t21,
+                ) {
+                    return false;
+                }
+            }
+            // check annotations (that we care about)
+            // TODO: more generally, we would check equality of 256-bit bit-vectors,
+            // but validity ensures each entry is 1, 2 or 3 (for now)
+            // c.f. https://github.com/dfinity/candid/issues/318
+            let mut a11 = false;
+            let mut a12 = false;
+            let mut a13 = false;
+            for _ in 0..leb128_decode(&mut tb1) {
+                match read_byte(&mut tb1) {
+                    1 => a11 = true,
+                    2 => a12 = true,
+                    3 => a13 = true,
+                    _ => {}
+                }
+            }
+            let mut a21 = false;
+            let mut a22 = false;
+            let mut a23 = false;
+            for _ in 0..leb128_decode(&mut tb2) {
+                match read_byte(&mut tb2) {
+                    1 => a21 = true,
+                    2 => a22 = true,
+                    3 => a23 = true,
+                    _ => {}
+                }
+            }
+            if a11 == a21 && a12 == a22 && a13 == a23 {
+                return true;
+            }
+            let annotation = |query, oneway, composite| {
+                if composite {
+                    3
+                } else if oneway {
+                    2
+                } else if query {
+                    1
+                } else {
+                    0
+                }
+            };
+            report.record(
+                Constructor::Function(annotation(a11, a12, a13)),
+                Constructor::Function(annotation(a21, a22, a23)),
+                variance,
+            );
+            false
+        }
+        (IDL_EXT_tuple, IDL_EXT_tuple) => {
+            let n1 = leb128_decode(&mut tb1);
+            let n2 = leb128_decode(&mut tb2);
+            if n1 != n2 {
+                report_arity_mismatch(
+                    report,
+                    PathElement::Component,
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    &mut tb1,
+                    &mut tb2,
+                    n1,
+                    n2,
+                    true,
+                );
+                return false;
+            }
+            for _ in 0..n1 {
+                let tag1 = leb128_decode(&mut tb1);
+                let t11 = sleb128_decode(&mut tb1);
+                let tag2 = leb128_decode(&mut tb2);
+                let t21 = sleb128_decode(&mut tb2);
+                if tag1 != tag2 {
+                    return report_mismatch(report, u1, u2, variance);
+                }
+                if !memory_compatible_at(
+                    rel,
+                    report,
+                    PathElement::Component(tag1),
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    end1,
+                    end2,
+                    t11,
+                    t21,
+                ) {
+                    return false;
+                }
+            }
+            true
+        }
+        (IDL_CON_record, IDL_CON_record) => {
+            let mut n1 = leb128_decode(&mut tb1);
+            let n2 = leb128_decode(&mut tb2);
+            let mut tag1 = 0;
+            let mut t11 = 0;
+            let mut advance = true;
+            let mut compatible = true;
+            for _ in 0..n2 {
+                let tag2 = leb128_decode(&mut tb2);
+                let t21 = sleb128_decode(&mut tb2);
+                if n1 == 0 {
+                    // Additional fields are only supported in the main actor type.
+                    if variance == TypeVariance::Invariance || !main_actor {
+                        let new_field = type_constructor(typtbl2, end2, t21);
+                        report_at(
+                            report,
+                            PathElement::Field(tag2),
+                            Constructor::Absent,
+                            new_field,
+                            variance,
+                        );
+                        return false;
+                    }
+                    continue;
+                };
+                if advance {
+                    loop {
+                        tag1 = leb128_decode(&mut tb1);
+                        t11 = sleb128_decode(&mut tb1);
+                        n1 -= 1;
+                        // Do not skip fields during invariance check.
+                        if variance == TypeVariance::Invariance || !(tag1 < tag2 && n1 > 0) {
+                            break;
+                        }
+                    }
+                };
+                if tag1 > tag2 {
+                    // Additional fields are only supported in the main actor type.
+                    if variance == TypeVariance::Invariance || !main_actor {
+                        let new_field = type_constructor(typtbl2, end2, t21);
+                        report_at(
+                            report,
+                            PathElement::Field(tag2),
+                            Constructor::Absent,
+                            new_field,
+                            variance,
+                        );
+                        return false;
+                    }
+                    advance = false; // reconsider this field in next round
+                    continue;
+                };
+                if !memory_compatible_at(
+                    rel,
+                    report,
+                    PathElement::Field(tag2),
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    end1,
+                    end2,
+                    t11,
+                    t21,
+                ) {
+                    // Report further incompatible stable variables.
+                    if !main_actor {
+                        return false;
+                    }
+                    compatible = false;
+                }
+                advance = true;
+            }
+            if variance == TypeVariance::Invariance && n1 != 0 {
+                let tag1 = leb128_decode(&mut tb1);
+                let t11 = sleb128_decode(&mut tb1);
+                let old_field = type_constructor(typtbl1, end1, t11);
+                report_at(
+                    report,
+                    PathElement::Field(tag1),
+                    old_field,
+                    Constructor::Absent,
+                    variance,
+                );
+                return false;
+            }
+            compatible
+        }
+        (IDL_CON_variant, IDL_CON_variant) => {
+            let n1 = leb128_decode(&mut tb1);
+            let mut n2 = leb128_decode(&mut tb2);
+            for _ in 0..n1 {
+                let tag1 = leb128_decode(&mut tb1);
+                let t11 = sleb128_decode(&mut tb1);
+                if n2 == 0 {
+                    let old_case = type_constructor(typtbl1, end1, t11);
+                    report_at(
+                        report,
+                        PathElement::Case(tag1),
+                        old_case,
+                        Constructor::Absent,
+                        variance,
+                    );
+                    return false;
+                };
+                let mut tag2: u32;
+                let mut t21: i32;
+                loop {
+                    tag2 = leb128_decode(&mut tb2);
+                    t21 = sleb128_decode(&mut tb2);
+                    n2 -= 1;
+                    if variance == TypeVariance::Invariance || !(tag2 < tag1 && n2 > 0) {
+                        break;
+                    }
+                }
+                if tag1 != tag2 {
+                    if variance == TypeVariance::Invariance && tag2 < tag1 {
+                        let new_case = type_constructor(typtbl2, end2, t21);
+                        report_at(
+                            report,
+                            PathElement::Case(tag2),
+                            Constructor::Absent,
+                            new_case,
+                            variance,
+                        );
+                    } else {
+                        let old_case = type_constructor(typtbl1, end1, t11);
+                        report_at(
+                            report,
+                            PathElement::Case(tag1),
+                            old_case,
+                            Constructor::Absent,
+                            variance,
+                        );
+                    }
+                    return false;
+                }
+                if !memory_compatible_at(
+                    rel,
+                    report,
+                    PathElement::Case(tag1),
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    end1,
+                    end2,
+                    t11,
+                    t21,
+                ) {
+                    return false;
+                }
+            }
+            if variance == TypeVariance::Invariance && n2 != 0 {
+                let tag2 = leb128_decode(&mut tb2);
+                let t21 = sleb128_decode(&mut tb2);
+                let new_case = type_constructor(typtbl2, end2, t21);
+                report_at(
+                    report,
+                    PathElement::Case(tag2),
+                    Constructor::Absent,
+                    new_case,
+                    variance,
+                );
+                return false;
+            }
+            true
+        }
+        (IDL_CON_service, IDL_CON_service) => {
+            let mut n1 = leb128_decode(&mut tb1);
+            let n2 = leb128_decode(&mut tb2);
+            for _ in 0..n2 {
+                let (len2, p2) = leb128_decode_ptr(&mut tb2);
+                Buf::advance(&mut tb2, len2 as usize);
+                let t21 = sleb128_decode(&mut tb2);
+                if n1 == 0 {
+                    let new_method = type_constructor(typtbl2, end2, t21);
+                    report_at(
+                        report,
+                        PathElement::Method(p2, len2 as usize),
+                        Constructor::Absent,
+                        new_method,
+                        variance,
+                    );
+                    return false;
+                };
+                let mut len1: u32;
+                let mut p1: *mut u8;
+                let mut t11: i32;
+                let mut cmp: i32;
+                loop {
+                    (len1, p1) = leb128_decode_ptr(&mut tb1);
+                    Buf::advance(&mut tb1, len1 as usize);
+                    t11 = sleb128_decode(&mut tb1);
+                    n1 -= 1;
+                    cmp = utf8_cmp(len1 as usize, p1, len2 as usize, p2);
+                    if variance != TypeVariance::Invariance && cmp < 0 && n1 > 0 {
+                        continue;
+                    };
+                    break;
+                }
+                if cmp != 0 {
+                    if variance == TypeVariance::Invariance && cmp < 0 {
+                        let old_method = type_constructor(typtbl1, end1, t11);
+                        report_at(
+                            report,
+                            PathElement::Method(p1, len1 as usize),
+                            old_method,
+                            Constructor::Absent,
+                            variance,
+                        );
+                    } else {
+                        let new_method = type_constructor(typtbl2, end2, t21);
+                        report_at(
+                            report,
+                            PathElement::Method(p2, len2 as usize),
+                            Constructor::Absent,
+                            new_method,
+                            variance,
+                        );
+                    }
+                    return false;
+                };
+                if !memory_compatible_at(
+                    rel,
+                    report,
+                    PathElement::Method(p2, len2 as usize),
+                    variance,
+                    typtbl1,
+                    typtbl2,
+                    end1,
+                    end2,
+                    t11,
+                    t21,
+                ) {
+                    return false;
+                }
+            }
+            if variance == TypeVariance::Invariance && n1 != 0 {
+                let (len1, p1) = leb128_decode_ptr(&mut tb1);
+                Buf::advance(&mut tb1, len1 as usize);
+                let t11 = sleb128_decode(&mut tb1);
+                let old_method = type_constructor(typtbl1, end1, t11);
+                report_at(
+                    report,
+                    PathElement::Method(p1, len1 as usize),
+                    old_method,
+                    Constructor::Absent,
+                    variance,
+                );
+                return false;
+            }
+            true
+        }
+        // default
+        (_, _) => report_mismatch(report, u1, u2, variance),
+    }
+}
+
+/// Memory compatibility check of nested types, at the path `element` of the report.
+unsafe fn memory_compatible_at(
+    rel: &BitRel,
+    report: &mut CompatibilityReport,
+    element: PathElement,
+    variance: TypeVariance,
+    typtbl1: *mut *mut u8,
+    typtbl2: *mut *mut u8,
+    end1: *mut u8,
+    end2: *mut u8,
+    t1: i32,
+    t2: i32,
+) -> bool {
+    report.enter(element);
+    let compatible = memory_compatible(
+        rel, report, variance, typtbl1, typtbl2, end1, end2, t1, t2, false,
+    );
+    report.exit();
+    compatible
+}
+
+unsafe fn type_constructor(typtbl: *mut *mut u8, end: *mut u8, t: i32) -> Constructor {
+    if t < 0 {
+        return Constructor::Type(t);
+    }
+    let mut tb = Buf {
+        ptr: *typtbl.add(t as usize),
+        end,
+    };
+    Constructor::Type(sleb128_decode(&mut tb))
+}
+
+/// Records incompatible type constructors and returns false.
+unsafe fn report_mismatch(
+    report: &mut CompatibilityReport,
+    u1: i32,
+    u2: i32,
+    variance: TypeVariance,
+) -> bool {
+    report.record(Constructor::Type(u1), Constructor::Type(u2), variance);
+    false
+}
+
+unsafe fn report_at(
+    report: &mut CompatibilityReport,
+    element: PathElement,
+    first: Constructor,
+    second: Constructor,
+    variance: TypeVariance,
+) {
+    report.enter(element);
+    report.record(first, second, variance);
+    report.exit();
+}
+
+/// Records the first parameter, result, or tuple component that is absent in the shorter
+/// of the two lists of `n1` and `n2` types, that are read from `tb1` and `tb2`.
+unsafe fn report_arity_mismatch(
+    report: &mut CompatibilityReport,
+    element: fn(u32) -> PathElement,
+    variance: TypeVariance,
+    typtbl1: *mut *mut u8,
+    typtbl2: *mut *mut u8,
+    tb1: &mut Buf,
+    tb2: &mut Buf,
+    n1: u32,
+    n2: u32,
+    tagged: bool,
+) {
+    let index = min(n1, n2);
+    let extra_type = |typtbl: *mut *mut u8, tb: &mut Buf| {
+        for _ in 0..index {
+            if tagged {
+                leb128_decode(tb);
+            }
+            sleb128_decode(tb);
+        }
+        if tagged {
+            leb128_decode(tb);
+        }
+        type_constructor(typtbl, tb.end, sleb128_decode(tb))
+    };
+    let (first, second) = if n1 > n2 {
+        (extra_type(typtbl1, tb1), Constructor::Absent)
+    } else {
+        (Constructor::Absent, extra_type(typtbl2, tb2))
+    };
+    report_at(report, element(index), first, second, variance);
+}
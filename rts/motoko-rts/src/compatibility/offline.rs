@@ -0,0 +1,334 @@
+//! Memory compatibility check outside of a canister.
+//!
+//! Allows checking an upgrade before it is deployed, by comparing the stable type of the new
+//! program version with a stable type recorded from the installed program version, e.g.
+//!
+//! ```ignore
+//! let old_type = TypeDescriptorBytes::new(&old_candid_data, &old_type_offsets)?;
+//! let new_type = TypeDescriptorBytes::new(&new_candid_data, &new_type_offsets)?;
+//! let check = check_compatibility(&old_type, &new_type, &new_field_names);
+//! if !check.is_compatible() {
+//!     println!("Memory-incompatible program upgrade: {}", check);
+//! }
+//! ```
+//!
+//! A stable type is given by the contents of the two blobs of its `TypeDescriptor`:
+//! * The Candid type table data.
+//! * The type offsets, a sequence of 64-bit little-endian offsets into the Candid data, one per
+//!   type, where the first type is the stable actor type.
+//!
+//! The optional field names name the record fields and variant cases of the new stable type in
+//! the diagnostics, as in the trap message of an incompatible upgrade.
+//!
+//! `moc --stable-type-descriptor` and the `rts_stable_type_descriptor` primitive emit the three
+//! parts as a single blob, see `EncodedTypeDescriptor`.
+//!
+//! The check is the same as during an upgrade, see `check::memory_compatible`. As the check
+//! does not bound its accesses of the type table, the descriptors are validated beforehand.
+
+#![allow(non_upper_case_globals)]
+
+use super::check::{memory_compatible, TypeVariance};
+use super::report::CompatibilityReport;
+use super::MAIN_ACTOR_TYPE_INDEX;
+use crate::bitrel::BitRel;
+use crate::idl_types::*;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+use core::fmt::{Display, Formatter, Result};
+use core::marker::PhantomData;
+
+/// Reason why a type descriptor is rejected.
+#[derive(Debug, PartialEq)]
+pub enum InvalidTypeDescriptor {
+    /// The encoded descriptor is not a sequence of three length-prefixed parts.
+    InvalidEncoding,
+    /// The type offsets are empty or not a sequence of 64-bit values.
+    InvalidOffsets,
+    /// The offset of the type at this index lies outside of the Candid data.
+    OffsetOutOfBounds(usize),
+    /// The type at this index is malformed, not supported by stable types, or refers to
+    /// a type outside of the type table.
+    InvalidType(usize),
+}
+
+impl Display for InvalidTypeDescriptor {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        match self {
+            InvalidTypeDescriptor::InvalidEncoding => write!(formatter, "invalid encoding"),
+            InvalidTypeDescriptor::InvalidOffsets => write!(formatter, "invalid type offsets"),
+            InvalidTypeDescriptor::OffsetOutOfBounds(index) => {
+                write!(formatter, "type offset {} out of bounds", index)
+            }
+            InvalidTypeDescriptor::InvalidType(index) => {
+                write!(formatter, "invalid type {}", index)
+            }
+        }
+    }
+}
+
+/// Parts of a type descriptor in its single-blob encoding: The Candid type table data, the type
+/// offsets, and the field names, each prefixed by its byte length (u64, little endian), as the
+/// type descriptor in the graph-copy metadata (see `stabilization::ic::metadata`).
+pub struct EncodedTypeDescriptor<'a> {
+    pub candid_data: &'a [u8],
+    pub type_offsets: &'a [u8],
+    pub field_names: &'a [u8],
+}
+
+impl<'a> EncodedTypeDescriptor<'a> {
+    /// Splits the encoded descriptor into its parts, without validating them.
+    pub fn decode(bytes: &'a [u8]) -> core::result::Result<Self, InvalidTypeDescriptor> {
+        const LENGTH_SIZE: usize = core::mem::size_of::<u64>();
+        let mut rest = bytes;
+        let mut parts: [&'a [u8]; 3] = [&[]; 3];
+        for part in parts.iter_mut() {
+            if rest.len() < LENGTH_SIZE {
+                return Err(InvalidTypeDescriptor::InvalidEncoding);
+            }
+            let (length, remainder) = rest.split_at(LENGTH_SIZE);
+            let length = u64::from_le_bytes(length.try_into().unwrap());
+            if length > remainder.len() as u64 {
+                return Err(InvalidTypeDescriptor::InvalidEncoding);
+            }
+            let (bytes, remainder) = remainder.split_at(length as usize);
+            *part = bytes;
+            rest = remainder;
+        }
+        if !rest.is_empty() {
+            return Err(InvalidTypeDescriptor::InvalidEncoding);
+        }
+        let [candid_data, type_offsets, field_names] = parts;
+        Ok(EncodedTypeDescriptor {
+            candid_data,
+            type_offsets,
+            field_names,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for part in &[self.candid_data, self.type_offsets, self.field_names] {
+            bytes.extend_from_slice(&(part.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(part);
+        }
+        bytes
+    }
+}
+
+/// Validated stable type, borrowing the Candid data of its type descriptor.
+pub struct TypeDescriptorBytes<'a> {
+    candid_data: &'a [u8],
+    /// Type table with absolute pointers into `candid_data`.
+    type_table: Vec<*mut u8>,
+}
+
+impl<'a> TypeDescriptorBytes<'a> {
+    pub fn new(
+        candid_data: &'a [u8],
+        type_offsets: &[u8],
+    ) -> core::result::Result<Self, InvalidTypeDescriptor> {
+        const OFFSET_SIZE: usize = core::mem::size_of::<u64>();
+        if type_offsets.is_empty() || type_offsets.len() % OFFSET_SIZE != 0 {
+            return Err(InvalidTypeDescriptor::InvalidOffsets);
+        }
+        let offsets: Vec<u64> = type_offsets
+            .chunks_exact(OFFSET_SIZE)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let type_count = offsets.len();
+        let mut type_table = Vec::with_capacity(type_count);
+        for (index, &offset) in offsets.iter().enumerate() {
+            if offset >= candid_data.len() as u64 {
+                return Err(InvalidTypeDescriptor::OffsetOutOfBounds(index));
+            }
+            let mut entry = Reader {
+                bytes: &candid_data[offset as usize..],
+            };
+            if valid_type(&mut entry, type_count).is_none() {
+                return Err(InvalidTypeDescriptor::InvalidType(index));
+            }
+            type_table.push(candid_data[offset as usize..].as_ptr() as *mut u8);
+        }
+        Ok(TypeDescriptorBytes {
+            candid_data,
+            type_table,
+        })
+    }
+
+    pub fn type_count(&self) -> usize {
+        self.type_table.len()
+    }
+
+    /// The type table is only read.
+    fn type_table(&self) -> *mut *mut u8 {
+        self.type_table.as_ptr() as *mut *mut u8
+    }
+
+    fn table_end(&self) -> *mut u8 {
+        self.candid_data.as_ptr_range().end as *mut u8
+    }
+}
+
+/// Result of an offline compatibility check. Displays the incompatibilities in the format of
+/// the trap message of an incompatible upgrade (see `CompatibilityReport`).
+pub struct CompatibilityCheck<'a> {
+    compatible: bool,
+    report: CompatibilityReport,
+    /// The report refers to the checked types and field names.
+    _types: PhantomData<&'a [u8]>,
+}
+
+impl<'a> CompatibilityCheck<'a> {
+    pub fn is_compatible(&self) -> bool {
+        self.compatible
+    }
+}
+
+impl<'a> Display for CompatibilityCheck<'a> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        self.report.fmt(formatter)
+    }
+}
+
+/// Checks whether the `new_type` of an upgrade is memory-compatible with the `old_type`.
+/// `new_field_names` can be empty, in which case fields are reported by their hashes.
+pub fn check_compatibility<'a>(
+    old_type: &'a TypeDescriptorBytes,
+    new_type: &'a TypeDescriptorBytes,
+    new_field_names: &'a [u8],
+) -> CompatibilityCheck<'a> {
+    let old_type_count = old_type.type_count();
+    let new_type_count = new_type.type_count();
+    let mut words = vec![0usize; BitRel::words(old_type_count, new_type_count)];
+    let cache = BitRel {
+        ptr: words.as_mut_ptr(),
+        end: words.as_mut_ptr_range().end,
+        size1: old_type_count,
+        size2: new_type_count,
+    };
+    let mut report = CompatibilityReport::new();
+    let compatible = unsafe {
+        cache.init();
+        report.set_new_field_names(
+            new_type.type_table(),
+            new_type.table_end(),
+            new_type_count,
+            new_field_names,
+        );
+        memory_compatible(
+            &cache,
+            &mut report,
+            TypeVariance::Covariance,
+            old_type.type_table(),
+            new_type.type_table(),
+            old_type.table_end(),
+            new_type.table_end(),
+            MAIN_ACTOR_TYPE_INDEX,
+            MAIN_ACTOR_TYPE_INDEX,
+            true,
+        )
+    };
+    CompatibilityCheck {
+        compatible,
+        report,
+        _types: PhantomData,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(byte)
+    }
+
+    fn skip(&mut self, length: usize) -> Option<()> {
+        self.bytes = self.bytes.get(length..)?;
+        Some(())
+    }
+
+    fn leb128(&mut self) -> Option<u32> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            result |= ((byte & 0b0111_1111) as u64) << shift;
+            if byte & 0b1000_0000 == 0 {
+                return u32::try_from(result).ok();
+            }
+            shift += 7;
+            if shift > 35 {
+                return None;
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Option<i32> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            result |= ((byte & 0b0111_1111) as i64) << shift;
+            shift += 7;
+            if byte & 0b1000_0000 == 0 {
+                if byte & 0b0100_0000 != 0 {
+                    result |= -1 << shift;
+                }
+                return i32::try_from(result).ok();
+            }
+            if shift > 35 {
+                return None;
+            }
+        }
+    }
+
+    /// Reads a reference to a primitive type or to a type of the table.
+    fn type_reference(&mut self, type_count: usize) -> Option<()> {
+        let t = self.sleb128()?;
+        let valid = if t >= 0 {
+            (t as usize) < type_count
+        } else {
+            unsafe { is_primitive_type(CompatibilityMode::MemoryCompatibility, t) }
+        };
+        valid.then_some(())
+    }
+}
+
+/// Validates a type table entry, as read by `check::memory_compatible`.
+fn valid_type(entry: &mut Reader, type_count: usize) -> Option<()> {
+    match entry.sleb128()? {
+        IDL_CON_opt | IDL_CON_vec | IDL_CON_alias => entry.type_reference(type_count),
+        IDL_CON_record | IDL_CON_variant | IDL_EXT_tuple => {
+            for _ in 0..entry.leb128()? {
+                entry.leb128()?;
+                entry.type_reference(type_count)?;
+            }
+            Some(())
+        }
+        IDL_CON_func => {
+            for _ in 0..2 {
+                for _ in 0..entry.leb128()? {
+                    entry.type_reference(type_count)?;
+                }
+            }
+            let annotations = entry.leb128()?;
+            entry.skip(annotations as usize)
+        }
+        IDL_CON_service => {
+            for _ in 0..entry.leb128()? {
+                let length = entry.leb128()?;
+                entry.skip(length as usize)?;
+                entry.type_reference(type_count)?;
+            }
+            Some(())
+        }
+        _ => None,
+    }
+}
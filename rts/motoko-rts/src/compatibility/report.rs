@@ -0,0 +1,409 @@
+//! Report of the incompatibilities found by the memory compatibility check.
+//!
+//! The report collects the first `MAX_ENTRIES` incompatibilities, at most one per stable
+//! variable. An entry denotes the path from the stable actor root to the incompatible type,
+//! the type constructors of the old and the new program version at this path, and the variance
+//! under which the types were compared, e.g.
+//!
+//! ```text
+//! state.items[].count: nat -> int (invariant)
+//! ```
+//!
+//! Path elements are written as follows:
+//! * `.name` for a record field, `#name` for a variant case, `.0` for a tuple component,
+//! * `?` for the content of an option, `[]` for the elements of an array,
+//! * `(arg 0)` and `(result 0)` for function parameters and results, `.name` for actor methods.
+//!
+//! The type descriptor only contains the hashes of record fields and variant cases. Their names
+//! are obtained from the field names of the new program version, as generated by the compiler
+//! for `idl_print`, and otherwise from the field names stored with the old stable type. Fields
+//! are only written as hashes, e.g. `_4846783_`, if the old program version was persisted by an
+//! earlier runtime system version that did not store the field names.
+//!
+//! The constructor `absent` denotes a missing field, case, method, parameter, or result.
+//! The option wrapping each stable variable is omitted in the path.
+
+#![allow(non_upper_case_globals)]
+
+use super::check::TypeVariance;
+use crate::{
+    buf::Buf, idl_types::*, memory::Memory, print::WriteBuf, rts_trap_with, text::text_of_str,
+    types::Value,
+};
+
+use core::fmt::{Display, Formatter, Result, Write};
+
+/// Maximum number of reported incompatibilities.
+const MAX_ENTRIES: usize = 4;
+
+/// Maximum number of recorded path elements. Deeper paths are cut off.
+const MAX_PATH_LENGTH: usize = 16;
+
+/// Maximum length of the trap message, such that it is not cut off by `rts_trap_with`.
+const MAX_MESSAGE_LENGTH: usize = 512 - "RTS error: ".len();
+
+#[derive(Clone, Copy)]
+pub(crate) enum PathElement {
+    Field(u32),
+    Case(u32),
+    Component(u32),
+    Option,
+    Element,
+    Argument(u32),
+    Result(u32),
+    Method(*const u8, usize),
+}
+
+/// Type constructor at the end of a path.
+#[derive(Clone, Copy)]
+pub(crate) enum Constructor {
+    Absent,
+    /// Primitive type or type constructor, as encoded in the type table.
+    Type(i32),
+    /// Function with its annotation, i.e. 0 (none), 1 (query), 2 (oneway), or 3 (composite query).
+    Function(u8),
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    path: [PathElement; MAX_PATH_LENGTH],
+    path_length: usize,
+    old: Constructor,
+    new: Constructor,
+    variance: TypeVariance,
+}
+
+/// Field names of a type table, see `FieldNames::lookup`.
+struct FieldNames {
+    type_table: *mut *mut u8,
+    table_end: *mut u8,
+    type_count: usize,
+    names: *const u8,
+    names_length: usize,
+}
+
+pub struct CompatibilityReport {
+    entries: [Entry; MAX_ENTRIES],
+    entry_count: usize,
+    omitted: bool,
+    path: [PathElement; MAX_PATH_LENGTH],
+    depth: usize,
+    /// Whether the check currently compares the new type with the old type, i.e. inside
+    /// function parameters, where the type tables are swapped.
+    swapped: bool,
+    new_field_names: Option<FieldNames>,
+    old_field_names: Option<FieldNames>,
+}
+
+impl CompatibilityReport {
+    pub fn new() -> Self {
+        let entry = Entry {
+            path: [PathElement::Option; MAX_PATH_LENGTH],
+            path_length: 0,
+            old: Constructor::Absent,
+            new: Constructor::Absent,
+            variance: TypeVariance::Covariance,
+        };
+        CompatibilityReport {
+            entries: [entry; MAX_ENTRIES],
+            entry_count: 0,
+            omitted: false,
+            path: [PathElement::Option; MAX_PATH_LENGTH],
+            depth: 0,
+            swapped: false,
+            new_field_names: None,
+            old_field_names: None,
+        }
+    }
+
+    /// Names the fields by the field names of the new type table. The table and the names are
+    /// only accessed when writing the report and need to be valid until then.
+    pub(crate) fn set_new_field_names(
+        &mut self,
+        type_table: *mut *mut u8,
+        table_end: *mut u8,
+        type_count: usize,
+        names: &[u8],
+    ) {
+        self.new_field_names = Some(FieldNames {
+            type_table,
+            table_end,
+            type_count,
+            names: names.as_ptr(),
+            names_length: names.len(),
+        });
+    }
+
+    /// Names the fields that are absent in the new type table, by the field names of the old
+    /// type table, with the same validity requirement as `set_new_field_names`.
+    pub(crate) fn set_old_field_names(
+        &mut self,
+        type_table: *mut *mut u8,
+        table_end: *mut u8,
+        type_count: usize,
+        names: &[u8],
+    ) {
+        self.old_field_names = Some(FieldNames {
+            type_table,
+            table_end,
+            type_count,
+            names: names.as_ptr(),
+            names_length: names.len(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    pub(crate) fn enter(&mut self, element: PathElement) {
+        if self.depth < MAX_PATH_LENGTH {
+            self.path[self.depth] = element;
+        }
+        self.depth += 1;
+    }
+
+    pub(crate) fn exit(&mut self) {
+        debug_assert!(self.depth > 0);
+        self.depth -= 1;
+    }
+
+    /// Toggles the direction of the comparison, around function parameters.
+    pub(crate) fn swap(&mut self) {
+        self.swapped = !self.swapped;
+    }
+
+    /// Records an incompatibility at the current path. The constructors are given in the order
+    /// of the compared type tables.
+    pub(crate) fn record(
+        &mut self,
+        first: Constructor,
+        second: Constructor,
+        variance: TypeVariance,
+    ) {
+        if self.entry_count == MAX_ENTRIES {
+            self.omitted = true;
+            return;
+        }
+        let (old, new) = if self.swapped {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        let entry = &mut self.entries[self.entry_count];
+        entry.path = self.path;
+        entry.path_length = self.depth;
+        entry.old = old;
+        entry.new = new;
+        entry.variance = variance;
+        self.entry_count += 1;
+    }
+
+    unsafe fn write_entry(&self, out: &mut Formatter, entry: &Entry) -> Result {
+        let recorded = core::cmp::min(entry.path_length, MAX_PATH_LENGTH);
+        for index in 0..recorded {
+            // Omit the option of stable variables.
+            if index == 1 {
+                if let (PathElement::Field(_), PathElement::Option) = (entry.path[0], entry.path[1])
+                {
+                    continue;
+                }
+            }
+            self.write_path_element(out, entry.path[index], index == 0)?;
+        }
+        if entry.path_length > recorded {
+            out.write_str("…")?;
+        }
+        write!(
+            out,
+            ": {} -> {} ({})",
+            ConstructorName(entry.old),
+            ConstructorName(entry.new),
+            match entry.variance {
+                TypeVariance::Covariance => "covariant",
+                TypeVariance::Contravariance => "contravariant",
+                TypeVariance::Invariance => "invariant",
+            }
+        )
+    }
+
+    unsafe fn write_path_element(
+        &self,
+        out: &mut Formatter,
+        element: PathElement,
+        root: bool,
+    ) -> Result {
+        match element {
+            PathElement::Field(tag) => {
+                if !root {
+                    out.write_str(".")?;
+                }
+                self.write_field_name(out, tag)
+            }
+            PathElement::Case(tag) => {
+                out.write_str("#")?;
+                self.write_field_name(out, tag)
+            }
+            PathElement::Component(index) => write!(out, ".{}", index),
+            PathElement::Option => out.write_str("?"),
+            PathElement::Element => out.write_str("[]"),
+            PathElement::Argument(index) => write!(out, "(arg {})", index),
+            PathElement::Result(index) => write!(out, "(result {})", index),
+            PathElement::Method(name, length) => {
+                out.write_str(".")?;
+                out.write_str(utf8_str(name, length))
+            }
+        }
+    }
+
+    unsafe fn write_field_name(&self, out: &mut Formatter, tag: u32) -> Result {
+        let lookup = |field_names: &Option<FieldNames>| {
+            field_names
+                .as_ref()
+                .and_then(|field_names| field_names.lookup(tag))
+        };
+        match lookup(&self.new_field_names).or_else(|| lookup(&self.old_field_names)) {
+            Some(name) => out.write_str(name),
+            None => write!(out, "_{}_", tag),
+        }
+    }
+
+    /// Writes the message of an incompatible upgrade and returns its length.
+    fn write_message(&self, buffer: &mut [u8; MAX_MESSAGE_LENGTH]) -> usize {
+        let mut out = WriteBuf::new(buffer);
+        let _ = out.write_str("Memory-incompatible program upgrade");
+        if !self.is_empty() {
+            let _ = write!(out, ": {}", self);
+        }
+        out.length()
+    }
+
+    /// Traps with the report of an incompatible upgrade.
+    pub unsafe fn trap(&self) -> ! {
+        let mut buffer = [0u8; MAX_MESSAGE_LENGTH];
+        let length = self.write_message(&mut buffer);
+        rts_trap_with(utf8_str(buffer.as_ptr(), length))
+    }
+
+    /// Returns the trap message of an incompatible upgrade as a text.
+    pub unsafe fn to_text<M: Memory>(&self, mem: &mut M) -> Value {
+        let mut buffer = [0u8; MAX_MESSAGE_LENGTH];
+        let length = self.write_message(&mut buffer);
+        text_of_str(mem, utf8_str(buffer.as_ptr(), length))
+    }
+}
+
+impl Default for CompatibilityReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes the entries, separated by semicolons. The type tables and field names of the check
+/// need to be valid, as the report refers to them.
+impl Display for CompatibilityReport {
+    fn fmt(&self, out: &mut Formatter) -> Result {
+        for index in 0..self.entry_count {
+            if index > 0 {
+                out.write_str("; ")?;
+            }
+            unsafe { self.write_entry(out, &self.entries[index])? };
+        }
+        if self.omitted {
+            out.write_str("; …")?;
+        }
+        Ok(())
+    }
+}
+
+impl FieldNames {
+    /// Looks up the name of a record field or variant case by its hash. The names are listed
+    /// in the order of the fields in the type table, as LEB128-length-prefixed strings.
+    unsafe fn lookup<'a>(&self, tag: u32) -> Option<&'a str> {
+        let mut names = Buf {
+            ptr: self.names as *mut u8,
+            end: self.names.add(self.names_length) as *mut u8,
+        };
+        for index in 0..self.type_count {
+            let mut entry = Buf {
+                ptr: *self.type_table.add(index),
+                end: self.table_end,
+            };
+            let constructor = sleb128_decode(&mut entry);
+            if constructor != IDL_CON_record && constructor != IDL_CON_variant {
+                continue;
+            }
+            for _ in 0..leb128_decode(&mut entry) {
+                let field_tag = leb128_decode(&mut entry);
+                sleb128_decode(&mut entry);
+                if names.ptr >= names.end {
+                    return None;
+                }
+                let length = leb128_decode(&mut names) as usize;
+                if length > names.end as usize - names.ptr as usize {
+                    return None;
+                }
+                if field_tag == tag {
+                    return Some(utf8_str(names.ptr, length));
+                }
+                names.ptr = names.ptr.add(length);
+            }
+        }
+        None
+    }
+}
+
+unsafe fn utf8_str<'a>(start: *const u8, length: usize) -> &'a str {
+    let bytes = core::slice::from_raw_parts(start, length);
+    // A message that is cut off may end in the middle of a character.
+    match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => core::str::from_utf8_unchecked(&bytes[..error.valid_up_to()]),
+    }
+}
+
+struct ConstructorName(Constructor);
+
+impl Display for ConstructorName {
+    fn fmt(&self, formatter: &mut Formatter) -> Result {
+        let name = match self.0 {
+            Constructor::Absent => "absent",
+            Constructor::Function(1) => "query func",
+            Constructor::Function(2) => "oneway func",
+            Constructor::Function(3) => "composite_query func",
+            Constructor::Function(_) => "func",
+            Constructor::Type(code) => match code {
+                IDL_PRIM_null => "null",
+                IDL_PRIM_bool => "bool",
+                IDL_PRIM_nat => "nat",
+                IDL_PRIM_int => "int",
+                IDL_PRIM_nat8 => "nat8",
+                IDL_PRIM_nat16 => "nat16",
+                IDL_PRIM_nat32 => "nat32",
+                IDL_PRIM_nat64 => "nat64",
+                IDL_PRIM_int8 => "int8",
+                IDL_PRIM_int16 => "int16",
+                IDL_PRIM_int32 => "int32",
+                IDL_PRIM_int64 => "int64",
+                IDL_PRIM_float32 => "float32",
+                IDL_PRIM_float64 => "float64",
+                IDL_PRIM_text => "text",
+                IDL_PRIM_reserved => "reserved",
+                IDL_PRIM_empty => "empty",
+                IDL_CON_opt => "opt",
+                IDL_CON_vec => "vec",
+                IDL_CON_record => "record",
+                IDL_CON_variant => "variant",
+                IDL_CON_func => "func",
+                IDL_CON_service => "service",
+                IDL_REF_principal => "principal",
+                IDL_EXT_region => "region",
+                IDL_EXT_blob => "blob",
+                IDL_EXT_tuple => "tuple",
+                IDL_CON_alias => "var",
+                _ => "unknown",
+            },
+        };
+        formatter.write_str(name)
+    }
+}
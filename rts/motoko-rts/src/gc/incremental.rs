@@ -82,12 +82,39 @@ unsafe fn incremental_gc<M: Memory>(mem: &mut M) {
     if state.phase == Phase::Pause {
         record_gc_start::<M>();
     }
-    IncrementalGC::instance(mem, state).empty_call_stack_increment(root_set());
+    measure_upgrade_gc_increment(|| {
+        IncrementalGC::instance(mem, state).empty_call_stack_increment(root_set());
+    });
     if state.phase == Phase::Pause {
         record_gc_stop::<M>();
     }
 }
 
+/// Account the GC increments after an upgrade until the completion of a GC run to the
+/// upgrade statistics, see `upgrade_history`.
+#[cfg(feature = "ic")]
+#[enhanced_orthogonal_persistence]
+unsafe fn measure_upgrade_gc_increment<F: FnOnce()>(increment: F) {
+    use crate::stabilization::ic0_performance_counter;
+    use crate::upgrade_history::ic::{is_gc_completion_pending, record_gc_increment};
+
+    if is_gc_completion_pending() {
+        let start = ic0_performance_counter(0);
+        increment();
+        let instructions = ic0_performance_counter(0) - start;
+        let completed = get_incremental_gc_state().phase == Phase::Pause;
+        record_gc_increment(instructions, completed);
+    } else {
+        increment();
+    }
+}
+
+#[cfg(feature = "ic")]
+#[classical_persistence]
+unsafe fn measure_upgrade_gc_increment<F: FnOnce()>(increment: F) {
+    increment();
+}
+
 #[cfg(feature = "ic")]
 unsafe fn record_gc_start<M: Memory>() {
     use crate::memory::ic::partitioned_memory;
@@ -17,7 +17,7 @@ static mut STATIC_VARIABLES: Value = crate::types::NULL_POINTER;
 static mut INITIALIZED_VARIABLES: usize = 0;
 
 /// GC root set.
-pub type Roots = [*mut Value; 6];
+pub type Roots = [*mut Value; 8];
 
 pub unsafe fn visit_roots<C, V: Fn(&mut C, *mut Value)>(
     roots: Roots,
@@ -35,7 +35,8 @@ pub unsafe fn visit_roots<C, V: Fn(&mut C, *mut Value)>(
 pub unsafe fn root_set() -> Roots {
     use crate::{
         continuation_table::continuation_table_loc,
-        persistence::{stable_actor_location, stable_type_descriptor},
+        idl_sub::cache::idl_sub_cache_location,
+        persistence::{stable_actor_location, stable_field_names_location, stable_type_descriptor},
         region::region0_get_ptr_loc,
     };
     [
@@ -44,7 +45,9 @@ pub unsafe fn root_set() -> Roots {
         stable_actor_location(),
         stable_type_descriptor().candid_data_location(),
         stable_type_descriptor().type_offsets_location(),
+        stable_field_names_location(),
         region0_get_ptr_loc(),
+        idl_sub_cache_location(),
     ]
 }
 
@@ -1,12 +1,11 @@
-#![allow(non_upper_case_globals)]
-
-use crate::bitrel::BitRel;
-use crate::buf::{read_byte, read_word, skip_leb128, Buf};
-use crate::idl_trap_with;
+use crate::buf::{skip_leb128, Buf};
+use crate::idl_error::{IdlError, IdlErrorCode};
+pub(crate) use crate::idl_types::*;
+use crate::idl_writer::IdlWriter;
 
 use crate::memory::{alloc_blob, Memory};
-use crate::types::{Words, TAG_BLOB_B};
-use crate::utf8::utf8_validate;
+use crate::types::{Value, Words, TAG_BLOB_B};
+use crate::utf8::utf8_valid;
 
 use core::cmp::min;
 
@@ -14,134 +13,140 @@ use motoko_rts_macros::{enhanced_orthogonal_persistence, ic_mem_fn};
 
 use crate::libc_declarations::{c_void, memcmp};
 
+mod budget;
+#[enhanced_orthogonal_persistence]
+mod decoder;
+#[enhanced_orthogonal_persistence]
+mod encoder;
 #[enhanced_orthogonal_persistence]
-use crate::types::Value;
+mod printer;
 
 extern "C" {
     // check instruction decoding limit, exported by moc
     pub fn idl_limit_check(decrement: bool, value_count: u64);
 }
 
-//
-// IDL constants
-//
-
-const IDL_PRIM_null: i32 = -1;
-const IDL_PRIM_bool: i32 = -2;
-const IDL_PRIM_nat: i32 = -3;
-const IDL_PRIM_int: i32 = -4;
-const IDL_PRIM_nat8: i32 = -5;
-const IDL_PRIM_nat16: i32 = -6;
-const IDL_PRIM_nat32: i32 = -7;
-const IDL_PRIM_nat64: i32 = -8;
-const IDL_PRIM_int8: i32 = -9;
-const IDL_PRIM_int16: i32 = -10;
-const IDL_PRIM_int32: i32 = -11;
-const IDL_PRIM_int64: i32 = -12;
-const IDL_PRIM_float32: i32 = -13;
-const IDL_PRIM_float64: i32 = -14;
-const IDL_PRIM_text: i32 = -15;
-const IDL_PRIM_reserved: i32 = -16;
-const IDL_PRIM_empty: i32 = -17;
+type IdlResult<T> = Result<T, IdlError>;
 
-const IDL_CON_opt: i32 = -18;
-const IDL_CON_vec: i32 = -19;
-const IDL_CON_record: i32 = -20;
-const IDL_CON_variant: i32 = -21;
-const IDL_CON_func: i32 = -22;
-const IDL_CON_service: i32 = -23;
-
-const IDL_REF_principal: i32 = -24;
-
-// Extended Candid only
-const IDL_EXT_region: i32 = -128;
-
-// Extended Candid only
-const IDL_CON_alias: i32 = 1;
+unsafe fn idl_error<T>(code: IdlErrorCode, buf: *mut Buf) -> IdlResult<T> {
+    Err(IdlError::new(code, (*buf).ptr))
+}
 
-const IDL_PRIM_lowest: i32 = -17;
+//
+// Checked readers, reporting malformed input as `IdlError` instead of trapping
+//
 
-// Only used for memory compatiblity checks for orthogonal persistence.
-#[enhanced_orthogonal_persistence]
-const IDL_EXT_blob: i32 = -129;
-#[enhanced_orthogonal_persistence]
-const IDL_EXT_tuple: i32 = -130;
+unsafe fn try_read_byte(buf: *mut Buf) -> IdlResult<u8> {
+    if (*buf).ptr >= (*buf).end {
+        return idl_error(IdlErrorCode::BufferOverrun, buf);
+    }
+    let byte = *(*buf).ptr;
+    (*buf).ptr = (*buf).ptr.add(1);
+    Ok(byte)
+}
 
-unsafe fn leb128_decode(buf: *mut Buf) -> u32 {
-    let value = crate::leb128::leb128_decode(buf);
-    assert!(value <= u32::MAX as usize);
-    value as u32
+unsafe fn try_read_word(buf: *mut Buf) -> IdlResult<u32> {
+    let mut bytes = [0u8; 4];
+    try_read_bytes(buf, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
 }
 
-unsafe fn sleb128_decode(buf: *mut Buf) -> i32 {
-    let value = crate::leb128::sleb128_decode(buf);
-    assert!(value >= i32::MIN as isize && value <= i32::MAX as isize);
-    value as i32
+unsafe fn try_read_bytes(buf: *mut Buf, bytes: &mut [u8]) -> IdlResult<()> {
+    let source = (*buf).ptr;
+    try_advance(buf, bytes.len())?;
+    core::ptr::copy_nonoverlapping(source, bytes.as_mut_ptr(), bytes.len());
+    Ok(())
 }
 
-pub unsafe fn leb128_decode_ptr(buf: *mut Buf) -> (u32, *mut u8) {
-    (leb128_decode(buf), (*buf).ptr)
+unsafe fn try_advance(buf: *mut Buf, n: usize) -> IdlResult<()> {
+    if n > (*buf).end as usize - (*buf).ptr as usize {
+        return idl_error(IdlErrorCode::BufferOverrun, buf);
+    }
+    (*buf).ptr = (*buf).ptr.add(n);
+    Ok(())
 }
 
-#[derive(Copy, Clone, PartialEq)]
-enum CompatibilityMode {
-    /// Pure Candid used for IC message payloads.
-    PureCandid,
-    /// Candidish stabilization (old stabilization format).
-    CandidishStabilization,
-    /// Memory compatibility of orthogonal persistence (with or without graph copying).
-    #[cfg(feature = "enhanced_orthogonal_persistence")]
-    MemoryCompatibility,
+unsafe fn try_skip_leb128(buf: *mut Buf) -> IdlResult<()> {
+    while try_read_byte(buf)? & 0b1000_0000 != 0 {}
+    Ok(())
 }
 
-unsafe fn is_primitive_type(mode: CompatibilityMode, ty: i32) -> bool {
-    if ty >= 0 {
-        return false;
-    }
-    if ty >= IDL_PRIM_lowest || ty == IDL_REF_principal {
-        return true;
+// Like `leb128_decode_checked`, the checked LEB128 decoders accept zero-padded encodings
+// up to the full 64-bit width (10 bytes), as produced by other Candid encoders, and only
+// reject values that do not fit the result type.
+unsafe fn try_leb128_decode(buf: *mut Buf) -> IdlResult<u32> {
+    let start = (*buf).ptr;
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = try_read_byte(buf)?;
+        // The 10th byte needs to be the last, and it must contribute at most 1 bit.
+        if shift == 63 && (byte & 0b1111_1110) != 0 {
+            return Err(IdlError::new(IdlErrorCode::NumberOverflow, start));
+        }
+        result |= ((byte & 0b0111_1111) as u64) << shift;
+        shift += 7;
+        if byte & 0b1000_0000 == 0 {
+            break;
+        }
     }
-    match mode {
-        CompatibilityMode::PureCandid => false,
-        CompatibilityMode::CandidishStabilization => ty == IDL_EXT_region,
-        #[cfg(feature = "enhanced_orthogonal_persistence")]
-        CompatibilityMode::MemoryCompatibility => ty == IDL_EXT_region || ty == IDL_EXT_blob,
+    if result > u32::MAX as u64 {
+        return Err(IdlError::new(IdlErrorCode::NumberOverflow, start));
     }
+    Ok(result as u32)
 }
 
-// TBR; based on Text.text_compare
-unsafe fn utf8_cmp(len1: usize, p1: *mut u8, len2: usize, p2: *mut u8) -> i32 {
-    let len = min(len1, len2);
-    let cmp = memcmp(p1 as *mut c_void, p2 as *mut c_void, len);
-    if cmp != 0 {
-        return cmp;
-    } else if len1 > len {
-        return 1;
-    } else if len2 > len {
-        return -1;
-    } else {
-        return 0;
+unsafe fn try_sleb128_decode(buf: *mut Buf) -> IdlResult<i32> {
+    let start = (*buf).ptr;
+    let mut result: i64 = 0;
+    let mut shift = 0;
+    let last_byte = loop {
+        let byte = try_read_byte(buf)?;
+        // The 10th byte needs to be the last, and it must only carry the sign.
+        if shift == 63
+            && (byte & 0b1000_0000 != 0
+                || (byte & 0b0111_1111 != 0 && byte & 0b0111_1111 != 0b0111_1111))
+        {
+            return Err(IdlError::new(IdlErrorCode::NumberOverflow, start));
+        }
+        result |= ((byte & 0b0111_1111) as i64) << shift;
+        shift += 7;
+        if byte & 0b1000_0000 == 0 {
+            break byte;
+        }
+    };
+    // Sign extend
+    if shift < i64::BITS && last_byte & 0b0100_0000 != 0 {
+        result |= !0 << shift;
     }
+    if result < i32::MIN as i64 || result > i32::MAX as i64 {
+        return Err(IdlError::new(IdlErrorCode::NumberOverflow, start));
+    }
+    Ok(result as i32)
 }
 
-unsafe fn check_typearg(mode: CompatibilityMode, ty: i32, n_types: u32) {
+// Reads a type argument of a type constructor and checks it
+unsafe fn read_typearg(mode: CompatibilityMode, buf: *mut Buf, n_types: u32) -> IdlResult<i32> {
+    let position = (*buf).ptr;
+    let ty = try_sleb128_decode(buf)?;
     // Arguments to type constructors can be primitive types or type indices
     if !(is_primitive_type(mode, ty) || (ty >= 0 && (ty as u32) < n_types)) {
-        idl_trap_with("invalid type argument");
+        return Err(IdlError::new(IdlErrorCode::InvalidTypeArgument, position));
     }
+    Ok(ty)
 }
 
-unsafe fn parse_fields(mode: CompatibilityMode, buf: *mut Buf, n_types: u32) {
+unsafe fn parse_fields(mode: CompatibilityMode, buf: *mut Buf, n_types: u32) -> IdlResult<()> {
     let mut next_valid = 0;
-    for n in (1..=leb128_decode(buf)).rev() {
-        let tag = leb128_decode(buf);
+    for n in (1..=try_leb128_decode(buf)?).rev() {
+        let tag = try_leb128_decode(buf)?;
         if (tag < next_valid) || (tag == 0xFFFFFFFF && n > 1) {
-            idl_trap_with("variant or record tag out of order");
+            return idl_error(IdlErrorCode::FieldsOutOfOrder, buf);
         }
         next_valid = tag + 1;
-        let t = sleb128_decode(buf);
-        check_typearg(mode, t, n_types);
+        read_typearg(mode, buf, n_types)?;
     }
+    Ok(())
 }
 
 // NB. This function assumes the allocation does not need to survive GC
@@ -152,6 +157,182 @@ unsafe fn alloc<M: Memory>(mem: &mut M, size: Words<usize>) -> *mut u8 {
         .payload_addr()
 }
 
+/// Parsed IDL header: the type description table, its size, and a pointer to the
+/// beginning of the list of main types.
+struct IdlHeader {
+    typtbl: *mut *mut u8,
+    n_types: u32,
+    main_types: *mut u8,
+}
+
+unsafe fn parse_type_entry(mode: CompatibilityMode, buf: *mut Buf, n_types: u32) -> IdlResult<()> {
+    let ty = try_sleb128_decode(buf)?;
+
+    if mode == CompatibilityMode::CandidishStabilization && ty == IDL_CON_alias {
+        // internal
+        // See Note [mutable stable values] in codegen/compile.ml
+        read_typearg(mode, buf, n_types)?;
+    } else if ty >= 0 {
+        return idl_error(IdlErrorCode::IllegalTypeTable, buf); // illegal
+    } else if is_primitive_type(mode, ty) {
+        // illegal
+        return idl_error(IdlErrorCode::PrimitiveInTypeTable, buf);
+    } else if ty == IDL_CON_opt {
+        read_typearg(mode, buf, n_types)?;
+    } else if ty == IDL_CON_vec {
+        read_typearg(mode, buf, n_types)?;
+    } else if ty == IDL_CON_record {
+        parse_fields(mode, buf, n_types)?;
+    } else if ty == IDL_CON_variant {
+        parse_fields(mode, buf, n_types)?;
+    } else if ty == IDL_CON_func {
+        // Arg types
+        for _ in 0..try_leb128_decode(buf)? {
+            read_typearg(mode, buf, n_types)?;
+        }
+        // Ret types
+        for _ in 0..try_leb128_decode(buf)? {
+            read_typearg(mode, buf, n_types)?;
+        }
+        // Annotations
+        for _ in 0..try_leb128_decode(buf)? {
+            let a = try_read_byte(buf)?;
+            if !(1 <= a && a <= 3) {
+                return idl_error(IdlErrorCode::InvalidFuncAnnotation, buf);
+            }
+            // TODO: shouldn't we also check
+            // * 1 (query) or 2 (oneway), but not both
+            // * 2 -> |Ret types| == 0
+            // c.f. https://github.com/dfinity/candid/issues/318
+            // NB: if this code changes, change sub type check below accordingly
+        }
+    } else if ty == IDL_CON_service {
+        let mut last_len: u32 = 0 as u32;
+        let mut last_p = core::ptr::null_mut();
+        for _ in 0..try_leb128_decode(buf)? {
+            // Name
+            let len = try_leb128_decode(buf)?;
+            let p = (*buf).ptr;
+            try_advance(buf, len as usize)?;
+            // Method names must be valid unicode
+            if !utf8_valid(p as *const _, len as usize) {
+                return Err(IdlError::new(IdlErrorCode::InvalidUtf8, p));
+            }
+            // Method names must be in order
+            if last_p != core::ptr::null_mut() {
+                let cmp = memcmp(
+                    last_p as *mut c_void,
+                    p as *mut c_void,
+                    min(last_len, len) as usize,
+                );
+                if cmp > 0 || (cmp == 0 && last_len >= len) {
+                    return Err(IdlError::new(IdlErrorCode::MethodsOutOfOrder, p));
+                }
+            }
+            last_len = len;
+            last_p = p;
+
+            // Type
+            read_typearg(mode, buf, n_types)?;
+        }
+    } else {
+        // Future type
+        let n = try_leb128_decode(buf)?;
+        try_advance(buf, n as usize)?;
+    }
+    Ok(())
+}
+
+unsafe fn check_service_methods(
+    buf: *mut Buf,
+    typtbl: *mut *mut u8,
+    n_types: u32,
+    i: u32,
+) -> IdlResult<()> {
+    // do not modify the main buf
+    let mut tmp_buf = Buf {
+        end: (*buf).end,
+        ptr: *typtbl.add(i as usize),
+    };
+
+    let ty = try_sleb128_decode(&mut tmp_buf)?;
+    if ty == IDL_CON_service {
+        for _ in 0..try_leb128_decode(&mut tmp_buf)? {
+            // Name
+            let len = try_leb128_decode(&mut tmp_buf)?;
+            try_advance(&mut tmp_buf, len as usize)?;
+            // Type
+            let t = try_sleb128_decode(&mut tmp_buf)?;
+            if !(t >= 0 && (t as u32) < n_types) {
+                return idl_error(IdlErrorCode::MethodNotConstructorType, &mut tmp_buf);
+            }
+            let mut tmp_buf2 = Buf {
+                end: (*buf).end,
+                ptr: *typtbl.add(t as usize),
+            };
+            let mty = try_sleb128_decode(&mut tmp_buf2)?;
+            if mty != IDL_CON_func {
+                return idl_error(IdlErrorCode::MethodNotFuncType, &mut tmp_buf);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checked version of `parse_idl_header`, reporting malformed input as `IdlError`.
+unsafe fn try_parse_idl_header<M: Memory>(
+    mem: &mut M,
+    mode: CompatibilityMode,
+    buf: *mut Buf,
+) -> IdlResult<IdlHeader> {
+    if (*buf).ptr == (*buf).end {
+        return idl_error(IdlErrorCode::EmptyInput, buf);
+    }
+
+    // Magic bytes (DIDL)
+    let magic = (*buf).ptr;
+    if try_read_word(buf).ok() != Some(0x4C444944) {
+        return Err(IdlError::new(IdlErrorCode::MissingMagic, magic));
+    }
+
+    // Create a table for the type description
+    let n_types = try_leb128_decode(buf)?;
+
+    // Early sanity check
+    if n_types as usize >= (*buf).end as usize - (*buf).ptr as usize {
+        return idl_error(IdlErrorCode::TooManyTypes, buf);
+    }
+
+    // Allocate the type table to be passed out
+    let typtbl: *mut *mut u8 = alloc(mem, Words(n_types as usize)) as *mut _;
+
+    // Go through the table
+    for i in 0..n_types {
+        *typtbl.add(i as usize) = (*buf).ptr;
+        parse_type_entry(mode, buf, n_types).map_err(|error| error.in_type(i))?;
+    }
+
+    // Now that we have the indices, we can go through it again
+    // and validate that all service method types are really function types
+    // (We could not do that in the first run because of possible forward
+    // references
+    for i in 0..n_types {
+        check_service_methods(buf, typtbl, n_types, i).map_err(|error| error.in_type(i))?;
+    }
+
+    // Now read the main types
+    let main_types = (*buf).ptr;
+    for _ in 0..try_leb128_decode(buf)? {
+        read_typearg(mode, buf, n_types)?;
+    }
+
+    Ok(IdlHeader {
+        typtbl,
+        n_types,
+        main_types,
+    })
+}
+
 /// This function parses the IDL magic header and type description. It
 ///
 /// * traps if the type description is not well-formed. In particular, it traps if any index into
@@ -184,185 +365,47 @@ unsafe fn parse_idl_header<M: Memory>(
         CompatibilityMode::PureCandid
     };
 
-    if (*buf).ptr == (*buf).end {
-        idl_trap_with(
-            "empty input. Expected Candid-encoded argument, but received a zero-length argument",
-        );
-    }
-
-    // Magic bytes (DIDL)
-    if read_word(buf) != 0x4C444944 {
-        idl_trap_with("missing magic bytes");
-    }
-
-    // Create a table for the type description
-    let n_types = leb128_decode(buf);
-
-    // Early sanity check
-    if (*buf).ptr.add(n_types as usize) >= (*buf).end {
-        idl_trap_with("too many types");
-    }
-
-    // Let the caller know about the table size
-    *typtbl_size_out = n_types as usize;
-
-    // Allocate the type table to be passed out
-    let typtbl: *mut *mut u8 = alloc(mem, Words(n_types as usize)) as *mut _;
-
-    // Go through the table
-    for i in 0..n_types {
-        *typtbl.add(i as usize) = (*buf).ptr;
-
-        let ty = sleb128_decode(buf);
-
-        if extended && ty == IDL_CON_alias {
-            // internal
-            // See Note [mutable stable values] in codegen/compile.ml
-            let t = sleb128_decode(buf);
-            check_typearg(mode, t, n_types);
-        } else if ty >= 0 {
-            idl_trap_with("illegal type table"); // illegal
-        } else if is_primitive_type(mode, ty) {
-            // illegal
-            idl_trap_with("primitive type in type table");
-        } else if ty == IDL_CON_opt {
-            let t = sleb128_decode(buf);
-            check_typearg(mode, t, n_types);
-        } else if ty == IDL_CON_vec {
-            let t = sleb128_decode(buf);
-            check_typearg(mode, t, n_types);
-        } else if ty == IDL_CON_record {
-            parse_fields(mode, buf, n_types);
-        } else if ty == IDL_CON_variant {
-            parse_fields(mode, buf, n_types);
-        } else if ty == IDL_CON_func {
-            // Arg types
-            for _ in 0..leb128_decode(buf) {
-                let t = sleb128_decode(buf);
-                check_typearg(mode, t, n_types);
-            }
-            // Ret types
-            for _ in 0..leb128_decode(buf) {
-                let t = sleb128_decode(buf);
-                check_typearg(mode, t, n_types);
-            }
-            // Annotations
-            for _ in 0..leb128_decode(buf) {
-                let a = read_byte(buf);
-                if !(1 <= a && a <= 3) {
-                    idl_trap_with("func annotation not within 1..3");
-                }
-                // TODO: shouldn't we also check
-                // * 1 (query) or 2 (oneway), but not both
-                // * 2 -> |Ret types| == 0
-                // c.f. https://github.com/dfinity/candid/issues/318
-                // NB: if this code changes, change sub type check below accordingly
-            }
-        } else if ty == IDL_CON_service {
-            let mut last_len: u32 = 0 as u32;
-            let mut last_p = core::ptr::null_mut();
-            for _ in 0..leb128_decode(buf) {
-                // Name
-                let (len, p) = leb128_decode_ptr(buf);
-                buf.advance(len as usize);
-                // Method names must be valid unicode
-                utf8_validate(p as *const _, len as usize);
-                // Method names must be in order
-                if last_p != core::ptr::null_mut() {
-                    let cmp = memcmp(
-                        last_p as *mut c_void,
-                        p as *mut c_void,
-                        min(last_len, len) as usize,
-                    );
-                    if cmp > 0 || (cmp == 0 && last_len >= len) {
-                        idl_trap_with("service method names out of order");
-                    }
-                }
-                last_len = len;
-                last_p = p;
-
-                // Type
-                let t = sleb128_decode(buf);
-                check_typearg(mode, t, n_types);
-            }
-        } else {
-            // Future type
-            let n = leb128_decode(buf);
-            buf.advance(n as usize);
-        }
-    }
-
-    // Now that we have the indices, we can go through it again
-    // and validate that all service method types are really function types
-    // (We could not do that in the first run because of possible forward
-    // references
-    for i in 0..n_types {
-        // do not modify the main buf
-        let mut tmp_buf = Buf {
-            end: (*buf).end,
-            ptr: *typtbl.add(i as usize),
-        };
-
-        let ty = sleb128_decode(&mut tmp_buf);
-        if ty == IDL_CON_service {
-            for _ in 0..leb128_decode(&mut tmp_buf) {
-                // Name
-                let len = leb128_decode(&mut tmp_buf);
-                Buf::advance(&mut tmp_buf, len as usize);
-                // Type
-                let t = sleb128_decode(&mut tmp_buf);
-                if !(t >= 0 && (t as u32) < n_types) {
-                    idl_trap_with("service method arg not a constructor type");
-                }
-                let mut tmp_buf2 = Buf {
-                    end: (*buf).end,
-                    ptr: *typtbl.add(t as usize),
-                };
-                let mty = sleb128_decode(&mut tmp_buf2);
-                if mty != IDL_CON_func {
-                    idl_trap_with("service method arg not a function type");
-                }
-            }
-        }
-    }
+    let header = try_parse_idl_header(mem, mode, buf).unwrap_or_else(|error| error.trap());
 
-    // Now read the main types
-    *main_types_out = (*buf).ptr;
-    for _ in 0..leb128_decode(buf) {
-        let t = sleb128_decode(buf);
-        check_typearg(mode, t, n_types);
-    }
-
-    *typtbl_out = typtbl;
+    *typtbl_size_out = header.n_types as usize;
+    *main_types_out = header.main_types;
+    *typtbl_out = header.typtbl;
 }
 
 // used for opt, bool, references...
-unsafe fn read_byte_tag(buf: *mut Buf) -> u8 {
-    let b = read_byte(buf);
+unsafe fn read_byte_tag(buf: *mut Buf) -> IdlResult<u8> {
+    let b = try_read_byte(buf)?;
     if b > 1 {
-        idl_trap_with("skip_any: byte tag not 0 or 1");
+        return Err(IdlError::new(
+            IdlErrorCode::InvalidByteTag,
+            (*buf).ptr.sub(1),
+        ));
     }
-    b
+    Ok(b)
 }
 
-unsafe fn skip_blob(buf: *mut Buf) {
-    let len = leb128_decode(buf);
-    buf.advance(len as usize);
+unsafe fn skip_blob(buf: *mut Buf) -> IdlResult<()> {
+    let len = try_leb128_decode(buf)?;
+    try_advance(buf, len as usize)
 }
 
-unsafe fn skip_text(buf: *mut Buf) {
-    let (len, p) = leb128_decode_ptr(buf);
-    buf.advance(len as usize); // advance first; does the bounds check
-    utf8_validate(p as *const _, len as usize);
+unsafe fn skip_text(buf: *mut Buf) -> IdlResult<()> {
+    let len = try_leb128_decode(buf)?;
+    let p = (*buf).ptr;
+    try_advance(buf, len as usize)?; // advance first; does the bounds check
+    if !utf8_valid(p as *const _, len as usize) {
+        return Err(IdlError::new(IdlErrorCode::InvalidUtf8, p));
+    }
+    Ok(())
 }
 
-unsafe fn skip_any_vec(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, count: u32) {
+unsafe fn skip_any_vec(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, count: u32) -> IdlResult<()> {
     if count == 0 {
-        return;
+        return Ok(());
     }
     idl_limit_check(false, count as u64);
     let ptr_before = (*buf).ptr;
-    skip_any(buf, typtbl, t, 0);
+    try_skip_any(buf, typtbl, t, 0)?;
     let ptr_after = (*buf).ptr;
     if ptr_after == ptr_before {
         // this looks like a vec null bomb, or equivalent, where skip_any
@@ -370,152 +413,268 @@ unsafe fn skip_any_vec(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, count: u32)
         // (This is easier to detect this way than by analyzing the type table,
         // where we’d have to chase single-field-records.)
         idl_limit_check(true, (count - 1) as u64);
-        return;
+        budget::charge_skipped(buf, (count - 1) as u64)?;
+        budget::charge_vec_null(buf, count as u64)?;
+        return Ok(());
     }
     for _ in 1..count {
-        skip_any(buf, typtbl, t, 0);
+        try_skip_any(buf, typtbl, t, 0)?;
     }
+    Ok(())
 }
 
-// Assumes buf is the encoding of type t, and fast-forwards past that
-// Assumes all type references in the typtbl are already checked
-//
-// This is currently implemented recursively, but we could
-// do this in a loop (by maintaining a stack of the t arguments)
-#[no_mangle]
-unsafe extern "C" fn skip_any(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, depth: i32) {
+// Checked version of `skip_any`, reporting malformed input as `IdlError`.
+unsafe fn try_skip_any(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, depth: i32) -> IdlResult<()> {
     if depth > 100 {
-        idl_trap_with("skip_any: too deeply nested record");
+        return idl_error(IdlErrorCode::TooDeeplyNested, buf);
     }
 
     idl_limit_check(true, 1); // decrement and check quota
+    budget::charge_skipped(buf, 1)?;
 
+    budget::enter_value(buf)?;
+    skip_value(buf, typtbl, t, depth)?;
+    budget::exit_value();
+    Ok(())
+}
+
+unsafe fn skip_value(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, depth: i32) -> IdlResult<()> {
     if t < 0 {
         // Primitive type
         match t {
             IDL_PRIM_null | IDL_PRIM_reserved => {}
             IDL_PRIM_bool => {
-                read_byte_tag(buf);
+                read_byte_tag(buf)?;
             }
             IDL_PRIM_nat | IDL_PRIM_int => {
-                skip_leb128(buf);
+                try_skip_leb128(buf)?;
             }
             IDL_PRIM_nat8 | IDL_PRIM_int8 => {
-                buf.advance(1);
+                try_advance(buf, 1)?;
             }
             IDL_PRIM_nat16 | IDL_PRIM_int16 => {
-                buf.advance(2);
+                try_advance(buf, 2)?;
             }
             IDL_PRIM_nat32 | IDL_PRIM_int32 | IDL_PRIM_float32 => {
-                buf.advance(4);
+                try_advance(buf, 4)?;
             }
             IDL_PRIM_nat64 | IDL_PRIM_int64 | IDL_PRIM_float64 => {
-                buf.advance(8);
+                try_advance(buf, 8)?;
             }
-            IDL_PRIM_text => skip_text(buf),
+            IDL_PRIM_text => skip_text(buf)?,
             IDL_PRIM_empty => {
-                idl_trap_with("skip_any: encountered empty");
+                return idl_error(IdlErrorCode::EmptyValue, buf);
             }
             IDL_REF_principal => {
-                if read_byte_tag(buf) != 0 {
-                    skip_blob(buf);
+                if read_byte_tag(buf)? != 0 {
+                    skip_blob(buf)?;
                 }
             }
             IDL_EXT_region => {
-                buf.advance(12); // id (u64) & page_count (u32)
-                skip_blob(buf); // vec_pages
+                try_advance(buf, 12)?; // id (u64) & page_count (u32)
+                skip_blob(buf)?; // vec_pages
             }
             _ => {
-                idl_trap_with("skip_any: unknown prim");
+                return idl_error(IdlErrorCode::UnknownPrimitive, buf);
             }
         }
+        Ok(())
     } else {
         // t >= 0
-        let mut tb = Buf {
-            ptr: *typtbl.add(t as usize),
-            end: (*buf).end,
-        };
-        let tc = sleb128_decode(&mut tb);
-        match tc {
-            IDL_CON_opt => {
-                let it = sleb128_decode(&mut tb);
-                if read_byte_tag(buf) != 0 {
-                    skip_any(buf, typtbl, it, 0);
-                }
-            }
-            IDL_CON_vec => {
-                let it = sleb128_decode(&mut tb);
-                let count = leb128_decode(buf);
-                skip_any_vec(buf, typtbl, it, count);
-            }
-            IDL_CON_record => {
-                for _ in 0..leb128_decode(&mut tb) {
-                    skip_leb128(&mut tb);
-                    let it = sleb128_decode(&mut tb);
-                    // This is just a quick check; we should be keeping
-                    // track of all enclosing records to detect larger loops
-                    if it == t {
-                        idl_trap_with("skip_any: recursive record");
-                    }
-                    skip_any(buf, typtbl, it, depth + 1);
-                }
+        skip_constructed(buf, typtbl, t, depth).map_err(|error| error.in_type(t as u32))
+    }
+}
+
+unsafe fn skip_constructed(
+    buf: *mut Buf,
+    typtbl: *mut *mut u8,
+    t: i32,
+    depth: i32,
+) -> IdlResult<()> {
+    let mut tb = Buf {
+        ptr: *typtbl.add(t as usize),
+        end: (*buf).end,
+    };
+    let tc = try_sleb128_decode(&mut tb)?;
+    match tc {
+        IDL_CON_opt => {
+            let it = try_sleb128_decode(&mut tb)?;
+            if read_byte_tag(buf)? != 0 {
+                try_skip_any(buf, typtbl, it, 0)?;
             }
-            IDL_CON_variant => {
-                let n = leb128_decode(&mut tb);
-                let i = leb128_decode(buf);
-                if i >= n {
-                    idl_trap_with("skip_any: variant tag too large");
-                }
-                for _ in 0..i {
-                    skip_leb128(&mut tb);
-                    skip_leb128(&mut tb);
+        }
+        IDL_CON_vec => {
+            let it = try_sleb128_decode(&mut tb)?;
+            let count = try_leb128_decode(buf)?;
+            skip_any_vec(buf, typtbl, it, count)?;
+        }
+        IDL_CON_record => {
+            for _ in 0..try_leb128_decode(&mut tb)? {
+                try_skip_leb128(&mut tb)?;
+                let it = try_sleb128_decode(&mut tb)?;
+                // This is just a quick check; we should be keeping
+                // track of all enclosing records to detect larger loops
+                if it == t {
+                    return idl_error(IdlErrorCode::RecursiveRecord, buf);
                 }
-                skip_leb128(&mut tb);
-                let it = sleb128_decode(&mut tb);
-                skip_any(buf, typtbl, it, 0);
+                try_skip_any(buf, typtbl, it, depth + 1)?;
             }
-            IDL_CON_func => {
-                if read_byte_tag(buf) == 0 {
-                    idl_trap_with("skip_any: skipping references");
+        }
+        IDL_CON_variant => {
+            let n = try_leb128_decode(&mut tb)?;
+            let position = (*buf).ptr;
+            let i = try_leb128_decode(buf)?;
+            if i >= n {
+                return Err(IdlError::new(IdlErrorCode::VariantTagTooLarge, position));
+            }
+            for _ in 0..i {
+                try_skip_leb128(&mut tb)?;
+                try_skip_leb128(&mut tb)?;
+            }
+            try_skip_leb128(&mut tb)?;
+            let it = try_sleb128_decode(&mut tb)?;
+            try_skip_any(buf, typtbl, it, 0)?;
+        }
+        IDL_CON_func => {
+            if read_byte_tag(buf)? == 0 {
+                return idl_error(IdlErrorCode::SkippingReferences, buf);
+            } else {
+                if read_byte_tag(buf)? == 0 {
+                    return idl_error(IdlErrorCode::SkippingReferences, buf);
                 } else {
-                    if read_byte_tag(buf) == 0 {
-                        idl_trap_with("skip_any: skipping references");
-                    } else {
-                        skip_blob(buf)
-                    }
-                    skip_text(buf)
+                    skip_blob(buf)?
                 }
+                skip_text(buf)?
             }
-            IDL_CON_service => {
-                if read_byte_tag(buf) == 0 {
-                    idl_trap_with("skip_any: skipping references");
-                } else {
-                    skip_blob(buf)
-                }
+        }
+        IDL_CON_service => {
+            if read_byte_tag(buf)? == 0 {
+                return idl_error(IdlErrorCode::SkippingReferences, buf);
+            } else {
+                skip_blob(buf)?
             }
-            IDL_CON_alias => {
-                // See Note [mutable stable values] in codegen/compile.ml
-                let it = sleb128_decode(&mut tb);
-                let tag = read_byte_tag(buf);
-                if tag == 0 {
-                    buf.advance(8);
-                    // this is the contents (not a reference)
-                    skip_any(buf, typtbl, it, 0);
-                } else {
-                    buf.advance(4);
-                }
+        }
+        IDL_CON_alias => {
+            // See Note [mutable stable values] in codegen/compile.ml
+            let it = try_sleb128_decode(&mut tb)?;
+            let tag = read_byte_tag(buf)?;
+            if tag == 0 {
+                try_advance(buf, 8)?;
+                // this is the contents (not a reference)
+                try_skip_any(buf, typtbl, it, 0)?;
+            } else {
+                try_advance(buf, 4)?;
             }
-            _ => {
-                // Future type
-                let n_data = leb128_decode(buf);
-                let n_ref = leb128_decode(buf);
-                buf.advance(n_data as usize);
-                if n_ref > 0 {
-                    idl_trap_with("skip_any: skipping references");
+        }
+        _ => {
+            // Future type
+            let n_data = try_leb128_decode(buf)?;
+            let n_ref = try_leb128_decode(buf)?;
+            try_advance(buf, n_data as usize)?;
+            if n_ref > 0 {
+                return idl_error(IdlErrorCode::SkippingReferences, buf);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Assumes buf is the encoding of type t, and fast-forwards past that
+// Assumes all type references in the typtbl are already checked
+//
+// This is currently implemented recursively, but we could
+// do this in a loop (by maintaining a stack of the t arguments)
+#[no_mangle]
+unsafe extern "C" fn skip_any(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, depth: i32) {
+    try_skip_any(buf, typtbl, t, depth).unwrap_or_else(|error| error.trap())
+}
+
+/// Checks that `blob` is a well-formed Candid message: The header must parse, all
+/// arguments must decode at their declared types, and no bytes may be left over.
+/// The values themselves are only skipped, not deserialized.
+///
+/// Returns a Candid-encoded
+/// `opt record { code : nat32; offset : nat64; type_index : opt nat32; message : text }`
+/// describing the first error, or `null` if the message is well-formed.
+/// The offset is relative to the start of the blob.
+#[ic_mem_fn]
+pub unsafe fn idl_validate<M: Memory>(mem: &mut M, blob: Value) -> Value {
+    // The decoding budget only applies to deserialization
+    budget::idl_budget_reset(false);
+    let blob = blob.as_blob();
+    let start = blob.payload_const();
+    let mut buf = Buf {
+        ptr: start as *mut u8,
+        end: start.add(blob.len().as_usize()) as *mut u8,
+    };
+    let result = validate_message(mem, &mut buf);
+
+    let mut writer = IdlWriter::new(mem);
+    // Type table: opt record { ... }, opt nat32
+    writer.write_leb128(mem, 3);
+    writer.write_sleb128(mem, IDL_CON_opt as i64);
+    writer.write_sleb128(mem, 1);
+    writer.write_sleb128(mem, IDL_CON_record as i64);
+    writer.write_leb128(mem, 4);
+    writer.write_leb128(mem, validate_report::CODE as u64);
+    writer.write_sleb128(mem, IDL_PRIM_nat32 as i64);
+    writer.write_leb128(mem, validate_report::OFFSET as u64);
+    writer.write_sleb128(mem, IDL_PRIM_nat64 as i64);
+    writer.write_leb128(mem, validate_report::MESSAGE as u64);
+    writer.write_sleb128(mem, IDL_PRIM_text as i64);
+    writer.write_leb128(mem, validate_report::TYPE_INDEX as u64);
+    writer.write_sleb128(mem, 2);
+    writer.write_sleb128(mem, IDL_CON_opt as i64);
+    writer.write_sleb128(mem, IDL_PRIM_nat32 as i64);
+    // Argument
+    writer.write_leb128(mem, 1);
+    writer.write_sleb128(mem, 0);
+    // Value
+    match result {
+        Ok(()) => writer.write_byte(mem, 0),
+        Err(error) => {
+            writer.write_byte(mem, 1);
+            writer.write_u32(mem, error.code as u32);
+            writer.write_u64(mem, error.offset(start) as u64);
+            writer.write_blob(mem, error.code.message().as_bytes());
+            match error.type_index {
+                None => writer.write_byte(mem, 0),
+                Some(index) => {
+                    writer.write_byte(mem, 1);
+                    writer.write_u32(mem, index);
                 }
             }
         }
     }
+    writer.finish()
+}
+
+mod validate_report {
+    use crate::idl_writer::idl_hash;
+
+    // Record fields, in the order of their hashes.
+    pub const CODE: u32 = idl_hash("code");
+    pub const OFFSET: u32 = idl_hash("offset");
+    pub const MESSAGE: u32 = idl_hash("message");
+    pub const TYPE_INDEX: u32 = idl_hash("type_index");
+
+    const _: () = assert!(CODE < OFFSET && OFFSET < MESSAGE && MESSAGE < TYPE_INDEX);
+}
+
+unsafe fn validate_message<M: Memory>(mem: &mut M, buf: *mut Buf) -> IdlResult<()> {
+    let header = try_parse_idl_header(mem, CompatibilityMode::PureCandid, buf)?;
+    let mut main_types = Buf {
+        ptr: header.main_types,
+        end: (*buf).end,
+    };
+    for _ in 0..try_leb128_decode(&mut main_types)? {
+        let t = try_sleb128_decode(&mut main_types)?;
+        try_skip_any(buf, header.typtbl, t, 0)?;
+    }
+    if (*buf).ptr != (*buf).end {
+        return idl_error(IdlErrorCode::LeftoverBytes, buf);
+    }
+    Ok(())
 }
 
 /*
@@ -583,671 +742,6 @@ unsafe extern "C" fn skip_fields(tb: *mut Buf, buf: *mut Buf, typtbl: *mut *mut
     }
 }
 
-unsafe fn is_null_opt_reserved(typtbl: *mut *mut u8, end: *mut u8, t: i32) -> bool {
-    if is_primitive_type(CompatibilityMode::PureCandid, t) {
-        return t == IDL_PRIM_null || t == IDL_PRIM_reserved;
-    }
-
-    // unfold t
-    let mut t = t;
-
-    let mut tb = Buf {
-        ptr: *typtbl.add(t as usize),
-        end: end,
-    };
-
-    t = sleb128_decode(&mut tb);
-
-    return t == IDL_CON_opt;
-}
-
-#[enhanced_orthogonal_persistence]
-#[derive(PartialEq, Clone, Copy, Debug)]
-pub(crate) enum TypeVariance {
-    Covariance,
-    Contravariance,
-    Invariance,
-}
-
-#[enhanced_orthogonal_persistence]
-impl TypeVariance {
-    fn invert(self) -> TypeVariance {
-        match self {
-            TypeVariance::Covariance => TypeVariance::Contravariance,
-            TypeVariance::Contravariance => TypeVariance::Covariance,
-            TypeVariance::Invariance => TypeVariance::Invariance,
-        }
-    }
-}
-
-#[enhanced_orthogonal_persistence]
-unsafe fn recurring_memory_check(
-    cache: &BitRel,
-    variance: TypeVariance,
-    t1: usize,
-    t2: usize,
-) -> bool {
-    match variance {
-        TypeVariance::Covariance => cache.visited(true, t1, t2),
-        TypeVariance::Contravariance => cache.visited(false, t1, t2),
-        TypeVariance::Invariance => cache.visited(true, t1, t2) && cache.visited(false, t2, t1),
-    }
-}
-
-#[enhanced_orthogonal_persistence]
-unsafe fn remember_memory_check(cache: &BitRel, variance: TypeVariance, t1: usize, t2: usize) {
-    match variance {
-        TypeVariance::Covariance => cache.visit(true, t1, t2),
-        TypeVariance::Contravariance => cache.visit(false, t1, t2),
-        TypeVariance::Invariance => {
-            cache.visit(true, t1, t2);
-            cache.visit(false, t2, t1);
-        }
-    }
-}
-
-/// Memory compatibility check for orthogonal persistence (with or without graph copying).
-/// Checks whether the new type (`typetbl2`) is compatible to the old type (`typetbl1`).
-/// The implementation is similar to the Candid sub-type test `sub()` below, however,
-/// with some relevant differences w.r.t. the permitted type relations:
-/// * Support of variable (MutBox) with type invariance.
-/// * Types cannot be made optional (no insertion of Option).
-/// * Same arity for function parameters and function return types.
-/// * Records cannot introduce additional optional fields.
-/// * Same arity for tuple types.
-/// * Records and tuples are distinct.
-#[enhanced_orthogonal_persistence]
-pub(crate) unsafe fn memory_compatible(
-    rel: &BitRel,
-    variance: TypeVariance,
-    typtbl1: *mut *mut u8,
-    typtbl2: *mut *mut u8,
-    end1: *mut u8,
-    end2: *mut u8,
-    t1: i32,
-    t2: i32,
-    main_actor: bool,
-) -> bool {
-    // Do not use the cache for the main actor sub-type relation, as it does not follow the ordinary sub-type rules,
-    // i.e. new actor fields can be inserted in new program versions.
-    // The `main_actor` flag only occurs non-recursively at the top level of the memory compatibility check.
-    if !main_actor && t1 >= 0 && t2 >= 0 {
-        let t1 = t1 as usize;
-        let t2 = t2 as usize;
-        if recurring_memory_check(rel, variance, t1, t2) {
-            return true;
-        };
-        remember_memory_check(rel, variance, t1, t2);
-    };
-
-    /* primitives reflexive */
-    if is_primitive_type(CompatibilityMode::MemoryCompatibility, t1)
-        && is_primitive_type(CompatibilityMode::MemoryCompatibility, t2)
-        && t1 == t2
-    {
-        return true;
-    }
-
-    // unfold t1, if necessary
-    let mut tb1 = Buf {
-        ptr: if t1 < 0 {
-            end1
-        } else {
-            *typtbl1.add(t1 as usize)
-        },
-        end: end1,
-    };
-
-    let u1 = if t1 >= 0 {
-        sleb128_decode(&mut tb1)
-    } else {
-        t1
-    };
-
-    // unfold t2, if necessary
-    let mut tb2 = Buf {
-        ptr: if t2 < 0 {
-            end2
-        } else {
-            *typtbl2.add(t2 as usize)
-        },
-        end: end2,
-    };
-
-    let u2 = if t2 >= 0 {
-        sleb128_decode(&mut tb2)
-    } else {
-        t2
-    };
-
-    match (u1, u2) {
-        (IDL_CON_alias, IDL_CON_alias) => {
-            let t11 = sleb128_decode(&mut tb1);
-            let t21 = sleb128_decode(&mut tb2);
-            // invariance
-            memory_compatible(
-                rel,
-                TypeVariance::Invariance,
-                typtbl1,
-                typtbl2,
-                end1,
-                end2,
-                t11,
-                t21,
-                false,
-            )
-        }
-        (IDL_PRIM_reserved, IDL_PRIM_reserved) | (IDL_PRIM_empty, IDL_PRIM_empty) => true,
-        (_, IDL_PRIM_reserved) | (IDL_PRIM_empty, _) | (IDL_PRIM_nat, IDL_PRIM_int) => {
-            variance != TypeVariance::Invariance
-        }
-        (_, IDL_CON_alias) | (IDL_CON_alias, _) => false,
-        (IDL_CON_opt, IDL_CON_opt) => {
-            let t11 = sleb128_decode(&mut tb1);
-            let t21 = sleb128_decode(&mut tb2);
-            memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-        }
-        (_, IDL_CON_opt) => false,
-        (IDL_CON_vec, IDL_CON_vec) => {
-            let t11 = sleb128_decode(&mut tb1);
-            let t21 = sleb128_decode(&mut tb2);
-            memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-        }
-        (IDL_CON_func, IDL_CON_func) => {
-            // contra in domain
-            let in1 = leb128_decode(&mut tb1);
-            let in2 = leb128_decode(&mut tb2);
-            if in1 != in2 {
-                return false;
-            }
-            for _ in 0..in1 {
-                let t11 = sleb128_decode(&mut tb1);
-                let t21 = sleb128_decode(&mut tb2);
-                // NB: invert p and args!
-                if !memory_compatible(
-                    rel,
-                    variance.invert(),
-                    typtbl2,
-                    typtbl1,
-                    end2,
-                    end1,
-                    t21,
-                    t11,
-                    false,
-                ) {
-                    return false;
-                }
-            }
-            // co in range
-            let out1 = leb128_decode(&mut tb1);
-            let out2 = leb128_decode(&mut tb2);
-            if out1 != out2 {
-                return false;
-            }
-            for _ in 0..out2 {
-                let t21 = sleb128_decode(&mut tb2);
-                let t11 = sleb128_decode(&mut tb1);
-                if !memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-                {
-                    return false;
-                }
-            }
-            // check annotations (that we care about)
-            // TODO: more generally, we would check equality of 256-bit bit-vectors,
-            // but validity ensures each entry is 1, 2 or 3 (for now)
-            // c.f. https://github.com/dfinity/candid/issues/318
-            let mut a11 = false;
-            let mut a12 = false;
-            let mut a13 = false;
-            for _ in 0..leb128_decode(&mut tb1) {
-                match read_byte(&mut tb1) {
-                    1 => a11 = true,
-                    2 => a12 = true,
-                    3 => a13 = true,
-                    _ => {}
-                }
-            }
-            let mut a21 = false;
-            let mut a22 = false;
-            let mut a23 = false;
-            for _ in 0..leb128_decode(&mut tb2) {
-                match read_byte(&mut tb2) {
-                    1 => a21 = true,
-                    2 => a22 = true,
-                    3 => a23 = true,
-                    _ => {}
-                }
-            }
-            a11 == a21 && a12 == a22 && a13 == a23
-        }
-        (IDL_EXT_tuple, IDL_EXT_tuple) => {
-            let n1 = leb128_decode(&mut tb1);
-            let n2 = leb128_decode(&mut tb2);
-            if n1 != n2 {
-                return false;
-            }
-            for _ in 0..n1 {
-                let tag1 = leb128_decode(&mut tb1);
-                let t11 = sleb128_decode(&mut tb1);
-                let tag2 = leb128_decode(&mut tb2);
-                let t21 = sleb128_decode(&mut tb2);
-                if tag1 != tag2 {
-                    return false;
-                }
-                if !memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-                {
-                    return false;
-                }
-            }
-            true
-        }
-        (IDL_CON_record, IDL_CON_record) => {
-            let mut n1 = leb128_decode(&mut tb1);
-            let n2 = leb128_decode(&mut tb2);
-            let mut tag1 = 0;
-            let mut t11 = 0;
-            let mut advance = true;
-            for _ in 0..n2 {
-                let tag2 = leb128_decode(&mut tb2);
-                let t21 = sleb128_decode(&mut tb2);
-                if n1 == 0 {
-                    // Additional fields are only supported in the main actor type.
-                    if variance == TypeVariance::Invariance || !main_actor {
-                        return false;
-                    }
-                    continue;
-                };
-                if advance {
-                    loop {
-                        tag1 = leb128_decode(&mut tb1);
-                        t11 = sleb128_decode(&mut tb1);
-                        n1 -= 1;
-                        // Do not skip fields during invariance check.
-                        if variance == TypeVariance::Invariance || !(tag1 < tag2 && n1 > 0) {
-                            break;
-                        }
-                    }
-                };
-                if tag1 > tag2 {
-                    // Additional fields are only supported in the main actor type.
-                    if variance == TypeVariance::Invariance || !main_actor {
-                        return false;
-                    }
-                    advance = false; // reconsider this field in next round
-                    continue;
-                };
-                if !memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-                {
-                    return false;
-                }
-                advance = true;
-            }
-            variance != TypeVariance::Invariance || n1 == 0
-        }
-        (IDL_CON_variant, IDL_CON_variant) => {
-            let n1 = leb128_decode(&mut tb1);
-            let mut n2 = leb128_decode(&mut tb2);
-            for _ in 0..n1 {
-                if n2 == 0 {
-                    return false;
-                };
-                let tag1 = leb128_decode(&mut tb1);
-                let t11 = sleb128_decode(&mut tb1);
-                let mut tag2: u32;
-                let mut t21: i32;
-                loop {
-                    tag2 = leb128_decode(&mut tb2);
-                    t21 = sleb128_decode(&mut tb2);
-                    n2 -= 1;
-                    if variance == TypeVariance::Invariance || !(tag2 < tag1 && n2 > 0) {
-                        break;
-                    }
-                }
-                if tag1 != tag2 {
-                    return false;
-                }
-                if !memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-                {
-                    return false;
-                }
-            }
-            variance != TypeVariance::Invariance || n2 == 0
-        }
-        (IDL_CON_service, IDL_CON_service) => {
-            let mut n1 = leb128_decode(&mut tb1);
-            let n2 = leb128_decode(&mut tb2);
-            for _ in 0..n2 {
-                if n1 == 0 {
-                    return false;
-                };
-                let (len2, p2) = leb128_decode_ptr(&mut tb2);
-                Buf::advance(&mut tb2, len2 as usize);
-                let t21 = sleb128_decode(&mut tb2);
-                let mut len1: u32;
-                let mut p1: *mut u8;
-                let mut t11: i32;
-                let mut cmp: i32;
-                loop {
-                    (len1, p1) = leb128_decode_ptr(&mut tb1);
-                    Buf::advance(&mut tb1, len1 as usize);
-                    t11 = sleb128_decode(&mut tb1);
-                    n1 -= 1;
-                    cmp = utf8_cmp(len1 as usize, p1, len2 as usize, p2);
-                    if variance != TypeVariance::Invariance && cmp < 0 && n1 > 0 {
-                        continue;
-                    };
-                    break;
-                }
-                if cmp != 0 {
-                    return false;
-                };
-                if !memory_compatible(rel, variance, typtbl1, typtbl2, end1, end2, t11, t21, false)
-                {
-                    return false;
-                }
-            }
-            variance != TypeVariance::Invariance || n1 == 0
-        }
-        // default
-        (_, _) => false,
-    }
-}
-
-// TODO: consider storing fixed args typtbl1...end2 in `rel` to use less stack.
-pub(crate) unsafe fn sub(
-    rel: &BitRel,
-    p: bool,
-    typtbl1: *mut *mut u8,
-    typtbl2: *mut *mut u8,
-    end1: *mut u8,
-    end2: *mut u8,
-    t1: i32,
-    t2: i32,
-) -> bool {
-    if t1 >= 0 && t2 >= 0 {
-        let t1 = t1 as usize;
-        let t2 = t2 as usize;
-        if rel.visited(p, t1, t2) {
-            // visited? (bit 0)
-            // return assumed or determined result
-            return rel.related(p, t1, t2);
-        };
-        // cache and continue
-        rel.visit(p, t1, t2); // mark visited
-        rel.assume(p, t1, t2); // assume t1 <:/:> t2 true
-    };
-
-    /* primitives reflexive */
-    if is_primitive_type(CompatibilityMode::PureCandid, t1)
-        && is_primitive_type(CompatibilityMode::PureCandid, t2)
-        && t1 == t2
-    {
-        return true;
-    }
-
-    // unfold t1, if necessary
-    let mut tb1 = Buf {
-        ptr: if t1 < 0 {
-            end1
-        } else {
-            *typtbl1.add(t1 as usize)
-        },
-        end: end1,
-    };
-
-    let u1 = if t1 >= 0 {
-        sleb128_decode(&mut tb1)
-    } else {
-        t1
-    };
-
-    // unfold t2, if necessary
-    let mut tb2 = Buf {
-        ptr: if t2 < 0 {
-            end2
-        } else {
-            *typtbl2.add(t2 as usize)
-        },
-        end: end2,
-    };
-
-    let u2 = if t2 >= 0 {
-        sleb128_decode(&mut tb2)
-    } else {
-        t2
-    };
-
-    // NB we use a trivial labelled loop so we can factor out the common failure continuation.
-    // exit either via 'return true' or 'break 'return_false' to memoize the negative result
-    'return_false: loop {
-        match (u1, u2) {
-            (_, IDL_CON_alias) | (IDL_CON_alias, _) => idl_trap_with("sub: unexpected alias"),
-            (_, IDL_PRIM_reserved)
-            | (IDL_PRIM_empty, _)
-            | (IDL_PRIM_nat, IDL_PRIM_int)
-            | (_, IDL_CON_opt) => return true, // apparently, this is admissable
-            (IDL_CON_vec, IDL_CON_vec) => {
-                let t11 = sleb128_decode(&mut tb1);
-                let t21 = sleb128_decode(&mut tb2);
-                if sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
-                    return true;
-                } else {
-                    break 'return_false;
-                }
-            }
-            (IDL_CON_func, IDL_CON_func) => {
-                // contra in domain
-                let in1 = leb128_decode(&mut tb1);
-                let mut in2 = leb128_decode(&mut tb2);
-                for _ in 0..in1 {
-                    let t11 = sleb128_decode(&mut tb1);
-                    if in2 == 0 {
-                        if !is_null_opt_reserved(typtbl1, end1, t11) {
-                            break 'return_false;
-                        }
-                    } else {
-                        let t21 = sleb128_decode(&mut tb2);
-                        in2 -= 1;
-                        // NB: invert p and args!
-                        if !sub(rel, !p, typtbl2, typtbl1, end2, end1, t21, t11) {
-                            break 'return_false;
-                        }
-                    }
-                }
-                while in2 > 0 {
-                    let _ = sleb128_decode(&mut tb2);
-                    in2 -= 1;
-                }
-                // co in range
-                let mut out1 = leb128_decode(&mut tb1);
-                let out2 = leb128_decode(&mut tb2);
-                for _ in 0..out2 {
-                    let t21 = sleb128_decode(&mut tb2);
-                    if out1 == 0 {
-                        if !is_null_opt_reserved(typtbl2, end2, t21) {
-                            break 'return_false;
-                        }
-                    } else {
-                        let t11 = sleb128_decode(&mut tb1);
-                        out1 -= 1;
-                        if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
-                            break 'return_false;
-                        }
-                    }
-                }
-                while out1 > 0 {
-                    let _ = sleb128_decode(&mut tb1);
-                    out1 -= 1;
-                }
-                // check annotations (that we care about)
-                // TODO: more generally, we would check equality of 256-bit bit-vectors,
-                // but validity ensures each entry is 1, 2 or 3 (for now)
-                // c.f. https://github.com/dfinity/candid/issues/318
-                let mut a11 = false;
-                let mut a12 = false;
-                let mut a13 = false;
-                for _ in 0..leb128_decode(&mut tb1) {
-                    match read_byte(&mut tb1) {
-                        1 => a11 = true,
-                        2 => a12 = true,
-                        3 => a13 = true,
-                        _ => {}
-                    }
-                }
-                let mut a21 = false;
-                let mut a22 = false;
-                let mut a23 = false;
-                for _ in 0..leb128_decode(&mut tb2) {
-                    match read_byte(&mut tb2) {
-                        1 => a21 = true,
-                        2 => a22 = true,
-                        3 => a23 = true,
-                        _ => {}
-                    }
-                }
-                if (a11 == a21) && (a12 == a22) && (a13 == a23) {
-                    return true;
-                } else {
-                    break 'return_false;
-                }
-            }
-            (IDL_CON_record, IDL_CON_record) => {
-                let mut n1 = leb128_decode(&mut tb1);
-                let n2 = leb128_decode(&mut tb2);
-                let mut tag1 = 0;
-                let mut t11 = 0;
-                let mut advance = true;
-                for _ in 0..n2 {
-                    let tag2 = leb128_decode(&mut tb2);
-                    let t21 = sleb128_decode(&mut tb2);
-                    if n1 == 0 {
-                        // check all remaining fields optional
-                        if !is_null_opt_reserved(typtbl2, end2, t21) {
-                            break 'return_false;
-                        }
-                        continue;
-                    };
-                    if advance {
-                        loop {
-                            tag1 = leb128_decode(&mut tb1);
-                            t11 = sleb128_decode(&mut tb1);
-                            n1 -= 1;
-                            if !(tag1 < tag2 && n1 > 0) {
-                                break;
-                            }
-                        }
-                    };
-                    if tag1 > tag2 {
-                        if !is_null_opt_reserved(typtbl2, end2, t21) {
-                            // missing, non_opt field
-                            break 'return_false;
-                        }
-                        advance = false; // reconsider this field in next round
-                        continue;
-                    };
-                    if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
-                        break 'return_false;
-                    }
-                    advance = true;
-                }
-                return true;
-            }
-            (IDL_CON_variant, IDL_CON_variant) => {
-                let n1 = leb128_decode(&mut tb1);
-                let mut n2 = leb128_decode(&mut tb2);
-                for _ in 0..n1 {
-                    if n2 == 0 {
-                        break 'return_false;
-                    };
-                    let tag1 = leb128_decode(&mut tb1);
-                    let t11 = sleb128_decode(&mut tb1);
-                    let mut tag2: u32;
-                    let mut t21: i32;
-                    loop {
-                        tag2 = leb128_decode(&mut tb2);
-                        t21 = sleb128_decode(&mut tb2);
-                        n2 -= 1;
-                        if !(tag2 < tag1 && n2 > 0) {
-                            break;
-                        }
-                    }
-                    if tag1 != tag2 {
-                        break 'return_false;
-                    };
-                    if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
-                        break 'return_false;
-                    }
-                }
-                return true;
-            }
-            (IDL_CON_service, IDL_CON_service) => {
-                let mut n1 = leb128_decode(&mut tb1);
-                let n2 = leb128_decode(&mut tb2);
-                for _ in 0..n2 {
-                    if n1 == 0 {
-                        break 'return_false;
-                    };
-                    let (len2, p2) = leb128_decode_ptr(&mut tb2);
-                    Buf::advance(&mut tb2, len2 as usize);
-                    let t21 = sleb128_decode(&mut tb2);
-                    let mut len1: u32;
-                    let mut p1: *mut u8;
-                    let mut t11: i32;
-                    let mut cmp: i32;
-                    loop {
-                        (len1, p1) = leb128_decode_ptr(&mut tb1);
-                        Buf::advance(&mut tb1, len1 as usize);
-                        t11 = sleb128_decode(&mut tb1);
-                        n1 -= 1;
-                        cmp = utf8_cmp(len1 as usize, p1, len2 as usize, p2);
-                        if cmp < 0 && n1 > 0 {
-                            continue;
-                        };
-                        break;
-                    }
-                    if cmp != 0 {
-                        break 'return_false;
-                    };
-                    if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
-                        break 'return_false;
-                    }
-                }
-                return true;
-            }
-            // default
-            (_, _) => {
-                break 'return_false;
-            }
-        }
-    }
-    // remember negative result ...
-    if t1 >= 0 && t2 >= 0 {
-        rel.disprove(p, t1 as usize, t2 as usize);
-    }
-    // .. only then return false
-    return false;
-}
-
-#[no_mangle]
-unsafe extern "C" fn idl_sub_buf_words(typtbl_size1: usize, typtbl_size2: usize) -> usize {
-    return BitRel::words(typtbl_size1, typtbl_size2);
-}
-
-#[no_mangle]
-unsafe extern "C" fn idl_sub_buf_init(
-    rel_buf: *mut usize,
-    typtbl_size1: usize,
-    typtbl_size2: usize,
-) {
-    let rel = BitRel {
-        ptr: rel_buf,
-        end: rel_buf.add(idl_sub_buf_words(typtbl_size1, typtbl_size2) as usize),
-        size1: typtbl_size1,
-        size2: typtbl_size2,
-    };
-    rel.init();
-}
-
 #[enhanced_orthogonal_persistence]
 #[ic_mem_fn]
 unsafe fn idl_alloc_typtbl<M: Memory>(
@@ -1258,47 +752,10 @@ unsafe fn idl_alloc_typtbl<M: Memory>(
     typtbl_end_out: *mut *mut u8,
     typtbl_size_out: *mut usize,
 ) {
-    use crate::persistence::compatibility::TypeDescriptor;
+    use crate::compatibility::TypeDescriptor;
 
     let mut type_descriptor = TypeDescriptor::new(candid_data, type_offsets);
     *typtbl_out = type_descriptor.build_type_table(mem);
     *typtbl_end_out = type_descriptor.type_table_end();
     *typtbl_size_out = type_descriptor.type_count();
 }
-
-#[no_mangle]
-unsafe extern "C" fn idl_sub(
-    rel_buf: *mut usize, // a buffer with at least 2 * typtbl_size1 * typtbl_size2 bits
-    typtbl1: *mut *mut u8,
-    typtbl2: *mut *mut u8,
-    typtbl_end1: *mut u8,
-    typtbl_end2: *mut u8,
-    typtbl_size1: usize,
-    typtbl_size2: usize,
-    t1: i32,
-    t2: i32,
-) -> bool {
-    debug_assert!(rel_buf != (0 as *mut usize));
-    debug_assert!(typtbl1 != (0 as *mut *mut u8));
-    debug_assert!(typtbl2 != (0 as *mut *mut u8));
-    debug_assert!(typtbl_end1 != (0 as *mut u8));
-    debug_assert!(typtbl_end2 != (0 as *mut u8));
-
-    let rel = BitRel {
-        ptr: rel_buf,
-        end: rel_buf.add(idl_sub_buf_words(typtbl_size1, typtbl_size2) as usize),
-        size1: typtbl_size1,
-        size2: typtbl_size2,
-    };
-    debug_assert!(t1 < (typtbl_size1 as i32) && t2 < (typtbl_size2 as i32));
-    return sub(
-        &rel,
-        true,
-        typtbl1,
-        typtbl2,
-        typtbl_end1,
-        typtbl_end2,
-        t1,
-        t2,
-    );
-}
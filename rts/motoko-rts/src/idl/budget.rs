@@ -0,0 +1,189 @@
+//! Decoding budget of Candid deserialization.
+//!
+//! In addition to the value quota of `idl_limit_check`, which bounds the number of decoded
+//! values relative to the message size, the budget bounds each of the following dimensions
+//! separately:
+//!
+//! * The nesting depth of decoded and skipped values.
+//! * The bytes allocated for decoded vectors, blobs and texts. Fixed-size values are
+//!   already bounded by the value quota.
+//! * The number of skipped values, i.e. values of record fields and variant cases that
+//!   are not expected by the receiver.
+//! * The number of elements of vectors with zero-sized element types, such as `vec null`.
+//!   These elements do not occupy any bytes in the message and are therefore the typical
+//!   means of amplification attacks.
+//!
+//! The limits are configured by the actor with `setCandidDecodingLimits`, where a limit of
+//! zero means unlimited (the default). Like the value quota, the limits are not persisted
+//! across upgrades and the budget only applies to the deserialization of Candid messages,
+//! not to the trusted destabilization of extended Candid.
+//!
+//! The usage is reset at the start of each deserialization by `idl_budget_reset`. An
+//! exhausted dimension traps with a dedicated message (see `IdlErrorCode`).
+
+use crate::buf::Buf;
+use crate::constants::WORD_SIZE;
+use crate::idl_error::{IdlError, IdlErrorCode};
+use crate::idl_types::*;
+use crate::rts_trap_with;
+
+use super::IdlResult;
+
+/// Dimensions of the decoding budget, as numbered in the interface to the compiler.
+#[repr(u32)]
+#[derive(Clone, Copy)]
+enum Dimension {
+    Depth = 0,
+    Allocation = 1,
+    Skipped = 2,
+    VecNull = 3,
+}
+
+const DIMENSIONS: usize = 4;
+
+impl Dimension {
+    unsafe fn from_u32(dimension: u32) -> Dimension {
+        match dimension {
+            0 => Dimension::Depth,
+            1 => Dimension::Allocation,
+            2 => Dimension::Skipped,
+            3 => Dimension::VecNull,
+            _ => rts_trap_with("invalid decoding limit dimension"),
+        }
+    }
+
+    fn error_code(self) -> IdlErrorCode {
+        match self {
+            Dimension::Depth => IdlErrorCode::DepthLimitExceeded,
+            Dimension::Allocation => IdlErrorCode::AllocationLimitExceeded,
+            Dimension::Skipped => IdlErrorCode::SkipLimitExceeded,
+            Dimension::VecNull => IdlErrorCode::VecNullLimitExceeded,
+        }
+    }
+}
+
+/// Configured limits per dimension, zero meaning unlimited.
+static mut LIMITS: [u64; DIMENSIONS] = [0; DIMENSIONS];
+
+/// Usage per dimension during the current deserialization. For the depth, the current
+/// nesting level.
+static mut USAGE: [u64; DIMENSIONS] = [0; DIMENSIONS];
+
+/// Whether the budget applies to the current deserialization.
+static mut ACTIVE: bool = false;
+
+unsafe fn charge(dimension: Dimension, amount: u64, position: *const u8) -> IdlResult<()> {
+    if !ACTIVE {
+        return Ok(());
+    }
+    let index = dimension as usize;
+    USAGE[index] = USAGE[index].saturating_add(amount);
+    if LIMITS[index] != 0 && USAGE[index] > LIMITS[index] {
+        return Err(IdlError::new(dimension.error_code(), position));
+    }
+    Ok(())
+}
+
+/// Enters a nested value, to be paired with `exit_value`. An error does not need to exit,
+/// as decoding errors are not recovered from while the budget is active.
+pub(super) unsafe fn enter_value(buf: *mut Buf) -> IdlResult<()> {
+    charge(Dimension::Depth, 1, (*buf).ptr)
+}
+
+pub(super) unsafe fn exit_value() {
+    if ACTIVE {
+        USAGE[Dimension::Depth as usize] -= 1;
+    }
+}
+
+pub(super) unsafe fn charge_skipped(buf: *mut Buf, count: u64) -> IdlResult<()> {
+    charge(Dimension::Skipped, count, (*buf).ptr)
+}
+
+pub(super) unsafe fn charge_vec_null(buf: *mut Buf, count: u64) -> IdlResult<()> {
+    charge(Dimension::VecNull, count, (*buf).ptr)
+}
+
+pub(super) unsafe fn charge_allocation(buf: *mut Buf, bytes: u64) -> IdlResult<()> {
+    charge(Dimension::Allocation, bytes, (*buf).ptr)
+}
+
+/// Charges a decoded vector of `count` elements of the received type `t`.
+pub(super) unsafe fn charge_vec(
+    buf: *mut Buf,
+    typtbl: *mut *mut u8,
+    t: i32,
+    count: u64,
+) -> IdlResult<()> {
+    if is_zero_sized(buf, typtbl, t, 0) {
+        charge_vec_null(buf, count)?;
+    }
+    charge_allocation(buf, count.saturating_mul(WORD_SIZE as u64))
+}
+
+/// Whether values of the received type `t` have an empty encoding: `null`, `reserved`, and
+/// records of such fields. Conservatively false for deeply nested records.
+unsafe fn is_zero_sized(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, depth: u32) -> bool {
+    if t < 0 {
+        return t == IDL_PRIM_null || t == IDL_PRIM_reserved;
+    }
+    if depth > 100 {
+        return false;
+    }
+    let mut tb = Buf {
+        ptr: *typtbl.add(t as usize),
+        end: (*buf).end,
+    };
+    if sleb128_decode(&mut tb) != IDL_CON_record {
+        return false;
+    }
+    for _ in 0..leb128_decode(&mut tb) {
+        leb128_decode(&mut tb);
+        if !is_zero_sized(buf, typtbl, sleb128_decode(&mut tb), depth + 1) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Resets the usage at the start of a deserialization. The budget only applies if `active`,
+/// i.e. for Candid messages but not for extended Candid.
+#[no_mangle]
+pub unsafe extern "C" fn idl_budget_reset(active: bool) {
+    ACTIVE = active;
+    USAGE = [0; DIMENSIONS];
+}
+
+// Entry points for generated code, trapping if the budget is exceeded.
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_budget_enter(buf: *mut Buf) {
+    enter_value(buf).unwrap_or_else(|error| error.trap())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_budget_exit() {
+    exit_value()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_budget_vec(buf: *mut Buf, typtbl: *mut *mut u8, t: i32, count: usize) {
+    charge_vec(buf, typtbl, t, count as u64).unwrap_or_else(|error| error.trap())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_budget_alloc(buf: *mut Buf, bytes: usize) {
+    charge_allocation(buf, bytes as u64).unwrap_or_else(|error| error.trap())
+}
+
+// Configuration by `setCandidDecodingLimits` and `getCandidDecodingLimits`.
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_set_decoding_limit(dimension: u32, limit: u64) {
+    LIMITS[Dimension::from_u32(dimension) as usize] = limit;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_get_decoding_limit(dimension: u32) -> u64 {
+    LIMITS[Dimension::from_u32(dimension) as usize]
+}
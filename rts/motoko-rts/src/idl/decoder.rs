@@ -0,0 +1,666 @@
+//! Native Candid decoder, converting a Candid message directly into heap values of the
+//! expected Motoko types, as an alternative to the generated deserialization code.
+//!
+//! The expected types are described by a type table in the format of the persistence
+//! type descriptor (c.f. `type_desc` in `compile_enhanced.ml`), accompanied by the Motoko
+//! label hashes of the record and variant fields in that table, listed in table order.
+//! The compiler only resorts to this decoder for types that the descriptor represents
+//! unambiguously, i.e. not for `Char`, `()`, references (`func` and `actor`) and non-shared
+//! types.
+//!
+//! The decoder follows the semantics of the generated code (`deserialize_go`), including
+//! the opt subtyping rules, the recursion depth limit, the value quota accounting of
+//! `idl_limit_check`, and the decoding budget (see `budget.rs`). Malformed input traps,
+//! while coercion failures either trap or, if recoverable, yield the `COERCION_FAILURE`
+//! sentinel.
+
+use super::budget::{charge_allocation, charge_vec, enter_value, exit_value};
+use super::{
+    alloc, idl_limit_check, leb128_decode, read_byte_tag, sleb128_decode, try_advance,
+    try_leb128_decode, try_parse_idl_header, try_read_bytes, try_skip_any, try_skip_leb128,
+    CompatibilityMode, IDL_CON_opt, IDL_CON_record, IDL_CON_variant, IDL_CON_vec, IDL_EXT_blob,
+    IDL_EXT_tuple, IDL_PRIM_bool, IDL_PRIM_float64, IDL_PRIM_int, IDL_PRIM_int16, IDL_PRIM_int32,
+    IDL_PRIM_int64, IDL_PRIM_int8, IDL_PRIM_nat, IDL_PRIM_nat16, IDL_PRIM_nat32, IDL_PRIM_nat64,
+    IDL_PRIM_nat8, IDL_PRIM_null, IDL_PRIM_reserved, IDL_PRIM_text, IDL_REF_principal, IdlResult,
+};
+use crate::barriers::{allocation_barrier, init_with_barrier};
+use crate::bigint::{bigint_leb128_decode, bigint_sleb128_decode};
+use crate::buf::{skip_leb128, Buf};
+use crate::idl_error::{IdlError, IdlErrorCode};
+use crate::idl_trap_with;
+use crate::memory::{alloc_array, alloc_blob, Memory};
+use crate::rts_trap_with;
+use crate::text::text_of_ptr_size;
+use crate::types::{
+    size_of, Bytes, Object, Value, Variant, Words, NULL_POINTER, TAG_ARRAY_I, TAG_ARRAY_T,
+    TAG_BLOB_B, TAG_BLOB_P, TAG_OBJECT, TAG_SOME, TAG_VARIANT, TRUE_VALUE,
+};
+use crate::utf8::utf8_valid;
+
+use motoko_rts_macros::ic_mem_fn;
+
+// Provided by generated code
+extern "C" {
+    /// Tags or boxes a number of the given primitive Candid type, received as raw
+    /// (zero-extended) little-endian bits.
+    fn value_from_word64(idl_type: i32, bits: u64) -> Value;
+}
+
+/// Sentinel of a recoverable coercion failure, c.f. `coercion_error_value` in the compiler.
+/// It is never stored on the heap and must not be dereferenced.
+const COERCION_FAILURE: Value = Value::from_raw(0xffff_ffff_ffff_fffd);
+
+/// The expected types, as given by the compiler. Also describes the value types of the encoder.
+pub(super) struct ExpectedTypes {
+    typtbl: *mut *mut u8,
+    pub(super) end: *mut u8,
+    pub(super) main_types: *mut u8,
+    field_hashes: *const u32,
+    /// Per type index: the index of the first field in `field_hashes` (records and variants only).
+    field_offsets: *mut usize,
+    /// Per type index: the hash blob of the object layout (records only), allocated on first use.
+    hash_blobs: *mut Value,
+}
+
+impl ExpectedTypes {
+    /// The descriptor is generated by the compiler and therefore trusted.
+    pub(super) unsafe fn new<M: Memory>(
+        mem: &mut M,
+        type_desc: Value,
+        field_hashes: Value,
+    ) -> ExpectedTypes {
+        let type_desc = type_desc.as_blob();
+        let start = type_desc.payload_const() as *mut u8;
+        let end = start.add(type_desc.len().as_usize());
+        let mut buf = Buf {
+            ptr: start.add(4), // skip magic bytes (DIDL)
+            end,
+        };
+
+        let n_types = leb128_decode(&mut buf) as usize;
+        let typtbl = alloc(mem, Words(n_types)) as *mut *mut u8;
+        let field_offsets = alloc(mem, Words(n_types)) as *mut usize;
+        let hash_blobs = alloc(mem, Words(n_types)) as *mut Value;
+
+        let mut field_count = 0;
+        for index in 0..n_types {
+            *typtbl.add(index) = buf.ptr;
+            *field_offsets.add(index) = field_count;
+            *hash_blobs.add(index) = NULL_POINTER;
+            match sleb128_decode(&mut buf) {
+                IDL_CON_opt | IDL_CON_vec => {
+                    sleb128_decode(&mut buf);
+                }
+                tc @ (IDL_CON_record | IDL_CON_variant | IDL_EXT_tuple) => {
+                    let n = leb128_decode(&mut buf);
+                    for _ in 0..n {
+                        skip_leb128(&mut buf);
+                        sleb128_decode(&mut buf);
+                    }
+                    if tc != IDL_EXT_tuple {
+                        field_count += n as usize;
+                    }
+                }
+                _ => rts_trap_with("idl_decode: unsupported type descriptor"),
+            }
+        }
+
+        ExpectedTypes {
+            typtbl,
+            end,
+            main_types: buf.ptr,
+            field_hashes: field_hashes.as_blob().payload_const() as *const u32,
+            field_offsets,
+            hash_blobs,
+        }
+    }
+
+    /// Returns a buffer positioned at the type constructor of a table entry.
+    pub(super) unsafe fn entry(&self, e: i32) -> Buf {
+        debug_assert!(e >= 0);
+        Buf {
+            ptr: *self.typtbl.add(e as usize),
+            end: self.end,
+        }
+    }
+
+    /// Whether a missing value of this type defaults to `null`.
+    unsafe fn is_null_opt_reserved(&self, e: i32) -> bool {
+        if e < 0 {
+            e == IDL_PRIM_null || e == IDL_PRIM_reserved
+        } else {
+            sleb128_decode(&mut self.entry(e)) == IDL_CON_opt
+        }
+    }
+
+    /// Position of the `field`-th field (in Candid order) of a record or variant in the
+    /// list of all fields of the type table.
+    pub(super) unsafe fn field_index(&self, e: i32, field: u32) -> usize {
+        *self.field_offsets.add(e as usize) + field as usize
+    }
+
+    /// Motoko label hash of the `field`-th field (in Candid order) of a record or variant.
+    pub(super) unsafe fn field_hash(&self, e: i32, field: u32) -> u32 {
+        *self.field_hashes.add(self.field_index(e, field))
+    }
+
+    /// The hash blob of objects of a record type: The Motoko label hashes in ascending order.
+    unsafe fn hash_blob<M: Memory>(&self, mem: &mut M, e: i32, n_fields: u32) -> Value {
+        let cached = self.hash_blobs.add(e as usize);
+        if *cached == NULL_POINTER {
+            let n = n_fields as usize;
+            let blob = alloc_blob(mem, TAG_BLOB_B, Words(n).to_bytes());
+            let hashes = blob.as_blob_mut().payload_addr() as *mut u64;
+            // Insertion sort, as Candid and Motoko hashes are mostly in the same order
+            for index in 0..n {
+                let hash = self.field_hash(e, index as u32) as u64;
+                let mut position = index;
+                while position > 0 && *hashes.add(position - 1) > hash {
+                    *hashes.add(position) = *hashes.add(position - 1);
+                    position -= 1;
+                }
+                *hashes.add(position) = hash;
+            }
+            *cached = allocation_barrier(blob);
+        }
+        *cached
+    }
+}
+
+/// Position of a field in the object layout, given by its label hash.
+pub(super) unsafe fn object_field_index(hash_blob: Value, hash: u32) -> usize {
+    let blob = hash_blob.as_blob();
+    let hashes = blob.payload_const() as *const u64;
+    let mut lower = 0;
+    let mut upper = blob.len().as_usize() / size_of::<u64>().to_bytes().as_usize();
+    while lower < upper {
+        let middle = (lower + upper) / 2;
+        if *hashes.add(middle) < hash as u64 {
+            lower = middle + 1;
+        } else {
+            upper = middle;
+        }
+    }
+    debug_assert_eq!(*hashes.add(lower), hash as u64);
+    lower
+}
+
+unsafe fn coercion_failed(can_recover: bool, message: &str) -> Value {
+    // Without a backtracking `opt` around, trap right away with a more precise message.
+    if !can_recover {
+        idl_trap_with(message);
+    }
+    COERCION_FAILURE
+}
+
+/// Wraps a value as an option, c.f. `Opt.inject` in the compiler.
+unsafe fn inject_opt<M: Memory>(mem: &mut M, value: Value) -> Value {
+    if value.is_scalar() || value.get_raw() == TRUE_VALUE {
+        return value;
+    }
+    if value == NULL_POINTER || value.tag() == TAG_SOME {
+        // ?ⁿnull for n > 0
+        let some = mem.alloc_words(size_of::<crate::types::Some>());
+        let object = some.get_ptr() as *mut crate::types::Some;
+        (*object).header.tag = TAG_SOME;
+        (*object).header.init_forward(some);
+        init_with_barrier(mem, &mut (*object).field, value);
+        allocation_barrier(some)
+    } else {
+        value
+    }
+}
+
+struct Decoder {
+    buf: *mut Buf,
+    typtbl: *mut *mut u8,
+    typtbl_size: u32,
+    expected: ExpectedTypes,
+}
+
+impl Decoder {
+    /// Returns the type constructor of a type of the received message and a buffer
+    /// positioned after it. The type table has been validated by the header parser.
+    unsafe fn constructor(&self, t: i32) -> (i32, Buf) {
+        if t < 0 {
+            let end = (*self.buf).end;
+            return (t, Buf { ptr: end, end });
+        }
+        let mut tb = Buf {
+            ptr: *self.typtbl.add(t as usize),
+            end: (*self.buf).end,
+        };
+        let tc = sleb128_decode(&mut tb);
+        (tc, tb)
+    }
+
+    unsafe fn skip(&self, t: i32) -> IdlResult<()> {
+        try_skip_any(self.buf, self.typtbl, t, 0)
+    }
+
+    unsafe fn mismatch(&self, t: i32, can_recover: bool) -> IdlResult<Value> {
+        self.skip(t)?;
+        Ok(coercion_failed(can_recover, "unexpected IDL type"))
+    }
+
+    /// Advances the record type buffer `tb` to the field with `tag`, skipping the values
+    /// of all fields before it, c.f. `find_field`. Returns the type of the found field.
+    unsafe fn find_field(&self, tb: *mut Buf, n: &mut u32, tag: u32) -> IdlResult<Option<i32>> {
+        while *n > 0 {
+            let last_p = (*tb).ptr;
+            let this_tag = leb128_decode(tb);
+            if this_tag < tag {
+                let it = sleb128_decode(tb);
+                self.skip(it)?;
+                *n -= 1;
+            } else if this_tag == tag {
+                *n -= 1;
+                return Ok(Some(sleb128_decode(tb)));
+            } else {
+                // Rewind reading tag
+                (*tb).ptr = last_p;
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes a value of the received type `t` as the expected type `e`.
+    unsafe fn decode<M: Memory>(
+        &self,
+        mem: &mut M,
+        t: i32,
+        e: i32,
+        depth: u32,
+        can_recover: bool,
+    ) -> IdlResult<Value> {
+        enter_value(self.buf)?;
+        let value = self.decode_value(mem, t, e, depth, can_recover)?;
+        exit_value();
+        Ok(value)
+    }
+
+    unsafe fn decode_value<M: Memory>(
+        &self,
+        mem: &mut M,
+        t: i32,
+        e: i32,
+        depth: u32,
+        can_recover: bool,
+    ) -> IdlResult<Value> {
+        idl_limit_check(true, 1); // decrement and check quota
+
+        // Factor 2 because at each step, the expected type could go through one
+        // level of opt that is not present in the value type
+        if depth > 2 * (self.typtbl_size + 1) {
+            idl_trap_with("circular record read");
+        }
+
+        // Reset the depth counter if we made progress
+        let old_pos = (*self.buf).ptr;
+        let child_depth = |buf: *mut Buf| if (*buf).ptr == old_pos { depth + 1 } else { 0 };
+
+        if e < 0 {
+            return self.decode_primitive(mem, t, e, can_recover);
+        }
+
+        let mut eb = self.expected.entry(e);
+        let (tc, mut tb) = self.constructor(t);
+        match sleb128_decode(&mut eb) {
+            IDL_CON_opt => {
+                let ie = sleb128_decode(&mut eb);
+                if t == IDL_PRIM_null || t == IDL_PRIM_reserved {
+                    return Ok(NULL_POINTER);
+                }
+                let value = if tc == IDL_CON_opt {
+                    let it = sleb128_decode(&mut tb);
+                    if read_byte_tag(self.buf)? == 0 {
+                        return Ok(NULL_POINTER);
+                    }
+                    self.decode(mem, it, ie, child_depth(self.buf), true)?
+                } else if self.expected.is_null_opt_reserved(ie) {
+                    // this check corresponds to `not (null <: <t>)` in the spec
+                    self.skip(t)?;
+                    return Ok(NULL_POINTER);
+                } else {
+                    // Try constituent type
+                    self.decode(mem, t, ie, child_depth(self.buf), true)?
+                };
+                if value == COERCION_FAILURE {
+                    // decoding failed, but this is opt, so: return null
+                    Ok(NULL_POINTER)
+                } else {
+                    Ok(inject_opt(mem, value))
+                }
+            }
+            IDL_CON_vec => {
+                if tc != IDL_CON_vec {
+                    return self.mismatch(t, can_recover);
+                }
+                let ie = sleb128_decode(&mut eb);
+                let it = sleb128_decode(&mut tb);
+                let len = try_leb128_decode(self.buf)?;
+                // Don't decrement just check quota
+                idl_limit_check(false, len as u64);
+                charge_vec(self.buf, self.typtbl, it, len as u64)?;
+                let array = alloc_array(mem, TAG_ARRAY_I, len as usize);
+                let mut failed = false;
+                for index in 0..len as usize {
+                    let mut value = self.decode(mem, it, ie, child_depth(self.buf), can_recover)?;
+                    if value == COERCION_FAILURE {
+                        failed = true;
+                        value = NULL_POINTER;
+                    }
+                    array.as_array().initialize(index, value, mem);
+                }
+                allocation_barrier(array);
+                Ok(if failed { COERCION_FAILURE } else { array })
+            }
+            ec @ (IDL_CON_record | IDL_EXT_tuple) => {
+                if tc != IDL_CON_record {
+                    return self.mismatch(t, can_recover);
+                }
+                let mut n_wire = leb128_decode(&mut tb);
+                let n = leb128_decode(&mut eb);
+                let (result, payload, hash_blob) = if ec == IDL_CON_record {
+                    let hash_blob = self.expected.hash_blob(mem, e, n);
+                    let object = mem.alloc_words(size_of::<Object>() + Words(n as usize));
+                    let header = object.get_ptr() as *mut Object;
+                    (*header).header.tag = TAG_OBJECT;
+                    (*header).header.init_forward(object);
+                    (*header).hash_blob = hash_blob;
+                    (object, header.payload_addr(), hash_blob)
+                } else {
+                    let array = alloc_array(mem, TAG_ARRAY_T, n as usize);
+                    (array, array.as_array().payload_addr(), NULL_POINTER)
+                };
+                let mut failed = false;
+                for field in 0..n {
+                    let tag = leb128_decode(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    let mut value = match self.find_field(&mut tb, &mut n_wire, tag)? {
+                        Some(it) => self.decode(mem, it, ie, child_depth(self.buf), can_recover)?,
+                        None if self.expected.is_null_opt_reserved(ie) => NULL_POINTER,
+                        None => coercion_failed(can_recover, "did not find field in record"),
+                    };
+                    if value == COERCION_FAILURE {
+                        failed = true;
+                        value = NULL_POINTER;
+                    }
+                    let index = if ec == IDL_CON_record {
+                        object_field_index(hash_blob, self.expected.field_hash(e, field))
+                    } else {
+                        field as usize
+                    };
+                    init_with_barrier(mem, payload.add(index), value);
+                }
+                // skip all possible trailing extra fields
+                while n_wire > 0 {
+                    skip_leb128(&mut tb);
+                    let it = sleb128_decode(&mut tb);
+                    self.skip(it)?;
+                    n_wire -= 1;
+                }
+                allocation_barrier(result);
+                Ok(if failed { COERCION_FAILURE } else { result })
+            }
+            IDL_CON_variant => {
+                if tc != IDL_CON_variant {
+                    return self.mismatch(t, can_recover);
+                }
+                let n = leb128_decode(&mut tb);
+                let index = try_leb128_decode(self.buf)?;
+                if index >= n {
+                    idl_trap_with("variant index out of bounds");
+                }
+                // Zoom past the previous entries
+                for _ in 0..index {
+                    skip_leb128(&mut tb);
+                    skip_leb128(&mut tb);
+                }
+                let tag = leb128_decode(&mut tb);
+                let it = sleb128_decode(&mut tb);
+
+                for field in 0..leb128_decode(&mut eb) {
+                    let expected_tag = leb128_decode(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    if expected_tag == tag {
+                        let value = self.decode(mem, it, ie, child_depth(self.buf), can_recover)?;
+                        if value == COERCION_FAILURE {
+                            return Ok(COERCION_FAILURE);
+                        }
+                        let variant = mem.alloc_words(size_of::<Variant>());
+                        let object = variant.get_ptr() as *mut Variant;
+                        (*object).header.tag = TAG_VARIANT;
+                        (*object).header.init_forward(variant);
+                        (*object).tag = self.expected.field_hash(e, field) as usize;
+                        init_with_barrier(mem, &mut (*object).field, value);
+                        return Ok(allocation_barrier(variant));
+                    }
+                }
+                self.skip(it)?;
+                Ok(coercion_failed(can_recover, "unexpected variant tag"))
+            }
+            _ => rts_trap_with("idl_decode: unsupported type descriptor"),
+        }
+    }
+
+    unsafe fn decode_primitive<M: Memory>(
+        &self,
+        mem: &mut M,
+        t: i32,
+        e: i32,
+        can_recover: bool,
+    ) -> IdlResult<Value> {
+        match e {
+            IDL_PRIM_reserved => {
+                self.skip(t)?;
+                // Any vanilla value works here
+                return Ok(NULL_POINTER);
+            }
+            IDL_EXT_blob => return self.decode_blob(mem, t, can_recover),
+            // Subtyping with nat
+            IDL_PRIM_int if t == IDL_PRIM_nat => return self.decode_nat(),
+            _ if t != e => return self.mismatch(t, can_recover),
+            _ => {}
+        }
+        let value = match e {
+            IDL_PRIM_null => NULL_POINTER,
+            IDL_PRIM_bool => {
+                if read_byte_tag(self.buf)? == 0 {
+                    Value::from_raw(0)
+                } else {
+                    Value::from_raw(TRUE_VALUE)
+                }
+            }
+            IDL_PRIM_nat => self.decode_nat()?,
+            IDL_PRIM_int => self.decode_int()?,
+            IDL_PRIM_nat8 | IDL_PRIM_int8 => self.decode_word(e, 1)?,
+            IDL_PRIM_nat16 | IDL_PRIM_int16 => self.decode_word(e, 2)?,
+            IDL_PRIM_nat32 | IDL_PRIM_int32 => self.decode_word(e, 4)?,
+            IDL_PRIM_nat64 | IDL_PRIM_int64 | IDL_PRIM_float64 => self.decode_word(e, 8)?,
+            IDL_PRIM_text => {
+                let len = try_leb128_decode(self.buf)? as usize;
+                let p = (*self.buf).ptr;
+                try_advance(self.buf, len)?; // advance first; does the bounds check
+                if !utf8_valid(p as *const _, len) {
+                    return Err(IdlError::new(IdlErrorCode::InvalidUtf8, p));
+                }
+                charge_allocation(self.buf, len as u64)?;
+                text_of_ptr_size(mem, p, Bytes(len))
+            }
+            IDL_REF_principal => {
+                if read_byte_tag(self.buf)? == 0 {
+                    idl_trap_with("unexpected principal reference");
+                }
+                let len = try_leb128_decode(self.buf)? as usize;
+                // at most 29 bytes, according to the IC interface specification
+                if len > 29 {
+                    idl_trap_with("principal too long");
+                }
+                self.read_blob(mem, TAG_BLOB_P, len)?
+            }
+            _ => rts_trap_with("idl_decode: unsupported type descriptor"),
+        };
+        Ok(value)
+    }
+
+    unsafe fn decode_blob<M: Memory>(
+        &self,
+        mem: &mut M,
+        t: i32,
+        can_recover: bool,
+    ) -> IdlResult<Value> {
+        let (tc, mut tb) = self.constructor(t);
+        if tc != IDL_CON_vec {
+            return self.mismatch(t, can_recover);
+        }
+        if sleb128_decode(&mut tb) != IDL_PRIM_nat8 {
+            self.skip(t)?;
+            return Ok(coercion_failed(can_recover, "blob not a vector of nat8"));
+        }
+        let len = try_leb128_decode(self.buf)? as usize;
+        self.read_blob(mem, TAG_BLOB_B, len)
+    }
+
+    unsafe fn read_blob<M: Memory>(
+        &self,
+        mem: &mut M,
+        tag: crate::types::Tag,
+        len: usize,
+    ) -> IdlResult<Value> {
+        let p = (*self.buf).ptr;
+        try_advance(self.buf, len)?;
+        charge_allocation(self.buf, len as u64)?;
+        let blob = alloc_blob(mem, tag, Bytes(len));
+        core::ptr::copy_nonoverlapping(p, blob.as_blob_mut().payload_addr(), len);
+        Ok(allocation_barrier(blob))
+    }
+
+    unsafe fn decode_word(&self, e: i32, size: usize) -> IdlResult<Value> {
+        let mut bytes = [0u8; 8];
+        try_read_bytes(self.buf, &mut bytes[..size])?;
+        Ok(value_from_word64(e, u64::from_le_bytes(bytes)))
+    }
+
+    // Numbers of at most 63 bits are converted by generated code, larger ones by the bigint library.
+    const MAX_WORD_LEB128_BYTES: usize = 9;
+
+    /// Checks the bounds of a (S)LEB128 number and returns its length in bytes.
+    unsafe fn leb128_length(&self) -> IdlResult<usize> {
+        let start = (*self.buf).ptr;
+        try_skip_leb128(self.buf)?;
+        let length = (*self.buf).ptr as usize - start as usize;
+        (*self.buf).ptr = start;
+        Ok(length)
+    }
+
+    unsafe fn decode_nat(&self) -> IdlResult<Value> {
+        if self.leb128_length()? > Self::MAX_WORD_LEB128_BYTES {
+            return Ok(bigint_leb128_decode(self.buf));
+        }
+        let value = crate::leb128::leb128_decode(self.buf);
+        Ok(value_from_word64(IDL_PRIM_nat, value as u64))
+    }
+
+    unsafe fn decode_int(&self) -> IdlResult<Value> {
+        if self.leb128_length()? > Self::MAX_WORD_LEB128_BYTES {
+            return Ok(bigint_sleb128_decode(self.buf));
+        }
+        let value = crate::leb128::sleb128_decode(self.buf);
+        Ok(value_from_word64(IDL_PRIM_int, value as u64))
+    }
+}
+
+/// Decodes the arguments of a Candid message `blob` as the expected types given by
+/// `type_desc` and `field_hashes` (see above). Returns an array of the decoded arguments,
+/// or the coercion failure sentinel if a coercion failed and `can_recover` is set.
+///
+/// Like the generated code, this expects the caller to set up the value quota of
+/// `idl_limit_check`.
+#[ic_mem_fn]
+pub unsafe fn idl_decode<M: Memory>(
+    mem: &mut M,
+    blob: Value,
+    type_desc: Value,
+    field_hashes: Value,
+    can_recover: bool,
+) -> Value {
+    let blob = blob.as_blob();
+    let start = blob.payload_const() as *mut u8;
+    let mut buf = Buf {
+        ptr: start,
+        end: start.add(blob.len().as_usize()),
+    };
+    decode_message(mem, &mut buf, type_desc, field_hashes, can_recover)
+        .unwrap_or_else(|error| error.trap())
+}
+
+unsafe fn decode_message<M: Memory>(
+    mem: &mut M,
+    buf: *mut Buf,
+    type_desc: Value,
+    field_hashes: Value,
+    can_recover: bool,
+) -> IdlResult<Value> {
+    let header = try_parse_idl_header(mem, CompatibilityMode::PureCandid, buf)?;
+    let decoder = Decoder {
+        buf,
+        typtbl: header.typtbl,
+        typtbl_size: header.n_types,
+        expected: ExpectedTypes::new(mem, type_desc, field_hashes),
+    };
+
+    let mut main_types = Buf {
+        ptr: header.main_types,
+        end: (*buf).end,
+    };
+    let mut expected_types = Buf {
+        ptr: decoder.expected.main_types,
+        end: decoder.expected.end,
+    };
+    let mut arg_count = leb128_decode(&mut main_types);
+    let n_args = leb128_decode(&mut expected_types);
+    let args = alloc_array(mem, TAG_ARRAY_T, n_args as usize);
+
+    let mut failed = false;
+    for index in 0..n_args as usize {
+        let e = sleb128_decode(&mut expected_types);
+        let optional = decoder.expected.is_null_opt_reserved(e);
+        let value = if arg_count == 0 {
+            if !(optional || can_recover) {
+                idl_trap_with("too few arguments");
+            }
+            COERCION_FAILURE
+        } else {
+            arg_count -= 1;
+            let t = sleb128_decode(&mut main_types);
+            let value = decoder.decode(mem, t, e, 0, optional || can_recover)?;
+            if value == COERCION_FAILURE && !(optional || can_recover) {
+                idl_trap_with("coercion failure encountered");
+            }
+            value
+        };
+        let value = if value != COERCION_FAILURE {
+            value
+        } else {
+            failed |= !optional;
+            NULL_POINTER
+        };
+        args.as_array().initialize(index, value, mem);
+    }
+
+    // Skip any extra arguments
+    while arg_count > 0 {
+        let t = sleb128_decode(&mut main_types);
+        decoder.skip(t)?;
+        arg_count -= 1;
+    }
+
+    if (*buf).ptr != (*buf).end {
+        return Err(IdlError::new(IdlErrorCode::LeftoverBytes, (*buf).ptr));
+    }
+
+    allocation_barrier(args);
+    Ok(if failed { COERCION_FAILURE } else { args })
+}
@@ -0,0 +1,215 @@
+//! Native Candid encoder, serializing heap values of the given Motoko types directly into
+//! a Candid message, as an alternative to the generated serialization code.
+//!
+//! The value types are described like the expected types of the native decoder (see
+//! `decoder.rs`), by the persistence type descriptor and the Motoko label hashes of its
+//! record and variant fields. The type table of the message, i.e. the Candid type
+//! descriptor of the same types, is also supplied by the compiler and copied verbatim.
+//! Both descriptors list the fields of records and variants in the same (Candid) order.
+//!
+//! The encoder follows the semantics of the generated code (`serialize_go`). The message
+//! is written to a growable blob, as the `Stream` of classical persistence is not available
+//! in 64-bit. Text is copied with `text_to_buf`, which traverses `Concat` nodes in place,
+//! without flattening them into an intermediate blob.
+
+use super::decoder::{object_field_index, ExpectedTypes};
+use super::{
+    leb128_decode, sleb128_decode, IDL_CON_opt, IDL_CON_record, IDL_CON_variant, IDL_CON_vec,
+    IDL_EXT_blob, IDL_EXT_tuple, IDL_PRIM_bool, IDL_PRIM_float64, IDL_PRIM_int, IDL_PRIM_int16,
+    IDL_PRIM_int32, IDL_PRIM_int64, IDL_PRIM_int8, IDL_PRIM_nat, IDL_PRIM_nat16, IDL_PRIM_nat32,
+    IDL_PRIM_nat64, IDL_PRIM_nat8, IDL_PRIM_null, IDL_PRIM_reserved, IDL_PRIM_text,
+    IDL_REF_principal,
+};
+use crate::bigint::{
+    bigint_leb128_encode, bigint_leb128_size, bigint_sleb128_encode, bigint_sleb128_size,
+};
+use crate::buf::{skip_leb128, Buf};
+use crate::idl_writer::IdlWriter;
+use crate::memory::Memory;
+use crate::rts_trap_with;
+use crate::text::{text_size, text_to_buf};
+use crate::types::{Value, Variant, NULL_POINTER, TAG_SOME, TRUE_VALUE};
+
+use motoko_rts_macros::ic_mem_fn;
+
+// Provided by generated code
+extern "C" {
+    /// Untags or unboxes a number of the given primitive Candid type, returning its raw
+    /// little-endian bits. Only called with compact (scalar) values for `nat` and `int`.
+    pub(super) fn value_to_word64(idl_type: i32, value: Value) -> u64;
+}
+
+/// The payload of an option, c.f. `Opt.project` in the compiler.
+pub(super) unsafe fn project_opt(value: Value) -> Value {
+    if value.is_scalar() || value.get_raw() == TRUE_VALUE {
+        return value;
+    }
+    let object = value.as_obj();
+    if object.tag() == TAG_SOME {
+        (*(object as *mut crate::types::Some)).field
+    } else {
+        value
+    }
+}
+
+unsafe fn payload<'a>(blob: Value) -> &'a [u8] {
+    let blob = blob.as_blob();
+    core::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize())
+}
+
+struct Encoder {
+    writer: IdlWriter,
+    types: ExpectedTypes,
+}
+
+impl Encoder {
+    /// Encodes a value of the type `e`.
+    unsafe fn encode<M: Memory>(&mut self, mem: &mut M, value: Value, e: i32) {
+        if e < 0 {
+            return self.encode_primitive(mem, value, e);
+        }
+
+        let mut eb = self.types.entry(e);
+        match sleb128_decode(&mut eb) {
+            IDL_CON_opt => {
+                if value == NULL_POINTER {
+                    self.writer.write_byte(mem, 0);
+                } else {
+                    self.writer.write_byte(mem, 1);
+                    let ie = sleb128_decode(&mut eb);
+                    self.encode(mem, project_opt(value), ie);
+                }
+            }
+            IDL_CON_vec => {
+                let ie = sleb128_decode(&mut eb);
+                let array = value.as_array();
+                let len = array.len();
+                self.writer.write_leb128(mem, len as u64);
+                for index in 0..len {
+                    self.encode(mem, array.get(index), ie);
+                }
+            }
+            IDL_CON_record => {
+                // The object may have more fields than its type, if it is of a subtype.
+                let object = value.as_object();
+                let hash_blob = (*object).hash_blob;
+                for field in 0..leb128_decode(&mut eb) {
+                    skip_leb128(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    let index = object_field_index(hash_blob, self.types.field_hash(e, field));
+                    self.encode(mem, object.get(index), ie);
+                }
+            }
+            IDL_EXT_tuple => {
+                let array = value.as_array();
+                for field in 0..leb128_decode(&mut eb) as usize {
+                    skip_leb128(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    self.encode(mem, array.get(field), ie);
+                }
+            }
+            IDL_CON_variant => {
+                let variant = value.as_obj() as *mut Variant;
+                for field in 0..leb128_decode(&mut eb) {
+                    skip_leb128(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    if self.types.field_hash(e, field) as usize == (*variant).tag {
+                        self.writer.write_leb128(mem, field as u64);
+                        return self.encode(mem, (*variant).field, ie);
+                    }
+                }
+                rts_trap_with("idl_encode: unexpected variant");
+            }
+            _ => rts_trap_with("idl_encode: unsupported type descriptor"),
+        }
+    }
+
+    unsafe fn encode_primitive<M: Memory>(&mut self, mem: &mut M, value: Value, e: i32) {
+        match e {
+            IDL_PRIM_null | IDL_PRIM_reserved => {}
+            IDL_PRIM_bool => self.writer.write_bool(mem, value.get_raw() == TRUE_VALUE),
+            IDL_PRIM_nat => self.encode_nat(mem, value),
+            IDL_PRIM_int => self.encode_int(mem, value),
+            IDL_PRIM_nat8 | IDL_PRIM_int8 => self.encode_word(mem, value, e, 1),
+            IDL_PRIM_nat16 | IDL_PRIM_int16 => self.encode_word(mem, value, e, 2),
+            IDL_PRIM_nat32 | IDL_PRIM_int32 => self.encode_word(mem, value, e, 4),
+            IDL_PRIM_nat64 | IDL_PRIM_int64 | IDL_PRIM_float64 => {
+                self.encode_word(mem, value, e, 8)
+            }
+            IDL_PRIM_text => {
+                let size = text_size(value).as_usize();
+                self.writer.write_leb128(mem, size as u64);
+                text_to_buf(value, self.writer.reserve_bytes(mem, size));
+            }
+            IDL_EXT_blob => self.writer.write_blob(mem, payload(value)),
+            IDL_REF_principal => {
+                self.writer.write_byte(mem, 1);
+                self.writer.write_blob(mem, payload(value));
+            }
+            _ => rts_trap_with("idl_encode: unsupported type descriptor"),
+        }
+    }
+
+    unsafe fn encode_word<M: Memory>(&mut self, mem: &mut M, value: Value, e: i32, size: usize) {
+        let bytes = value_to_word64(e, value).to_le_bytes();
+        self.writer.write_bytes(mem, &bytes[..size]);
+    }
+
+    // Compact numbers are converted by generated code, boxed ones by the bigint library.
+
+    unsafe fn encode_nat<M: Memory>(&mut self, mem: &mut M, value: Value) {
+        if value.is_scalar() {
+            let value = value_to_word64(IDL_PRIM_nat, value);
+            self.writer.write_leb128(mem, value);
+        } else {
+            let size = bigint_leb128_size(value);
+            bigint_leb128_encode(value, self.writer.reserve_bytes(mem, size));
+        }
+    }
+
+    unsafe fn encode_int<M: Memory>(&mut self, mem: &mut M, value: Value) {
+        if value.is_scalar() {
+            let value = value_to_word64(IDL_PRIM_int, value) as i64;
+            self.writer.write_sleb128(mem, value);
+        } else {
+            let size = bigint_sleb128_size(value);
+            bigint_sleb128_encode(value, self.writer.reserve_bytes(mem, size));
+        }
+    }
+}
+
+/// Encodes `value` as a Candid message of the types given by `type_desc` and `field_hashes`
+/// (see above), with the type table `header`. For a single type, `value` is the argument
+/// itself, otherwise a tuple of the arguments. Returns the message as a blob.
+#[ic_mem_fn]
+pub unsafe fn idl_encode<M: Memory>(
+    mem: &mut M,
+    value: Value,
+    header: Value,
+    type_desc: Value,
+    field_hashes: Value,
+) -> Value {
+    let types = ExpectedTypes::new(mem, type_desc, field_hashes);
+    let mut main_types = Buf {
+        ptr: types.main_types,
+        end: types.end,
+    };
+    let mut encoder = Encoder {
+        writer: IdlWriter::new(mem),
+        types,
+    };
+    // The writer has already written the magic bytes (DIDL)
+    encoder.writer.write_bytes(mem, &payload(header)[4..]);
+
+    let n_args = leb128_decode(&mut main_types);
+    if n_args == 1 {
+        let e = sleb128_decode(&mut main_types);
+        encoder.encode(mem, value, e);
+    } else {
+        for index in 0..n_args as usize {
+            let e = sleb128_decode(&mut main_types);
+            encoder.encode(mem, value.as_array().get(index), e);
+        }
+    }
+    encoder.writer.finish()
+}
@@ -0,0 +1,352 @@
+//! Renders heap values in the Candid textual value syntax, e.g.
+//! `record { name = "x"; items = vec { 1; 2 } }`, for human-readable dumps of
+//! stable variables in logs.
+//!
+//! The types are described like the expected types of the native decoder (see `decoder.rs`),
+//! by the persistence type descriptor and the Motoko label hashes of its record and variant
+//! fields, together with the Candid names of these fields in the same order.
+//!
+//! The text is formatted with a `WriteBuf` directly into a text blob of bounded size. Longer
+//! renderings are cut off at a character boundary and end with an ellipsis.
+
+use super::decoder::{object_field_index, ExpectedTypes};
+use super::encoder::{project_opt, value_to_word64};
+use super::{
+    alloc, leb128_decode, sleb128_decode, IDL_CON_opt, IDL_CON_record, IDL_CON_variant,
+    IDL_CON_vec, IDL_EXT_blob, IDL_EXT_tuple, IDL_PRIM_bool, IDL_PRIM_float64, IDL_PRIM_int,
+    IDL_PRIM_int16, IDL_PRIM_int32, IDL_PRIM_int64, IDL_PRIM_int8, IDL_PRIM_nat, IDL_PRIM_nat16,
+    IDL_PRIM_nat32, IDL_PRIM_nat64, IDL_PRIM_nat8, IDL_PRIM_null, IDL_PRIM_reserved, IDL_PRIM_text,
+    IDL_REF_principal,
+};
+use crate::barriers::allocation_barrier;
+use crate::bigint::{check, mp_isneg, mp_iszero, tmp_bigint};
+use crate::buf::{skip_leb128, Buf};
+use crate::memory::{alloc_blob, Memory};
+use crate::principal_id::principal_of_blob;
+use crate::print::WriteBuf;
+use crate::rts_trap_with;
+use crate::text::blob_of_text;
+use crate::tommath_bindings::{
+    mp_abs, mp_count_bits, mp_div, mp_get_i64, mp_init_copy, mp_int, mp_set_u64,
+};
+use crate::types::{Bytes, Value, Variant, Words, NULL_POINTER, TAG_BLOB_T, TRUE_VALUE};
+
+use core::fmt::Write;
+use motoko_rts_macros::ic_mem_fn;
+
+/// Maximum size of a rendering in bytes.
+const MAX_TEXT_SIZE: usize = 64 * 1024;
+
+const ELLIPSIS: &str = "…";
+
+/// The Candid names of the record and variant fields, in the order of the field hashes.
+struct FieldNames {
+    /// Per field: the address and the length of its name.
+    names: *mut (*const u8, usize),
+}
+
+impl FieldNames {
+    /// The names are LEB128-length-prefixed. They are generated by the compiler and therefore
+    /// trusted.
+    unsafe fn new<M: Memory>(mem: &mut M, field_names: Value, n_fields: usize) -> FieldNames {
+        let blob = field_names.as_blob();
+        let start = blob.payload_const() as *mut u8;
+        let mut buf = Buf {
+            ptr: start,
+            end: start.add(blob.len().as_usize()),
+        };
+        let names = alloc(mem, Words(2 * n_fields)) as *mut (*const u8, usize);
+        for index in 0..n_fields {
+            let length = leb128_decode(&mut buf) as usize;
+            *names.add(index) = (buf.ptr, length);
+            buf.ptr = buf.ptr.add(length);
+        }
+        FieldNames { names }
+    }
+
+    unsafe fn get<'b>(&self, index: usize) -> &'b str {
+        let (start, length) = *self.names.add(index);
+        core::str::from_utf8_unchecked(core::slice::from_raw_parts(start, length))
+    }
+}
+
+struct Printer<'a> {
+    buf: WriteBuf<'a>,
+    capacity: usize,
+    types: ExpectedTypes,
+    names: FieldNames,
+}
+
+impl<'a> Printer<'a> {
+    fn is_full(&self) -> bool {
+        self.buf.length() >= self.capacity
+    }
+
+    fn write(&mut self, text: &str) {
+        let _ = self.buf.write_str(text);
+    }
+
+    /// Renders a value of the type `e`.
+    unsafe fn print<M: Memory>(&mut self, mem: &mut M, value: Value, e: i32) {
+        // Stop traversing once the output is cut off.
+        if self.is_full() {
+            return;
+        }
+
+        if e < 0 {
+            return self.print_primitive(mem, value, e);
+        }
+
+        let mut eb = self.types.entry(e);
+        match sleb128_decode(&mut eb) {
+            IDL_CON_opt => {
+                if value == NULL_POINTER {
+                    self.write("null");
+                } else {
+                    self.write("opt ");
+                    let ie = sleb128_decode(&mut eb);
+                    self.print(mem, project_opt(value), ie);
+                }
+            }
+            IDL_CON_vec => {
+                let ie = sleb128_decode(&mut eb);
+                let array = value.as_array();
+                self.write("vec {");
+                for index in 0..array.len() {
+                    self.write(if index == 0 { " " } else { "; " });
+                    self.print(mem, array.get(index), ie);
+                    if self.is_full() {
+                        return;
+                    }
+                }
+                self.write(if array.len() == 0 { "}" } else { " }" });
+            }
+            IDL_CON_record => {
+                // The object may have more fields than its type, if it is of a subtype.
+                let object = value.as_object();
+                let hash_blob = (*object).hash_blob;
+                let n = leb128_decode(&mut eb);
+                self.write("record {");
+                for field in 0..n {
+                    skip_leb128(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    let index = object_field_index(hash_blob, self.types.field_hash(e, field));
+                    self.write(if field == 0 { " " } else { "; " });
+                    self.write(self.names.get(self.types.field_index(e, field)));
+                    self.write(" = ");
+                    self.print(mem, object.get(index), ie);
+                }
+                self.write(if n == 0 { "}" } else { " }" });
+            }
+            IDL_EXT_tuple => {
+                let array = value.as_array();
+                let n = leb128_decode(&mut eb);
+                self.write("record {");
+                for field in 0..n {
+                    skip_leb128(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    self.write(if field == 0 { " " } else { "; " });
+                    self.print(mem, array.get(field as usize), ie);
+                }
+                self.write(if n == 0 { "}" } else { " }" });
+            }
+            IDL_CON_variant => {
+                let variant = value.as_obj() as *mut Variant;
+                for field in 0..leb128_decode(&mut eb) {
+                    skip_leb128(&mut eb);
+                    let ie = sleb128_decode(&mut eb);
+                    if self.types.field_hash(e, field) as usize == (*variant).tag {
+                        self.write("variant { ");
+                        self.write(self.names.get(self.types.field_index(e, field)));
+                        if ie != IDL_PRIM_null {
+                            self.write(" = ");
+                            self.print(mem, (*variant).field, ie);
+                        }
+                        self.write(" }");
+                        return;
+                    }
+                }
+                rts_trap_with("idl_print: unexpected variant");
+            }
+            _ => rts_trap_with("idl_print: unsupported type descriptor"),
+        }
+    }
+
+    unsafe fn print_primitive<M: Memory>(&mut self, mem: &mut M, value: Value, e: i32) {
+        let bits = || value_to_word64(e, value);
+        let _ = match e {
+            IDL_PRIM_null => write!(self.buf, "null"),
+            IDL_PRIM_reserved => write!(self.buf, "reserved"),
+            IDL_PRIM_bool => write!(self.buf, "{}", value.get_raw() == TRUE_VALUE),
+            IDL_PRIM_nat | IDL_PRIM_int if value.is_scalar() => {
+                write!(self.buf, "{}", bits() as i64)
+            }
+            IDL_PRIM_nat | IDL_PRIM_int => {
+                self.print_bigint(mem, value);
+                Ok(())
+            }
+            IDL_PRIM_nat8 => write!(self.buf, "{}", bits() as u8),
+            IDL_PRIM_nat16 => write!(self.buf, "{}", bits() as u16),
+            IDL_PRIM_nat32 => write!(self.buf, "{}", bits() as u32),
+            IDL_PRIM_nat64 => write!(self.buf, "{}", bits()),
+            IDL_PRIM_int8 => write!(self.buf, "{}", bits() as i8),
+            IDL_PRIM_int16 => write!(self.buf, "{}", bits() as i16),
+            IDL_PRIM_int32 => write!(self.buf, "{}", bits() as i32),
+            IDL_PRIM_int64 => write!(self.buf, "{}", bits() as i64),
+            IDL_PRIM_float64 => {
+                self.print_float(f64::from_bits(bits()));
+                Ok(())
+            }
+            IDL_PRIM_text => {
+                let blob = blob_of_text(mem, value).as_blob();
+                let bytes =
+                    core::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize());
+                self.print_text(core::str::from_utf8_unchecked(bytes));
+                Ok(())
+            }
+            IDL_EXT_blob => {
+                let blob = value.as_blob();
+                self.write("blob \"");
+                for index in 0..blob.len().as_usize() {
+                    let byte = blob.get(index);
+                    let _ = if byte.is_ascii_graphic() && byte != b'"' && byte != b'\\'
+                        || byte == b' '
+                    {
+                        write!(self.buf, "{}", byte as char)
+                    } else {
+                        write!(self.buf, "\\{:02x}", byte)
+                    };
+                }
+                write!(self.buf, "\"")
+            }
+            IDL_REF_principal => {
+                let text = principal_of_blob(mem, value).as_blob();
+                let bytes =
+                    core::slice::from_raw_parts(text.payload_const(), text.len().as_usize());
+                write!(
+                    self.buf,
+                    "principal \"{}\"",
+                    core::str::from_utf8_unchecked(bytes)
+                )
+            }
+            _ => rts_trap_with("idl_print: unsupported type descriptor"),
+        };
+    }
+
+    /// Renders a float with the Candid spellings `nan`, `inf`, and `-inf` of the special values.
+    /// Finite values are written with a fraction or an exponent, e.g. `1.0` or `1e300`.
+    fn print_float(&mut self, number: f64) {
+        let _ = if number.is_nan() {
+            write!(self.buf, "nan")
+        } else if number.is_infinite() {
+            write!(self.buf, "{}inf", if number < 0.0 { "-" } else { "" })
+        } else {
+            write!(self.buf, "{:?}", number)
+        };
+    }
+
+    /// Renders a text literal with the Candid escapes: `\n`, `\r`, `\t`, `\\`, `\"`, `\HH`
+    /// for other ASCII control characters, and `\u{...}` for non-ASCII control characters.
+    fn print_text(&mut self, text: &str) {
+        self.write("\"");
+        for character in text.chars() {
+            let _ = match character {
+                '\n' => write!(self.buf, "\\n"),
+                '\r' => write!(self.buf, "\\r"),
+                '\t' => write!(self.buf, "\\t"),
+                '\\' => write!(self.buf, "\\\\"),
+                '"' => write!(self.buf, "\\\""),
+                _ if character.is_ascii_control() => write!(self.buf, "\\{:02x}", character as u32),
+                _ if character.is_control() => write!(self.buf, "\\u{{{:x}}}", character as u32),
+                _ => write!(self.buf, "{}", character),
+            };
+        }
+        self.write("\"");
+    }
+
+    /// Renders a boxed `Nat` or `Int` in decimal.
+    unsafe fn print_bigint<M: Memory>(&mut self, mem: &mut M, value: Value) {
+        // Digits in base 10^18, which fits in 60 bits
+        const BASE: u64 = 1_000_000_000_000_000_000;
+        const BASE_BITS: usize = 59;
+
+        let mut n: mp_int = core::mem::zeroed();
+        check(mp_init_copy(&mut n, value.as_bigint().mp_int_ptr()));
+        if mp_isneg(&n) {
+            self.write("-");
+            check(mp_abs(&n, &mut n));
+        }
+        let mut base = tmp_bigint();
+        mp_set_u64(&mut base, BASE);
+        let mut digit = tmp_bigint();
+
+        let max_digits = mp_count_bits(&n) as usize / BASE_BITS + 1;
+        let digits = alloc(mem, Words(max_digits)) as *mut u64;
+        let mut count = 0;
+        loop {
+            check(mp_div(&n, &base, &mut n, &mut digit));
+            *digits.add(count) = mp_get_i64(&digit) as u64;
+            count += 1;
+            if mp_iszero(&n) {
+                break;
+            }
+        }
+
+        let _ = write!(self.buf, "{}", *digits.add(count - 1));
+        for index in (0..count - 1).rev() {
+            let _ = write!(self.buf, "{:018}", *digits.add(index));
+        }
+    }
+}
+
+/// Renders `value` of the type given by `type_desc`, `field_hashes` and `field_names`
+/// (see above) in the Candid textual syntax. Returns the rendering as a text.
+#[ic_mem_fn]
+pub unsafe fn idl_print<M: Memory>(
+    mem: &mut M,
+    value: Value,
+    type_desc: Value,
+    field_hashes: Value,
+    field_names: Value,
+) -> Value {
+    let n_fields = field_hashes.as_blob().len().as_usize() / core::mem::size_of::<u32>();
+    let types = ExpectedTypes::new(mem, type_desc, field_hashes);
+    let names = FieldNames::new(mem, field_names, n_fields);
+    let mut main_types = Buf {
+        ptr: types.main_types,
+        end: types.end,
+    };
+    if leb128_decode(&mut main_types) != 1 {
+        rts_trap_with("idl_print: expected a single type");
+    }
+    let e = sleb128_decode(&mut main_types);
+
+    let text = alloc_blob(mem, TAG_BLOB_T, Bytes(MAX_TEXT_SIZE));
+    let payload = text.as_blob_mut().payload_addr();
+    let capacity = MAX_TEXT_SIZE - ELLIPSIS.len();
+    let mut printer = Printer {
+        buf: WriteBuf::new(core::slice::from_raw_parts_mut(payload, capacity)),
+        capacity,
+        types,
+        names,
+    };
+    printer.print(mem, value, e);
+
+    let mut length = printer.buf.length();
+    if printer.is_full() {
+        // Drop a partially written character and mark the cut.
+        let mut start = length - 1;
+        while *payload.add(start) & 0b1100_0000 == 0b1000_0000 {
+            start -= 1;
+        }
+        let width = (*payload.add(start)).leading_ones().max(1) as usize;
+        if start + width > length {
+            length = start;
+        }
+        let ellipsis = ELLIPSIS.as_bytes();
+        core::ptr::copy_nonoverlapping(ellipsis.as_ptr(), payload.add(length), ellipsis.len());
+        length += ellipsis.len();
+    }
+    text.as_blob_mut().shrink(Bytes(length));
+    allocation_barrier(text)
+}
@@ -0,0 +1,126 @@
+//! Structured errors of the Candid decoder.
+//!
+//! The checked decoding functions in `idl.rs` report malformed input as an `IdlError`
+//! rather than trapping right away. The entry points used by generated code still trap,
+//! with the message of the error, while `idl_validate` returns the error as a Candid value
+//! such that actors can inspect and reject malformed payloads themselves.
+//!
+//! NB: The error codes are part of the interface to Motoko code and must not be renumbered.
+
+use crate::{idl_trap_with, rts_trap_with};
+
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IdlErrorCode {
+    BufferOverrun = 1,
+    NumberOverflow = 2,
+    EmptyInput = 3,
+    MissingMagic = 4,
+    TooManyTypes = 5,
+    IllegalTypeTable = 6,
+    PrimitiveInTypeTable = 7,
+    InvalidTypeArgument = 8,
+    FieldsOutOfOrder = 9,
+    InvalidFuncAnnotation = 10,
+    MethodsOutOfOrder = 11,
+    MethodNotConstructorType = 12,
+    MethodNotFuncType = 13,
+    InvalidUtf8 = 14,
+    InvalidByteTag = 15,
+    TooDeeplyNested = 16,
+    EmptyValue = 17,
+    UnknownPrimitive = 18,
+    RecursiveRecord = 19,
+    VariantTagTooLarge = 20,
+    SkippingReferences = 21,
+    UnexpectedAlias = 22,
+    LeftoverBytes = 23,
+    DepthLimitExceeded = 24,
+    AllocationLimitExceeded = 25,
+    SkipLimitExceeded = 26,
+    VecNullLimitExceeded = 27,
+}
+
+impl IdlErrorCode {
+    /// The message used when trapping on this error.
+    pub fn message(self) -> &'static str {
+        use IdlErrorCode::*;
+        match self {
+            BufferOverrun => "read out of buffer",
+            NumberOverflow => "LEB128 number out of range",
+            EmptyInput => {
+                "empty input. Expected Candid-encoded argument, but received a zero-length argument"
+            }
+            MissingMagic => "missing magic bytes",
+            TooManyTypes => "too many types",
+            IllegalTypeTable => "illegal type table",
+            PrimitiveInTypeTable => "primitive type in type table",
+            InvalidTypeArgument => "invalid type argument",
+            FieldsOutOfOrder => "variant or record tag out of order",
+            InvalidFuncAnnotation => "func annotation not within 1..3",
+            MethodsOutOfOrder => "service method names out of order",
+            MethodNotConstructorType => "service method arg not a constructor type",
+            MethodNotFuncType => "service method arg not a function type",
+            InvalidUtf8 => "string is not UTF-8",
+            InvalidByteTag => "skip_any: byte tag not 0 or 1",
+            TooDeeplyNested => "skip_any: too deeply nested record",
+            EmptyValue => "skip_any: encountered empty",
+            UnknownPrimitive => "skip_any: unknown prim",
+            RecursiveRecord => "skip_any: recursive record",
+            VariantTagTooLarge => "skip_any: variant tag too large",
+            SkippingReferences => "skip_any: skipping references",
+            UnexpectedAlias => "sub: unexpected alias",
+            LeftoverBytes => "left-over bytes",
+            DepthLimitExceeded => "exceeded nesting depth limit",
+            AllocationLimitExceeded => "exceeded allocation limit",
+            SkipLimitExceeded => "exceeded skipped value limit",
+            VecNullLimitExceeded => "exceeded vec null limit",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IdlError {
+    pub code: IdlErrorCode,
+    /// Position in the decoded buffer where the error was detected, or null if the
+    /// error does not originate from a buffer (e.g. in the subtype check).
+    pub position: *const u8,
+    /// Index of the innermost type table entry that was processed, if any.
+    pub type_index: Option<u32>,
+}
+
+impl IdlError {
+    pub fn new(code: IdlErrorCode, position: *const u8) -> IdlError {
+        IdlError {
+            code,
+            position,
+            type_index: None,
+        }
+    }
+
+    /// Attribute the error to a type table entry, unless a more specific
+    /// (i.e. nested) entry has already been recorded.
+    pub fn in_type(mut self, type_index: u32) -> IdlError {
+        if self.type_index.is_none() {
+            self.type_index = Some(type_index);
+        }
+        self
+    }
+
+    /// Byte offset of the error relative to the start of the buffer.
+    pub fn offset(&self, start: *const u8) -> usize {
+        if self.position.is_null() {
+            0
+        } else {
+            self.position as usize - start as usize
+        }
+    }
+
+    pub unsafe fn trap(self) -> ! {
+        match self.code {
+            // Keep the message of the former `utf8_validate` trap.
+            IdlErrorCode::InvalidUtf8 => rts_trap_with("utf8_validate: string is not UTF-8"),
+            code => idl_trap_with(code.message()),
+        }
+    }
+}
@@ -0,0 +1,374 @@
+//! Candid subtype check, c.f. the subtyping rules of the Candid specification, used for
+//! the coercion of function and service references on deserialization.
+//!
+//! Independent of the IC, such that it can be tested natively (see `motoko-rts-tests`).
+
+use crate::bitrel::BitRel;
+use crate::buf::{read_byte, Buf};
+use crate::idl_error::{IdlError, IdlErrorCode};
+use crate::idl_types::*;
+use crate::libc_declarations::{c_void, memcmp};
+
+use core::cmp::min;
+use motoko_rts_macros::enhanced_orthogonal_persistence;
+
+#[enhanced_orthogonal_persistence]
+pub mod cache;
+
+// TBR; based on Text.text_compare
+pub(crate) unsafe fn utf8_cmp(len1: usize, p1: *mut u8, len2: usize, p2: *mut u8) -> i32 {
+    let len = min(len1, len2);
+    let cmp = memcmp(p1 as *mut c_void, p2 as *mut c_void, len);
+    if cmp != 0 {
+        return cmp;
+    } else if len1 > len {
+        return 1;
+    } else if len2 > len {
+        return -1;
+    } else {
+        return 0;
+    }
+}
+
+unsafe fn is_null_opt_reserved(typtbl: *mut *mut u8, end: *mut u8, t: i32) -> bool {
+    if is_primitive_type(CompatibilityMode::PureCandid, t) {
+        return t == IDL_PRIM_null || t == IDL_PRIM_reserved;
+    }
+
+    // unfold t
+    let mut t = t;
+
+    let mut tb = Buf {
+        ptr: *typtbl.add(t as usize),
+        end: end,
+    };
+
+    t = sleb128_decode(&mut tb);
+
+    return t == IDL_CON_opt;
+}
+
+// TODO: consider storing fixed args typtbl1...end2 in `rel` to use less stack.
+pub(crate) unsafe fn sub(
+    rel: &BitRel,
+    p: bool,
+    typtbl1: *mut *mut u8,
+    typtbl2: *mut *mut u8,
+    end1: *mut u8,
+    end2: *mut u8,
+    t1: i32,
+    t2: i32,
+) -> bool {
+    if t1 >= 0 && t2 >= 0 {
+        let t1 = t1 as usize;
+        let t2 = t2 as usize;
+        if rel.visited(p, t1, t2) {
+            // visited? (bit 0)
+            // return assumed or determined result
+            return rel.related(p, t1, t2);
+        };
+        // cache and continue
+        rel.visit(p, t1, t2); // mark visited
+        rel.assume(p, t1, t2); // assume t1 <:/:> t2 true
+    };
+
+    /* primitives reflexive */
+    if is_primitive_type(CompatibilityMode::PureCandid, t1)
+        && is_primitive_type(CompatibilityMode::PureCandid, t2)
+        && t1 == t2
+    {
+        return true;
+    }
+
+    // unfold t1, if necessary
+    let mut tb1 = Buf {
+        ptr: if t1 < 0 {
+            end1
+        } else {
+            *typtbl1.add(t1 as usize)
+        },
+        end: end1,
+    };
+
+    let u1 = if t1 >= 0 {
+        sleb128_decode(&mut tb1)
+    } else {
+        t1
+    };
+
+    // unfold t2, if necessary
+    let mut tb2 = Buf {
+        ptr: if t2 < 0 {
+            end2
+        } else {
+            *typtbl2.add(t2 as usize)
+        },
+        end: end2,
+    };
+
+    let u2 = if t2 >= 0 {
+        sleb128_decode(&mut tb2)
+    } else {
+        t2
+    };
+
+    // NB we use a trivial labelled loop so we can factor out the common failure continuation.
+    // exit either via 'return true' or 'break 'return_false' to memoize the negative result
+    'return_false: loop {
+        match (u1, u2) {
+            (_, IDL_CON_alias) | (IDL_CON_alias, _) => {
+                let t = if u1 == IDL_CON_alias { t1 } else { t2 };
+                IdlError::new(IdlErrorCode::UnexpectedAlias, core::ptr::null())
+                    .in_type(t as u32)
+                    .trap()
+            }
+            (_, IDL_PRIM_reserved)
+            | (IDL_PRIM_empty, _)
+            | (IDL_PRIM_nat, IDL_PRIM_int)
+            | (_, IDL_CON_opt) => return true, // apparently, this is admissable
+            (IDL_CON_vec, IDL_CON_vec) => {
+                let t11 = sleb128_decode(&mut tb1);
+                let t21 = sleb128_decode(&mut tb2);
+                if sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
+                    return true;
+                } else {
+                    break 'return_false;
+                }
+            }
+            (IDL_CON_func, IDL_CON_func) => {
+                // contra in domain
+                let in1 = leb128_decode(&mut tb1);
+                let mut in2 = leb128_decode(&mut tb2);
+                for _ in 0..in1 {
+                    let t11 = sleb128_decode(&mut tb1);
+                    if in2 == 0 {
+                        if !is_null_opt_reserved(typtbl1, end1, t11) {
+                            break 'return_false;
+                        }
+                    } else {
+                        let t21 = sleb128_decode(&mut tb2);
+                        in2 -= 1;
+                        // NB: invert p and args!
+                        if !sub(rel, !p, typtbl2, typtbl1, end2, end1, t21, t11) {
+                            break 'return_false;
+                        }
+                    }
+                }
+                while in2 > 0 {
+                    let _ = sleb128_decode(&mut tb2);
+                    in2 -= 1;
+                }
+                // co in range
+                let mut out1 = leb128_decode(&mut tb1);
+                let out2 = leb128_decode(&mut tb2);
+                for _ in 0..out2 {
+                    let t21 = sleb128_decode(&mut tb2);
+                    if out1 == 0 {
+                        if !is_null_opt_reserved(typtbl2, end2, t21) {
+                            break 'return_false;
+                        }
+                    } else {
+                        let t11 = sleb128_decode(&mut tb1);
+                        out1 -= 1;
+                        if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
+                            break 'return_false;
+                        }
+                    }
+                }
+                while out1 > 0 {
+                    let _ = sleb128_decode(&mut tb1);
+                    out1 -= 1;
+                }
+                // check annotations (that we care about)
+                // TODO: more generally, we would check equality of 256-bit bit-vectors,
+                // but validity ensures each entry is 1, 2 or 3 (for now)
+                // c.f. https://github.com/dfinity/candid/issues/318
+                let mut a11 = false;
+                let mut a12 = false;
+                let mut a13 = false;
+                for _ in 0..leb128_decode(&mut tb1) {
+                    match read_byte(&mut tb1) {
+                        1 => a11 = true,
+                        2 => a12 = true,
+                        3 => a13 = true,
+                        _ => {}
+                    }
+                }
+                let mut a21 = false;
+                let mut a22 = false;
+                let mut a23 = false;
+                for _ in 0..leb128_decode(&mut tb2) {
+                    match read_byte(&mut tb2) {
+                        1 => a21 = true,
+                        2 => a22 = true,
+                        3 => a23 = true,
+                        _ => {}
+                    }
+                }
+                if (a11 == a21) && (a12 == a22) && (a13 == a23) {
+                    return true;
+                } else {
+                    break 'return_false;
+                }
+            }
+            (IDL_CON_record, IDL_CON_record) => {
+                let mut n1 = leb128_decode(&mut tb1);
+                let n2 = leb128_decode(&mut tb2);
+                // next field of t1 not below the current field of t2, if any
+                let mut field1: Option<(u32, i32)> = None;
+                for _ in 0..n2 {
+                    let tag2 = leb128_decode(&mut tb2);
+                    let t21 = sleb128_decode(&mut tb2);
+                    // skip the fields of t1 that are absent in t2
+                    while n1 > 0 && field1.map_or(true, |(tag1, _)| tag1 < tag2) {
+                        field1 = Some((leb128_decode(&mut tb1), sleb128_decode(&mut tb1)));
+                        n1 -= 1;
+                    }
+                    match field1 {
+                        Some((tag1, t11)) if tag1 == tag2 => {
+                            if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
+                                break 'return_false;
+                            }
+                        }
+                        _ => {
+                            if !is_null_opt_reserved(typtbl2, end2, t21) {
+                                // missing, non_opt field
+                                break 'return_false;
+                            }
+                        }
+                    }
+                }
+                return true;
+            }
+            (IDL_CON_variant, IDL_CON_variant) => {
+                let n1 = leb128_decode(&mut tb1);
+                let mut n2 = leb128_decode(&mut tb2);
+                for _ in 0..n1 {
+                    if n2 == 0 {
+                        break 'return_false;
+                    };
+                    let tag1 = leb128_decode(&mut tb1);
+                    let t11 = sleb128_decode(&mut tb1);
+                    let mut tag2: u32;
+                    let mut t21: i32;
+                    loop {
+                        tag2 = leb128_decode(&mut tb2);
+                        t21 = sleb128_decode(&mut tb2);
+                        n2 -= 1;
+                        if !(tag2 < tag1 && n2 > 0) {
+                            break;
+                        }
+                    }
+                    if tag1 != tag2 {
+                        break 'return_false;
+                    };
+                    if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
+                        break 'return_false;
+                    }
+                }
+                return true;
+            }
+            (IDL_CON_service, IDL_CON_service) => {
+                let mut n1 = leb128_decode(&mut tb1);
+                let n2 = leb128_decode(&mut tb2);
+                for _ in 0..n2 {
+                    if n1 == 0 {
+                        break 'return_false;
+                    };
+                    let (len2, p2) = leb128_decode_ptr(&mut tb2);
+                    Buf::advance(&mut tb2, len2 as usize);
+                    let t21 = sleb128_decode(&mut tb2);
+                    let mut len1: u32;
+                    let mut p1: *mut u8;
+                    let mut t11: i32;
+                    let mut cmp: i32;
+                    loop {
+                        (len1, p1) = leb128_decode_ptr(&mut tb1);
+                        Buf::advance(&mut tb1, len1 as usize);
+                        t11 = sleb128_decode(&mut tb1);
+                        n1 -= 1;
+                        cmp = utf8_cmp(len1 as usize, p1, len2 as usize, p2);
+                        if cmp < 0 && n1 > 0 {
+                            continue;
+                        };
+                        break;
+                    }
+                    if cmp != 0 {
+                        break 'return_false;
+                    };
+                    if !sub(rel, p, typtbl1, typtbl2, end1, end2, t11, t21) {
+                        break 'return_false;
+                    }
+                }
+                return true;
+            }
+            // default
+            (_, _) => {
+                break 'return_false;
+            }
+        }
+    }
+    // remember negative result ...
+    if t1 >= 0 && t2 >= 0 {
+        rel.disprove(p, t1 as usize, t2 as usize);
+    }
+    // .. only then return false
+    return false;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_sub_buf_words(typtbl_size1: usize, typtbl_size2: usize) -> usize {
+    return BitRel::words(typtbl_size1, typtbl_size2);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_sub_buf_init(
+    rel_buf: *mut usize,
+    typtbl_size1: usize,
+    typtbl_size2: usize,
+) {
+    let rel = BitRel {
+        ptr: rel_buf,
+        end: rel_buf.add(idl_sub_buf_words(typtbl_size1, typtbl_size2) as usize),
+        size1: typtbl_size1,
+        size2: typtbl_size2,
+    };
+    rel.init();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn idl_sub(
+    rel_buf: *mut usize, // a buffer with at least 2 * typtbl_size1 * typtbl_size2 bits
+    typtbl1: *mut *mut u8,
+    typtbl2: *mut *mut u8,
+    typtbl_end1: *mut u8,
+    typtbl_end2: *mut u8,
+    typtbl_size1: usize,
+    typtbl_size2: usize,
+    t1: i32,
+    t2: i32,
+) -> bool {
+    debug_assert!(rel_buf != (0 as *mut usize));
+    debug_assert!(typtbl1 != (0 as *mut *mut u8));
+    debug_assert!(typtbl2 != (0 as *mut *mut u8));
+    debug_assert!(typtbl_end1 != (0 as *mut u8));
+    debug_assert!(typtbl_end2 != (0 as *mut u8));
+
+    let rel = BitRel {
+        ptr: rel_buf,
+        end: rel_buf.add(idl_sub_buf_words(typtbl_size1, typtbl_size2) as usize),
+        size1: typtbl_size1,
+        size2: typtbl_size2,
+    };
+    debug_assert!(t1 < (typtbl_size1 as i32) && t2 < (typtbl_size2 as i32));
+    return sub(
+        &rel,
+        true,
+        typtbl1,
+        typtbl2,
+        typtbl_end1,
+        typtbl_end2,
+        t1,
+        t2,
+    );
+}
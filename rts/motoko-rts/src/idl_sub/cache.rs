@@ -0,0 +1,168 @@
+//! Memoization of Candid subtype checks across calls.
+//!
+//! The subtype checks during the deserialization of a Candid message are memoized in a
+//! `BitRel` over the type table of the message and the static type table of the program
+//! (see Note [Candid subtype checks] in the compiler). Instead of a fresh table per message,
+//! the tables are kept in a bounded cache, such that repeated calls with the same message
+//! types reuse the results of prior calls.
+//!
+//! The cache is a heap array of `CACHE_ENTRIES` slots, referenced by a GC root. A message
+//! type table is mapped to a slot by a hash of the two type tables, replacing a previous
+//! entry of the same slot. An entry is a blob of the layout:
+//!
+//! ```text
+//! ╔══════╤═══════╤═══════╤════════════╤═══════════════════════╤═══════════════╗
+//! ║ hash │ size1 │ size2 │ length (n) │ message type table    │ BitRel words  ║
+//! ║      │       │       │            │ (n bytes, word-padded)│               ║
+//! ╚══════╧═══════╧═══════╧════════════╧═══════════════════════╧═══════════════╝
+//! ```
+//!
+//! The copy of the message type table rules out hash collisions. The static type table does
+//! not need to be compared, as the cache does not survive upgrades.
+//!
+//! Within a message, the subtype checks assume pairs to be related while checking them
+//! (co-inductively). When such a check fails, pairs that have been proven on the basis of the
+//! failed assumption remain set. Therefore, only definite results are carried over to later
+//! messages: Disproven pairs are always definite. Proven pairs are only definite if no pair
+//! of the table has been disproven, and are otherwise cleared on the next lookup.
+//!
+//! NB: The returned `BitRel` words are only valid during the current message, as a GC
+//! increment may move the entry.
+
+use super::{idl_sub_buf_init, idl_sub_buf_words};
+use crate::barriers::{allocation_barrier, write_with_barrier};
+use crate::memory::{alloc_array, alloc_blob, Memory};
+use crate::types::{Bytes, Value, Words, NULL_POINTER, TAG_ARRAY_M, TAG_BLOB_B};
+
+use core::ptr::addr_of_mut;
+use motoko_rts_macros::ic_mem_fn;
+
+/// Number of cached message type tables.
+const CACHE_ENTRIES: usize = 32;
+
+/// Entries above this size are not cached.
+const MAX_ENTRY_SIZE: Bytes<usize> = Bytes(64 * 1024);
+
+const HEADER_WORDS: usize = 4;
+
+/// Array of the cache entries, or null if not yet used.
+/// Reset on upgrades, with the static type table.
+static mut IDL_SUB_CACHE: Value = NULL_POINTER;
+
+/// GC root pointer required for GC marking and updating.
+#[cfg(feature = "ic")]
+pub(crate) unsafe fn idl_sub_cache_location() -> *mut Value {
+    addr_of_mut!(IDL_SUB_CACHE)
+}
+
+/// Bytes of a type table, from its first entry up to its end.
+unsafe fn table_bytes<'a>(typtbl: *mut *mut u8, typtbl_end: *mut u8, size: usize) -> &'a [u8] {
+    if size == 0 {
+        return &[];
+    }
+    let start = *typtbl;
+    core::slice::from_raw_parts(start, typtbl_end as usize - start as usize)
+}
+
+/// FNV-1a
+fn hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+unsafe fn entry_matches(entry: Value, key: u64, size1: usize, size2: usize, table1: &[u8]) -> bool {
+    if entry == NULL_POINTER {
+        return false;
+    }
+    let header = entry.as_blob().payload_const() as *const usize;
+    *header as u64 == key
+        && *header.add(1) == size1
+        && *header.add(2) == size2
+        && *header.add(3) == table1.len()
+        && core::slice::from_raw_parts(header.add(HEADER_WORDS) as *const u8, table1.len())
+            == table1
+}
+
+unsafe fn rel_buf(entry: Value, table1_length: usize) -> *mut usize {
+    let header = entry.as_blob_mut().payload_addr() as *mut usize;
+    header.add(HEADER_WORDS + Bytes(table1_length).to_words().as_usize())
+}
+
+/// Bits of the `BitRel` pairs: The lower bit marks a visited pair, the upper bit a
+/// disproven pair (see `bitrel.rs`).
+const VISITED_BITS: usize = usize::MAX / 3;
+const DISPROVEN_BITS: usize = VISITED_BITS << 1;
+
+/// Clears the proven pairs of a cached `BitRel` if any of its pairs has been disproven, as
+/// these may rest on a failed assumption. The disproven pairs are retained.
+unsafe fn retain_definite_results(words: *mut usize, length: usize) {
+    let words = core::slice::from_raw_parts_mut(words, length);
+    if words.iter().all(|word| word & DISPROVEN_BITS == 0) {
+        return;
+    }
+    for word in words.iter_mut() {
+        let disproven = (*word & DISPROVEN_BITS) >> 1;
+        *word &= disproven | (disproven << 1);
+    }
+}
+
+/// Returns initialized or previously used `BitRel` words for the subtype checks between
+/// the message type table (`typtbl1`) and the static type table (`typtbl2`). Large tables
+/// get fresh words that are not cached.
+#[ic_mem_fn]
+pub unsafe fn idl_sub_cache_lookup<M: Memory>(
+    mem: &mut M,
+    typtbl1: *mut *mut u8,
+    typtbl_end1: *mut u8,
+    typtbl_size1: usize,
+    typtbl2: *mut *mut u8,
+    typtbl_end2: *mut u8,
+    typtbl_size2: usize,
+) -> *mut usize {
+    let table1 = table_bytes(typtbl1, typtbl_end1, typtbl_size1);
+    let table2 = table_bytes(typtbl2, typtbl_end2, typtbl_size2);
+    let key = hash(hash(0xcbf2_9ce4_8422_2325, table1), table2);
+
+    let rel_words = idl_sub_buf_words(typtbl_size1, typtbl_size2);
+    let size =
+        Words(HEADER_WORDS + rel_words).to_bytes() + Bytes(table1.len()).to_words().to_bytes();
+    if size > MAX_ENTRY_SIZE {
+        // Temporary, collected by the next GC run
+        let blob = alloc_blob(mem, TAG_BLOB_B, Words(rel_words).to_bytes());
+        allocation_barrier(blob);
+        let words = blob.as_blob_mut().payload_addr() as *mut usize;
+        idl_sub_buf_init(words, typtbl_size1, typtbl_size2);
+        return words;
+    }
+
+    let location = addr_of_mut!(IDL_SUB_CACHE);
+    if *location == NULL_POINTER {
+        let cache = alloc_array(mem, TAG_ARRAY_M, CACHE_ENTRIES);
+        for index in 0..CACHE_ENTRIES {
+            cache.as_array().initialize(index, NULL_POINTER, mem);
+        }
+        write_with_barrier(mem, location, allocation_barrier(cache));
+    }
+    let cache = (*location).as_array();
+    let index = (key % CACHE_ENTRIES as u64) as usize;
+    let entry = cache.get(index);
+    if entry_matches(entry, key, typtbl_size1, typtbl_size2, table1) {
+        let words = rel_buf(entry, table1.len());
+        retain_definite_results(words, rel_words);
+        return words;
+    }
+
+    let entry = alloc_blob(mem, TAG_BLOB_B, size);
+    let header = entry.as_blob_mut().payload_addr() as *mut usize;
+    *header = key as usize;
+    *header.add(1) = typtbl_size1;
+    *header.add(2) = typtbl_size2;
+    *header.add(3) = table1.len();
+    let copy = header.add(HEADER_WORDS) as *mut u8;
+    core::ptr::copy_nonoverlapping(table1.as_ptr(), copy, table1.len());
+    let words = rel_buf(entry, table1.len());
+    idl_sub_buf_init(words, typtbl_size1, typtbl_size2);
+    cache.set(index, allocation_barrier(entry), mem);
+    words
+}
@@ -0,0 +1,97 @@
+//! Candid type table constants and readers, shared by the decoder (`idl.rs`) and the
+//! subtype check (`idl_sub.rs`).
+
+#![allow(non_upper_case_globals)]
+// Without the IC, only the subtype check is compiled, for testing.
+#![cfg_attr(not(feature = "ic"), allow(dead_code))]
+
+use crate::buf::Buf;
+
+use motoko_rts_macros::enhanced_orthogonal_persistence;
+
+//
+// IDL constants
+//
+
+pub(crate) const IDL_PRIM_null: i32 = -1;
+pub(crate) const IDL_PRIM_bool: i32 = -2;
+pub(crate) const IDL_PRIM_nat: i32 = -3;
+pub(crate) const IDL_PRIM_int: i32 = -4;
+pub(crate) const IDL_PRIM_nat8: i32 = -5;
+pub(crate) const IDL_PRIM_nat16: i32 = -6;
+pub(crate) const IDL_PRIM_nat32: i32 = -7;
+pub(crate) const IDL_PRIM_nat64: i32 = -8;
+pub(crate) const IDL_PRIM_int8: i32 = -9;
+pub(crate) const IDL_PRIM_int16: i32 = -10;
+pub(crate) const IDL_PRIM_int32: i32 = -11;
+pub(crate) const IDL_PRIM_int64: i32 = -12;
+pub(crate) const IDL_PRIM_float32: i32 = -13;
+pub(crate) const IDL_PRIM_float64: i32 = -14;
+pub(crate) const IDL_PRIM_text: i32 = -15;
+pub(crate) const IDL_PRIM_reserved: i32 = -16;
+pub(crate) const IDL_PRIM_empty: i32 = -17;
+
+pub(crate) const IDL_CON_opt: i32 = -18;
+pub(crate) const IDL_CON_vec: i32 = -19;
+pub(crate) const IDL_CON_record: i32 = -20;
+pub(crate) const IDL_CON_variant: i32 = -21;
+pub(crate) const IDL_CON_func: i32 = -22;
+pub(crate) const IDL_CON_service: i32 = -23;
+
+pub(crate) const IDL_REF_principal: i32 = -24;
+
+// Extended Candid only
+pub(crate) const IDL_EXT_region: i32 = -128;
+
+// Extended Candid only
+pub(crate) const IDL_CON_alias: i32 = 1;
+
+pub(crate) const IDL_PRIM_lowest: i32 = -17;
+
+// Only used for memory compatiblity checks for orthogonal persistence.
+#[enhanced_orthogonal_persistence]
+pub(crate) const IDL_EXT_blob: i32 = -129;
+#[enhanced_orthogonal_persistence]
+pub(crate) const IDL_EXT_tuple: i32 = -130;
+
+pub(crate) unsafe fn leb128_decode(buf: *mut Buf) -> u32 {
+    let value = crate::leb128::leb128_decode(buf);
+    assert!(value <= u32::MAX as usize);
+    value as u32
+}
+
+pub(crate) unsafe fn sleb128_decode(buf: *mut Buf) -> i32 {
+    let value = crate::leb128::sleb128_decode(buf);
+    assert!(value >= i32::MIN as isize && value <= i32::MAX as isize);
+    value as i32
+}
+
+pub unsafe fn leb128_decode_ptr(buf: *mut Buf) -> (u32, *mut u8) {
+    (leb128_decode(buf), (*buf).ptr)
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub(crate) enum CompatibilityMode {
+    /// Pure Candid used for IC message payloads.
+    PureCandid,
+    /// Candidish stabilization (old stabilization format).
+    CandidishStabilization,
+    /// Memory compatibility of orthogonal persistence (with or without graph copying).
+    #[cfg(feature = "enhanced_orthogonal_persistence")]
+    MemoryCompatibility,
+}
+
+pub(crate) unsafe fn is_primitive_type(mode: CompatibilityMode, ty: i32) -> bool {
+    if ty >= 0 {
+        return false;
+    }
+    if ty >= IDL_PRIM_lowest || ty == IDL_REF_principal {
+        return true;
+    }
+    match mode {
+        CompatibilityMode::PureCandid => false,
+        CompatibilityMode::CandidishStabilization => ty == IDL_EXT_region,
+        #[cfg(feature = "enhanced_orthogonal_persistence")]
+        CompatibilityMode::MemoryCompatibility => ty == IDL_EXT_region || ty == IDL_EXT_blob,
+    }
+}
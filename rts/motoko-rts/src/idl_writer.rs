@@ -0,0 +1,145 @@
+//! Growable output buffer for Candid messages that are produced by the RTS,
+//! e.g. diagnostic reports that are returned to Motoko code as a `Blob` to be
+//! decoded with `from_candid`.
+//!
+//! The buffer is backed by a heap blob that is reallocated on demand. Intermediate
+//! blobs are temporary and are not retained, such that no allocation barrier is needed
+//! for them. Only the final blob returned by `finish` is subject to the allocation barrier.
+//!
+//! NB: The writer holds a raw pointer that is not visible to the GC. It must therefore
+//! only be used in local variables and not across GC increments.
+
+use crate::barriers::allocation_barrier;
+use crate::mem_utils::memcpy_bytes;
+use crate::memory::{alloc_blob, Memory};
+use crate::types::{Bytes, Value, TAG_BLOB_B};
+
+const INITIAL_CAPACITY: usize = 64;
+
+/// Candid field name hash, c.f. the Candid specification.
+pub const fn idl_hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut hash: u32 = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        hash = hash.wrapping_mul(223).wrapping_add(bytes[index] as u32);
+        index += 1;
+    }
+    hash
+}
+
+pub struct IdlWriter {
+    blob: Value,
+    length: usize,
+}
+
+impl IdlWriter {
+    /// Start a new Candid message, including the magic `DIDL` header.
+    pub unsafe fn new<M: Memory>(mem: &mut M) -> IdlWriter {
+        let mut writer = IdlWriter {
+            blob: alloc_blob(mem, TAG_BLOB_B, Bytes(INITIAL_CAPACITY)),
+            length: 0,
+        };
+        writer.write_bytes(mem, b"DIDL");
+        writer
+    }
+
+    unsafe fn capacity(&self) -> usize {
+        self.blob.as_blob().len().as_usize()
+    }
+
+    unsafe fn reserve<M: Memory>(&mut self, mem: &mut M, additional: usize) {
+        let required = self.length + additional;
+        if required <= self.capacity() {
+            return;
+        }
+        let new_capacity = core::cmp::max(2 * self.capacity(), required);
+        let new_blob = alloc_blob(mem, TAG_BLOB_B, Bytes(new_capacity));
+        memcpy_bytes(
+            new_blob.as_blob_mut().payload_addr() as usize,
+            self.blob.as_blob().payload_const() as usize,
+            Bytes(self.length),
+        );
+        self.blob = new_blob;
+    }
+
+    /// Number of bytes written so far.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub unsafe fn write_bytes<M: Memory>(&mut self, mem: &mut M, bytes: &[u8]) {
+        self.reserve(mem, bytes.len());
+        let target = self.blob.as_blob_mut().payload_addr().add(self.length);
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), target, bytes.len());
+        self.length += bytes.len();
+    }
+
+    /// Append `n` bytes to be filled in by the caller, returning their address.
+    /// The address is invalidated by the next write.
+    pub unsafe fn reserve_bytes<M: Memory>(&mut self, mem: &mut M, n: usize) -> *mut u8 {
+        self.reserve(mem, n);
+        let target = self.blob.as_blob_mut().payload_addr().add(self.length);
+        self.length += n;
+        target
+    }
+
+    pub unsafe fn write_byte<M: Memory>(&mut self, mem: &mut M, byte: u8) {
+        self.write_bytes(mem, &[byte]);
+    }
+
+    pub unsafe fn write_bool<M: Memory>(&mut self, mem: &mut M, value: bool) {
+        self.write_byte(mem, value as u8);
+    }
+
+    pub unsafe fn write_u16<M: Memory>(&mut self, mem: &mut M, value: u16) {
+        self.write_bytes(mem, &value.to_le_bytes());
+    }
+
+    pub unsafe fn write_u32<M: Memory>(&mut self, mem: &mut M, value: u32) {
+        self.write_bytes(mem, &value.to_le_bytes());
+    }
+
+    pub unsafe fn write_u64<M: Memory>(&mut self, mem: &mut M, value: u64) {
+        self.write_bytes(mem, &value.to_le_bytes());
+    }
+
+    pub unsafe fn write_leb128<M: Memory>(&mut self, mem: &mut M, mut value: u64) {
+        loop {
+            let byte = (value & 0b0111_1111) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.write_byte(mem, byte);
+                break;
+            }
+            self.write_byte(mem, byte | 0b1000_0000);
+        }
+    }
+
+    pub unsafe fn write_sleb128<M: Memory>(&mut self, mem: &mut M, mut value: i64) {
+        loop {
+            let byte = (value & 0b0111_1111) as u8;
+            value >>= 7;
+            if (value == 0 && byte & 0b0100_0000 == 0)
+                || (value == -1 && byte & 0b0100_0000 == 0b0100_0000)
+            {
+                self.write_byte(mem, byte);
+                break;
+            }
+            self.write_byte(mem, byte | 0b1000_0000);
+        }
+    }
+
+    /// Length-prefixed byte sequence, as used for `text` and `blob`.
+    pub unsafe fn write_blob<M: Memory>(&mut self, mem: &mut M, bytes: &[u8]) {
+        self.write_leb128(mem, bytes.len() as u64);
+        self.write_bytes(mem, bytes);
+    }
+
+    /// Complete the message and return it as a blob.
+    pub unsafe fn finish(self) -> Value {
+        self.blob.as_blob_mut().shrink(Bytes(self.length));
+        allocation_barrier(self.blob);
+        self.blob
+    }
+}
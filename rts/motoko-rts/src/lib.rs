@@ -31,6 +31,8 @@ pub mod bitrel;
 mod blob_iter;
 pub mod buf;
 mod char;
+#[enhanced_orthogonal_persistence]
+pub mod compatibility;
 pub mod constants;
 pub mod continuation_table;
 #[cfg(feature = "ic")]
@@ -38,6 +40,10 @@ mod float;
 pub mod gc;
 #[cfg(feature = "ic")]
 mod idl;
+pub mod idl_error;
+pub mod idl_sub;
+mod idl_types;
+pub mod idl_writer;
 pub mod leb128;
 mod libc_declarations;
 pub mod mem_utils;
@@ -58,6 +64,7 @@ pub mod text;
 pub mod text_iter;
 mod tommath_bindings;
 pub mod types;
+pub mod upgrade_history;
 pub mod utf8;
 mod visitor;
 
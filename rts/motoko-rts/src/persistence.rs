@@ -2,16 +2,21 @@
 //!
 //! Persistent metadata table, located at 6MB, in the static partition space.
 
-pub mod compatibility;
-
 use motoko_rts_macros::ic_mem_fn;
 
 use crate::{
-    barriers::write_with_barrier,
+    barriers::{allocation_barrier, write_with_barrier},
+    compatibility::{
+        memory_compatible,
+        offline::{EncodedTypeDescriptor, TypeDescriptorBytes},
+        report::CompatibilityReport,
+        TypeDescriptor,
+    },
     constants::{KB, MB},
     gc::incremental::{partitioned_heap::allocate_initial_memory, State},
-    memory::Memory,
-    persistence::compatibility::memory_compatible,
+    mem_utils::memcpy_bytes,
+    memory::{alloc_blob, Memory},
+    print::WriteBuf,
     region::{
         LEGACY_VERSION_NO_STABLE_MEMORY, LEGACY_VERSION_REGIONS, LEGACY_VERSION_SOME_STABLE_MEMORY,
         VERSION_GRAPH_COPY_NO_REGIONS, VERSION_GRAPH_COPY_REGIONS, VERSION_STABLE_HEAP_NO_REGIONS,
@@ -19,19 +24,25 @@ use crate::{
     },
     rts_trap_with,
     stable_mem::read_persistence_version,
-    types::{Bytes, Value, TAG_BLOB_B},
+    text::text_of_str,
+    types::{Bytes, Value, NULL_POINTER, TAG_BLOB_B},
+    upgrade_history::{
+        ic::{measure_compatibility_check, start_upgrade},
+        UpgradeHistory,
+    },
 };
 
-use self::compatibility::TypeDescriptor;
+use core::fmt::Write;
 
 const FINGERPRINT: [char; 32] = [
     'M', 'O', 'T', 'O', 'K', 'O', ' ', 'O', 'R', 'T', 'H', 'O', 'G', 'O', 'N', 'A', 'L', ' ', 'P',
     'E', 'R', 'S', 'I', 'S', 'T', 'E', 'N', 'C', 'E', ' ', '6', '4',
 ];
-const VERSION: usize = 1;
+/// Version of the persistent metadata layout. Older versions are migrated in place, see `MIGRATIONS`.
+const VERSION: usize = 2;
 /// The `Value` representation in the default-initialized Wasm memory.
 /// The GC ignores this value since it is a scalar representation.
-const DEFAULT_VALUE: Value = Value::from_scalar(0);
+pub(crate) const DEFAULT_VALUE: Value = Value::from_scalar(0);
 
 /// The persistent metadata stored at the defined location `METADATA_ADDRESS` in memory.
 /// Use a long-term representation by relying on C layout.
@@ -53,6 +64,13 @@ struct PersistentMetadata {
     incremental_gc_state: State,
     /// Upgrade performance statistics: Total number of instructions consumed by the last upgrade.
     upgrade_instructions: u64,
+    /// Blob with the names of the record fields and variant cases of the stable type, naming
+    /// removed fields in the report of a memory-incompatible upgrade. Appended to the layout,
+    /// such that it is `DEFAULT_VALUE` in the zero-initialized memory of earlier versions.
+    /// Constitutes a GC root and requires pointer forwarding.
+    stable_field_names: Value,
+    /// Statistics of the recent upgrades. Added in version 2.
+    upgrade_history: UpgradeHistory,
 }
 
 /// Location of the persistent metadata. Prereserved and fixed forever.
@@ -64,6 +82,22 @@ pub const HEAP_START: usize = METADATA_ADDRESS + METADATA_RESERVE;
 
 const _: () = assert!(core::mem::size_of::<PersistentMetadata>() <= METADATA_RESERVE);
 
+/// In-place migration of the persistent metadata from a version to its next version.
+/// The migration can only access the metadata, as the heap is not yet usable, and needs to
+/// keep the metadata within the `METADATA_RESERVE`. Added fields need to be initialized by the
+/// migration.
+type MetadataMigration = unsafe fn(*mut PersistentMetadata);
+
+/// Chain of the metadata migrations, where the migration at index `i` upgrades the version
+/// `i + 1` to the version `i + 2`. To be extended on every increase of `VERSION`, such that
+/// a metadata layout change does not require a graph copy of the heap.
+const MIGRATIONS: [MetadataMigration; VERSION - 1] = [add_upgrade_history];
+
+/// Version 2: Adds the upgrade history.
+unsafe fn add_upgrade_history(metadata: *mut PersistentMetadata) {
+    (*metadata).upgrade_history = UpgradeHistory::default();
+}
+
 impl PersistentMetadata {
     fn get() -> *mut Self {
         METADATA_ADDRESS as *mut Self
@@ -81,14 +115,20 @@ impl PersistentMetadata {
         initialized
     }
 
-    unsafe fn check_version(self: *const Self) {
-        if (*self).version != VERSION {
+    /// Migrates the metadata of an older version to the current `VERSION`.
+    unsafe fn upgrade_version(self: *mut Self) {
+        let version = (*self).version;
+        if version > VERSION {
             panic!(
                 "Incompatible persistent memory version: {} instead of {}.",
-                (*self).version,
-                VERSION
+                version, VERSION
             );
         }
+        for migration in &MIGRATIONS[version - 1..] {
+            migration(self);
+            (*self).version += 1;
+        }
+        debug_assert_eq!((*self).version, VERSION);
     }
 
     unsafe fn initialize<M: Memory>(self: *mut Self) {
@@ -99,6 +139,8 @@ impl PersistentMetadata {
         (*self).stable_type = TypeDescriptor::default();
         (*self).incremental_gc_state = IncrementalGC::<M>::initial_gc_state(HEAP_START);
         (*self).upgrade_instructions = 0;
+        (*self).stable_field_names = DEFAULT_VALUE;
+        (*self).upgrade_history = UpgradeHistory::default();
     }
 }
 
@@ -109,7 +151,7 @@ pub unsafe fn initialize_memory<M: Memory>() {
     allocate_initial_memory(Bytes(HEAP_START));
     let metadata = PersistentMetadata::get();
     if use_enhanced_orthogonal_persistence() && metadata.is_initialized() {
-        metadata.check_version();
+        metadata.upgrade_version();
     } else {
         metadata.initialize::<M>();
     }
@@ -144,6 +186,7 @@ pub unsafe fn save_stable_actor<M: Memory>(mem: &mut M, actor: Value) {
     (*metadata).stable_type.assert_initialized();
     let location = &mut (*metadata).stable_actor as *mut Value;
     write_with_barrier(mem, location, actor);
+    start_upgrade();
 }
 
 /// Free the stable actor sub-record after a completed upgrade.
@@ -189,21 +232,131 @@ pub unsafe extern "C" fn contains_field(actor: Value, field_hash: usize) -> bool
 /// The type is stored in the persistent metadata memory for later retrieval on canister upgrades.
 /// On an upgrade, the memory compatibility between the new and existing stable type is checked.
 /// The `new_type` value points to a blob encoding the new stable actor type.
+/// The `new_field_names` name the record fields and variant cases of the new type in the trap
+/// message of an incompatible upgrade.
 #[ic_mem_fn]
 pub unsafe fn register_stable_type<M: Memory>(
     mem: &mut M,
     new_candid_data: Value,
     new_type_offsets: Value,
+    new_field_names: Value,
 ) {
     assert_eq!(new_candid_data.tag(), TAG_BLOB_B);
     assert_eq!(new_type_offsets.tag(), TAG_BLOB_B);
+    assert_eq!(new_field_names.tag(), TAG_BLOB_B);
     let mut new_type = TypeDescriptor::new(new_candid_data, new_type_offsets);
     let metadata = PersistentMetadata::get();
     let old_type = &mut (*metadata).stable_type;
-    if !old_type.is_default() && !memory_compatible(mem, old_type, &mut new_type) {
-        rts_trap_with("Memory-incompatible program upgrade");
+    let old_field_names = (*metadata).stable_field_names.forward_if_possible();
+    let mut report = CompatibilityReport::new();
+    if !old_type.is_default()
+        && !measure_compatibility_check(|| {
+            memory_compatible(
+                mem,
+                old_type,
+                &mut new_type,
+                old_field_names,
+                new_field_names,
+                &mut report,
+            )
+        })
+    {
+        report.trap();
     }
     (*metadata).stable_type.assign(mem, &new_type);
+    let location = &mut (*metadata).stable_field_names as *mut Value;
+    write_with_barrier(mem, location, new_field_names.forward_if_possible());
+}
+
+/// Dry run of the compatibility check of `register_stable_type`, e.g. for a query before an
+/// upgrade: Checks whether the new stable type is memory-compatible with the registered stable
+/// type, without registering the new type.
+/// The new type is given by its type descriptor in the single-blob encoding (see
+/// `EncodedTypeDescriptor`), as emitted by `moc --stable-type-descriptor` for the new program
+/// version.
+/// Returns null if an upgrade to the new type is possible, otherwise a text with the reason why
+/// the upgrade would trap.
+/// Called by the `rts_check_stable_type_compatibility` prim. Unlike the descriptors generated by
+/// the compiler, the new type descriptor is supplied by the user and is therefore validated first.
+#[ic_mem_fn]
+pub unsafe fn check_stable_type_compatibility<M: Memory>(
+    mem: &mut M,
+    new_type_descriptor: Value,
+) -> Value {
+    assert_eq!(new_type_descriptor.tag(), TAG_BLOB_B);
+    let encoded =
+        match EncodedTypeDescriptor::decode(blob_bytes(new_type_descriptor)).and_then(|encoded| {
+            TypeDescriptorBytes::new(encoded.candid_data, encoded.type_offsets)?;
+            Ok(encoded)
+        }) {
+            Ok(encoded) => encoded,
+            Err(error) => {
+                let mut buffer = [0u8; 64];
+                let mut out = WriteBuf::new(&mut buffer);
+                let _ = write!(out, "Invalid type descriptor: {}", error);
+                let length = out.length();
+                return text_of_str(mem, core::str::from_utf8(&buffer[..length]).unwrap());
+            }
+        };
+    let new_candid_data = blob_of_bytes(mem, encoded.candid_data);
+    let new_type_offsets = blob_of_bytes(mem, encoded.type_offsets);
+    let new_field_names = blob_of_bytes(mem, encoded.field_names);
+    let mut new_type = TypeDescriptor::new(new_candid_data, new_type_offsets);
+    let metadata = PersistentMetadata::get();
+    let old_type = &mut (*metadata).stable_type;
+    let old_field_names = (*metadata).stable_field_names.forward_if_possible();
+    let mut report = CompatibilityReport::new();
+    if old_type.is_default()
+        || memory_compatible(
+            mem,
+            old_type,
+            &mut new_type,
+            old_field_names,
+            new_field_names,
+            &mut report,
+        )
+    {
+        NULL_POINTER
+    } else {
+        report.to_text(mem)
+    }
+}
+
+/// The registered stable type in the single-blob encoding of `check_stable_type_compatibility`.
+/// Called by the `rts_stable_type_descriptor` prim.
+#[ic_mem_fn]
+pub unsafe fn get_stable_type_descriptor<M: Memory>(mem: &mut M) -> Value {
+    let metadata = PersistentMetadata::get();
+    let stable_type = &(*metadata).stable_type;
+    stable_type.assert_initialized();
+    let field_names = (*metadata).stable_field_names.forward_if_possible();
+    let encoded = EncodedTypeDescriptor {
+        candid_data: blob_bytes(stable_type.candid_data().forward_if_possible()),
+        type_offsets: blob_bytes(stable_type.type_offsets().forward_if_possible()),
+        field_names: if field_names == DEFAULT_VALUE {
+            &[]
+        } else {
+            blob_bytes(field_names)
+        },
+    }
+    .encode();
+    blob_of_bytes(mem, &encoded)
+}
+
+unsafe fn blob_bytes<'a>(blob: Value) -> &'a [u8] {
+    let blob = blob.as_blob();
+    core::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize())
+}
+
+unsafe fn blob_of_bytes<M: Memory>(mem: &mut M, bytes: &[u8]) -> Value {
+    let blob = alloc_blob(mem, TAG_BLOB_B, Bytes(bytes.len()));
+    let payload_addr = blob.as_blob_mut().payload_addr();
+    memcpy_bytes(
+        payload_addr as usize,
+        bytes.as_ptr() as usize,
+        Bytes(bytes.len()),
+    );
+    allocation_barrier(blob)
 }
 
 pub(crate) unsafe fn stable_type_descriptor() -> &'static mut TypeDescriptor {
@@ -211,11 +364,22 @@ pub(crate) unsafe fn stable_type_descriptor() -> &'static mut TypeDescriptor {
     &mut (*metadata).stable_type
 }
 
+/// GC root pointer required for GC marking and updating.
+pub(crate) unsafe fn stable_field_names_location() -> *mut Value {
+    let metadata = PersistentMetadata::get();
+    &mut (*metadata).stable_field_names as *mut Value
+}
+
 pub(crate) unsafe fn get_incremental_gc_state() -> &'static mut State {
     let metadata = PersistentMetadata::get();
     &mut (*metadata).incremental_gc_state
 }
 
+pub(crate) unsafe fn persistent_upgrade_history() -> &'static mut UpgradeHistory {
+    let metadata = PersistentMetadata::get();
+    &mut (*metadata).upgrade_history
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn get_upgrade_instructions() -> u64 {
     let metadata = PersistentMetadata::get();
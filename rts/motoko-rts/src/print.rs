@@ -71,6 +71,11 @@ impl<'a> WriteBuf<'a> {
         Self { buf, offset: 0 }
     }
 
+    /// Number of bytes written so far.
+    pub(crate) fn length(&self) -> usize {
+        self.offset
+    }
+
     pub(crate) fn reset(&mut self) {
         self.offset = 0;
     }
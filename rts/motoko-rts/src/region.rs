@@ -12,13 +12,15 @@ pub const LEGACY_VERSION_SOME_STABLE_MEMORY: usize = 1;
 pub const LEGACY_VERSION_REGIONS: usize = 2;
 
 // New versions, used with enhanced orthogonal persistence
-pub(crate) const VERSION_GRAPH_COPY_NO_REGIONS: usize = 3;
-pub(crate) const VERSION_GRAPH_COPY_REGIONS: usize = 4;
+pub(crate) const VERSION_GRAPH_COPY_NO_REGIONS: usize =
+    motoko_stable_format::metadata::VERSION_GRAPH_COPY_NO_REGIONS as usize;
+pub(crate) const VERSION_GRAPH_COPY_REGIONS: usize =
+    motoko_stable_format::metadata::VERSION_GRAPH_COPY_REGIONS as usize;
 pub(crate) const VERSION_STABLE_HEAP_NO_REGIONS: usize = 5;
 pub(crate) const VERSION_STABLE_HEAP_REGIONS: usize = 6;
 
 const _: () = assert!(meta_data::size::PAGE_IN_BYTES == crate::stable_mem::PAGE_SIZE);
-const _: () = assert!(meta_data::size::PAGES_IN_BLOCK <= u8::MAX as u32);
+const _: () = assert!(meta_data::size::MAX_PAGES_IN_BLOCK <= u8::MAX as u32);
 const _: () = assert!(meta_data::max::BLOCKS <= u16::MAX);
 const _: () = assert!(meta_data::max::REGIONS <= u64::MAX - 1);
 
@@ -67,6 +69,12 @@ pub(crate) static mut REGION_TOTAL_ALLOCATED_BLOCKS: u32 = 0;
 // Base offset for blocks
 pub(crate) static mut BLOCK_BASE: u64 = 0;
 
+// Block size as a power of two, mirrored from stable memory once the region layout is
+// initialized. Before, it describes the block size to use for a new region layout.
+// The shift and mask split region offsets into block rank and intra-block index.
+pub(crate) static mut REGION_BLOCK_SHIFT: u32 = meta_data::size::MAX_BLOCK_SHIFT;
+pub(crate) static mut REGION_BLOCK_MASK: u64 = meta_data::size::MAX_BLOCK_IN_BYTES - 1;
+
 // Scalar sentinel value recognized in the GC as "no root", i.e. (!`is_ptr()`).
 // Same design like `continuation_table::TABLE`.
 pub(crate) const NO_REGION: Value = Value::from_scalar(0);
@@ -74,6 +82,27 @@ pub(crate) const NO_REGION: Value = Value::from_scalar(0);
 // Region 0 -- classic API for stable memory, as a dedicated region.
 pub(crate) static mut REGION_0: Value = NO_REGION;
 
+// This impl encapsulates encoding of optional block IDs within a u16.
+// Used by the free block list to link released blocks.
+impl BlockId {
+    pub fn from_u16(id: u16) -> Option<Self> {
+        if id == 0 {
+            None
+        } else {
+            Some(BlockId(id - 1))
+        }
+    }
+    pub fn into_u16(opblock: Option<BlockId>) -> u16 {
+        match opblock {
+            None => 0,
+            Some(b) => {
+                debug_assert!(b.0 < meta_data::max::BLOCKS);
+                b.0 + 1
+            }
+        }
+    }
+}
+
 // This impl encapsulates encoding of optional region IDs within a u64.
 // Used by block-region table to encode the (optional) region ID of a block.
 impl RegionId {
@@ -123,7 +152,7 @@ impl RegionSizeInPages {
             None => 0,
             Some(s) => {
                 debug_assert!(
-                    s.0 <= meta_data::max::BLOCKS as u64 * meta_data::size::PAGES_IN_BLOCK as u64
+                    s.0 <= meta_data::max::BLOCKS as u64 * meta_data::size::pages_in_block() as u64
                 );
                 s.0 + 1
             }
@@ -188,21 +217,22 @@ impl RegionObject {
         let av = AccessVector::from_value(&(*self.0).vec_pages);
 
         // Which block (rank relative to this region)?
-        let block_rank = offset / meta_data::size::BLOCK_IN_BYTES;
+        let block_rank = offset >> meta_data::size::block_shift();
 
         // Where in that block?
-        let intra_block_index = offset % meta_data::size::BLOCK_IN_BYTES;
+        let intra_block_index = offset & meta_data::size::block_mask();
 
         // Where is that block located in stable memory (global rank)?
         let block_id = av.get_ith_block_id(block_rank as u32);
 
         // address of the byte to load from stable memory:
-        let offset =
-            BLOCK_BASE + block_id.0 as u64 * meta_data::size::BLOCK_IN_BYTES + intra_block_index;
+        let offset = BLOCK_BASE
+            + ((block_id.0 as u64) << meta_data::size::block_shift())
+            + intra_block_index;
         (
             offset,
             block_id,
-            meta_data::size::BLOCK_IN_BYTES - intra_block_index,
+            meta_data::size::block_in_bytes() - intra_block_index,
         )
     }
 
@@ -255,9 +285,34 @@ mod meta_data {
         pub const BLOCK_REGION_TABLE: u64 =
             super::max::BLOCKS as u64 * BLOCK_REGION_TABLE_ENTRY as u64;
 
-        pub const PAGES_IN_BLOCK: u32 = 128;
-        pub const PAGE_IN_BYTES: u64 = 1 << 16;
-        pub const BLOCK_IN_BYTES: u64 = PAGE_IN_BYTES * (PAGES_IN_BLOCK as u64);
+        /// Default and maximum block size. The block size of a region layout is fixed
+        /// when the layout is initialized, see `REGION_BLOCK_SHIFT`.
+        pub const MAX_PAGES_IN_BLOCK: u32 = 128;
+        pub const PAGE_SHIFT: u32 = 16;
+        pub const PAGE_IN_BYTES: u64 = 1 << PAGE_SHIFT;
+        pub const MAX_BLOCK_SHIFT: u32 = PAGE_SHIFT + MAX_PAGES_IN_BLOCK.trailing_zeros();
+        pub const MAX_BLOCK_IN_BYTES: u64 = PAGE_IN_BYTES * (MAX_PAGES_IN_BLOCK as u64);
+
+        // The default layout keeps the original 8MB blocks.
+        const _: () = assert!(MAX_PAGES_IN_BLOCK.is_power_of_two());
+        const _: () = assert!(1 << MAX_BLOCK_SHIFT == MAX_BLOCK_IN_BYTES);
+        const _: () = assert!(MAX_BLOCK_IN_BYTES == 8 * 1024 * 1024);
+
+        pub fn block_shift() -> u32 {
+            unsafe { crate::region::REGION_BLOCK_SHIFT }
+        }
+
+        pub fn block_mask() -> u64 {
+            unsafe { crate::region::REGION_BLOCK_MASK }
+        }
+
+        pub fn pages_in_block() -> u32 {
+            1 << (block_shift() - PAGE_SHIFT)
+        }
+
+        pub fn block_in_bytes() -> u64 {
+            1 << block_shift()
+        }
 
         // Static memory footprint, ignoring any dynamically-allocated pages.
         pub unsafe fn static_mem_in_pages(block_base: u64) -> u64 {
@@ -267,7 +322,7 @@ mod meta_data {
         }
 
         pub unsafe fn total_required_pages(block_base: u64, total_allocated_blocks: u64) -> u64 {
-            static_mem_in_pages(block_base) + (total_allocated_blocks * (PAGES_IN_BLOCK as u64))
+            static_mem_in_pages(block_base) + (total_allocated_blocks * (pages_in_block() as u64))
         }
     }
 
@@ -290,11 +345,15 @@ mod meta_data {
 
         pub const BLOCK_REGION_TABLE: u64 = TOTAL_ALLOCATED_REGIONS + bytes_of::<u64>();
 
-        pub const FREE: u64 = BLOCK_REGION_TABLE + super::size::BLOCK_REGION_TABLE;
+        pub const FREE_BLOCK_HEAD: u64 = BLOCK_REGION_TABLE + super::size::BLOCK_REGION_TABLE;
+
+        pub const FREE_BLOCK_COUNT: u64 = FREE_BLOCK_HEAD + bytes_of::<u16>();
+
+        pub const FREE: u64 = FREE_BLOCK_COUNT + bytes_of::<u32>();
 
         pub const BASE_LOW: u64 = 16 * super::size::PAGE_IN_BYTES;
 
-        pub const BASE_HIGH: u64 = super::size::BLOCK_IN_BYTES;
+        pub const BASE_HIGH: u64 = super::size::MAX_BLOCK_IN_BYTES;
     }
 
     pub mod total_allocated_blocks {
@@ -368,6 +427,62 @@ mod meta_data {
             write_u16(rank_offset, rank);
             write_u8(page_count_offset, allocated_pages);
         }
+
+        /// Mark the block as available and link it to the next free block (if any).
+        /// The link is stored in the otherwise unused rank field, encoded as block ID + 1.
+        /// The page count field retains the number of pages that the previous owner had
+        /// allocated in the block, i.e. that may hold data.
+        pub fn set_free(b: BlockId, next: Option<BlockId>, used_pages: u8) {
+            let region_offset = index(&b);
+            let rank_offset = region_offset + bytes_of::<u64>();
+            let page_count_offset = rank_offset + bytes_of::<u16>();
+            write_u64(region_offset, RegionId::into_u64(None));
+            write_u16(rank_offset, BlockId::into_u16(next));
+            write_u8(page_count_offset, used_pages);
+        }
+
+        /// The next free block in the free block list and the number of pages that may
+        /// hold data, for a block that is available.
+        pub fn get_free(b: BlockId) -> (Option<BlockId>, u8) {
+            debug_assert!(get(b.clone()).is_none());
+            let rank_offset = index(&b) + bytes_of::<u64>();
+            let page_count_offset = rank_offset + bytes_of::<u16>();
+            (
+                BlockId::from_u16(read_u16(rank_offset)),
+                read_u8(page_count_offset),
+            )
+        }
+    }
+
+    /// Stack of blocks released by `region_free`, for reuse by `region_grow`.
+    /// The list is threaded through the block-region table (see `block_region_table::set_free`).
+    /// NB: Stable memories formatted before this list existed have zeros in these fields,
+    /// denoting the empty list.
+    pub mod free_blocks {
+        use super::{block_region_table, offset};
+        use crate::region::BlockId;
+        use crate::stable_mem::{read_u16, read_u32, write_u16, write_u32};
+
+        pub fn count() -> u32 {
+            read_u32(offset::FREE_BLOCK_COUNT)
+        }
+
+        /// `used_pages`: The number of pages of the block that may hold data.
+        pub fn push(b: BlockId, used_pages: u8) {
+            let head = BlockId::from_u16(read_u16(offset::FREE_BLOCK_HEAD));
+            block_region_table::set_free(b.clone(), head, used_pages);
+            write_u16(offset::FREE_BLOCK_HEAD, BlockId::into_u16(Some(b)));
+            write_u32(offset::FREE_BLOCK_COUNT, count() + 1);
+        }
+
+        /// Returns the block and the number of its pages that may hold data.
+        pub fn pop() -> Option<(BlockId, u8)> {
+            let head = BlockId::from_u16(read_u16(offset::FREE_BLOCK_HEAD))?;
+            let (next, used_pages) = block_region_table::get_free(head.clone());
+            write_u16(offset::FREE_BLOCK_HEAD, BlockId::into_u16(next));
+            write_u32(offset::FREE_BLOCK_COUNT, count() - 1);
+            Some((head, used_pages))
+        }
     }
 }
 
@@ -379,7 +494,7 @@ unsafe fn write_magic() {
     write_u32(meta_data::offset::VERSION, meta_data::version::VERSION);
     write_u16(
         meta_data::offset::BLOCK_PAGES,
-        meta_data::size::PAGES_IN_BLOCK as u16,
+        meta_data::size::pages_in_block() as u16,
     );
     write_u64(meta_data::offset::BLOCK_BASE, BLOCK_BASE);
 }
@@ -401,7 +516,7 @@ unsafe fn alloc_region<M: Memory>(
     debug_assert!(
         page_count
             <= (vec_pages.as_blob().len().as_usize() / meta_data::bytes_of::<u16>() as usize)
-                * meta_data::size::PAGES_IN_BLOCK as usize
+                * meta_data::size::pages_in_block() as usize
     );
     (*region).page_count = page_count;
     init_with_barrier(mem, &mut (*region).vec_pages, vec_pages);
@@ -424,7 +539,7 @@ unsafe fn init_region<M: Memory>(
     debug_assert!(
         page_count
             <= (vec_pages.as_blob().len().as_usize() / meta_data::bytes_of::<u16>() as usize)
-                * meta_data::size::PAGES_IN_BLOCK as usize
+                * meta_data::size::pages_in_block() as usize
     );
     (*r).page_count = page_count;
     write_with_barrier(mem, &mut (*r).vec_pages, vec_pages);
@@ -536,7 +651,7 @@ pub unsafe fn region_new<M: Memory>(mem: &mut M) -> Value {
 
 pub unsafe fn region_recover<M: Memory>(mem: &mut M, rid: &RegionId) -> Value {
     use meta_data::bytes_of;
-    use meta_data::size::PAGES_IN_BLOCK;
+    use meta_data::size::pages_in_block;
 
     if rid.0 >= meta_data::total_allocated_regions::get() {
         region_trap_with("cannot recover un-allocated region");
@@ -558,9 +673,9 @@ pub unsafe fn region_recover<M: Memory>(mem: &mut M, rid: &RegionId) -> Value {
             }
         }
     };
-    debug_assert!(page_count < (u32::MAX - (PAGES_IN_BLOCK - 1)));
+    debug_assert!(page_count < (u32::MAX - (pages_in_block() - 1)));
 
-    let block_count = (page_count + PAGES_IN_BLOCK - 1) / PAGES_IN_BLOCK;
+    let block_count = (page_count + pages_in_block() - 1) / pages_in_block();
     let vec_pages = alloc_blob(
         mem,
         TAG_BLOB_B,
@@ -604,7 +719,7 @@ fn upgrade_version_to_regions() {
 
 pub(crate) unsafe fn region_migration_from_no_stable_memory<M: Memory>(mem: &mut M) {
     use crate::stable_mem::{get_version, grow, size, write};
-    use meta_data::size::{PAGES_IN_BLOCK, PAGE_IN_BYTES};
+    use meta_data::size::{MAX_PAGES_IN_BLOCK, PAGE_IN_BYTES};
 
     if uses_enhanced_orthogonal_persistence!() {
         assert!(
@@ -617,11 +732,11 @@ pub(crate) unsafe fn region_migration_from_no_stable_memory<M: Memory>(mem: &mut
 
     assert_eq!(size(), 0);
 
-    // pages required for meta_data (9/ 960KiB), much less than PAGES_IN_BLOCK (128/ 8MB) for a full block
+    // pages required for meta_data (9/ 960KiB), much less than MAX_PAGES_IN_BLOCK (128/ 8MB) for a full block
     let meta_data_pages =
         (meta_data::offset::FREE - 1 + (PAGE_IN_BYTES as u64 - 1)) / PAGE_IN_BYTES as u64;
 
-    assert!(meta_data_pages <= PAGES_IN_BLOCK as u64);
+    assert!(meta_data_pages <= MAX_PAGES_IN_BLOCK as u64);
 
     // initially, only allocate meta_data_pages, not a full block, to reduce overhead for
     // canisters that don't allocate regions
@@ -656,21 +771,21 @@ pub(crate) unsafe fn region_migration_from_no_stable_memory<M: Memory>(mem: &mut
 }
 
 pub fn block_page_count(rank: u16, block_count: u32, page_count: u32) -> u8 {
-    use meta_data::size::PAGES_IN_BLOCK;
+    use meta_data::size::pages_in_block;
     debug_assert!(block_count > 0);
     debug_assert!((rank as u32) < block_count);
     debug_assert_eq!(
         block_count,
-        (page_count + (PAGES_IN_BLOCK as u32 - 1)) / meta_data::size::PAGES_IN_BLOCK
+        (page_count + (pages_in_block() as u32 - 1)) / meta_data::size::pages_in_block()
     );
     if (rank as u32) == block_count - 1 {
         // final, full or partial block
-        let rem = page_count - ((block_count - 1) * PAGES_IN_BLOCK as u32);
-        debug_assert!(rem <= PAGES_IN_BLOCK);
+        let rem = page_count - ((block_count - 1) * pages_in_block() as u32);
+        debug_assert!(rem <= pages_in_block());
         rem as u8
     } else {
         // internal, full block
-        meta_data::size::PAGES_IN_BLOCK as u8
+        meta_data::size::pages_in_block() as u8
     }
 }
 
@@ -690,24 +805,30 @@ pub(crate) unsafe fn region_migration_from_some_stable_memory<M: Memory>(mem: &m
 
     use crate::stable_mem::{grow, read, size, write};
 
-    let header_len = meta_data::size::BLOCK_IN_BYTES as u32;
+    // The relocated head block must cover the meta data area up to `BASE_HIGH`,
+    // so this migration always uses the maximum block size.
+    set_block_geometry(meta_data::size::MAX_PAGES_IN_BLOCK);
+
+    let header_len = meta_data::size::block_in_bytes() as u32;
+    debug_assert_eq!(header_len as u64, meta_data::offset::BASE_HIGH);
 
     let stable_mem_pages = size();
 
-    if stable_mem_pages > (meta_data::size::PAGES_IN_BLOCK * meta_data::max::BLOCKS as u32) as u64 {
+    if stable_mem_pages > (meta_data::size::pages_in_block() * meta_data::max::BLOCKS as u32) as u64
+    {
         region_trap_with("migration failure (too many pages for region0 )")
     };
 
     let region0_pages = stable_mem_pages as u32;
 
-    let region0_blocks =
-        (region0_pages + (meta_data::size::PAGES_IN_BLOCK - 1)) / (meta_data::size::PAGES_IN_BLOCK);
+    let region0_blocks = (region0_pages + (meta_data::size::pages_in_block() - 1))
+        / (meta_data::size::pages_in_block());
     assert!(region0_blocks > 0);
 
     let prev_pages = grow(
-        (meta_data::size::PAGES_IN_BLOCK + // <-- For new region manager
+        (meta_data::size::pages_in_block() + // <-- For new region manager
 	 /* Bump out region0 to nearest block boundary: */
-	 region0_blocks * meta_data::size::PAGES_IN_BLOCK
+	 region0_blocks * meta_data::size::pages_in_block()
             - region0_pages)
             .into(),
     );
@@ -730,7 +851,7 @@ pub(crate) unsafe fn region_migration_from_some_stable_memory<M: Memory>(mem: &m
     // Move it:
     read(0, header_bytes); // Save first block as "head block".
     write(
-        (region0_blocks * (meta_data::size::BLOCK_IN_BYTES as u32)).into(),
+        (region0_blocks * (meta_data::size::block_in_bytes() as u32)).into(),
         header_bytes,
     );
 
@@ -803,9 +924,11 @@ pub(crate) unsafe fn region_migration_from_regions_plus<M: Memory>(mem: &mut M)
     };
 
     let block_pages = read_u16(meta_data::offset::BLOCK_PAGES);
-    if block_pages as u32 != meta_data::size::PAGES_IN_BLOCK {
+    if !valid_block_pages(block_pages as usize) {
         region_trap_with("migration failure (unexpected block size)")
     };
+    // The block size is fixed by the existing layout.
+    set_block_geometry(block_pages as u32);
 
     let block_base = read_u64(meta_data::offset::BLOCK_BASE);
     if block_base < meta_data::offset::FREE {
@@ -821,12 +944,35 @@ pub(crate) unsafe fn region_migration_from_regions_plus<M: Memory>(mem: &mut M)
     region_reserve_id_span(mem, None, RegionId(LAST_RESERVED_REGION_ID));
 }
 
+fn valid_block_pages(block_pages: usize) -> bool {
+    block_pages.is_power_of_two() && block_pages <= meta_data::size::MAX_PAGES_IN_BLOCK as usize
+}
+
+unsafe fn set_block_geometry(block_pages: u32) {
+    debug_assert!(valid_block_pages(block_pages as usize));
+    REGION_BLOCK_SHIFT = meta_data::size::PAGE_SHIFT + block_pages.trailing_zeros();
+    REGION_BLOCK_MASK = meta_data::size::block_in_bytes() - 1;
+}
+
+// Block size for a new region layout. Ignored if the layout already exists.
+unsafe fn set_block_pages(block_pages: usize) {
+    if !valid_block_pages(block_pages) {
+        region_trap_with("invalid block size")
+    };
+    set_block_geometry(block_pages as u32);
+}
+
 //
 // region manager migration/initialization, with pre-existing stable data.
 //
 #[classical_persistence]
 #[ic_mem_fn(ic_only)]
-pub(crate) unsafe fn region_init<M: Memory>(mem: &mut M, use_stable_regions: usize) {
+pub(crate) unsafe fn region_init<M: Memory>(
+    mem: &mut M,
+    use_stable_regions: usize,
+    block_pages: usize,
+) {
+    set_block_pages(block_pages);
     match crate::stable_mem::get_version() {
         LEGACY_VERSION_NO_STABLE_MEMORY => {
             assert!(crate::stable_mem::size() == 0);
@@ -857,7 +1003,12 @@ pub(crate) unsafe fn region_init<M: Memory>(mem: &mut M, use_stable_regions: usi
 
 #[enhanced_orthogonal_persistence]
 #[ic_mem_fn(ic_only)]
-pub(crate) unsafe fn region_init<M: Memory>(mem: &mut M, use_stable_regions: usize) {
+pub(crate) unsafe fn region_init<M: Memory>(
+    mem: &mut M,
+    use_stable_regions: usize,
+    block_pages: usize,
+) {
+    set_block_pages(block_pages);
     match crate::stable_mem::get_version() {
         VERSION_STABLE_HEAP_NO_REGIONS | VERSION_GRAPH_COPY_NO_REGIONS => {
             if use_stable_regions != 0 {
@@ -892,9 +1043,9 @@ pub unsafe fn region_size<M: Memory>(_mem: &mut M, r: Value) -> u64 {
 
 #[ic_mem_fn]
 pub unsafe fn region_grow<M: Memory>(mem: &mut M, r: Value, new_pages: u64) -> u64 {
-    use meta_data::size::{total_required_pages, PAGES_IN_BLOCK};
+    use meta_data::size::{pages_in_block, total_required_pages};
 
-    let max_pages_in_region = meta_data::max::BLOCKS as u32 * PAGES_IN_BLOCK;
+    let max_pages_in_region = meta_data::max::BLOCKS as u32 * pages_in_block();
 
     let r = r.as_region();
     let old_page_count = (*r).page_count;
@@ -907,14 +1058,21 @@ pub unsafe fn region_grow<M: Memory>(mem: &mut M, r: Value, new_pages: u64) -> u
 
     let new_pages_ = new_pages as u32;
 
-    let old_block_count = (old_page_count as u32 + (PAGES_IN_BLOCK - 1)) / PAGES_IN_BLOCK;
+    let old_block_count = (old_page_count as u32 + (pages_in_block() - 1)) / pages_in_block();
     let new_block_count =
-        (old_page_count as u32 + new_pages_ + (PAGES_IN_BLOCK - 1)) / PAGES_IN_BLOCK;
+        (old_page_count as u32 + new_pages_ + (pages_in_block() - 1)) / pages_in_block();
     let inc_block_count = new_block_count - old_block_count;
 
+    // Released blocks are reused first, before allocating fresh ones.
+    let reused_block_count = core::cmp::min(inc_block_count, meta_data::free_blocks::count());
+
     // Determine the required total number of allocated blocks,
     let old_total_blocks = meta_data::total_allocated_blocks::get();
-    let new_total_blocks = old_total_blocks as u64 + inc_block_count as u64;
+    let new_total_blocks = old_total_blocks as u64 + (inc_block_count - reused_block_count) as u64;
+
+    if new_total_blocks > meta_data::max::BLOCKS as u64 {
+        return u64::MAX;
+    }
 
     // Actually grow stable memory with more pages as required,
     // while respecting the global maximum limit on pages.
@@ -969,12 +1127,20 @@ pub unsafe fn region_grow<M: Memory>(mem: &mut M, r: Value, new_pages: u64) -> u
     // Record new associations, between the region and each new block:
     // - in block_region_table (stable memory, for persistence).
     // - in region representation (heap memory, for fast access operations).
+    let mut fresh_block_count: u32 = 0;
     for i in old_block_count..new_block_count {
-        // rel_i starts at zero.
-        let rel_i: u16 = (i - old_block_count) as u16;
-
-        // (to do -- handle case where allocating this way has run out.)
-        let block_id: u16 = (old_total_blocks + rel_i as u32) as u16;
+        let block_id = match meta_data::free_blocks::pop() {
+            Some((block_id, used_pages)) => {
+                // Reused blocks may still hold data of the region that released them.
+                zero_block_pages(&block_id, 0, used_pages as u32);
+                block_id.0
+            }
+            None => {
+                let block_id = (old_total_blocks + fresh_block_count) as u16;
+                fresh_block_count += 1;
+                block_id
+            }
+        };
 
         // Update stable memory with new association.
         let block_page_count = block_page_count(i as u16, new_block_count, (*r).page_count as u32);
@@ -984,14 +1150,133 @@ pub unsafe fn region_grow<M: Memory>(mem: &mut M, r: Value, new_pages: u64) -> u
         new_pages.set_ith_block_id(i, &BlockId(block_id));
     }
 
+    debug_assert_eq!(
+        old_total_blocks + fresh_block_count,
+        meta_data::total_allocated_blocks::get()
+    );
+
     allocation_barrier(new_vec_pages);
     write_with_barrier(mem, &mut (*r).vec_pages, new_vec_pages);
     old_page_count as u64
 }
 
+const ZERO_CHUNK_LENGTH: usize = 1024;
+const _: () = assert!(meta_data::size::PAGE_IN_BYTES % ZERO_CHUNK_LENGTH as u64 == 0);
+
+/// Zero source for clearing reused blocks, much smaller than a page to keep it off the stack.
+static ZERO_CHUNK: [u8; ZERO_CHUNK_LENGTH] = [0; ZERO_CHUNK_LENGTH];
+
+// Zero the pages `[first_page, last_page)` of a block.
+unsafe fn zero_block_pages(block_id: &BlockId, first_page: u32, last_page: u32) {
+    use crate::stable_mem::write;
+    use meta_data::size::{block_in_bytes, pages_in_block, PAGE_IN_BYTES};
+
+    debug_assert!(first_page <= last_page);
+    debug_assert!(last_page <= pages_in_block());
+    let block_offset = BLOCK_BASE + block_id.0 as u64 * block_in_bytes();
+    let mut offset = first_page as u64 * PAGE_IN_BYTES;
+    let end = last_page as u64 * PAGE_IN_BYTES;
+    while offset < end {
+        write(block_offset + offset, &ZERO_CHUNK);
+        offset += ZERO_CHUNK_LENGTH as u64;
+    }
+}
+
+// Reduce the region to `new_page_count` pages, releasing the trailing blocks that are no
+// longer used for reuse by other regions. Discarded pages in the retained last block are
+// zeroed, such that a later `region_grow` again yields zero-initialized pages.
+unsafe fn region_truncate<M: Memory>(mem: &mut M, r: *mut Region, new_page_count: u32) {
+    use meta_data::size::pages_in_block;
+
+    let r_id = RegionId::from_id(r.read_id64());
+    debug_assert_ne!(r_id.0, 0);
+    let old_page_count = (*r).page_count as u32;
+    debug_assert!(new_page_count <= old_page_count);
+
+    let old_block_count = (old_page_count + (pages_in_block() - 1)) / pages_in_block();
+    let new_block_count = (new_page_count + (pages_in_block() - 1)) / pages_in_block();
+    let av = AccessVector::from_value(&(*r).vec_pages);
+
+    for i in new_block_count..old_block_count {
+        let block_id = av.get_ith_block_id(i);
+        debug_assert!(match meta_data::block_region_table::get(block_id.clone()) {
+            Some((rid, rank, _)) => rid == r_id && rank as u32 == i,
+            None => false,
+        });
+        let used_pages = block_page_count(i as u16, old_block_count, old_page_count);
+        meta_data::free_blocks::push(block_id, used_pages);
+    }
+
+    if new_block_count > 0 {
+        let last_block_rank = (new_block_count - 1) as u16;
+        let last_block_id = av.get_ith_block_id(last_block_rank as u32);
+        let first_page = new_page_count - (new_block_count - 1) * pages_in_block();
+        let last_page = core::cmp::min(
+            old_page_count - (new_block_count - 1) * pages_in_block(),
+            pages_in_block(),
+        );
+        zero_block_pages(&last_block_id, first_page, last_page);
+
+        let last_page_count = block_page_count(last_block_rank, new_block_count, new_page_count);
+        let assoc = Some((r_id, last_block_rank, last_page_count));
+        meta_data::block_region_table::set(last_block_id, assoc);
+    }
+
+    let new_vec_pages = alloc_blob(
+        mem,
+        TAG_BLOB_B,
+        Bytes(new_block_count as usize * meta_data::bytes_of::<u16>() as usize),
+    );
+    let new_vec_byte_count = new_block_count * meta_data::bytes_of::<u16>() as u32;
+
+    // Copy the retained region-block associations into new heap object.
+    crate::mem_utils::memcpy_bytes(
+        new_vec_pages.as_blob_mut().payload_addr() as usize,
+        (*r).vec_pages.as_blob().payload_const() as usize,
+        Bytes(new_vec_byte_count as usize),
+    );
+
+    allocation_barrier(new_vec_pages);
+    (*r).page_count = new_page_count as usize;
+    write_with_barrier(mem, &mut (*r).vec_pages, new_vec_pages);
+}
+
+// Shrink the region by the given number of trailing pages and return the previous size,
+// or `u64::MAX` if the region has fewer pages.
+#[ic_mem_fn]
+pub unsafe fn region_shrink<M: Memory>(mem: &mut M, r: Value, pages: u64) -> u64 {
+    let r = r.as_region();
+
+    if r.read_id64() == 0 {
+        region_trap_with("cannot shrink region 0")
+    };
+
+    let old_page_count = (*r).page_count as u64;
+    if pages > old_page_count {
+        return u64::MAX;
+    }
+
+    region_truncate(mem, r, (old_page_count - pages) as u32);
+    old_page_count
+}
+
+// Release all blocks of a region for reuse by other regions.
+// The region is left empty, such that any further access traps, but may be grown again.
+// The region ID is never recycled.
+#[ic_mem_fn]
+pub unsafe fn region_free<M: Memory>(mem: &mut M, r: Value) {
+    let r = r.as_region();
+
+    if r.read_id64() == 0 {
+        region_trap_with("cannot free region 0")
+    };
+
+    region_truncate(mem, r, 0);
+}
+
 pub(crate) unsafe fn region_load<M: Memory>(_mem: &mut M, r: Value, offset: u64, dst: &mut [u8]) {
     use crate::stable_mem::read;
-    use meta_data::size::BLOCK_IN_BYTES;
+    use meta_data::size::block_in_bytes;
 
     let r = RegionObject::from_value(&r);
 
@@ -1037,10 +1322,10 @@ pub(crate) unsafe fn region_load<M: Memory>(_mem: &mut M, r: Value, offset: u64,
                 // case: internal (full) block.
                 read(
                     s,
-                    core::slice::from_raw_parts_mut(d, BLOCK_IN_BYTES as usize),
+                    core::slice::from_raw_parts_mut(d, block_in_bytes() as usize),
                 );
-                d = d.offset(BLOCK_IN_BYTES as isize);
-                i += BLOCK_IN_BYTES;
+                d = d.offset(block_in_bytes() as isize);
+                i += block_in_bytes();
             }
         }
     }
@@ -1048,7 +1333,7 @@ pub(crate) unsafe fn region_load<M: Memory>(_mem: &mut M, r: Value, offset: u64,
 
 pub(crate) unsafe fn region_store<M: Memory>(_mem: &mut M, r: Value, offset: u64, src: &[u8]) {
     use crate::stable_mem::write;
-    use meta_data::size::BLOCK_IN_BYTES;
+    use meta_data::size::block_in_bytes;
 
     let r = RegionObject::from_value(&r);
 
@@ -1088,14 +1373,694 @@ pub(crate) unsafe fn region_store<M: Memory>(_mem: &mut M, r: Value, offset: u64
                 break;
             } else {
                 // case: internal (full) block.
-                write(d, core::slice::from_raw_parts(s, BLOCK_IN_BYTES as usize));
-                s = s.offset(BLOCK_IN_BYTES as isize);
-                i += BLOCK_IN_BYTES;
+                write(d, core::slice::from_raw_parts(s, block_in_bytes() as usize));
+                s = s.offset(block_in_bytes() as isize);
+                i += block_in_bytes();
             }
         }
     }
 }
 
+// Copy `len` bytes from region `src` at `src_offset` to region `dst` at `dst_offset`.
+// The data is staged through a page-sized buffer on the stack, one chunk per pair of
+// source and destination blocks, instead of a heap blob. Overlapping ranges within the
+// same region are handled like `memmove`.
+#[ic_mem_fn]
+pub unsafe fn region_copy<M: Memory>(
+    _mem: &mut M,
+    src: Value,
+    src_offset: u64,
+    dst: Value,
+    dst_offset: u64,
+    len: u64,
+) {
+    use crate::stable_mem::{read, write};
+    use meta_data::size::{block_in_bytes, PAGE_IN_BYTES};
+
+    let src = RegionObject::from_value(&src);
+    let dst = RegionObject::from_value(&dst);
+
+    src.check_relative_range(src_offset, len);
+    dst.check_relative_range(dst_offset, len);
+
+    if len == 0 {
+        return;
+    };
+
+    // Copy backwards if the destination range starts within the source range.
+    let backwards = src.id() == dst.id() && src_offset < dst_offset;
+
+    let mut buffer: [u8; PAGE_IN_BYTES as usize] = [0; PAGE_IN_BYTES as usize];
+    let mut copied = 0; // invariant: copied = # of bytes copied.
+    while copied < len {
+        let remaining = len - copied;
+        let (chunk_src, chunk_dst, chunk_len) = if backwards {
+            // Chunk ending at `src_offset + remaining` and `dst_offset + remaining`.
+            let src_end = src_offset + remaining;
+            let dst_end = dst_offset + remaining;
+            let (_, _, src_rest) = src.relative_into_absolute_info(src_end - 1);
+            let (_, _, dst_rest) = dst.relative_into_absolute_info(dst_end - 1);
+            let chunk_len = remaining
+                .min(block_in_bytes() - src_rest + 1)
+                .min(block_in_bytes() - dst_rest + 1)
+                .min(PAGE_IN_BYTES);
+            let (s, _, _) = src.relative_into_absolute_info(src_end - chunk_len);
+            let (d, _, _) = dst.relative_into_absolute_info(dst_end - chunk_len);
+            (s, d, chunk_len)
+        } else {
+            // Chunk starting at `src_offset + copied` and `dst_offset + copied`.
+            let (s, _, src_rest) = src.relative_into_absolute_info(src_offset + copied);
+            let (d, _, dst_rest) = dst.relative_into_absolute_info(dst_offset + copied);
+            let chunk_len = remaining.min(src_rest).min(dst_rest).min(PAGE_IN_BYTES);
+            (s, d, chunk_len)
+        };
+        let chunk = &mut buffer[..chunk_len as usize];
+        read(chunk_src, chunk);
+        write(chunk_dst, chunk);
+        copied += chunk_len;
+    }
+}
+
+// -- Region integrity check.
+
+// Whether stable memory starts with the region meta data, i.e. the `MOREGION` magic.
+unsafe fn has_region_meta_data() -> bool {
+    use crate::stable_mem::{read, size};
+
+    let mut magic_bytes: [u8; 8] = [0; 8];
+    if size() > 0 {
+        read(meta_data::offset::MAGIC, &mut magic_bytes);
+    }
+    &magic_bytes == meta_data::version::MAGIC
+}
+
+// Block status flags, used by `region_check`.
+const BLOCK_REFERENCED: u8 = 1; // referenced by a checked region
+const BLOCK_FREE: u8 = 2; // in the free block list
+const BLOCK_DOUBLE_ASSIGNED: u8 = 4; // used more than once (by regions or the free list)
+const BLOCK_MISMATCHED: u8 = 8; // block-region table disagrees with the region's access vector
+const BLOCK_OWNED: u8 = 16; // block-region table names a region
+
+// Field hashes of the Candid report of `region_check`, in ascending order.
+mod check_report {
+    use crate::idl_writer::idl_hash;
+
+    pub const MISMATCHED_BLOCKS: u32 = idl_hash("mismatched_blocks");
+    pub const BLOCK_PAGES: u32 = idl_hash("block_pages");
+    pub const INVALID_REGIONS: u32 = idl_hash("invalid_regions");
+    pub const PAGE_COUNT_MISMATCHES: u32 = idl_hash("page_count_mismatches");
+    pub const MAGIC_VALID: u32 = idl_hash("magic_valid");
+    pub const VERSION: u32 = idl_hash("version");
+    pub const DOUBLE_ASSIGNED_BLOCKS: u32 = idl_hash("double_assigned_blocks");
+    pub const ORPHANED_BLOCKS: u32 = idl_hash("orphaned_blocks");
+    pub const UNLISTED_REGIONS: u32 = idl_hash("unlisted_regions");
+    pub const FREE_BLOCKS: u32 = idl_hash("free_blocks");
+    pub const TOTAL_BLOCKS: u32 = idl_hash("total_blocks");
+
+    const _: () = assert!(
+        MISMATCHED_BLOCKS < BLOCK_PAGES
+            && BLOCK_PAGES < INVALID_REGIONS
+            && INVALID_REGIONS < PAGE_COUNT_MISMATCHES
+            && PAGE_COUNT_MISMATCHES < MAGIC_VALID
+            && MAGIC_VALID < VERSION
+            && VERSION < DOUBLE_ASSIGNED_BLOCKS
+            && DOUBLE_ASSIGNED_BLOCKS < ORPHANED_BLOCKS
+            && ORPHANED_BLOCKS < UNLISTED_REGIONS
+            && UNLISTED_REGIONS < FREE_BLOCKS
+            && FREE_BLOCKS < TOTAL_BLOCKS
+    );
+
+    // Fields of a page count mismatch record.
+    pub const REGION: u32 = idl_hash("region");
+    pub const TABLE_PAGE_COUNT: u32 = idl_hash("table_page_count");
+    pub const PAGE_COUNT: u32 = idl_hash("page_count");
+
+    const _: () = assert!(REGION < TABLE_PAGE_COUNT && TABLE_PAGE_COUNT < PAGE_COUNT);
+}
+
+// Temporary array in a heap blob, zero-initialized. No allocation barrier is needed, as the
+// blob is only used during the current call.
+unsafe fn scratch_slice<'a, M: Memory, T>(mem: &mut M, length: usize) -> &'a mut [T] {
+    let alignment = core::mem::align_of::<T>();
+    let size = length * core::mem::size_of::<T>() + alignment;
+    let blob = alloc_blob(mem, TAG_BLOB_B, Bytes(size)).as_blob_mut();
+    crate::mem_utils::memzero_bytes(blob.payload_addr() as usize, Bytes(size));
+    let address = (blob.payload_addr() as usize + alignment - 1) & !(alignment - 1);
+    core::slice::from_raw_parts_mut(address as *mut T, length)
+}
+
+// An entry of the block-region table that names a region.
+#[derive(Clone, Copy)]
+struct TableEntry {
+    region: u64,
+    rank: u16,
+    block: u16,
+    page_count: u8,
+}
+
+// Check the table entries of a single region, sorted by rank: The region id must have been
+// allocated, the ranks must be exactly `0..entries.len()`, and all but the last block must be
+// full. Blocks that occupy the same rank are marked as double-assigned.
+unsafe fn table_region_valid(
+    entries: &[TableEntry],
+    block_pages: u16,
+    total_regions: u64,
+    status: *mut Blob,
+) -> bool {
+    let region = entries[0].region;
+    let mut valid = region == 0 || (region > LAST_RESERVED_REGION_ID && region < total_regions);
+    for (index, entry) in entries.iter().enumerate() {
+        if index > 0 && entries[index - 1].rank == entry.rank {
+            for block in [entries[index - 1].block, entry.block] {
+                let old = status.get(block as usize);
+                status.set(block as usize, old | BLOCK_DOUBLE_ASSIGNED);
+            }
+        }
+        let last = index == entries.len() - 1;
+        valid &= entry.rank as usize == index
+            && entry.page_count > 0
+            && entry.page_count as u16 <= block_pages
+            && (last || entry.page_count as u16 == block_pages);
+    }
+    valid
+}
+
+// Sum of the page counts that the block-region table records for the blocks of a region.
+// Blocks that are not recorded for this region (at the expected rank) are not counted.
+unsafe fn region_table_page_count(r: &RegionObject, block_count: u32, total_blocks: u32) -> u64 {
+    let av = AccessVector::from_value(&(*r.0).vec_pages);
+    let mut page_count = 0;
+    for i in 0..block_count {
+        let block_id = av.get_ith_block_id(i);
+        if block_id.0 as u32 >= total_blocks {
+            continue;
+        }
+        match meta_data::block_region_table::get(block_id) {
+            Some((rid, rank, block_page_count)) if rid == r.id() && rank as u32 == i => {
+                page_count += block_page_count as u64;
+            }
+            _ => {}
+        }
+    }
+    page_count
+}
+
+unsafe fn region_block_count(r: &RegionObject) -> u32 {
+    use meta_data::size::pages_in_block;
+    let block_count = ((*r.0).page_count as u32 + (pages_in_block() - 1)) / pages_in_block();
+    // Tolerate access vectors that are too short for the page count.
+    let vector_length = (*r.0).vec_pages.as_blob().len().as_usize() as u32 / 2;
+    core::cmp::min(block_count, vector_length)
+}
+
+// Write the ids of the blocks whose status flags match `expected` under the mask `flag`,
+// as Candid `vec nat16`.
+unsafe fn write_block_list<M: Memory>(
+    mem: &mut M,
+    writer: &mut crate::idl_writer::IdlWriter,
+    status: *mut Blob,
+    total_blocks: u32,
+    flag: u8,
+    expected: u8,
+) {
+    let matches = |block: usize| status.get(block) & flag == expected;
+    let count = (0..total_blocks as usize)
+        .filter(|&block| matches(block))
+        .count();
+    writer.write_leb128(mem, count as u64);
+    for block in 0..total_blocks as usize {
+        if matches(block) {
+            writer.write_u16(mem, block as u16);
+        }
+    }
+}
+
+// Write region ids as Candid `vec nat64`.
+unsafe fn write_region_list<M: Memory>(
+    mem: &mut M,
+    writer: &mut crate::idl_writer::IdlWriter,
+    regions: &[u64],
+) {
+    writer.write_leb128(mem, regions.len() as u64);
+    for region in regions {
+        writer.write_u64(mem, *region);
+    }
+}
+
+// Check the stable region layout. The regions are reconstructed from the block-region table,
+// independently of the live regions. In addition, the table is cross-checked against the access
+// vectors of the given live regions (region 0 is included implicitly). Each live region should
+// only be passed once.
+//
+// Returns a Candid-encoded report of the following Motoko type:
+// ```
+// {
+//   magic_valid : Bool; // `MOREGION` magic present
+//   version : Nat32; // stored layout version
+//   block_pages : Nat16; // stored block size in pages
+//   total_blocks : Nat32; // number of allocated blocks
+//   free_blocks : Nat32; // number of blocks in the free block list
+//   orphaned_blocks : [Nat16]; // neither owned by any region nor free
+//   double_assigned_blocks : [Nat16]; // used more than once (by regions or the free list)
+//   mismatched_blocks : [Nat16]; // block-region table disagrees with a live region's access vector
+//   invalid_regions : [Nat64]; // regions with an unallocated id, missing or duplicate blocks,
+//                              // or partial blocks before their last block in the table
+//   unlisted_regions : [Nat64]; // regions owning blocks in the table that were not passed
+//   page_count_mismatches : [{ region : Nat64; page_count : Nat64; table_page_count : Nat64 }];
+// }
+// ```
+// If `repair` is set, orphaned blocks are released to the free block list.
+// Blocks that the block-region table assigns to a region are never released, even if that
+// region has not been passed, as they may belong to a live region.
+#[ic_mem_fn]
+pub unsafe fn region_check<M: Memory>(mem: &mut M, regions: Value, repair: bool) -> Value {
+    use crate::idl::{
+        IDL_CON_record, IDL_CON_vec, IDL_PRIM_bool, IDL_PRIM_nat16, IDL_PRIM_nat32, IDL_PRIM_nat64,
+    };
+    use crate::idl_writer::IdlWriter;
+    use crate::stable_mem::{read_u16, read_u32};
+
+    let magic_valid = has_region_meta_data();
+    let (version, block_pages, total_blocks) = if magic_valid {
+        (
+            read_u32(meta_data::offset::VERSION),
+            read_u16(meta_data::offset::BLOCK_PAGES),
+            meta_data::total_allocated_blocks::get(),
+        )
+    } else {
+        (0, 0, 0)
+    };
+
+    // Temporary status flags per block, no allocation barrier needed.
+    let status = alloc_blob(mem, TAG_BLOB_B, Bytes(total_blocks as usize)).as_blob_mut();
+    crate::mem_utils::memzero_bytes(status.payload_addr() as usize, Bytes(total_blocks as usize));
+
+    let mark = |block_id: &BlockId, flag: u8| {
+        let old = status.get(block_id.0 as usize);
+        let double = if old & (BLOCK_REFERENCED | BLOCK_FREE) != 0 {
+            BLOCK_DOUBLE_ASSIGNED
+        } else {
+            0
+        };
+        status.set(block_id.0 as usize, old | flag | double);
+        double == 0
+    };
+
+    // Collect the owned blocks from the block-region table, grouped by region and rank.
+    let entries = scratch_slice::<M, TableEntry>(mem, total_blocks as usize);
+    let mut owned_blocks = 0;
+    for block in 0..total_blocks {
+        if let Some((rid, rank, page_count)) =
+            meta_data::block_region_table::get(BlockId(block as u16))
+        {
+            status.set(block as usize, BLOCK_OWNED);
+            entries[owned_blocks] = TableEntry {
+                region: rid.0,
+                rank,
+                block: block as u16,
+                page_count,
+            };
+            owned_blocks += 1;
+        }
+    }
+    let entries = &mut entries[..owned_blocks];
+    entries.sort_unstable_by_key(|entry| (entry.region, entry.rank));
+
+    // The ids of the given live regions, sorted.
+    let regions = regions.as_array();
+    let region_count = regions.len() + 1;
+    let region_at = |index: usize| {
+        if index == 0 {
+            REGION_0
+        } else {
+            regions.get(index - 1)
+        }
+    };
+    let listed = scratch_slice::<M, u64>(mem, region_count);
+    let mut listed_count = 0;
+    for index in 0..region_count {
+        let region = region_at(index);
+        if region != NO_REGION {
+            listed[listed_count] = RegionObject::from_value(&region).id().0;
+            listed_count += 1;
+        }
+    }
+    let listed = &mut listed[..listed_count];
+    listed.sort_unstable();
+
+    // Check the regions recorded in the table, one group of entries per region.
+    let total_regions = if magic_valid {
+        meta_data::total_allocated_regions::get()
+    } else {
+        0
+    };
+    let invalid_regions = scratch_slice::<M, u64>(mem, owned_blocks);
+    let unlisted_regions = scratch_slice::<M, u64>(mem, owned_blocks);
+    let (mut invalid_count, mut unlisted_count) = (0, 0);
+    let mut start = 0;
+    while start < owned_blocks {
+        let region = entries[start].region;
+        let mut end = start + 1;
+        while end < owned_blocks && entries[end].region == region {
+            end += 1;
+        }
+        if !table_region_valid(&entries[start..end], block_pages, total_regions, status) {
+            invalid_regions[invalid_count] = region;
+            invalid_count += 1;
+        }
+        if listed.binary_search(&region).is_err() {
+            unlisted_regions[unlisted_count] = region;
+            unlisted_count += 1;
+        }
+        start = end;
+    }
+
+    // Walk the free block list, stopping at blocks that were already visited (cycles).
+    let mut free_blocks = 0;
+    if magic_valid {
+        let mut next = BlockId::from_u16(read_u16(meta_data::offset::FREE_BLOCK_HEAD));
+        while let Some(block_id) = next {
+            if block_id.0 as u32 >= total_blocks {
+                break;
+            }
+            free_blocks += 1;
+            if meta_data::block_region_table::get(block_id.clone()).is_some() {
+                mark(&block_id, BLOCK_FREE | BLOCK_DOUBLE_ASSIGNED);
+                break;
+            }
+            if !mark(&block_id, BLOCK_FREE) {
+                break;
+            }
+            next = meta_data::block_region_table::get_free(block_id).0;
+        }
+    }
+
+    // Check the access vectors of the live regions.
+    let mut page_count_mismatches = 0;
+    for index in 0..region_count {
+        let region = region_at(index);
+        if !magic_valid || region == NO_REGION {
+            continue;
+        }
+        let r = RegionObject::from_value(&region);
+        let block_count = region_block_count(&r);
+        let av = AccessVector::from_value(&(*r.0).vec_pages);
+        for i in 0..block_count {
+            let block_id = av.get_ith_block_id(i);
+            if block_id.0 as u32 >= total_blocks {
+                continue;
+            }
+            let recorded = match meta_data::block_region_table::get(block_id.clone()) {
+                Some((rid, rank, _)) => rid == r.id() && rank as u32 == i,
+                None => false,
+            };
+            let flag = if recorded {
+                BLOCK_REFERENCED
+            } else {
+                BLOCK_REFERENCED | BLOCK_MISMATCHED
+            };
+            mark(&block_id, flag);
+        }
+        if region_table_page_count(&r, block_count, total_blocks) != (*r.0).page_count as u64 {
+            page_count_mismatches += 1;
+        }
+    }
+
+    let mut writer = IdlWriter::new(mem);
+
+    // Type table
+    writer.write_leb128(mem, 5);
+    // 0: report record
+    writer.write_sleb128(mem, IDL_CON_record as i64);
+    writer.write_leb128(mem, 11);
+    for (field, field_type) in [
+        (check_report::MISMATCHED_BLOCKS, 1),
+        (check_report::BLOCK_PAGES, IDL_PRIM_nat16),
+        (check_report::INVALID_REGIONS, 2),
+        (check_report::PAGE_COUNT_MISMATCHES, 3),
+        (check_report::MAGIC_VALID, IDL_PRIM_bool),
+        (check_report::VERSION, IDL_PRIM_nat32),
+        (check_report::DOUBLE_ASSIGNED_BLOCKS, 1),
+        (check_report::ORPHANED_BLOCKS, 1),
+        (check_report::UNLISTED_REGIONS, 2),
+        (check_report::FREE_BLOCKS, IDL_PRIM_nat32),
+        (check_report::TOTAL_BLOCKS, IDL_PRIM_nat32),
+    ] {
+        writer.write_leb128(mem, field as u64);
+        writer.write_sleb128(mem, field_type as i64);
+    }
+    // 1: block list
+    writer.write_sleb128(mem, IDL_CON_vec as i64);
+    writer.write_sleb128(mem, IDL_PRIM_nat16 as i64);
+    // 2: region list
+    writer.write_sleb128(mem, IDL_CON_vec as i64);
+    writer.write_sleb128(mem, IDL_PRIM_nat64 as i64);
+    // 3: page count mismatch list
+    writer.write_sleb128(mem, IDL_CON_vec as i64);
+    writer.write_sleb128(mem, 4);
+    // 4: page count mismatch
+    writer.write_sleb128(mem, IDL_CON_record as i64);
+    writer.write_leb128(mem, 3);
+    for field in [
+        check_report::REGION,
+        check_report::TABLE_PAGE_COUNT,
+        check_report::PAGE_COUNT,
+    ] {
+        writer.write_leb128(mem, field as u64);
+        writer.write_sleb128(mem, IDL_PRIM_nat64 as i64);
+    }
+
+    // Argument types
+    writer.write_leb128(mem, 1);
+    writer.write_sleb128(mem, 0);
+
+    // Argument value
+    write_block_list(
+        mem,
+        &mut writer,
+        status,
+        total_blocks,
+        BLOCK_MISMATCHED,
+        BLOCK_MISMATCHED,
+    );
+    writer.write_u16(mem, block_pages);
+    write_region_list(mem, &mut writer, &invalid_regions[..invalid_count]);
+    writer.write_leb128(mem, page_count_mismatches);
+    for index in 0..region_count {
+        let region = region_at(index);
+        if !magic_valid || region == NO_REGION {
+            continue;
+        }
+        let r = RegionObject::from_value(&region);
+        let page_count = (*r.0).page_count as u64;
+        let table_page_count = region_table_page_count(&r, region_block_count(&r), total_blocks);
+        if table_page_count != page_count {
+            writer.write_u64(mem, r.id().0);
+            writer.write_u64(mem, table_page_count);
+            writer.write_u64(mem, page_count);
+        }
+    }
+    writer.write_bool(mem, magic_valid);
+    writer.write_u32(mem, version);
+    write_block_list(
+        mem,
+        &mut writer,
+        status,
+        total_blocks,
+        BLOCK_DOUBLE_ASSIGNED,
+        BLOCK_DOUBLE_ASSIGNED,
+    );
+    write_block_list(
+        mem,
+        &mut writer,
+        status,
+        total_blocks,
+        BLOCK_REFERENCED | BLOCK_FREE | BLOCK_OWNED,
+        0,
+    );
+    write_region_list(mem, &mut writer, &unlisted_regions[..unlisted_count]);
+    writer.write_u32(mem, free_blocks);
+    writer.write_u32(mem, total_blocks);
+
+    if repair {
+        for block in 0..total_blocks as usize {
+            if status.get(block) & (BLOCK_REFERENCED | BLOCK_FREE | BLOCK_OWNED) == 0 {
+                // The former use of the block is unknown.
+                meta_data::free_blocks::push(BlockId(block as u16), block_pages as u8);
+            }
+        }
+    }
+
+    writer.finish()
+}
+
+// -- Region usage statistics.
+
+// Field hashes of the Candid report of `region_stats`, in ascending order.
+mod stats_report {
+    use crate::idl_writer::idl_hash;
+
+    pub const STABLE_MEMORY_PAGES: u32 = idl_hash("stable_memory_pages");
+    pub const ALLOCATED_REGIONS: u32 = idl_hash("allocated_regions");
+    pub const SLACK_PAGES: u32 = idl_hash("slack_pages");
+    pub const USED_PAGES: u32 = idl_hash("used_pages");
+    pub const EMPTY_REGIONS: u32 = idl_hash("empty_regions");
+    pub const REGIONS: u32 = idl_hash("regions");
+    pub const FREE_BLOCKS: u32 = idl_hash("free_blocks");
+    pub const TOTAL_BLOCKS: u32 = idl_hash("total_blocks");
+
+    const _: () = assert!(
+        STABLE_MEMORY_PAGES < ALLOCATED_REGIONS
+            && ALLOCATED_REGIONS < SLACK_PAGES
+            && SLACK_PAGES < USED_PAGES
+            && USED_PAGES < EMPTY_REGIONS
+            && EMPTY_REGIONS < REGIONS
+            && REGIONS < FREE_BLOCKS
+            && FREE_BLOCKS < TOTAL_BLOCKS
+    );
+
+    // Fields of a per-region record.
+    pub const ID: u32 = idl_hash("id");
+    pub const BLOCKS: u32 = idl_hash("blocks");
+    pub const PAGES: u32 = idl_hash("pages");
+
+    const _: () = assert!(ID < SLACK_PAGES && SLACK_PAGES < BLOCKS && BLOCKS < PAGES);
+}
+
+// Report the stable memory usage per region, as recorded in the block-region table.
+//
+// Returns a Candid-encoded report of the following Motoko type:
+// ```
+// {
+//   stable_memory_pages : Nat64; // logical stable memory size
+//   allocated_regions : Nat64; // number of region ids handed out so far
+//   total_blocks : Nat32; // number of allocated blocks
+//   free_blocks : Nat32; // number of blocks in the free block list
+//   used_pages : Nat64; // pages in use by all regions
+//   slack_pages : Nat64; // unused pages in the last blocks of all regions
+//   empty_regions : Nat64; // allocated region ids without blocks
+//   regions : [{ id : Nat64; blocks : Nat32; pages : Nat64; slack_pages : Nat64 }];
+// }
+// ```
+// Only regions that own blocks are listed, by ascending id. Region ids are never recycled,
+// so the reserved ids and the empty or freed regions are only counted, keeping the report
+// bounded by the number of blocks.
+#[ic_mem_fn]
+pub unsafe fn region_stats<M: Memory>(mem: &mut M) -> Value {
+    use crate::idl::{IDL_CON_record, IDL_CON_vec, IDL_PRIM_nat32, IDL_PRIM_nat64};
+    use crate::idl_writer::IdlWriter;
+    use meta_data::size::pages_in_block;
+
+    let (allocated_regions, total_blocks, free_blocks) = if has_region_meta_data() {
+        (
+            meta_data::total_allocated_regions::get(),
+            meta_data::total_allocated_blocks::get(),
+            meta_data::free_blocks::count(),
+        )
+    } else {
+        (0, 0, 0)
+    };
+
+    // Temporary tables, no allocation barrier needed.
+    // Collect the distinct ids of the regions owning blocks.
+    let ids = scratch_slice::<M, u64>(mem, total_blocks as usize);
+    let mut region_count = 0;
+    for block in 0..total_blocks {
+        if let Some((rid, _, _)) = meta_data::block_region_table::get(BlockId(block as u16)) {
+            ids[region_count] = rid.0;
+            region_count += 1;
+        }
+    }
+    ids[..region_count].sort_unstable();
+    let mut distinct = 0;
+    for index in 0..region_count {
+        if distinct == 0 || ids[distinct - 1] != ids[index] {
+            ids[distinct] = ids[index];
+            distinct += 1;
+        }
+    }
+    let region_count = distinct;
+    let ids = &ids[..region_count];
+
+    let blocks = scratch_slice::<M, u32>(mem, region_count);
+    let pages = scratch_slice::<M, u64>(mem, region_count);
+
+    let mut used_pages = 0;
+    for block in 0..total_blocks {
+        if let Some((rid, _, page_count)) =
+            meta_data::block_region_table::get(BlockId(block as u16))
+        {
+            used_pages += page_count as u64;
+            let index = ids.binary_search(&rid.0).unwrap();
+            blocks[index] += 1;
+            pages[index] += page_count as u64;
+        }
+    }
+    // Ids of an inconsistent table may exceed the allocated regions.
+    let empty_regions = allocated_regions.saturating_sub(region_count as u64);
+    let slack_pages_of =
+        |index: usize| blocks[index] as u64 * pages_in_block() as u64 - pages[index];
+    let slack_pages = (0..region_count).map(slack_pages_of).sum::<u64>();
+
+    let mut writer = IdlWriter::new(mem);
+
+    // Type table
+    writer.write_leb128(mem, 3);
+    // 0: report record
+    writer.write_sleb128(mem, IDL_CON_record as i64);
+    writer.write_leb128(mem, 8);
+    for (field, field_type) in [
+        (stats_report::STABLE_MEMORY_PAGES, IDL_PRIM_nat64),
+        (stats_report::ALLOCATED_REGIONS, IDL_PRIM_nat64),
+        (stats_report::SLACK_PAGES, IDL_PRIM_nat64),
+        (stats_report::USED_PAGES, IDL_PRIM_nat64),
+        (stats_report::EMPTY_REGIONS, IDL_PRIM_nat64),
+        (stats_report::REGIONS, 1),
+        (stats_report::FREE_BLOCKS, IDL_PRIM_nat32),
+        (stats_report::TOTAL_BLOCKS, IDL_PRIM_nat32),
+    ] {
+        writer.write_leb128(mem, field as u64);
+        writer.write_sleb128(mem, field_type as i64);
+    }
+    // 1: region list
+    writer.write_sleb128(mem, IDL_CON_vec as i64);
+    writer.write_sleb128(mem, 2);
+    // 2: region record
+    writer.write_sleb128(mem, IDL_CON_record as i64);
+    writer.write_leb128(mem, 4);
+    for (field, field_type) in [
+        (stats_report::ID, IDL_PRIM_nat64),
+        (stats_report::SLACK_PAGES, IDL_PRIM_nat64),
+        (stats_report::BLOCKS, IDL_PRIM_nat32),
+        (stats_report::PAGES, IDL_PRIM_nat64),
+    ] {
+        writer.write_leb128(mem, field as u64);
+        writer.write_sleb128(mem, field_type as i64);
+    }
+
+    // Argument types
+    writer.write_leb128(mem, 1);
+    writer.write_sleb128(mem, 0);
+
+    // Argument value
+    writer.write_u64(mem, crate::stable_mem::size());
+    writer.write_u64(mem, allocated_regions);
+    writer.write_u64(mem, slack_pages);
+    writer.write_u64(mem, used_pages);
+    writer.write_u64(mem, empty_regions);
+    writer.write_leb128(mem, region_count as u64);
+    for index in 0..region_count {
+        writer.write_u64(mem, ids[index]);
+        writer.write_u64(mem, slack_pages_of(index));
+        writer.write_u32(mem, blocks[index]);
+        writer.write_u64(mem, pages[index]);
+    }
+    writer.write_u32(mem, free_blocks);
+    writer.write_u32(mem, total_blocks);
+
+    writer.finish()
+}
+
 // -- Region load operations.
 
 #[ic_mem_fn]
@@ -1161,6 +2126,19 @@ pub(crate) unsafe fn region_load_blob<M: Memory>(
     blob_val
 }
 
+// Load `len` bytes of the region to `dst` in main memory, without an intermediate blob.
+// Used by the bulk loads of arrays, directly into the array payload.
+#[ic_mem_fn]
+pub unsafe fn region_load_bytes<M: Memory>(
+    mem: &mut M,
+    r: Value,
+    offset: u64,
+    dst: *mut u8,
+    len: usize,
+) {
+    region_load(mem, r, offset, core::slice::from_raw_parts_mut(dst, len));
+}
+
 // -- Region store operations.
 
 #[ic_mem_fn]
@@ -1215,3 +2193,16 @@ pub(crate) unsafe fn region_store_blob<M: Memory>(mem: &mut M, r: Value, offset:
         region_store(mem, r, offset + (len / 2) as u64, bytes_high);
     }
 }
+
+// Store `len` bytes from `src` in main memory to the region, without an intermediate blob.
+// Used by the bulk stores of arrays.
+#[ic_mem_fn]
+pub unsafe fn region_store_bytes<M: Memory>(
+    mem: &mut M,
+    r: Value,
+    offset: u64,
+    src: *const u8,
+    len: usize,
+) {
+    region_store(mem, r, offset, core::slice::from_raw_parts(src, len));
+}
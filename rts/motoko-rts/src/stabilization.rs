@@ -15,6 +15,7 @@
 //!  
 //! See `GraphCopyStabilization.md` for the stable format specification and the employed algorithm.
 
+pub mod checksum;
 pub mod deserialization;
 pub mod graph_copy;
 pub mod layout;
@@ -36,7 +37,8 @@ use self::layout::StableValue;
 extern "C" {
     pub fn moc_stabilization_instruction_limit() -> u64;
     pub fn moc_stable_memory_access_limit() -> u64;
-    fn ic0_performance_counter(number: u32) -> u64;
+    pub fn moc_stabilization_deduplication() -> bool;
+    pub fn ic0_performance_counter(number: u32) -> u64;
 }
 
 // Dummy value used for non-stable objects that are potentially reachable from
@@ -0,0 +1,157 @@
+//! Integrity protection of the graph-copy-based stable format.
+//!
+//! The serialized data is divided into chunks of `CHUNK_SIZE` bytes, each protected by a CRC32
+//! checksum. The checksums are computed after the serialization has been completed, as the
+//! serialization patches pointers in place, and are verified before the deserialization accesses
+//! the serialized data. Both steps are part of the incremental graph copy and are bounded by its
+//! execution limits.
+//!
+//! The checksum table is stored after the type descriptor, and protected together with the type
+//! descriptor by a checksum in the metadata (see `motoko_stable_format::metadata`).
+//!
+//! A corruption of the stable memory traps with the stable memory offset of the corrupted chunk,
+//! rather than deserializing a broken heap.
+
+use core::fmt::Write;
+
+use crate::{
+    barriers::allocation_barrier,
+    constants::KB,
+    memory::{alloc_blob, Memory},
+    print::WriteBuf,
+    rts_trap_with,
+    stable_mem::ic0_stable64_read,
+    types::{Bytes, Value, TAG_BLOB_B},
+};
+
+use motoko_stable_format::crc32::crc32;
+
+pub use motoko_stable_format::metadata::CHUNK_SIZE;
+
+const CHECKSUM_SIZE: usize = core::mem::size_of::<u32>();
+
+/// Chunk checksums of the serialized data, computed or verified in increments.
+pub struct ChunkChecksums {
+    /// Blob with a 32-bit little-endian checksum per chunk.
+    table: Value,
+    /// Stable memory address of the serialized data.
+    data_start: u64,
+    data_length: u64,
+    /// Offset of the next chunk to be computed or verified, relative to `data_start`.
+    position: u64,
+}
+
+impl ChunkChecksums {
+    /// Prepare the computation of the checksums of the completely serialized data.
+    pub unsafe fn allocate<M: Memory>(
+        mem: &mut M,
+        data_start: u64,
+        data_length: u64,
+    ) -> ChunkChecksums {
+        let chunks = (data_length + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let table = alloc_blob(mem, TAG_BLOB_B, Bytes(chunks as usize * CHECKSUM_SIZE));
+        allocation_barrier(table);
+        ChunkChecksums {
+            table,
+            data_start,
+            data_length,
+            position: 0,
+        }
+    }
+
+    /// Prepare the verification of the serialized data against the stored checksum `table`.
+    pub unsafe fn load(table: Value, data_start: u64, data_length: u64) -> ChunkChecksums {
+        let chunks = (data_length + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        // The table itself is protected by the metadata checksum.
+        assert_eq!(
+            table.as_blob().len().as_usize() as u64,
+            chunks * CHECKSUM_SIZE as u64
+        );
+        ChunkChecksums {
+            table,
+            data_start,
+            data_length,
+            position: 0,
+        }
+    }
+
+    pub fn table(&self) -> Value {
+        self.table
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.position >= self.data_length
+    }
+
+    /// Amount of serialized data that has been read for computation or verification.
+    pub fn processed_length(&self) -> u64 {
+        self.position
+    }
+
+    /// Compute the checksum of the next chunk.
+    pub unsafe fn compute_next(&mut self) {
+        let checksum = self.next_chunk_checksum();
+        *self.table_entry() = checksum.to_le_bytes();
+        self.position += CHUNK_SIZE;
+    }
+
+    /// Verify the checksum of the next chunk.
+    /// Returns the stable memory address of the chunk as error if the checksum does not match.
+    pub unsafe fn verify_next(&mut self) -> Result<(), u64> {
+        let checksum = self.next_chunk_checksum();
+        if *self.table_entry() != checksum.to_le_bytes() {
+            return Err(self.data_start + self.position);
+        }
+        self.position += CHUNK_SIZE;
+        Ok(())
+    }
+
+    unsafe fn table_entry(&self) -> *mut [u8; CHECKSUM_SIZE] {
+        let index = (self.position / CHUNK_SIZE) as usize;
+        let payload = self.table.as_blob_mut().payload_addr();
+        payload.add(index * CHECKSUM_SIZE) as *mut [u8; CHECKSUM_SIZE]
+    }
+
+    unsafe fn next_chunk_checksum(&self) -> u32 {
+        debug_assert!(!self.is_completed());
+        const BUFFER_SIZE: usize = KB;
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let end = core::cmp::min(self.position + CHUNK_SIZE, self.data_length);
+        let mut offset = self.position;
+        let mut checksum = 0;
+        while offset < end {
+            let length = core::cmp::min(end - offset, BUFFER_SIZE as u64) as usize;
+            ic0_stable64_read(
+                buffer.as_mut_ptr() as u64,
+                self.data_start + offset,
+                length as u64,
+            );
+            checksum = crc32(checksum, &buffer[..length]);
+            offset += length as u64;
+        }
+        checksum
+    }
+}
+
+/// Checksum of the payloads of a sequence of blobs.
+pub unsafe fn blobs_checksum(blobs: &[Value]) -> u32 {
+    blobs.iter().fold(0, |checksum, blob| {
+        let blob = blob.as_blob();
+        let payload = core::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize());
+        crc32(checksum, payload)
+    })
+}
+
+/// Trap on a checksum mismatch of the `area` stored at the stable memory `address`.
+pub unsafe fn trap_corruption(area: &str, address: u64) -> ! {
+    const MAX_MESSAGE_LENGTH: usize = 256;
+    let mut buffer = [0u8; MAX_MESSAGE_LENGTH];
+    let mut out = WriteBuf::new(&mut buffer);
+    let _ = write!(
+        out,
+        "Stable memory corruption detected in the {} at offset {}",
+        area, address
+    );
+    let length = out.length();
+    rts_trap_with(core::str::from_utf8_unchecked(&buffer[..length]))
+}
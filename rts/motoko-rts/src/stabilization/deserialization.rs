@@ -13,6 +13,7 @@ use crate::{
 use self::{scan_stack::ScanStack, stable_memory_access::StableMemoryAccess};
 
 use super::{
+    checksum::{trap_corruption, ChunkChecksums},
     clear_stable_memory,
     graph_copy::{limit::ExecutionMonitor, GraphCopy},
     layout::{deserialize, StableValue},
@@ -26,6 +27,10 @@ pub struct Deserialization {
     stable_root: Option<Value>,
     limit: ExecutionMonitor,
     clear_position: u64,
+    /// Pending verification of the serialized data before the graph copy, see `checksum.rs`.
+    verification: Option<ChunkChecksums>,
+    /// Amount of verified data after the completed verification.
+    verified_length: u64,
 }
 
 /// Helper type to pass serialization context instead of closures.
@@ -49,7 +54,7 @@ impl<'a, M: Memory> DeserializationContext<'a, M> {
 /// Graph-copy-based deserialization.
 /// Usage:
 /// ```
-/// let deserialization = Deserialization::start(mem, stable_start, stable_size);
+/// let deserialization = Deserialization::start(mem, stable_start, stable_size, chunk_checksums);
 /// while !deserialization.is_completed() {
 ///     deserialization.copy_increment();
 /// }
@@ -58,10 +63,19 @@ impl<'a, M: Memory> DeserializationContext<'a, M> {
 /// mechanism to avoid instruction limit exceeding.
 impl Deserialization {
     /// Start the deserialization, followed by a series of copy increments.
-    pub fn start<M: Memory>(mem: &mut M, stable_start: u64, stable_size: u64) -> Deserialization {
+    /// If `chunk_checksums` are stored for the serialized data, the data is verified in the first
+    /// increments, before the graph copy starts.
+    pub fn start<M: Memory>(
+        mem: &mut M,
+        stable_start: u64,
+        stable_size: u64,
+        chunk_checksums: Option<Value>,
+    ) -> Deserialization {
         let from_space = StableMemoryAccess::open(stable_start, stable_size);
         let scan_stack = unsafe { ScanStack::new(mem) };
         let limit = ExecutionMonitor::new();
+        let verification = chunk_checksums
+            .map(|table| unsafe { ChunkChecksums::load(table, stable_start, stable_size) });
         let mut deserialization = Deserialization {
             from_space,
             scan_stack,
@@ -70,11 +84,33 @@ impl Deserialization {
             stable_root: None,
             limit,
             clear_position: stable_start,
+            verification,
+            verified_length: 0,
         };
-        deserialization.start(mem, StableValue::serialize(Value::from_ptr(0)));
+        if deserialization.verification.is_none() {
+            deserialization.start_graph_copy(mem);
+        }
         deserialization
     }
 
+    fn start_graph_copy<M: Memory>(&mut self, mem: &mut M) {
+        self.start(mem, StableValue::serialize(Value::from_ptr(0)));
+    }
+
+    /// Verify the next chunk of the serialized data, and start the graph copy once the
+    /// verification has been completed.
+    unsafe fn verify_increment<M: Memory>(&mut self, mem: &mut M) {
+        let verification = self.verification.as_mut().unwrap();
+        if let Err(address) = verification.verify_next() {
+            trap_corruption("serialized data chunk", address);
+        }
+        if verification.is_completed() {
+            self.verified_length = verification.processed_length();
+            self.verification = None;
+            self.start_graph_copy(mem);
+        }
+    }
+
     pub fn get_stable_root(&self) -> Value {
         self.stable_root.unwrap()
     }
@@ -119,7 +155,11 @@ impl Deserialization {
         let deserialized_memory = unsafe { deserialized_size() as u64 };
         debug_assert!(self.clear_position >= self.stable_start);
         let cleared_memory = self.clear_position - self.stable_start;
-        deserialized_memory + cleared_memory
+        let verified_memory = match &self.verification {
+            Some(verification) => verification.processed_length(),
+            None => self.verified_length,
+        };
+        deserialized_memory + cleared_memory + verified_memory
     }
 }
 
@@ -159,6 +199,10 @@ impl GraphCopy<StableValue, Value, u32> for Deserialization {
     /// Note:
     /// * The deserialized memory may contain free space at a partition end.
     fn scan<M: Memory>(&mut self, mem: &mut M) {
+        if self.verification.is_some() {
+            unsafe { self.verify_increment(mem) };
+            return;
+        }
         let target_object = unsafe { self.scan_stack.pop() };
         debug_assert!(target_object != STACK_EMPTY);
         unsafe {
@@ -178,7 +222,7 @@ impl GraphCopy<StableValue, Value, u32> for Deserialization {
     }
 
     fn scanning_completed(&self) -> bool {
-        unsafe { self.scan_stack.is_empty() }
+        self.verification.is_none() && unsafe { self.scan_stack.is_empty() }
     }
 
     fn cleanup_completed(&self) -> bool {
@@ -187,7 +231,7 @@ impl GraphCopy<StableValue, Value, u32> for Deserialization {
         self.clear_position >= self.stable_end()
     }
 
-    fn cleanup(&mut self) {
+    fn cleanup<M: Memory>(&mut self, _mem: &mut M) {
         // Optimum value according to experimental measurements:
         // Smallest chunk size that does not cause noticeable performance regression.
         // The granularity is still small enough to meet the instruction limit.
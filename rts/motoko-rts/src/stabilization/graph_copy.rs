@@ -38,7 +38,7 @@ pub trait GraphCopy<S: Copy, T: Copy, P: Copy + Default> {
 
     /// Perform optional cleanup work after completed scanning and copying.
     /// This work can be done in incremental steps.
-    fn cleanup(&mut self) {}
+    fn cleanup<M: Memory>(&mut self, _mem: &mut M) {}
 
     /// Determine whether the entire graph copy algorithm has been completed.
     /// This includes an incremental copying and an incremental cleanup phase.
@@ -59,7 +59,7 @@ pub trait GraphCopy<S: Copy, T: Copy, P: Copy + Default> {
         }
         if self.scanning_completed() {
             while !self.cleanup_completed() && !self.time_over() {
-                self.cleanup();
+                self.cleanup(mem);
             }
             if self.cleanup_completed() {
                 self.complete();
@@ -2,28 +2,34 @@ mod metadata;
 mod performance;
 
 use motoko_rts_macros::ic_mem_fn;
+use motoko_stable_format::metadata::UpgradeStatistics;
 
 use crate::{
+    compatibility::{memory_compatible, report::CompatibilityReport, TypeDescriptor},
     gc::incremental::{is_gc_stopped, resume_gc, stop_gc},
     memory::Memory,
-    persistence::{
-        compatibility::{memory_compatible, TypeDescriptor},
-        set_upgrade_instructions,
-    },
+    persistence::{persistent_upgrade_history, set_upgrade_instructions},
     rts_trap_with,
     stabilization::ic::metadata::StabilizationMetadata,
     stable_mem::{self, moc_stable_mem_set_size, PAGE_SIZE},
     types::Value,
+    upgrade_history::{
+        ic::{complete_upgrade, measure_compatibility_check, start_upgrade},
+        PersistenceMode,
+    },
 };
 
-use self::{metadata::UpgradeStatistics, performance::InstructionMeter};
+use self::performance::InstructionMeter;
 
 use super::graph_copy::GraphCopy;
-use super::{deserialization::Deserialization, serialization::Serialization};
+use super::{
+    deserialization::Deserialization, moc_stabilization_deduplication, serialization::Serialization,
+};
 
 struct StabilizationState {
     old_candid_data: Value,
     old_type_offsets: Value,
+    old_field_names: Value,
     completed: bool,
     serialization: Serialization,
     instruction_meter: InstructionMeter,
@@ -34,10 +40,12 @@ impl StabilizationState {
         serialization: Serialization,
         old_candid_data: Value,
         old_type_offsets: Value,
+        old_field_names: Value,
     ) -> StabilizationState {
         StabilizationState {
             old_candid_data,
             old_type_offsets,
+            old_field_names,
             completed: false,
             serialization,
             instruction_meter: InstructionMeter::new(),
@@ -59,6 +67,8 @@ pub unsafe extern "C" fn is_graph_stabilization_started() -> bool {
 /// `old_candid_data`: A blob encoding the Candid type as a table.
 /// `old_type_offsets`: A blob encoding the type offsets in the Candid type table.
 ///   Type index 0 represents the stable actor object to be serialized.
+/// `old_field_names`: A blob with the names of the record fields and variant cases in the type
+///   table, for reporting incompatibilities on the subsequent destabilization.
 /// Note:
 /// - Once started, the heap is invalidated. All application messages must be blocked after this start.
 #[ic_mem_fn(ic_only)]
@@ -67,16 +77,24 @@ pub unsafe fn start_graph_stabilization<M: Memory>(
     stable_actor: Value,
     old_candid_data: Value,
     old_type_offsets: Value,
+    old_field_names: Value,
 ) {
     assert!(STABILIZATION_STATE.is_none());
     assert!(is_gc_stopped());
+    start_upgrade();
     let stable_memory_pages = stable_mem::size(); // Backup the virtual size.
     let serialized_data_start = stable_memory_pages * PAGE_SIZE;
-    let serialization = Serialization::start(mem, stable_actor, serialized_data_start);
+    let serialization = Serialization::start(
+        mem,
+        stable_actor,
+        serialized_data_start,
+        moc_stabilization_deduplication(),
+    );
     STABILIZATION_STATE = Some(StabilizationState::new(
         serialization,
         old_candid_data,
         old_type_offsets,
+        old_field_names,
     ));
 }
 
@@ -124,6 +142,9 @@ unsafe fn write_metadata() {
         serialized_data_start,
         serialized_data_length,
         type_descriptor,
+        field_names: state.old_field_names,
+        chunk_checksums: Some(state.serialization.chunk_checksums()),
+        upgrade_history: *persistent_upgrade_history(),
     };
     state.instruction_meter.stop();
     metadata.store(&mut state.instruction_meter);
@@ -148,6 +169,8 @@ static mut DESTABILIZATION_STATE: Option<DestabilizationState> = None;
 /// `new_candid_data`: A blob encoding the Candid type as a table.
 /// `new_type_offsets`: A blob encoding the type offsets in the Candid type table.
 ///   Type index 0 represents the stable actor object to be serialized.
+/// `new_field_names`: A blob with the names of the record fields and variant cases in the type
+///   table, for reporting incompatibilities.
 /// Traps if the stable state is incompatible with the new program version and the upgrade is not
 /// possible.
 #[ic_mem_fn(ic_only)]
@@ -155,6 +178,7 @@ pub unsafe fn start_graph_destabilization<M: Memory>(
     mem: &mut M,
     new_candid_data: Value,
     new_type_offsets: Value,
+    new_field_names: Value,
 ) {
     assert!(DESTABILIZATION_STATE.is_none());
 
@@ -162,9 +186,22 @@ pub unsafe fn start_graph_destabilization<M: Memory>(
     instruction_meter.start();
     let mut new_type_descriptor = TypeDescriptor::new(new_candid_data, new_type_offsets);
     let (metadata, statistics) = StabilizationMetadata::load(mem);
+    // The persistent metadata has been reinitialized on the upgrade.
+    *persistent_upgrade_history() = metadata.upgrade_history;
     let mut old_type_descriptor = metadata.type_descriptor;
-    if !memory_compatible(mem, &mut old_type_descriptor, &mut new_type_descriptor) {
-        rts_trap_with("Memory-incompatible program upgrade");
+    let old_field_names = metadata.field_names;
+    let mut report = CompatibilityReport::new();
+    if !measure_compatibility_check(|| {
+        memory_compatible(
+            mem,
+            &mut old_type_descriptor,
+            &mut new_type_descriptor,
+            old_field_names,
+            new_field_names,
+            &mut report,
+        )
+    }) {
+        report.trap();
     }
     // Restore the virtual size.
     moc_stable_mem_set_size(metadata.serialized_data_start / PAGE_SIZE);
@@ -176,6 +213,7 @@ pub unsafe fn start_graph_destabilization<M: Memory>(
         mem,
         metadata.serialized_data_start,
         metadata.serialized_data_length,
+        metadata.chunk_checksums,
     );
     instruction_meter.stop();
     DESTABILIZATION_STATE = Some(DestabilizationState {
@@ -230,15 +268,11 @@ unsafe fn memory_sanity_check<M: Memory>(_mem: &mut M) {
         };
 
         let state = DESTABILIZATION_STATE.as_mut().unwrap();
+        let stable_root = &mut state.deserialization.get_stable_root() as *mut Value;
         let unused_root = &mut Value::from_scalar(0) as *mut Value;
-        let roots = [
-            &mut state.deserialization.get_stable_root() as *mut Value,
-            unused_root,
-            unused_root,
-            unused_root,
-            unused_root,
-            unused_root,
-        ];
+        // Independent of the number of GC roots.
+        let roots =
+            core::array::from_fn(|index| if index == 0 { stable_root } else { unused_root });
         check_memory(
             _mem,
             get_partitioned_heap(),
@@ -250,9 +284,14 @@ unsafe fn memory_sanity_check<M: Memory>(_mem: &mut M) {
 
 unsafe fn record_upgrade_costs() {
     let state = DESTABILIZATION_STATE.as_ref().unwrap();
-    let total_instructions = state.stabilization_statistics.stabilization_instructions
-        + state.instruction_meter.total_elapsed();
-    set_upgrade_instructions(total_instructions);
+    let stabilization_instructions = state.stabilization_statistics.stabilization_instructions;
+    let destabilization_instructions = state.instruction_meter.total_elapsed();
+    set_upgrade_instructions(stabilization_instructions + destabilization_instructions);
+    complete_upgrade(
+        PersistenceMode::GraphCopyStabilization,
+        stabilization_instructions,
+        destabilization_instructions,
+    );
 }
 
 /// Returns the deserialized stable actor root after the completed destabilization.
@@ -1,69 +1,41 @@
-//! Graph-copy-bazed serialization format:
-//!
-//! (Very first word is zeroed and backed up in last page)
-//! -- Stable memory
-//! Raw stable memory or region data, size S in pages
-//! -- Stable variables
-//! Serialized data address N:
-//!   Serialized object graph, length L
-//!   (possible zero padding)
-//! Type descriptor address M:
-//!   Candid type table
-//!     Byte length (u64)
-//!     Data
-//!   Type offset table
-//!     Byte length (u64)
-//!     Data
-//!   (possible zero padding)
-//! -- Last physical page (metadata):
-//!   (zero padding to align at page end)
-//!   Upgrade statistics (instructions) (u64)
-//!   Serialized data address N (u64)
-//!   Serialized data length L (u64)
-//!   Type descriptor address M (u64)
-//!   First word of page 0
-//!   Version 3 or 4 (u32) (match with `VERSION_GRAPH_COPY_NO_REGIONS` and `VERSION_GRAPH_COPY_REGIONS` in `region.rs` and `compile.ml`.
-//! -- page end
+//! Storing and loading the metadata of the graph-copy-based stabilization, see
+//! `motoko_stable_format::metadata` for the stable memory layout.
 
 use crate::{
     barriers::allocation_barrier,
+    compatibility::TypeDescriptor,
     memory::{alloc_blob, Memory},
-    persistence::compatibility::TypeDescriptor,
+    persistence::DEFAULT_VALUE,
     region::{
         VERSION_GRAPH_COPY_NO_REGIONS, VERSION_GRAPH_COPY_REGIONS, VERSION_STABLE_HEAP_NO_REGIONS,
         VERSION_STABLE_HEAP_REGIONS,
     },
-    stabilization::{clear_stable_memory, grant_stable_space},
+    stabilization::{
+        checksum::{blobs_checksum, trap_corruption},
+        clear_stable_memory, grant_stable_space,
+    },
     stable_mem::{
         get_version, ic0_stable64_read, ic0_stable64_size, ic0_stable64_write, read_u32, read_u64,
         set_version, write_u32, write_u64, PAGE_SIZE,
     },
     types::{size_of, Bytes, Tag, Value, TAG_BLOB_B},
+    upgrade_history::{UpgradeHistory, UPGRADE_HISTORY_MARKER},
 };
 
-use super::performance::InstructionMeter;
-
-#[repr(C)]
-#[derive(Default)]
-pub struct UpgradeStatistics {
-    pub stabilization_instructions: u64,
-}
+use motoko_stable_format::metadata::{LastPageRecord, UpgradeStatistics, CHECKSUM_MARKER};
 
-#[repr(C)]
-#[derive(Default)]
-struct LastPageRecord {
-    statistics: UpgradeStatistics,
-    serialized_data_address: u64,
-    serialized_data_length: u64,
-    type_descriptor_address: u64,
-    first_word_backup: u32,
-    version: u32,
-}
+use super::performance::InstructionMeter;
 
 pub struct StabilizationMetadata {
     pub serialized_data_start: u64,
     pub serialized_data_length: u64,
     pub type_descriptor: TypeDescriptor,
+    /// Names of the record fields and variant cases in the type descriptor, or `DEFAULT_VALUE`
+    /// if the data was stabilized by an earlier version without field names.
+    pub field_names: Value,
+    /// Blob of the chunk checksums, absent if stored by an older program version.
+    pub chunk_checksums: Option<Value>,
+    pub upgrade_history: UpgradeHistory,
 }
 
 impl StabilizationMetadata {
@@ -104,6 +76,18 @@ impl StabilizationMetadata {
         Self::write_blob(offset, descriptor.type_offsets());
     }
 
+    /// Loads the field names following the type descriptor. Earlier versions did not store the
+    /// field names, such that the type descriptor is only followed by the zero padding before the
+    /// last page record, which is read as an empty blob of field names.
+    fn load_field_names<M: Memory>(mem: &mut M, offset: &mut u64) -> Value {
+        let length_size = size_of::<u64>().to_bytes().as_usize() as u64;
+        let limit = Self::metadata_location();
+        if *offset + length_size > limit || read_u64(*offset) > limit - *offset - length_size {
+            return DEFAULT_VALUE;
+        }
+        Self::read_blob(mem, TAG_BLOB_B, offset)
+    }
+
     fn read_length(offset: &mut u64) -> u64 {
         let length = read_u64(*offset);
         // Note: Do not use `types::size_of()` as it rounds to 64-bit words.
@@ -172,7 +156,20 @@ impl StabilizationMetadata {
         Self::align_page_start(&mut offset);
         let type_descriptor_address = offset;
         Self::save_type_descriptor(&mut offset, &self.type_descriptor);
+        Self::write_blob(&mut offset, self.field_names);
+        let chunk_checksums = self.chunk_checksums.unwrap();
+        Self::write_blob(&mut offset, chunk_checksums);
+        let metadata_checksum = unsafe {
+            blobs_checksum(&[
+                self.type_descriptor.candid_data(),
+                self.type_descriptor.type_offsets(),
+                self.field_names,
+                chunk_checksums,
+            ])
+        };
         Self::align_page_start(&mut offset);
+        // Dedicated last page, such that the metadata does not overwrite the type descriptor.
+        Self::ensure_space(offset, PAGE_SIZE);
         let first_word_backup = read_u32(0);
         // Clear very first word that is backed up in the last page.
         // This ensures compatibility with old legacy version 0 using no
@@ -183,6 +180,10 @@ impl StabilizationMetadata {
             stabilization_instructions: measurement.total_elapsed(),
         };
         let last_page_record = LastPageRecord {
+            metadata_checksum: metadata_checksum as u64,
+            checksum_marker: CHECKSUM_MARKER,
+            upgrade_history: self.upgrade_history,
+            upgrade_history_marker: UPGRADE_HISTORY_MARKER,
             statistics,
             serialized_data_address: self.serialized_data_start,
             serialized_data_length: self.serialized_data_length,
@@ -202,10 +203,33 @@ impl StabilizationMetadata {
         write_u32(0, last_page_record.first_word_backup);
         let mut offset = last_page_record.type_descriptor_address;
         let type_descriptor = Self::load_type_descriptor(mem, &mut offset);
+        let field_names = Self::load_field_names(mem, &mut offset);
+        let chunk_checksums = if last_page_record.has_checksums() {
+            let chunk_checksums = Self::read_blob(mem, TAG_BLOB_B, &mut offset);
+            let metadata_checksum = unsafe {
+                blobs_checksum(&[
+                    type_descriptor.candid_data(),
+                    type_descriptor.type_offsets(),
+                    field_names,
+                    chunk_checksums,
+                ])
+            };
+            if metadata_checksum as u64 != last_page_record.metadata_checksum {
+                unsafe {
+                    trap_corruption("type descriptor", last_page_record.type_descriptor_address)
+                };
+            }
+            Some(chunk_checksums)
+        } else {
+            None
+        };
         let metadata = StabilizationMetadata {
             serialized_data_start: last_page_record.serialized_data_address,
             serialized_data_length: last_page_record.serialized_data_length,
             type_descriptor,
+            field_names,
+            chunk_checksums,
+            upgrade_history: last_page_record.upgrade_history(),
         };
         (metadata, last_page_record.statistics)
     }
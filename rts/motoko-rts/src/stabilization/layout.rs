@@ -62,113 +62,53 @@ mod stable_region;
 mod stable_some;
 mod stable_variant;
 
-/// Different kinds of objects used in the stable format.
-#[repr(u64)]
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum StableObjectKind {
-    ArrayImmutable = 1,
-    ArrayMutable = 2,
-    ArrayTuple = 3,
-    ArraySharedFunction = 4,
-    MutBox = 5,
-    Object = 6,
-    BlobBytes = 7,
-    BlobText = 8,
-    BlobPrincipal = 9,
-    BlobActor = 10,
-    Bits64Unsigned = 11,
-    Bits64Signed = 12,
-    Bits64Float = 13,
-    Region = 14,
-    Variant = 15,
-    Concat = 16,
-    BigInt = 17,
-    Some = 18,
-}
+pub use motoko_stable_format::layout::StableObjectKind;
 
 #[repr(C)]
 pub struct StableTag(u64);
 
-impl StableObjectKind {
-    pub fn encode(&self) -> StableTag {
-        StableTag(*self as u64)
+impl From<StableObjectKind> for StableTag {
+    fn from(kind: StableObjectKind) -> StableTag {
+        StableTag(kind as u64)
     }
 }
 
 impl StableTag {
     pub fn decode(&self) -> StableObjectKind {
-        const STABLE_TAG_ARRAY_IMMUTABLE: u64 = StableObjectKind::ArrayImmutable as u64;
-        const STABLE_TAG_ARRAY_MUTABLE: u64 = StableObjectKind::ArrayMutable as u64;
-        const STABLE_TAG_ARRAY_TUPLE: u64 = StableObjectKind::ArrayTuple as u64;
-        const STABLE_TAG_ARRAY_SHARED_FUNCTION: u64 = StableObjectKind::ArraySharedFunction as u64;
-        const STABLE_TAG_MUTBOX: u64 = StableObjectKind::MutBox as u64;
-        const STABLE_TAG_OBJECT: u64 = StableObjectKind::Object as u64;
-        const STABLE_TAG_BLOB_BYTES: u64 = StableObjectKind::BlobBytes as u64;
-        const STABLE_TAG_BLOB_TEXT: u64 = StableObjectKind::BlobText as u64;
-        const STABLE_TAG_BLOB_PRINCIPAL: u64 = StableObjectKind::BlobPrincipal as u64;
-        const STABLE_TAG_BLOB_ACTOR: u64 = StableObjectKind::BlobActor as u64;
-        const STABLE_TAG_BITS64_UNSIGNED: u64 = StableObjectKind::Bits64Unsigned as u64;
-        const STABLE_TAG_BITS64_SIGNED: u64 = StableObjectKind::Bits64Signed as u64;
-        const STABLE_TAG_BITS64_FLOAT: u64 = StableObjectKind::Bits64Float as u64;
-        const STABLE_TAG_REGION: u64 = StableObjectKind::Region as u64;
-        const STABLE_TAG_VARIANT: u64 = StableObjectKind::Variant as u64;
-        const STABLE_TAG_CONCAT: u64 = StableObjectKind::Concat as u64;
-        const STABLE_TAG_BIGINT: u64 = StableObjectKind::BigInt as u64;
-        const STABLE_TAG_SOME: u64 = StableObjectKind::Some as u64;
-        match self.0 {
-            STABLE_TAG_ARRAY_IMMUTABLE => StableObjectKind::ArrayImmutable,
-            STABLE_TAG_ARRAY_MUTABLE => StableObjectKind::ArrayMutable,
-            STABLE_TAG_ARRAY_TUPLE => StableObjectKind::ArrayTuple,
-            STABLE_TAG_ARRAY_SHARED_FUNCTION => StableObjectKind::ArraySharedFunction,
-            STABLE_TAG_MUTBOX => StableObjectKind::MutBox,
-            STABLE_TAG_OBJECT => StableObjectKind::Object,
-            STABLE_TAG_BLOB_BYTES => StableObjectKind::BlobBytes,
-            STABLE_TAG_BLOB_TEXT => StableObjectKind::BlobText,
-            STABLE_TAG_BLOB_PRINCIPAL => StableObjectKind::BlobPrincipal,
-            STABLE_TAG_BLOB_ACTOR => StableObjectKind::BlobActor,
-            STABLE_TAG_BITS64_UNSIGNED => StableObjectKind::Bits64Unsigned,
-            STABLE_TAG_BITS64_SIGNED => StableObjectKind::Bits64Signed,
-            STABLE_TAG_BITS64_FLOAT => StableObjectKind::Bits64Float,
-            STABLE_TAG_REGION => StableObjectKind::Region,
-            STABLE_TAG_VARIANT => StableObjectKind::Variant,
-            STABLE_TAG_CONCAT => StableObjectKind::Concat,
-            STABLE_TAG_BIGINT => StableObjectKind::BigInt,
-            STABLE_TAG_SOME => StableObjectKind::Some,
-            _ => unsafe { rts_trap_with("Invalid tag") },
-        }
+        StableObjectKind::from_tag(self.0)
+            .unwrap_or_else(|| unsafe { rts_trap_with("Invalid tag") })
     }
 }
 
-impl StableObjectKind {
-    fn deserialize(tag: Tag) -> StableObjectKind {
-        match tag {
-            // During the marking phase of the incremental GC, the mutator can see
-            // array slice information in the object tag.
-            TAG_ARRAY_I | TAG_ARRAY_M | TAG_ARRAY_T | TAG_ARRAY_S | TAG_ARRAY_SLICE_MIN.. => {
-                match base_array_tag(tag) {
-                    TAG_ARRAY_I => StableObjectKind::ArrayImmutable,
-                    TAG_ARRAY_M => StableObjectKind::ArrayMutable,
-                    TAG_ARRAY_T => StableObjectKind::ArrayTuple,
-                    TAG_ARRAY_S => StableObjectKind::ArraySharedFunction,
-                    _ => unreachable!("invalid array tag"),
-                }
+/// The stable object kind of a main memory object.
+fn stable_object_kind(tag: Tag) -> StableObjectKind {
+    match tag {
+        // During the marking phase of the incremental GC, the mutator can see
+        // array slice information in the object tag.
+        TAG_ARRAY_I | TAG_ARRAY_M | TAG_ARRAY_T | TAG_ARRAY_S | TAG_ARRAY_SLICE_MIN.. => {
+            match base_array_tag(tag) {
+                TAG_ARRAY_I => StableObjectKind::ArrayImmutable,
+                TAG_ARRAY_M => StableObjectKind::ArrayMutable,
+                TAG_ARRAY_T => StableObjectKind::ArrayTuple,
+                TAG_ARRAY_S => StableObjectKind::ArraySharedFunction,
+                _ => unreachable!("invalid array tag"),
             }
-            TAG_MUTBOX => StableObjectKind::MutBox,
-            TAG_OBJECT => StableObjectKind::Object,
-            TAG_BLOB_B => StableObjectKind::BlobBytes,
-            TAG_BLOB_T => StableObjectKind::BlobText,
-            TAG_BLOB_P => StableObjectKind::BlobPrincipal,
-            TAG_BLOB_A => StableObjectKind::BlobActor,
-            TAG_BITS64_U => StableObjectKind::Bits64Unsigned,
-            TAG_BITS64_S => StableObjectKind::Bits64Signed,
-            TAG_BITS64_F => StableObjectKind::Bits64Float,
-            TAG_REGION => StableObjectKind::Region,
-            TAG_VARIANT => StableObjectKind::Variant,
-            TAG_CONCAT => StableObjectKind::Concat,
-            TAG_BIGINT => StableObjectKind::BigInt,
-            TAG_SOME => StableObjectKind::Some,
-            _ => unreachable!("invalid tag"),
         }
+        TAG_MUTBOX => StableObjectKind::MutBox,
+        TAG_OBJECT => StableObjectKind::Object,
+        TAG_BLOB_B => StableObjectKind::BlobBytes,
+        TAG_BLOB_T => StableObjectKind::BlobText,
+        TAG_BLOB_P => StableObjectKind::BlobPrincipal,
+        TAG_BLOB_A => StableObjectKind::BlobActor,
+        TAG_BITS64_U => StableObjectKind::Bits64Unsigned,
+        TAG_BITS64_S => StableObjectKind::Bits64Signed,
+        TAG_BITS64_F => StableObjectKind::Bits64Float,
+        TAG_REGION => StableObjectKind::Region,
+        TAG_VARIANT => StableObjectKind::Variant,
+        TAG_CONCAT => StableObjectKind::Concat,
+        TAG_BIGINT => StableObjectKind::BigInt,
+        TAG_SOME => StableObjectKind::Some,
+        _ => unreachable!("invalid tag"),
     }
 }
 
@@ -248,7 +188,7 @@ where
     }
 
     unsafe fn serialize(stable_memory: &mut StableMemoryStream, main_object: Value) {
-        let stable_tag = StableObjectKind::deserialize(main_object.tag()).encode();
+        let stable_tag = StableTag::from(stable_object_kind(main_object.tag()));
         let main_object = main_object.as_obj() as *mut T;
         stable_memory.write(&stable_tag);
         unsafe {
@@ -373,7 +313,7 @@ pub fn scan_serialized<
 }
 
 pub unsafe fn serialize(stable_memory: &mut StableMemoryStream, main_object: Value) {
-    match StableObjectKind::deserialize(main_object.tag()) {
+    match stable_object_kind(main_object.tag()) {
         StableObjectKind::ArrayImmutable
         | StableObjectKind::ArrayMutable
         | StableObjectKind::ArrayTuple
@@ -1,3 +1,4 @@
+pub mod deduplication;
 pub mod stable_memory_stream;
 
 use crate::{
@@ -6,9 +7,13 @@ use crate::{
     types::{FwdPtr, Tag, Value, TAG_CLOSURE, TAG_FWD_PTR},
 };
 
-use self::stable_memory_stream::{ScanStream, StableMemoryStream};
+use self::{
+    deduplication::Deduplication,
+    stable_memory_stream::{ScanStream, StableMemoryStream},
+};
 
 use super::{
+    checksum::ChunkChecksums,
     graph_copy::{limit::ExecutionMonitor, GraphCopy},
     layout::{scan_serialized, StableToSpace, StableValue},
     DUMMY_VALUE,
@@ -18,6 +23,9 @@ pub struct Serialization {
     to_space: StableMemoryStream,
     limit: ExecutionMonitor,
     array_slice: Option<ArraySlice>,
+    deduplication: Option<Deduplication>,
+    /// Computed after the completed graph copy, see `checksum.rs`.
+    checksums: Option<ChunkChecksums>,
 }
 
 pub struct ArraySlice {
@@ -54,7 +62,7 @@ impl<'a, M> SerializationContext<'a, M> {
 /// whenever the heap layout is changed.
 /// Usage:
 /// ```
-/// let serialization = Serialization::start(root, stable_start);
+/// let serialization = Serialization::start(root, stable_start, deduplication);
 /// while !serialization.is_completed() {
 ///     serialization.copy_increment();
 /// }
@@ -63,13 +71,27 @@ impl Serialization {
     /// Start the graph-copy-based heap serialization from the stable `root` object
     /// by writing the serialized data to the stable memory at offset `stable_start`.
     /// The start is followed by a series of copy increments before the serialization is completed.
-    pub fn start<M: Memory>(mem: &mut M, root: Value, stable_start: u64) -> Serialization {
+    /// `deduplication` enables the sharing of identical blobs and texts in the serialized data,
+    /// see `deduplication.rs`.
+    pub fn start<M: Memory>(
+        mem: &mut M,
+        root: Value,
+        stable_start: u64,
+        deduplication: bool,
+    ) -> Serialization {
         let to_space = StableMemoryStream::open(stable_start);
         let limit = ExecutionMonitor::new();
+        let deduplication = if deduplication {
+            Some(unsafe { Deduplication::new(mem) })
+        } else {
+            None
+        };
         let mut serialization = Serialization {
             limit,
             to_space,
             array_slice: None,
+            deduplication,
+            checksums: None,
         };
         serialization.start(mem, root);
         serialization
@@ -83,6 +105,12 @@ impl Serialization {
         self.to_space.written_length()
     }
 
+    /// Blob of the chunk checksums of the serialized data, see `checksum.rs`.
+    pub fn chunk_checksums(&self) -> Value {
+        debug_assert!(self.is_completed());
+        self.checksums.as_ref().unwrap().table()
+    }
+
     /// Resolve the Brooks forwarding pointer of the incremental GC by considering potential
     /// forwarding objects (`FwdPtr`) used in Cheney's algorithm for stabilization.
     unsafe fn resolve_gc_forwarding(object: Value) -> Value {
@@ -117,7 +145,18 @@ impl Serialization {
     }
 
     fn processed_memory(&self) -> u64 {
-        self.to_space.written_length() + self.to_space.scanned_length()
+        let deduplicated_length = match &self.deduplication {
+            Some(deduplication) => deduplication.processed_length(),
+            None => 0,
+        };
+        let checksummed_length = match &self.checksums {
+            Some(checksums) => checksums.processed_length(),
+            None => 0,
+        };
+        self.to_space.written_length()
+            + self.to_space.scanned_length()
+            + deduplicated_length
+            + checksummed_length
     }
 }
 
@@ -146,10 +185,18 @@ impl GraphCopy<Value, StableValue, u32> for Serialization {
         }
     }
 
-    fn copy<M: Memory>(&mut self, _mem: &mut M, object: Value) -> StableValue {
+    fn copy<M: Memory>(&mut self, mem: &mut M, object: Value) -> StableValue {
         unsafe {
             let object = Self::resolve_gc_forwarding(object);
             debug_assert!(object.is_obj());
+            let tag = Self::read_object_tag(object);
+            if let Some(deduplication) = &mut self.deduplication {
+                if Deduplication::is_applicable(tag) {
+                    if let Some(original) = deduplication.find_or_record(mem, object, tag) {
+                        return self.get_forward_address(original).unwrap();
+                    }
+                }
+            }
             let address = self.to_space.written_length();
             serialize(&mut self.to_space, object);
             debug_assert!(self.to_space.written_length() >= address);
@@ -182,6 +229,25 @@ impl GraphCopy<Value, StableValue, u32> for Serialization {
         self.to_space.scan_completed()
     }
 
+    fn cleanup_completed(&self) -> bool {
+        match &self.checksums {
+            Some(checksums) => checksums.is_completed(),
+            None => false,
+        }
+    }
+
+    /// Compute the chunk checksums, as the serialized data is final after the scanning.
+    fn cleanup<M: Memory>(&mut self, mem: &mut M) {
+        let start = self.to_space.base_address();
+        let length = self.to_space.written_length();
+        let checksums = self
+            .checksums
+            .get_or_insert_with(|| unsafe { ChunkChecksums::allocate(mem, start, length) });
+        unsafe {
+            checksums.compute_next();
+        }
+    }
+
     fn complete(&mut self) {
         self.to_space.close();
     }
@@ -0,0 +1,160 @@
+//! Optional deduplication of identical blobs and texts during the serialization.
+//!
+//! Identical blobs (`TAG_BLOB_B`) and texts (`TAG_BLOB_T`) that are reached through different
+//! paths are serialized only once, and all references are redirected to the same object in the
+//! stable format. This is sound because these objects are immutable.
+//!
+//! Before copying a blob, the serialization looks it up in a hash table of the blobs serialized
+//! so far. A table entry records the content hash, the tag, and the original blob in main memory.
+//! The length and the payload of the original blob remain readable after its serialization, as the
+//! forwarding object (`FwdPtr`) only overwrites the object header. The target address of a found
+//! blob is obtained from its forwarding object.
+//!
+//! The table is allocated in main memory, as the heap is no longer used after the serialization.
+//! It grows by doubling up to `MAXIMUM_CAPACITY` entries. Beyond, further blobs are still looked up
+//! but no longer recorded.
+//!
+//! Hashing and comparing the blob contents is accounted as processed memory, such that the
+//! `ExecutionMonitor` also bounds the deduplication work of a graph copy increment.
+
+use crate::{
+    barriers::allocation_barrier,
+    mem_utils::memzero,
+    memory::{alloc_blob, Memory},
+    types::{Blob, Bytes, Tag, Value, TAG_BLOB_B, TAG_BLOB_T},
+};
+
+#[repr(C)]
+struct Entry {
+    hash: u64,
+    /// Tag of the original blob, as the header is overwritten by the forwarding object.
+    tag: Tag,
+    /// Original blob in main memory, or `FREE`.
+    blob: usize,
+}
+
+const FREE: usize = 0;
+
+const INITIAL_CAPACITY: usize = 1024;
+
+/// Limits the table size to 24 MB.
+const MAXIMUM_CAPACITY: usize = 1024 * 1024;
+
+pub struct Deduplication {
+    entries: *mut Entry,
+    /// Power of two.
+    capacity: usize,
+    count: usize,
+    /// Amount of hashed and compared main memory.
+    processed_length: u64,
+}
+
+impl Deduplication {
+    pub unsafe fn new<M: Memory>(mem: &mut M) -> Deduplication {
+        Deduplication {
+            entries: Self::allocate_entries(mem, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            count: 0,
+            processed_length: 0,
+        }
+    }
+
+    /// Only blobs and texts are deduplicated.
+    pub fn is_applicable(tag: Tag) -> bool {
+        tag == TAG_BLOB_B || tag == TAG_BLOB_T
+    }
+
+    pub fn processed_length(&self) -> u64 {
+        self.processed_length
+    }
+
+    /// Look up a blob before its serialization. Returns an identical blob that has already been
+    /// serialized, or otherwise records `blob`, which then needs to be serialized by the caller.
+    pub unsafe fn find_or_record<M: Memory>(
+        &mut self,
+        mem: &mut M,
+        blob: Value,
+        tag: Tag,
+    ) -> Option<Value> {
+        debug_assert!(Self::is_applicable(tag));
+        let hash = self.content_hash(blob, tag);
+        let mut index = hash as usize & (self.capacity - 1);
+        loop {
+            let entry = self.entries.add(index);
+            if (*entry).blob == FREE {
+                break;
+            }
+            if (*entry).hash == hash
+                && (*entry).tag == tag
+                && self.equal_contents((*entry).blob, blob)
+            {
+                return Some(Value::from_ptr((*entry).blob));
+            }
+            index = (index + 1) & (self.capacity - 1);
+        }
+        if self.count * 2 >= self.capacity {
+            if self.capacity >= MAXIMUM_CAPACITY {
+                return None;
+            }
+            self.grow(mem);
+        }
+        self.insert(hash, tag, blob.get_ptr());
+        None
+    }
+
+    unsafe fn allocate_entries<M: Memory>(mem: &mut M, capacity: usize) -> *mut Entry {
+        let size = Bytes(capacity * core::mem::size_of::<Entry>());
+        let table = alloc_blob(mem, TAG_BLOB_B, size);
+        let entries = table.as_blob_mut().payload_addr() as *mut Entry;
+        memzero(entries as usize, size.to_words());
+        allocation_barrier(table);
+        entries
+    }
+
+    unsafe fn grow<M: Memory>(&mut self, mem: &mut M) {
+        let old_entries = self.entries;
+        let old_capacity = self.capacity;
+        self.capacity *= 2;
+        self.entries = Self::allocate_entries(mem, self.capacity);
+        self.count = 0;
+        for index in 0..old_capacity {
+            let entry = old_entries.add(index);
+            if (*entry).blob != FREE {
+                self.insert((*entry).hash, (*entry).tag, (*entry).blob);
+            }
+        }
+    }
+
+    unsafe fn insert(&mut self, hash: u64, tag: Tag, blob: usize) {
+        let mut index = hash as usize & (self.capacity - 1);
+        while (*self.entries.add(index)).blob != FREE {
+            index = (index + 1) & (self.capacity - 1);
+        }
+        *self.entries.add(index) = Entry { hash, tag, blob };
+        self.count += 1;
+    }
+
+    /// FNV-1a over the tag and the payload.
+    unsafe fn content_hash(&mut self, blob: Value, tag: Tag) -> u64 {
+        let bytes = Self::payload(blob.get_ptr());
+        self.processed_length += bytes.len() as u64;
+        bytes
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325 ^ tag as u64, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3)
+            })
+    }
+
+    unsafe fn equal_contents(&mut self, original: usize, blob: Value) -> bool {
+        let original = Self::payload(original);
+        let bytes = Self::payload(blob.get_ptr());
+        self.processed_length += bytes.len() as u64;
+        original == bytes
+    }
+
+    /// The payload of a blob, also if it has already been replaced by a forwarding object.
+    unsafe fn payload(address: usize) -> &'static [u8] {
+        let blob = address as *const Blob;
+        core::slice::from_raw_parts(blob.payload_const(), blob.len().as_usize())
+    }
+}
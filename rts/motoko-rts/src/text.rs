@@ -134,7 +134,7 @@ struct Crumb {
 }
 
 #[no_mangle]
-unsafe extern "C" fn text_to_buf(mut s: Value, mut buf: *mut u8) {
+pub unsafe extern "C" fn text_to_buf(mut s: Value, mut buf: *mut u8) {
     let mut next_crumb: *const Crumb = core::ptr::null();
 
     loop {
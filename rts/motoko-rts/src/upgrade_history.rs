@@ -0,0 +1,135 @@
+//! History of the recent upgrades, for capacity planning of upgrades.
+//!
+//! The persistent metadata retains the statistics of the last `UPGRADE_HISTORY_LENGTH` upgrades
+//! in a ring buffer. An upgrade is recorded in multiple steps:
+//! * The stabilization before the upgrade starts the record (`UpgradeHistory::start`), noting the
+//!   heap size.
+//! * The compatibility check of the new stable type is measured
+//!   (`UpgradeHistory::add_compatibility_check`).
+//! * The completed destabilization after the upgrade adds the record to the history
+//!   (`UpgradeHistory::complete`).
+//! * The GC increments after the upgrade are accounted to the record until a GC run completes
+//!   (`UpgradeHistory::add_gc_increment`).
+//!
+//! The graph-copy-based stabilization carries the history in the stable memory metadata, as the
+//! persistent metadata is reinitialized on such an upgrade (see `stabilization::ic::metadata`).
+//!
+//! The recording on the IC is implemented in `ic`, with the history retained in the persistent
+//! metadata (see `persistence`). The history is returned to Motoko code as a Candid-encoded
+//! report, see `to_candid`.
+
+#[cfg(feature = "ic")]
+#[enhanced_orthogonal_persistence]
+pub mod ic;
+
+use crate::idl_types::{IDL_CON_record, IDL_CON_vec, IDL_PRIM_nat64};
+use crate::idl_writer::IdlWriter;
+use crate::memory::Memory;
+use crate::types::Value;
+
+#[cfg(feature = "ic")]
+use motoko_rts_macros::{classical_persistence, enhanced_orthogonal_persistence};
+
+pub use motoko_stable_format::upgrade_history::{
+    PersistenceMode, UpgradeHistory, UpgradeRecord, UPGRADE_HISTORY_LENGTH, UPGRADE_HISTORY_MARKER,
+};
+
+/// Returns the retained records of `history`, starting with the last upgrade, as a Candid-encoded
+/// value of the following Motoko type:
+/// ```
+/// [{
+///   timestamp : Nat64;
+///   persistence_mode : Nat64; // 1: enhanced orthogonal persistence, 2: graph copy, 3: Candid
+///   stabilization_instructions : Nat64;
+///   destabilization_instructions : Nat64;
+///   compatibility_check_instructions : Nat64;
+///   gc_completion_instructions : Nat64;
+///   heap_size_before : Nat64;
+///   heap_size_after : Nat64;
+/// }]
+/// ```
+pub unsafe fn to_candid<M: Memory>(mem: &mut M, history: &UpgradeHistory) -> Value {
+    let mut writer = IdlWriter::new(mem);
+
+    // Type table
+    writer.write_leb128(mem, 2);
+    // 0: record list
+    writer.write_sleb128(mem, IDL_CON_vec as i64);
+    writer.write_sleb128(mem, 1);
+    // 1: upgrade record
+    writer.write_sleb128(mem, IDL_CON_record as i64);
+    writer.write_leb128(mem, 8);
+    for field in [
+        history_report::HEAP_SIZE_AFTER,
+        history_report::DESTABILIZATION_INSTRUCTIONS,
+        history_report::PERSISTENCE_MODE,
+        history_report::HEAP_SIZE_BEFORE,
+        history_report::GC_COMPLETION_INSTRUCTIONS,
+        history_report::TIMESTAMP,
+        history_report::STABILIZATION_INSTRUCTIONS,
+        history_report::COMPATIBILITY_CHECK_INSTRUCTIONS,
+    ] {
+        writer.write_leb128(mem, field as u64);
+        writer.write_sleb128(mem, IDL_PRIM_nat64 as i64);
+    }
+
+    // Argument types
+    writer.write_leb128(mem, 1);
+    writer.write_sleb128(mem, 0);
+
+    // Argument value
+    let length = history.length();
+    writer.write_leb128(mem, length as u64);
+    for n in 0..length {
+        let record = history.recent(n);
+        writer.write_u64(mem, record.heap_size_after);
+        writer.write_u64(mem, record.destabilization_instructions);
+        writer.write_u64(mem, record.persistence_mode);
+        writer.write_u64(mem, record.heap_size_before);
+        writer.write_u64(mem, record.gc_completion_instructions);
+        writer.write_u64(mem, record.timestamp);
+        writer.write_u64(mem, record.stabilization_instructions);
+        writer.write_u64(mem, record.compatibility_check_instructions);
+    }
+    writer.finish()
+}
+
+/// Candid field hashes of the upgrade history report, in ascending order.
+mod history_report {
+    use crate::idl_writer::idl_hash;
+
+    pub const HEAP_SIZE_AFTER: u32 = idl_hash("heap_size_after");
+    pub const DESTABILIZATION_INSTRUCTIONS: u32 = idl_hash("destabilization_instructions");
+    pub const PERSISTENCE_MODE: u32 = idl_hash("persistence_mode");
+    pub const HEAP_SIZE_BEFORE: u32 = idl_hash("heap_size_before");
+    pub const GC_COMPLETION_INSTRUCTIONS: u32 = idl_hash("gc_completion_instructions");
+    pub const TIMESTAMP: u32 = idl_hash("timestamp");
+    pub const STABILIZATION_INSTRUCTIONS: u32 = idl_hash("stabilization_instructions");
+    pub const COMPATIBILITY_CHECK_INSTRUCTIONS: u32 = idl_hash("compatibility_check_instructions");
+
+    const _: () = assert!(
+        HEAP_SIZE_AFTER < DESTABILIZATION_INSTRUCTIONS
+            && DESTABILIZATION_INSTRUCTIONS < PERSISTENCE_MODE
+            && PERSISTENCE_MODE < HEAP_SIZE_BEFORE
+            && HEAP_SIZE_BEFORE < GC_COMPLETION_INSTRUCTIONS
+            && GC_COMPLETION_INSTRUCTIONS < TIMESTAMP
+            && TIMESTAMP < STABILIZATION_INSTRUCTIONS
+            && STABILIZATION_INSTRUCTIONS < COMPATIBILITY_CHECK_INSTRUCTIONS
+    );
+}
+
+/// Classical persistence does not record an upgrade history.
+#[cfg(feature = "ic")]
+#[classical_persistence]
+mod classical {
+    use motoko_rts_macros::ic_mem_fn;
+
+    use super::{to_candid, UpgradeHistory};
+    use crate::memory::Memory;
+    use crate::types::Value;
+
+    #[ic_mem_fn]
+    pub unsafe fn get_upgrade_history<M: Memory>(mem: &mut M) -> Value {
+        to_candid(mem, &UpgradeHistory::default())
+    }
+}
@@ -0,0 +1,83 @@
+//! Recording of the upgrade history in the persistent metadata.
+//!
+//! The history can be queried by `get_upgrade_history`.
+
+use motoko_rts_macros::ic_mem_fn;
+
+use crate::{
+    memory::{ic::partitioned_memory::get_heap_size, Memory},
+    persistence::{get_upgrade_instructions, persistent_upgrade_history, set_upgrade_instructions},
+    rts_trap_with,
+    stabilization::ic0_performance_counter,
+    types::Value,
+};
+
+use super::{to_candid, PersistenceMode};
+
+extern "C" {
+    // System time re-exported by moc.
+    fn ic0_time() -> u64;
+}
+
+/// Start the record of an upgrade, on the stabilization by the upgraded program version.
+pub(crate) unsafe fn start_upgrade() {
+    persistent_upgrade_history().start(get_heap_size().as_usize() as u64);
+}
+
+/// Run the memory compatibility check of an upgrade and record its instructions.
+pub(crate) unsafe fn measure_compatibility_check<F: FnOnce() -> bool>(check: F) -> bool {
+    let start = ic0_performance_counter(0);
+    let compatible = check();
+    let instructions = ic0_performance_counter(0) - start;
+    persistent_upgrade_history().add_compatibility_check(instructions);
+    compatible
+}
+
+/// Add the record of the upgrade to the history, on the completion of the destabilization.
+/// `destabilization_instructions` include the compatibility check.
+pub(crate) unsafe fn complete_upgrade(
+    mode: PersistenceMode,
+    stabilization_instructions: u64,
+    destabilization_instructions: u64,
+) {
+    persistent_upgrade_history().complete(
+        ic0_time(),
+        mode,
+        stabilization_instructions,
+        destabilization_instructions,
+        get_heap_size().as_usize() as u64,
+    );
+}
+
+/// Called by the compiled code at the end of the destabilization with the instructions of the
+/// destabilization, when not using graph-copy-based stabilization. The instructions of the
+/// stabilization have been set by the upgraded program version, see `set_upgrade_instructions`.
+/// Not called on the initial installation.
+#[no_mangle]
+pub unsafe extern "C" fn record_upgrade(persistence_mode: u64, instructions: u64) {
+    let stabilization_instructions = get_upgrade_instructions();
+    set_upgrade_instructions(stabilization_instructions + instructions);
+    complete_upgrade(
+        PersistenceMode::from_raw(persistence_mode)
+            .unwrap_or_else(|| rts_trap_with("Invalid persistence mode")),
+        stabilization_instructions,
+        instructions,
+    );
+}
+
+pub(crate) unsafe fn is_gc_completion_pending() -> bool {
+    persistent_upgrade_history().is_gc_completion_pending()
+}
+
+/// Account a GC increment to the last upgrade. `completed` denotes whether the increment has
+/// completed a GC run.
+pub(crate) unsafe fn record_gc_increment(instructions: u64, completed: bool) {
+    persistent_upgrade_history().add_gc_increment(instructions, completed);
+}
+
+/// Returns the Candid-encoded records of the recent upgrades, starting with the last upgrade,
+/// see `to_candid`.
+#[ic_mem_fn]
+pub unsafe fn get_upgrade_history<M: Memory>(mem: &mut M) -> Value {
+    to_candid(mem, persistent_upgrade_history())
+}
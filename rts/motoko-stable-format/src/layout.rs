@@ -0,0 +1,119 @@
+//! Object kinds of the stable format.
+//!
+//! Each object in the serialized object graph starts with a 64-bit tag denoting its
+//! `StableObjectKind`. The object payloads are defined in `stabilization/layout` in the RTS.
+//! New object kinds can be added with backwards compatibility, but the existing tags must not
+//! change.
+
+/// Different kinds of objects used in the stable format.
+#[repr(u64)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum StableObjectKind {
+    ArrayImmutable = 1,
+    ArrayMutable = 2,
+    ArrayTuple = 3,
+    ArraySharedFunction = 4,
+    MutBox = 5,
+    Object = 6,
+    BlobBytes = 7,
+    BlobText = 8,
+    BlobPrincipal = 9,
+    BlobActor = 10,
+    Bits64Unsigned = 11,
+    Bits64Signed = 12,
+    Bits64Float = 13,
+    Region = 14,
+    Variant = 15,
+    Concat = 16,
+    BigInt = 17,
+    Some = 18,
+}
+
+impl StableObjectKind {
+    pub const ALL: [StableObjectKind; 18] = [
+        StableObjectKind::ArrayImmutable,
+        StableObjectKind::ArrayMutable,
+        StableObjectKind::ArrayTuple,
+        StableObjectKind::ArraySharedFunction,
+        StableObjectKind::MutBox,
+        StableObjectKind::Object,
+        StableObjectKind::BlobBytes,
+        StableObjectKind::BlobText,
+        StableObjectKind::BlobPrincipal,
+        StableObjectKind::BlobActor,
+        StableObjectKind::Bits64Unsigned,
+        StableObjectKind::Bits64Signed,
+        StableObjectKind::Bits64Float,
+        StableObjectKind::Region,
+        StableObjectKind::Variant,
+        StableObjectKind::Concat,
+        StableObjectKind::BigInt,
+        StableObjectKind::Some,
+    ];
+
+    /// The object kind of a stable tag, or `None` for an invalid tag.
+    pub fn from_tag(tag: u64) -> Option<StableObjectKind> {
+        const STABLE_TAG_ARRAY_IMMUTABLE: u64 = StableObjectKind::ArrayImmutable as u64;
+        const STABLE_TAG_ARRAY_MUTABLE: u64 = StableObjectKind::ArrayMutable as u64;
+        const STABLE_TAG_ARRAY_TUPLE: u64 = StableObjectKind::ArrayTuple as u64;
+        const STABLE_TAG_ARRAY_SHARED_FUNCTION: u64 = StableObjectKind::ArraySharedFunction as u64;
+        const STABLE_TAG_MUTBOX: u64 = StableObjectKind::MutBox as u64;
+        const STABLE_TAG_OBJECT: u64 = StableObjectKind::Object as u64;
+        const STABLE_TAG_BLOB_BYTES: u64 = StableObjectKind::BlobBytes as u64;
+        const STABLE_TAG_BLOB_TEXT: u64 = StableObjectKind::BlobText as u64;
+        const STABLE_TAG_BLOB_PRINCIPAL: u64 = StableObjectKind::BlobPrincipal as u64;
+        const STABLE_TAG_BLOB_ACTOR: u64 = StableObjectKind::BlobActor as u64;
+        const STABLE_TAG_BITS64_UNSIGNED: u64 = StableObjectKind::Bits64Unsigned as u64;
+        const STABLE_TAG_BITS64_SIGNED: u64 = StableObjectKind::Bits64Signed as u64;
+        const STABLE_TAG_BITS64_FLOAT: u64 = StableObjectKind::Bits64Float as u64;
+        const STABLE_TAG_REGION: u64 = StableObjectKind::Region as u64;
+        const STABLE_TAG_VARIANT: u64 = StableObjectKind::Variant as u64;
+        const STABLE_TAG_CONCAT: u64 = StableObjectKind::Concat as u64;
+        const STABLE_TAG_BIGINT: u64 = StableObjectKind::BigInt as u64;
+        const STABLE_TAG_SOME: u64 = StableObjectKind::Some as u64;
+        match tag {
+            STABLE_TAG_ARRAY_IMMUTABLE => Some(StableObjectKind::ArrayImmutable),
+            STABLE_TAG_ARRAY_MUTABLE => Some(StableObjectKind::ArrayMutable),
+            STABLE_TAG_ARRAY_TUPLE => Some(StableObjectKind::ArrayTuple),
+            STABLE_TAG_ARRAY_SHARED_FUNCTION => Some(StableObjectKind::ArraySharedFunction),
+            STABLE_TAG_MUTBOX => Some(StableObjectKind::MutBox),
+            STABLE_TAG_OBJECT => Some(StableObjectKind::Object),
+            STABLE_TAG_BLOB_BYTES => Some(StableObjectKind::BlobBytes),
+            STABLE_TAG_BLOB_TEXT => Some(StableObjectKind::BlobText),
+            STABLE_TAG_BLOB_PRINCIPAL => Some(StableObjectKind::BlobPrincipal),
+            STABLE_TAG_BLOB_ACTOR => Some(StableObjectKind::BlobActor),
+            STABLE_TAG_BITS64_UNSIGNED => Some(StableObjectKind::Bits64Unsigned),
+            STABLE_TAG_BITS64_SIGNED => Some(StableObjectKind::Bits64Signed),
+            STABLE_TAG_BITS64_FLOAT => Some(StableObjectKind::Bits64Float),
+            STABLE_TAG_REGION => Some(StableObjectKind::Region),
+            STABLE_TAG_VARIANT => Some(StableObjectKind::Variant),
+            STABLE_TAG_CONCAT => Some(StableObjectKind::Concat),
+            STABLE_TAG_BIGINT => Some(StableObjectKind::BigInt),
+            STABLE_TAG_SOME => Some(StableObjectKind::Some),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StableObjectKind::ArrayImmutable => "immutable array",
+            StableObjectKind::ArrayMutable => "mutable array",
+            StableObjectKind::ArrayTuple => "tuple",
+            StableObjectKind::ArraySharedFunction => "shared function",
+            StableObjectKind::MutBox => "mutable box",
+            StableObjectKind::Object => "object",
+            StableObjectKind::BlobBytes => "blob",
+            StableObjectKind::BlobText => "text",
+            StableObjectKind::BlobPrincipal => "principal",
+            StableObjectKind::BlobActor => "actor reference",
+            StableObjectKind::Bits64Unsigned => "boxed Nat64",
+            StableObjectKind::Bits64Signed => "boxed Int64",
+            StableObjectKind::Bits64Float => "boxed Float",
+            StableObjectKind::Region => "region",
+            StableObjectKind::Variant => "variant",
+            StableObjectKind::Concat => "text concatenation",
+            StableObjectKind::BigInt => "big integer",
+            StableObjectKind::Some => "option",
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Long-term layout of the stable memory of the graph-copy-based stabilization.
+//!
+//! Shared by the RTS, which stores and loads the stable memory inside the canister, and the
+//! offline stable memory inspector (`motoko-stable-inspector`), which reads a dump of it.
+//! The definitions therefore have no dependencies on the RTS and do not allocate.
+//!
+//! See `design/GraphCopyStabilization.md` for the format.
+
+#![no_std]
+
+pub mod crc32;
+pub mod layout;
+pub mod metadata;
+pub mod upgrade_history;
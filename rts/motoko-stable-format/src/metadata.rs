@@ -0,0 +1,119 @@
+//! Graph-copy-bazed serialization format:
+//!
+//! (Very first word is zeroed and backed up in last page)
+//! -- Stable memory
+//! Raw stable memory or region data, size S in pages
+//! -- Stable variables
+//! Serialized data address N:
+//!   Serialized object graph, length L
+//!   (possible zero padding)
+//! Type descriptor address M:
+//!   Candid type table
+//!     Byte length (u64)
+//!     Data
+//!   Type offset table
+//!     Byte length (u64)
+//!     Data
+//!   Field names of the type table
+//!     Byte length (u64)
+//!     Data
+//!   Chunk checksums of the serialized data (32-bit little-endian CRC32 per `CHUNK_SIZE` bytes)
+//!     Byte length (u64)
+//!     Data
+//!   (possible zero padding)
+//! -- Last physical page (metadata):
+//!   (zero padding to align at page end)
+//!   Metadata checksum (u64), CRC32 of the type descriptor, the field names, and the chunk checksums
+//!   Checksum marker (u64), zero if no checksums are stored
+//!   Upgrade history (see `upgrade_history::UpgradeHistory`)
+//!   Upgrade history marker (u64), zero if no upgrade history is stored
+//!   Upgrade statistics (instructions) (u64)
+//!   Serialized data address N (u64)
+//!   Serialized data length L (u64)
+//!   Type descriptor address M (u64)
+//!   First word of page 0
+//!   Version 3 or 4 (u32) (`VERSION_GRAPH_COPY_NO_REGIONS` and `VERSION_GRAPH_COPY_REGIONS`, match with `compile.ml`).
+//! -- page end
+
+use core::convert::TryInto;
+
+use crate::upgrade_history::UpgradeHistory;
+
+/// Stable memory version of the graph-copy-based stabilization without regions.
+pub const VERSION_GRAPH_COPY_NO_REGIONS: u32 = 3;
+/// Stable memory version of the graph-copy-based stabilization with regions.
+pub const VERSION_GRAPH_COPY_REGIONS: u32 = 4;
+
+/// Size of the checksummed chunks of the serialized data.
+pub const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Denotes stored checksums in the `LastPageRecord`, analogous to `UPGRADE_HISTORY_MARKER`.
+pub const CHECKSUM_MARKER: u64 = u64::from_le_bytes(*b"CHECKSUM");
+
+/// Size of the `LastPageRecord` at the end of the last stable memory page.
+pub const LAST_PAGE_RECORD_SIZE: usize = core::mem::size_of::<LastPageRecord>();
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct UpgradeStatistics {
+    pub stabilization_instructions: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct LastPageRecord {
+    pub metadata_checksum: u64,
+    pub checksum_marker: u64,
+    pub upgrade_history: UpgradeHistory,
+    pub upgrade_history_marker: u64,
+    pub statistics: UpgradeStatistics,
+    pub serialized_data_address: u64,
+    pub serialized_data_length: u64,
+    pub type_descriptor_address: u64,
+    pub first_word_backup: u32,
+    pub version: u32,
+}
+
+impl LastPageRecord {
+    /// Reads the record from its little-endian representation, as stored at the end of the last
+    /// stable memory page.
+    pub fn from_le_bytes(bytes: &[u8; LAST_PAGE_RECORD_SIZE]) -> LastPageRecord {
+        let mut words = bytes
+            .chunks_exact(core::mem::size_of::<u64>())
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()));
+        let mut word = || words.next().unwrap();
+        let metadata_checksum = word();
+        let checksum_marker = word();
+        let upgrade_history = UpgradeHistory::read(&mut words);
+        let mut word = || words.next().unwrap();
+        let upgrade_history_marker = word();
+        let statistics = UpgradeStatistics {
+            stabilization_instructions: word(),
+        };
+        let serialized_data_address = word();
+        let serialized_data_length = word();
+        let type_descriptor_address = word();
+        let last_word = word();
+        LastPageRecord {
+            metadata_checksum,
+            checksum_marker,
+            upgrade_history,
+            upgrade_history_marker,
+            statistics,
+            serialized_data_address,
+            serialized_data_length,
+            type_descriptor_address,
+            first_word_backup: last_word as u32,
+            version: (last_word >> 32) as u32,
+        }
+    }
+
+    pub fn has_checksums(&self) -> bool {
+        self.checksum_marker == CHECKSUM_MARKER
+    }
+
+    /// The stored upgrade history, empty if stored by a program version without history.
+    pub fn upgrade_history(&self) -> UpgradeHistory {
+        UpgradeHistory::from_stored(self.upgrade_history_marker, self.upgrade_history)
+    }
+}
@@ -0,0 +1,196 @@
+//! Ring buffer of the recent upgrades, retained in the persistent metadata and carried in the
+//! stable memory metadata of the graph-copy-based stabilization.
+//!
+//! The recording on the IC and the Candid report are implemented in `upgrade_history` in the RTS.
+
+/// Number of upgrades retained in the history.
+pub const UPGRADE_HISTORY_LENGTH: usize = 16;
+
+/// Denotes a stored upgrade history in the stable memory metadata of the graph-copy-based
+/// stabilization, see `UpgradeHistory::from_stored`.
+pub const UPGRADE_HISTORY_MARKER: u64 = u64::from_le_bytes(*b"UPGRHIST");
+
+/// Persistence mode used by an upgrade, stored as `UpgradeRecord::persistence_mode`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PersistenceMode {
+    EnhancedOrthogonalPersistence = 1,
+    GraphCopyStabilization = 2,
+    /// Upgrade from classical persistence.
+    CandidStabilization = 3,
+}
+
+impl PersistenceMode {
+    /// The persistence mode of a stored record, or `None` for an unknown value.
+    pub fn from_raw(value: u64) -> Option<PersistenceMode> {
+        match value {
+            1 => Some(PersistenceMode::EnhancedOrthogonalPersistence),
+            2 => Some(PersistenceMode::GraphCopyStabilization),
+            3 => Some(PersistenceMode::CandidStabilization),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PersistenceMode::EnhancedOrthogonalPersistence => "enhanced orthogonal persistence",
+            PersistenceMode::GraphCopyStabilization => "graph copy",
+            PersistenceMode::CandidStabilization => "Candid stabilization",
+        }
+    }
+}
+
+/// Statistics of a single upgrade.
+/// Use a long-term representation by relying on C layout, with only 64-bit fields.
+/// Zero denotes an unknown value, e.g. for a stabilization by an older program version.
+#[repr(C)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct UpgradeRecord {
+    /// System time at the completion of the upgrade, in nanoseconds since 1970-01-01.
+    pub timestamp: u64,
+    /// See `PersistenceMode`.
+    pub persistence_mode: u64,
+    /// Instructions of the stabilization before the upgrade.
+    pub stabilization_instructions: u64,
+    /// Instructions of the destabilization after the upgrade, without the compatibility check.
+    pub destabilization_instructions: u64,
+    /// Instructions of the memory compatibility check of the new stable type.
+    pub compatibility_check_instructions: u64,
+    /// Instructions of the GC increments after the upgrade until the completion of a GC run.
+    pub gc_completion_instructions: u64,
+    /// Heap size in bytes at the start of the stabilization.
+    pub heap_size_before: u64,
+    /// Heap size in bytes at the completion of the destabilization.
+    pub heap_size_after: u64,
+}
+
+impl UpgradeRecord {
+    fn read<I: Iterator<Item = u64>>(words: &mut I) -> UpgradeRecord {
+        let mut word = || words.next().unwrap();
+        UpgradeRecord {
+            timestamp: word(),
+            persistence_mode: word(),
+            stabilization_instructions: word(),
+            destabilization_instructions: word(),
+            compatibility_check_instructions: word(),
+            gc_completion_instructions: word(),
+            heap_size_before: word(),
+            heap_size_after: word(),
+        }
+    }
+}
+
+/// Ring buffer of the recent upgrades, part of the persistent metadata.
+/// Use a long-term representation by relying on C layout, with only 64-bit fields.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct UpgradeHistory {
+    records: [UpgradeRecord; UPGRADE_HISTORY_LENGTH],
+    /// Total number of recorded upgrades. The last upgrade is recorded at index
+    /// `(count - 1) % UPGRADE_HISTORY_LENGTH`.
+    count: u64,
+    /// The record of the upgrade in progress.
+    pending: UpgradeRecord,
+    /// The `count` at which the GC increments are accounted to the last record, or zero if
+    /// a GC run has been completed since the last upgrade.
+    gc_completion_count: u64,
+}
+
+impl UpgradeHistory {
+    /// The history stored in the stable memory metadata next to `marker`. Program versions
+    /// without upgrade history did not store a marker, such that their history is empty.
+    pub fn from_stored(marker: u64, stored: UpgradeHistory) -> UpgradeHistory {
+        if marker == UPGRADE_HISTORY_MARKER {
+            stored
+        } else {
+            UpgradeHistory::default()
+        }
+    }
+
+    /// Reads the history from consecutive 64-bit words, in the order of the C layout.
+    pub(crate) fn read<I: Iterator<Item = u64>>(words: &mut I) -> UpgradeHistory {
+        let mut history = UpgradeHistory::default();
+        for record in history.records.iter_mut() {
+            *record = UpgradeRecord::read(words);
+        }
+        history.count = words.next().unwrap();
+        history.pending = UpgradeRecord::read(words);
+        history.gc_completion_count = words.next().unwrap();
+        history
+    }
+
+    /// Total number of recorded upgrades, including the ones no longer retained.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Number of retained upgrade records.
+    pub fn length(&self) -> usize {
+        core::cmp::min(self.count, UPGRADE_HISTORY_LENGTH as u64) as usize
+    }
+
+    /// The record of the `n`-th most recent upgrade, starting with 0 for the last upgrade.
+    pub fn recent(&self, n: usize) -> &UpgradeRecord {
+        assert!(n < self.length());
+        let index = (self.count as usize - 1 - n) % UPGRADE_HISTORY_LENGTH;
+        &self.records[index]
+    }
+
+    fn last_mut(&mut self) -> &mut UpgradeRecord {
+        let index = (self.count as usize - 1) % UPGRADE_HISTORY_LENGTH;
+        &mut self.records[index]
+    }
+
+    /// Start the record of an upgrade, on the stabilization by the upgraded program version.
+    pub fn start(&mut self, heap_size_before: u64) {
+        self.pending = UpgradeRecord {
+            heap_size_before,
+            ..UpgradeRecord::default()
+        };
+    }
+
+    /// Account the instructions of a memory compatibility check to the pending upgrade.
+    pub fn add_compatibility_check(&mut self, instructions: u64) {
+        self.pending.compatibility_check_instructions += instructions;
+    }
+
+    /// Add the record of the pending upgrade to the history, on the completion of the
+    /// destabilization. `destabilization_instructions` include the compatibility check.
+    pub fn complete(
+        &mut self,
+        timestamp: u64,
+        mode: PersistenceMode,
+        stabilization_instructions: u64,
+        destabilization_instructions: u64,
+        heap_size_after: u64,
+    ) {
+        let mut record = self.pending;
+        record.timestamp = timestamp;
+        record.persistence_mode = mode as u64;
+        record.stabilization_instructions = stabilization_instructions;
+        record.destabilization_instructions =
+            destabilization_instructions.saturating_sub(record.compatibility_check_instructions);
+        record.heap_size_after = heap_size_after;
+
+        let index = self.count as usize % UPGRADE_HISTORY_LENGTH;
+        self.records[index] = record;
+        self.count += 1;
+        self.pending = UpgradeRecord::default();
+        self.gc_completion_count = self.count;
+    }
+
+    /// Whether the GC increments are still accounted to the last upgrade.
+    pub fn is_gc_completion_pending(&self) -> bool {
+        self.gc_completion_count != 0
+    }
+
+    /// Account a GC increment to the last upgrade. `completed` denotes whether the increment
+    /// has completed a GC run.
+    pub fn add_gc_increment(&mut self, instructions: u64, completed: bool) {
+        debug_assert!(self.is_gc_completion_pending());
+        debug_assert_eq!(self.gc_completion_count, self.count);
+        self.last_mut().gc_completion_instructions += instructions;
+        if completed {
+            self.gc_completion_count = 0;
+        }
+    }
+}
@@ -0,0 +1,499 @@
+//! JSON export of the stable variables.
+//!
+//! The object graph is exported starting from the stable actor, guided by the stored stable type:
+//! * Numbers are exported as JSON numbers, of arbitrary precision for `Nat` and `Int`.
+//! * Texts are exported as strings, blobs as hexadecimal strings, and principals, actor references,
+//!   and shared functions in the textual principal format.
+//! * Options are exported as arrays with zero or one element, to distinguish nested options.
+//! * Records, objects, and variants are exported as JSON objects. As the stable format only stores
+//!   the hashes of the labels, labels are exported as `_<hash>_`, unless their names are given.
+//! * Mutable variables and mutable arrays are exported by their current value.
+//!
+//! Shared objects are exported at each reference. A cyclic reference, which can only occur through
+//! mutable values, is exported as `{"$cycle": <offset>}`, denoting the offset of the referenced
+//! object in the serialized data.
+//!
+//! Without type, or for values of type `Any`, the object graph is exported by the stable object
+//! kinds. Scalars are then exported as `{"$scalar": "<raw hexadecimal encoding>"}`, as their type
+//! cannot be determined from the encoding.
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt::Write;
+
+use motoko_stable_format::crc32::crc32;
+
+use crate::{
+    graph::{Object, StableGraph, StableObjectKind, StableValue},
+    types::{Type, TypeDefinition, TypeTable},
+    InspectionError, Result,
+};
+
+#[derive(Default)]
+pub struct ExportOptions {
+    /// The program has been compiled with `--experimental-rtti`, which reduces the number of
+    /// payload bits in the scalar encoding of `Nat`, `Int`, `Nat64`, and `Int64`.
+    pub rtti: bool,
+    /// Names of the record fields and variant cases, by the hashes of the labels.
+    pub labels: HashMap<u32, String>,
+}
+
+impl ExportOptions {
+    /// Names labels by the given field names, see `idl_hash`.
+    pub fn add_labels<'a, I: Iterator<Item = &'a str>>(&mut self, names: I) {
+        for name in names {
+            self.labels.insert(idl_hash(name), name.to_string());
+        }
+    }
+}
+
+/// Hash of a field label, as computed by the compiler (`IdlHash` in `mo_idl`).
+pub fn idl_hash(label: &str) -> u32 {
+    label.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(223).wrapping_add(byte as u32)
+    })
+}
+
+/// Exports the object graph from the root as JSON. Without `types`, the graph is exported by the
+/// stable object kinds.
+pub fn export_json(
+    graph: &StableGraph,
+    types: Option<&TypeTable>,
+    options: &ExportOptions,
+) -> Result<String> {
+    let mut export = JsonExport {
+        graph,
+        types,
+        options,
+        path: HashSet::new(),
+        output: String::new(),
+    };
+    let root = graph.root();
+    match types {
+        Some(types) => export.value(root, types.actor_type())?,
+        None => export.untyped(root)?,
+    }
+    Ok(export.output)
+}
+
+struct JsonExport<'a, 'g> {
+    graph: &'a StableGraph<'g>,
+    types: Option<&'a TypeTable>,
+    options: &'a ExportOptions,
+    /// Addresses of the objects on the current path from the root, to detect cycles.
+    path: HashSet<u64>,
+    output: String,
+}
+
+impl<'a, 'g> JsonExport<'a, 'g> {
+    fn value(&mut self, value: StableValue, typ: Type) -> Result<()> {
+        self.visit(value, |export| export.typed(value, typ))
+    }
+
+    fn visit<F: FnOnce(&mut Self) -> Result<()>>(
+        &mut self,
+        value: StableValue,
+        export: F,
+    ) -> Result<()> {
+        if !value.is_pointer() {
+            return export(self);
+        }
+        let address = value.address();
+        if !self.path.insert(address) {
+            write!(self.output, "{{\"$cycle\":{}}}", address).unwrap();
+            return Ok(());
+        }
+        let result = export(self);
+        self.path.remove(&address);
+        result
+    }
+
+    fn typed(&mut self, value: StableValue, typ: Type) -> Result<()> {
+        let rtti = self.options.rtti;
+        let mismatch = InspectionError::TypeMismatch(value.raw());
+        match typ {
+            Type::Null => self.output.push_str("null"),
+            Type::Bool => match value.raw() {
+                0 => self.output.push_str("false"),
+                1 => self.output.push_str("true"),
+                _ => return Err(mismatch),
+            },
+            Type::Nat | Type::Int if value.is_pointer() => match self.graph.object(value)? {
+                Object::BigInt(is_negative, magnitude) => {
+                    if is_negative {
+                        self.output.push('-');
+                    }
+                    self.output.push_str(&decimal(magnitude));
+                }
+                _ => return Err(mismatch),
+            },
+            Type::Nat | Type::Int => self.scalar(value, if rtti { 62 } else { 63 }, true)?,
+            Type::Nat64 | Type::Int64 if value.is_pointer() => {
+                let bits = self.bits64(value)?;
+                match (typ, bits) {
+                    (Type::Nat64, (StableObjectKind::Bits64Unsigned, bits)) => {
+                        write!(self.output, "{}", bits).unwrap()
+                    }
+                    (Type::Int64, (StableObjectKind::Bits64Signed, bits)) => {
+                        write!(self.output, "{}", bits as i64).unwrap()
+                    }
+                    _ => return Err(mismatch),
+                }
+            }
+            Type::Nat64 => self.scalar(value, if rtti { 60 } else { 63 }, false)?,
+            Type::Int64 => self.scalar(value, if rtti { 60 } else { 63 }, true)?,
+            Type::Nat8 => self.scalar(value, 8, false)?,
+            Type::Nat16 => self.scalar(value, 16, false)?,
+            Type::Nat32 => self.scalar(value, 32, false)?,
+            Type::Int8 => self.scalar(value, 8, true)?,
+            Type::Int16 => self.scalar(value, 16, true)?,
+            Type::Int32 => self.scalar(value, 32, true)?,
+            Type::Float64 => match self.bits64(value)? {
+                (StableObjectKind::Bits64Float, bits) => self.float(f64::from_bits(bits)),
+                _ => return Err(mismatch),
+            },
+            Type::Float32 | Type::Empty => return Err(mismatch),
+            Type::Text => {
+                let text = self.text(value)?;
+                self.string(&String::from_utf8_lossy(&text));
+            }
+            Type::Blob => match self.graph.object(value)? {
+                Object::Blob(StableObjectKind::BlobBytes, bytes) => {
+                    self.string(&hexadecimal(bytes))
+                }
+                _ => return Err(mismatch),
+            },
+            Type::Principal => match self.graph.object(value)? {
+                Object::Blob(StableObjectKind::BlobPrincipal, bytes) => {
+                    self.string(&principal(bytes))
+                }
+                _ => return Err(mismatch),
+            },
+            Type::Region => match self.graph.object(value)? {
+                Object::Region(id, page_count, _) => self.region(id, page_count),
+                _ => return Err(mismatch),
+            },
+            Type::Reserved => self.untyped_object(value)?,
+            Type::Table(index) => self.composite(value, index)?,
+        }
+        Ok(())
+    }
+
+    fn composite(&mut self, value: StableValue, index: usize) -> Result<()> {
+        let mismatch = InspectionError::TypeMismatch(value.raw());
+        let definition = self.types.unwrap().definition(index).clone();
+        match definition {
+            TypeDefinition::Opt(typ) => {
+                if value.is_null() {
+                    self.output.push_str("[]");
+                    return Ok(());
+                }
+                self.output.push('[');
+                // Only `null` and nested options are boxed in `Some`.
+                match value.is_pointer().then(|| self.graph.object(value)) {
+                    Some(Ok(Object::Some(field))) => self.value(field, typ)?,
+                    _ => self.typed(value, typ)?,
+                }
+                self.output.push(']');
+            }
+            TypeDefinition::Vec(typ) => match self.graph.object(value)? {
+                Object::Array(StableObjectKind::ArrayImmutable, elements)
+                | Object::Array(StableObjectKind::ArrayMutable, elements) => {
+                    self.sequence(&elements, |export, element| export.value(element, typ))?
+                }
+                _ => return Err(mismatch),
+            },
+            TypeDefinition::Tuple(types) => match self.graph.object(value)? {
+                Object::Array(StableObjectKind::ArrayTuple, elements)
+                    if elements.len() == types.len() =>
+                {
+                    let elements: Vec<_> = elements.into_iter().zip(types).collect();
+                    self.sequence(&elements, |export, (element, typ)| {
+                        export.value(element, typ)
+                    })?
+                }
+                _ => return Err(mismatch),
+            },
+            TypeDefinition::Record(fields) => match self.graph.object(value)? {
+                Object::Object(hash_blob, values) => {
+                    let hashes = self.field_hashes(hash_blob)?;
+                    let mut entries = vec![];
+                    for (hash, typ) in fields {
+                        let index = hashes
+                            .iter()
+                            .position(|field_hash| *field_hash == hash as u64)
+                            .ok_or(InspectionError::TypeMismatch(value.raw()))?;
+                        entries.push((hash, values[index], typ));
+                    }
+                    self.entries(&entries, |export, (_, field, typ)| export.value(field, typ))?
+                }
+                _ => return Err(mismatch),
+            },
+            TypeDefinition::Variant(cases) => match self.graph.object(value)? {
+                Object::Variant(tag, field) => {
+                    let typ = cases
+                        .iter()
+                        .find(|(hash, _)| *hash as u64 == tag)
+                        .map(|(_, typ)| *typ)
+                        .ok_or(mismatch)?;
+                    self.entries(&[(tag as u32, field, typ)], |export, (_, field, typ)| {
+                        export.value(field, typ)
+                    })?
+                }
+                _ => return Err(mismatch),
+            },
+            TypeDefinition::Func | TypeDefinition::Service => self.untyped_object(value)?,
+            TypeDefinition::Alias(typ) => {
+                match value.is_pointer().then(|| self.graph.object(value)) {
+                    Some(Ok(Object::MutBox(field))) => self.value(field, typ)?,
+                    // Mutable arrays and regions are not boxed.
+                    _ => self.typed(value, typ)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn untyped(&mut self, value: StableValue) -> Result<()> {
+        self.visit(value, |export| export.untyped_object(value))
+    }
+
+    fn untyped_object(&mut self, value: StableValue) -> Result<()> {
+        if value.is_null() {
+            self.output.push_str("null");
+            return Ok(());
+        }
+        if !value.is_pointer() {
+            write!(self.output, "{{\"$scalar\":\"{:#x}\"}}", value.raw()).unwrap();
+            return Ok(());
+        }
+        match self.graph.object(value)? {
+            Object::Array(StableObjectKind::ArraySharedFunction, elements)
+                if elements.len() == 2 =>
+            {
+                self.output.push_str("{\"principal\":");
+                self.untyped(elements[0])?;
+                self.output.push_str(",\"method\":");
+                self.untyped(elements[1])?;
+                self.output.push('}');
+            }
+            Object::Array(_, elements) => {
+                self.sequence(&elements, |export, element| export.untyped(element))?
+            }
+            Object::MutBox(field) => self.untyped(field)?,
+            Object::Object(hash_blob, values) => {
+                let hashes = self.field_hashes(hash_blob)?;
+                let entries: Vec<_> = hashes
+                    .into_iter()
+                    .zip(values)
+                    .map(|(hash, field)| (hash as u32, field, ()))
+                    .collect();
+                self.entries(&entries, |export, (_, field, _)| export.untyped(field))?
+            }
+            Object::Blob(StableObjectKind::BlobBytes, bytes) => self.string(&hexadecimal(bytes)),
+            Object::Blob(StableObjectKind::BlobText, _) | Object::Concat(_, _, _) => {
+                let text = self.text(value)?;
+                self.string(&String::from_utf8_lossy(&text));
+            }
+            Object::Blob(_, bytes) => self.string(&principal(bytes)),
+            Object::Bits64(StableObjectKind::Bits64Signed, bits) => {
+                write!(self.output, "{}", bits as i64).unwrap()
+            }
+            Object::Bits64(StableObjectKind::Bits64Float, bits) => self.float(f64::from_bits(bits)),
+            Object::Bits64(_, bits) => write!(self.output, "{}", bits).unwrap(),
+            Object::Region(id, page_count, _) => self.region(id, page_count),
+            Object::Variant(tag, field) => self
+                .entries(&[(tag as u32, field, ())], |export, (_, field, _)| {
+                    export.untyped(field)
+                })?,
+            Object::BigInt(is_negative, magnitude) => {
+                if is_negative {
+                    self.output.push('-');
+                }
+                self.output.push_str(&decimal(magnitude));
+            }
+            Object::Some(field) => {
+                self.output.push('[');
+                self.untyped(field)?;
+                self.output.push(']');
+            }
+        }
+        Ok(())
+    }
+
+    fn sequence<T: Copy, F: Fn(&mut Self, T) -> Result<()>>(
+        &mut self,
+        elements: &[T],
+        export: F,
+    ) -> Result<()> {
+        self.output.push('[');
+        for (index, element) in elements.iter().enumerate() {
+            if index > 0 {
+                self.output.push(',');
+            }
+            export(self, *element)?;
+        }
+        self.output.push(']');
+        Ok(())
+    }
+
+    fn entries<T: Copy, F: Fn(&mut Self, (u32, StableValue, T)) -> Result<()>>(
+        &mut self,
+        entries: &[(u32, StableValue, T)],
+        export: F,
+    ) -> Result<()> {
+        self.output.push('{');
+        for (index, entry) in entries.iter().enumerate() {
+            if index > 0 {
+                self.output.push(',');
+            }
+            let label = match self.options.labels.get(&entry.0) {
+                Some(name) => name.clone(),
+                None => format!("_{}_", entry.0),
+            };
+            self.string(&label);
+            self.output.push(':');
+            export(self, *entry)?;
+        }
+        self.output.push('}');
+        Ok(())
+    }
+
+    /// The 64-bit label hashes of an object, in the order of its fields.
+    fn field_hashes(&self, hash_blob: StableValue) -> Result<Vec<u64>> {
+        match self.graph.object(hash_blob)? {
+            Object::Blob(StableObjectKind::BlobBytes, bytes) => Ok(bytes
+                .chunks_exact(8)
+                .map(|hash| u64::from_le_bytes(hash.try_into().unwrap()))
+                .collect()),
+            _ => Err(InspectionError::TypeMismatch(hash_blob.raw())),
+        }
+    }
+
+    /// Decodes a compact scalar with `bits` payload bits, stored in the most significant bits.
+    fn scalar(&mut self, value: StableValue, bits: u32, signed: bool) -> Result<()> {
+        if value.raw() & 0b1 != 0 {
+            return Err(InspectionError::TypeMismatch(value.raw()));
+        }
+        let shift = u64::BITS - bits;
+        if signed {
+            write!(self.output, "{}", (value.raw() as i64) >> shift).unwrap();
+        } else {
+            write!(self.output, "{}", value.raw() >> shift).unwrap();
+        }
+        Ok(())
+    }
+
+    fn bits64(&self, value: StableValue) -> Result<(StableObjectKind, u64)> {
+        match self.graph.object(value)? {
+            Object::Bits64(kind, bits) => Ok((kind, bits)),
+            _ => Err(InspectionError::TypeMismatch(value.raw())),
+        }
+    }
+
+    /// The UTF-8 encoding of a text, also if it is concatenated.
+    fn text(&self, value: StableValue) -> Result<Vec<u8>> {
+        match self.graph.object(value)? {
+            Object::Blob(StableObjectKind::BlobText, bytes) => Ok(bytes.to_vec()),
+            Object::Concat(_, text1, text2) => {
+                let mut text = self.text(text1)?;
+                text.extend(self.text(text2)?);
+                Ok(text)
+            }
+            _ => Err(InspectionError::TypeMismatch(value.raw())),
+        }
+    }
+
+    fn float(&mut self, value: f64) {
+        if value.is_finite() {
+            write!(self.output, "{:?}", value).unwrap();
+        } else {
+            self.string(&format!("{}", value));
+        }
+    }
+
+    fn region(&mut self, id: u64, page_count: u64) {
+        write!(
+            self.output,
+            "{{\"id\":{},\"page_count\":{}}}",
+            id, page_count
+        )
+        .unwrap();
+    }
+
+    fn string(&mut self, text: &str) {
+        self.output.push('"');
+        for character in text.chars() {
+            match character {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\r' => self.output.push_str("\\r"),
+                '\t' => self.output.push_str("\\t"),
+                character if character.is_control() => {
+                    write!(self.output, "\\u{:04x}", character as u32).unwrap()
+                }
+                character => self.output.push(character),
+            }
+        }
+        self.output.push('"');
+    }
+}
+
+fn hexadecimal(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decimal representation of a little-endian magnitude.
+fn decimal(magnitude: &[u8]) -> String {
+    const BASE: u64 = 1_000_000_000;
+    let mut digits = magnitude.to_vec();
+    digits.reverse();
+    let mut groups = vec![];
+    while digits.iter().any(|digit| *digit != 0) {
+        let mut remainder = 0u64;
+        for digit in digits.iter_mut() {
+            let current = (remainder << 8) | *digit as u64;
+            *digit = (current / BASE) as u8;
+            remainder = current % BASE;
+        }
+        groups.push(remainder);
+    }
+    match groups.split_last() {
+        None => "0".to_string(),
+        Some((most_significant, rest)) => {
+            let mut result = most_significant.to_string();
+            for group in rest.iter().rev() {
+                write!(result, "{:09}", group).unwrap();
+            }
+            result
+        }
+    }
+}
+
+/// Textual representation of a principal, see `principal_id.rs` in the RTS.
+fn principal(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut data = crc32(0, bytes).to_be_bytes().to_vec();
+    data.extend_from_slice(bytes);
+    let mut encoded = String::new();
+    let mut buffer = 0u32;
+    let mut pending_bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        pending_bits += 8;
+        while pending_bits >= 5 {
+            pending_bits -= 5;
+            encoded.push(ALPHABET[((buffer >> pending_bits) & 0b11111) as usize] as char);
+        }
+    }
+    if pending_bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - pending_bits)) & 0b11111) as usize] as char);
+    }
+    let groups: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(5)
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect();
+    groups.join("-")
+}
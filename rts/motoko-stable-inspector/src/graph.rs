@@ -0,0 +1,258 @@
+//! Reader of the serialized object graph.
+//!
+//! Mirrors the long-term layout of the stable format, see `stabilization/layout.rs` in the RTS:
+//! Each object starts with a 64-bit `StableObjectKind` tag, followed by the static part and the
+//! dynamic payload of the object, all aligned to 64 bits. Pointers are 64-bit skewed offsets in
+//! the serialized data. Scalars use the main memory encoding of enhanced orthogonal persistence.
+//!
+//! The graph copy serializes the objects contiguously, starting with the root object at offset 0.
+//! The objects can therefore be visited either by following the pointers from the root, or by a
+//! linear scan of the serialized data.
+
+use std::convert::TryInto;
+
+pub use motoko_stable_format::layout::StableObjectKind;
+
+use crate::{InspectionError, Result};
+
+const WORD_SIZE: u64 = 8;
+
+/// Main memory encoding of `true`, a scalar although its lowest bit is set.
+const TRUE_VALUE: u64 = 0x1;
+
+/// Main memory encoding of `null`, retained by the serialization.
+const NULL_POINTER: u64 = 0xffff_ffff_ffff_fffb;
+
+/// A pointer or a scalar in the stable format.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StableValue(u64);
+
+impl StableValue {
+    pub const fn from_raw(value: u64) -> StableValue {
+        StableValue(value)
+    }
+
+    pub fn from_address(address: u64) -> StableValue {
+        debug_assert_eq!(address % WORD_SIZE, 0);
+        StableValue(address.wrapping_sub(1))
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_pointer(&self) -> bool {
+        self.0 & 0b1 == 1 && self.0 != TRUE_VALUE && self.0 != NULL_POINTER
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0 == NULL_POINTER
+    }
+
+    /// Offset of the referenced object in the serialized data.
+    pub fn address(&self) -> u64 {
+        debug_assert!(self.is_pointer());
+        self.0.wrapping_add(1)
+    }
+}
+
+/// A decoded object of the stable format. Pointers are not followed.
+#[derive(Debug)]
+pub enum Object<'a> {
+    Array(StableObjectKind, Vec<StableValue>),
+    MutBox(StableValue),
+    /// The hash blob contains the 64-bit hashes of the field labels, in the order of the fields.
+    Object(StableValue, Vec<StableValue>),
+    Blob(StableObjectKind, &'a [u8]),
+    Bits64(StableObjectKind, u64),
+    /// Region id, page count, and blob of the 16-bit page ids.
+    Region(u64, u64, StableValue),
+    /// Hash of the variant label, and the value.
+    Variant(u64, StableValue),
+    /// Number of bytes, and the two texts.
+    Concat(u64, StableValue, StableValue),
+    /// Sign and the magnitude in little-endian encoding.
+    BigInt(bool, &'a [u8]),
+    Some(StableValue),
+}
+
+impl<'a> Object<'a> {
+    pub fn kind(&self) -> StableObjectKind {
+        match self {
+            Object::Array(kind, _) | Object::Blob(kind, _) | Object::Bits64(kind, _) => *kind,
+            Object::MutBox(_) => StableObjectKind::MutBox,
+            Object::Object(_, _) => StableObjectKind::Object,
+            Object::Region(_, _, _) => StableObjectKind::Region,
+            Object::Variant(_, _) => StableObjectKind::Variant,
+            Object::Concat(_, _, _) => StableObjectKind::Concat,
+            Object::BigInt(_, _) => StableObjectKind::BigInt,
+            Object::Some(_) => StableObjectKind::Some,
+        }
+    }
+
+    /// The values stored in the object, including pointers and scalars.
+    pub fn values(&self) -> Vec<StableValue> {
+        match self {
+            Object::Array(_, elements) => elements.clone(),
+            Object::Object(hash_blob, fields) => {
+                let mut values = vec![*hash_blob];
+                values.extend_from_slice(fields);
+                values
+            }
+            Object::MutBox(field) | Object::Variant(_, field) | Object::Some(field) => {
+                vec![*field]
+            }
+            Object::Region(_, _, vec_pages) => vec![*vec_pages],
+            Object::Concat(_, text1, text2) => vec![*text1, *text2],
+            Object::Blob(_, _) | Object::Bits64(_, _) | Object::BigInt(_, _) => vec![],
+        }
+    }
+}
+
+fn round_to_u64(length: u64) -> u64 {
+    (length + WORD_SIZE - 1) & !(WORD_SIZE - 1)
+}
+
+/// The serialized object graph.
+pub struct StableGraph<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StableGraph<'a> {
+    pub fn new(data: &'a [u8]) -> StableGraph<'a> {
+        StableGraph { data }
+    }
+
+    pub fn length(&self) -> u64 {
+        self.data.len() as u64
+    }
+
+    pub fn root(&self) -> StableValue {
+        StableValue::from_address(0)
+    }
+
+    fn bytes(&self, address: u64, length: u64) -> Result<&'a [u8]> {
+        address
+            .checked_add(length)
+            .filter(|end| *end <= self.length())
+            .map(|end| &self.data[address as usize..end as usize])
+            .ok_or(InspectionError::OutOfBounds("serialized data", address))
+    }
+
+    fn read_u64(&self, address: u64) -> Result<u64> {
+        let bytes = self.bytes(address, WORD_SIZE)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_values(&self, address: u64, count: u64) -> Result<Vec<StableValue>> {
+        let length = count
+            .checked_mul(WORD_SIZE)
+            .ok_or(InspectionError::OutOfBounds("serialized data", address))?;
+        let bytes = self.bytes(address, length)?;
+        Ok(bytes
+            .chunks_exact(WORD_SIZE as usize)
+            .map(|word| StableValue(u64::from_le_bytes(word.try_into().unwrap())))
+            .collect())
+    }
+
+    /// Decode the object referenced by the pointer `value`.
+    pub fn object(&self, value: StableValue) -> Result<Object<'a>> {
+        if !value.is_pointer() {
+            return Err(InspectionError::TypeMismatch(value.raw()));
+        }
+        self.object_at(value.address()).map(|(object, _)| object)
+    }
+
+    /// Decode the object at `address`, also returning its size in the serialized data.
+    pub fn object_at(&self, address: u64) -> Result<(Object<'a>, u64)> {
+        let tag = self.read_u64(address)?;
+        let kind =
+            StableObjectKind::from_tag(tag).ok_or(InspectionError::InvalidObject(address))?;
+        let payload = address + WORD_SIZE;
+        let word = |index: u64| self.read_u64(payload + index * WORD_SIZE);
+        let value = |index: u64| word(index).map(StableValue);
+        let (object, payload_size) = match kind {
+            StableObjectKind::ArrayImmutable
+            | StableObjectKind::ArrayMutable
+            | StableObjectKind::ArrayTuple
+            | StableObjectKind::ArraySharedFunction => {
+                let length = word(0)?;
+                let elements = self.read_values(payload + WORD_SIZE, length)?;
+                (Object::Array(kind, elements), (length + 1) * WORD_SIZE)
+            }
+            StableObjectKind::MutBox => (Object::MutBox(value(0)?), WORD_SIZE),
+            StableObjectKind::Object => {
+                let size = word(0)?;
+                let fields = self.read_values(payload + 2 * WORD_SIZE, size)?;
+                (Object::Object(value(1)?, fields), (size + 2) * WORD_SIZE)
+            }
+            StableObjectKind::BlobBytes
+            | StableObjectKind::BlobText
+            | StableObjectKind::BlobPrincipal
+            | StableObjectKind::BlobActor => {
+                let length = word(0)?;
+                let bytes = self.bytes(payload + WORD_SIZE, length)?;
+                (Object::Blob(kind, bytes), WORD_SIZE + round_to_u64(length))
+            }
+            StableObjectKind::Bits64Unsigned
+            | StableObjectKind::Bits64Signed
+            | StableObjectKind::Bits64Float => (Object::Bits64(kind, word(0)?), WORD_SIZE),
+            StableObjectKind::Region => {
+                (Object::Region(word(0)?, word(1)?, value(2)?), 3 * WORD_SIZE)
+            }
+            StableObjectKind::Variant => (Object::Variant(word(0)?, value(1)?), 2 * WORD_SIZE),
+            StableObjectKind::Concat => (
+                Object::Concat(word(0)?, value(1)?, value(2)?),
+                3 * WORD_SIZE,
+            ),
+            StableObjectKind::BigInt => {
+                // The sign is a `bool` followed by padding, see `StableBigInt`.
+                let is_negative = self.bytes(payload, 1)?[0] != 0;
+                let number_of_bits = word(1)?;
+                let length = (number_of_bits + u8::BITS as u64 - 1) >> 3;
+                let magnitude = self.bytes(payload + 2 * WORD_SIZE, length)?;
+                (
+                    Object::BigInt(is_negative, magnitude),
+                    2 * WORD_SIZE + round_to_u64(length),
+                )
+            }
+            StableObjectKind::Some => (Object::Some(value(0)?), WORD_SIZE),
+        };
+        Ok((object, WORD_SIZE + payload_size))
+    }
+
+    /// Visit all objects by a linear scan of the serialized data, in the order of serialization.
+    pub fn objects(&self) -> Objects<'_, 'a> {
+        Objects {
+            graph: self,
+            address: 0,
+        }
+    }
+}
+
+/// Linear scan of the serialized objects, yielding the address, the object and its size.
+pub struct Objects<'g, 'a> {
+    graph: &'g StableGraph<'a>,
+    address: u64,
+}
+
+impl<'g, 'a> Iterator for Objects<'g, 'a> {
+    type Item = Result<(u64, Object<'a>, u64)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.address >= self.graph.length() {
+            return None;
+        }
+        let address = self.address;
+        match self.graph.object_at(address) {
+            Ok((object, size)) => {
+                self.address += size;
+                Some(Ok((address, object, size)))
+            }
+            Err(error) => {
+                self.address = self.graph.length();
+                Some(Err(error))
+            }
+        }
+    }
+}
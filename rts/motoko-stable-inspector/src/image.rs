@@ -0,0 +1,224 @@
+//! A stable memory image of the graph-copy-based stabilization.
+//!
+//! Reads the stable memory layout of `motoko_stable_format::metadata`, shared with the RTS: The
+//! serialized object graph is followed by the type descriptor, its field names, and the chunk
+//! checksums, and the metadata is stored at the end of the last page. Fields added by newer
+//! program versions precede the fields of older versions and are denoted by markers.
+
+use std::convert::TryInto;
+
+use motoko_stable_format::{
+    crc32::crc32,
+    metadata::{
+        LastPageRecord, CHUNK_SIZE, LAST_PAGE_RECORD_SIZE, VERSION_GRAPH_COPY_NO_REGIONS,
+        VERSION_GRAPH_COPY_REGIONS,
+    },
+    upgrade_history::UpgradeRecord,
+};
+
+use crate::{graph::StableGraph, types::TypeTable, InspectionError, Result};
+
+pub const PAGE_SIZE: u64 = 64 * 1024;
+
+const WORD_SIZE: u64 = 8;
+
+/// Outcome of the checksum verification.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Checksums {
+    Verified,
+    /// The image has been stored by an older program version without checksums.
+    Absent,
+}
+
+pub struct StableImage {
+    memory: Vec<u8>,
+    pub version: u32,
+    /// Instructions of the stabilization.
+    pub stabilization_instructions: u64,
+    pub serialized_data_address: u64,
+    pub serialized_data_length: u64,
+    pub type_descriptor_address: u64,
+    pub candid_data: Vec<u8>,
+    pub type_offsets: Vec<u8>,
+    /// Names of the record fields and variant cases of the type table, as LEB128-length-prefixed
+    /// strings. Empty if stored by an older program version without field names.
+    pub field_names: Vec<u8>,
+    /// 32-bit little-endian checksums per chunk, if stored.
+    pub chunk_checksums: Option<Vec<u8>>,
+    metadata_checksum: u64,
+    /// The recent upgrades before the stabilization, starting with the last upgrade.
+    pub upgrade_history: Vec<UpgradeRecord>,
+}
+
+impl StableImage {
+    /// Parses a dump of the entire stable memory.
+    pub fn parse(mut memory: Vec<u8>) -> Result<StableImage> {
+        let size = memory.len() as u64;
+        if size == 0 || size & (PAGE_SIZE - 1) != 0 {
+            return Err(InspectionError::InvalidImageSize(size));
+        }
+        let record_start = memory.len() - LAST_PAGE_RECORD_SIZE;
+        let record = LastPageRecord::from_le_bytes(memory[record_start..].try_into().unwrap());
+        let version = record.version;
+        if version != VERSION_GRAPH_COPY_NO_REGIONS && version != VERSION_GRAPH_COPY_REGIONS {
+            return Err(InspectionError::UnsupportedVersion(version));
+        }
+        let serialized_data_address = record.serialized_data_address;
+        let serialized_data_length = record.serialized_data_length;
+        let type_descriptor_address = record.type_descriptor_address;
+
+        // The stabilization zeroes the very first word of the stable memory.
+        memory[..4].copy_from_slice(&record.first_word_backup.to_le_bytes());
+
+        let mut offset = type_descriptor_address;
+        let candid_data = read_blob(&memory, &mut offset)?.to_vec();
+        let type_offsets = read_blob(&memory, &mut offset)?.to_vec();
+        let (field_names, chunk_checksums) = if record.has_checksums() {
+            let field_names = read_blob(&memory, &mut offset)?.to_vec();
+            let chunk_checksums = read_blob(&memory, &mut offset)?.to_vec();
+            (field_names, Some(chunk_checksums))
+        } else {
+            // Older program versions may have stored field names but no checksums.
+            let field_names = read_blob(&memory, &mut offset)
+                .map(<[u8]>::to_vec)
+                .unwrap_or_default();
+            (field_names, None)
+        };
+        let upgrade_history = record.upgrade_history();
+        let upgrade_history = (0..upgrade_history.length())
+            .map(|n| *upgrade_history.recent(n))
+            .collect();
+        serialized_data_address
+            .checked_add(serialized_data_length)
+            .filter(|end| *end <= size)
+            .ok_or(InspectionError::OutOfBounds(
+                "serialized data",
+                serialized_data_address,
+            ))?;
+        Ok(StableImage {
+            memory,
+            version,
+            stabilization_instructions: record.statistics.stabilization_instructions,
+            serialized_data_address,
+            serialized_data_length,
+            type_descriptor_address,
+            candid_data,
+            type_offsets,
+            field_names,
+            chunk_checksums,
+            metadata_checksum: record.metadata_checksum,
+            upgrade_history,
+        })
+    }
+
+    /// Total size of the stable memory.
+    pub fn size(&self) -> u64 {
+        self.memory.len() as u64
+    }
+
+    pub fn has_regions(&self) -> bool {
+        self.version == VERSION_GRAPH_COPY_REGIONS
+    }
+
+    pub fn serialized_data(&self) -> &[u8] {
+        let start = self.serialized_data_address as usize;
+        &self.memory[start..start + self.serialized_data_length as usize]
+    }
+
+    pub fn graph(&self) -> StableGraph<'_> {
+        StableGraph::new(self.serialized_data())
+    }
+
+    pub fn type_table(&self) -> Result<TypeTable> {
+        TypeTable::new(&self.candid_data, &self.type_offsets)
+    }
+
+    /// The stored names of the record fields and variant cases, see `ExportOptions::add_labels`.
+    pub fn labels(&self) -> Result<Vec<&str>> {
+        let invalid = || InspectionError::InvalidFieldNames;
+        let mut names = vec![];
+        let mut rest = &self.field_names[..];
+        while !rest.is_empty() {
+            let mut length = 0usize;
+            let mut shift = 0;
+            loop {
+                let (byte, remainder) = rest.split_first().ok_or_else(invalid)?;
+                rest = remainder;
+                length |= ((byte & 0x7f) as usize)
+                    .checked_shl(shift)
+                    .ok_or_else(invalid)?;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            if length > rest.len() {
+                return Err(invalid());
+            }
+            let (name, remainder) = rest.split_at(length);
+            names.push(std::str::from_utf8(name).map_err(|_| invalid())?);
+            rest = remainder;
+        }
+        Ok(names)
+    }
+
+    /// Verifies the metadata checksum and the chunk checksums, as the destabilization does.
+    pub fn verify_checksums(&self) -> Result<Checksums> {
+        let chunk_checksums = match &self.chunk_checksums {
+            Some(chunk_checksums) => chunk_checksums,
+            None => return Ok(Checksums::Absent),
+        };
+        let metadata_checksum = [
+            &self.candid_data,
+            &self.type_offsets,
+            &self.field_names,
+            chunk_checksums,
+        ]
+        .iter()
+        .fold(0, |checksum, blob| crc32(checksum, blob));
+        if metadata_checksum as u64 != self.metadata_checksum {
+            return Err(InspectionError::ChecksumMismatch(
+                "type descriptor",
+                self.type_descriptor_address,
+            ));
+        }
+        verify_chunks(self.serialized_data(), chunk_checksums)
+            .map_err(|offset| {
+                InspectionError::ChecksumMismatch(
+                    "serialized data chunk",
+                    self.serialized_data_address + offset,
+                )
+            })
+            .map(|_| Checksums::Verified)
+    }
+}
+
+/// Verifies the serialized `data` against the 32-bit little-endian `chunk_checksums`.
+/// Returns the offset of the first corrupted chunk as error.
+pub fn verify_chunks(data: &[u8], chunk_checksums: &[u8]) -> std::result::Result<(), u64> {
+    let chunks = data.chunks(CHUNK_SIZE as usize);
+    if chunks.len() * 4 != chunk_checksums.len() {
+        return Err(0);
+    }
+    for (index, (chunk, checksum)) in chunks.zip(chunk_checksums.chunks_exact(4)).enumerate() {
+        if crc32(0, chunk).to_le_bytes() != checksum {
+            return Err(index as u64 * CHUNK_SIZE);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a blob of the type descriptor, stored as 64-bit length followed by the data.
+fn read_blob<'a>(memory: &'a [u8], offset: &mut u64) -> Result<&'a [u8]> {
+    let position = *offset;
+    let read = |start: u64, length: u64| {
+        start
+            .checked_add(length)
+            .and_then(|end| memory.get(start as usize..end as usize))
+            .ok_or(InspectionError::OutOfBounds("type descriptor", position))
+    };
+    let length = u64::from_le_bytes(read(position, WORD_SIZE)?.try_into().unwrap());
+    let blob = read(position + WORD_SIZE, length)?;
+    *offset = position + WORD_SIZE + length;
+    Ok(blob)
+}
@@ -0,0 +1,80 @@
+//! Offline inspection of stable memory images of the graph-copy-based stabilization.
+//!
+//! Reads a dump of the stable memory of a canister that has been stabilized by the graph copy
+//! (see `design/GraphCopyStabilization.md`), without deploying the canister:
+//! * `image`: The metadata in the last page, the stored stable type and its field names, and the
+//!   checksums.
+//! * `graph`: The serialized object graph in the stable format (`stabilization/layout.rs` in the RTS).
+//! * `types`: The Candid type table of the stable actor type.
+//! * `statistics`: Object counts and sizes per stable object kind.
+//! * `export`: JSON export of the stable variables, guided by the stable actor type.
+//!
+//! The long-term stable format, i.e. the metadata layout, the stable object kinds, the upgrade
+//! history, and the CRC32 of the checksums, is shared with the RTS by `motoko_stable_format`.
+//! The object payloads are read according to `stabilization/layout` in the RTS.
+
+pub mod export;
+pub mod graph;
+pub mod image;
+pub mod statistics;
+pub mod types;
+
+use std::fmt::{Display, Formatter};
+
+/// Reason why a stable memory image cannot be inspected.
+#[derive(Debug, PartialEq)]
+pub enum InspectionError {
+    /// The image is not a non-empty sequence of stable memory pages.
+    InvalidImageSize(u64),
+    /// The image has not been stored by the graph-copy-based stabilization.
+    UnsupportedVersion(u32),
+    /// The named area exceeds the image at this address.
+    OutOfBounds(&'static str, u64),
+    /// The checksum of the named area does not match at this address.
+    ChecksumMismatch(&'static str, u64),
+    /// The stored stable type is malformed at this type index.
+    InvalidType(usize),
+    /// The stored field names of the stable type are malformed.
+    InvalidFieldNames,
+    /// The object at this address of the serialized data has an invalid stable tag.
+    InvalidObject(u64),
+    /// The stable value, given in its raw encoding, does not match the stable type.
+    TypeMismatch(u64),
+}
+
+impl Display for InspectionError {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        match self {
+            InspectionError::InvalidImageSize(size) => {
+                write!(formatter, "invalid stable memory size {}", size)
+            }
+            InspectionError::UnsupportedVersion(version) => write!(
+                formatter,
+                "unsupported stable memory version {}, no graph-copy stabilization",
+                version
+            ),
+            InspectionError::OutOfBounds(area, address) => {
+                write!(formatter, "{} out of bounds at offset {}", area, address)
+            }
+            InspectionError::ChecksumMismatch(area, address) => write!(
+                formatter,
+                "stable memory corruption detected in the {} at offset {}",
+                area, address
+            ),
+            InspectionError::InvalidType(index) => write!(formatter, "invalid type {}", index),
+            InspectionError::InvalidFieldNames => write!(formatter, "invalid field names"),
+            InspectionError::InvalidObject(address) => {
+                write!(formatter, "invalid object at offset {}", address)
+            }
+            InspectionError::TypeMismatch(value) => {
+                write!(
+                    formatter,
+                    "value does not match the stable type: {:#x}",
+                    value
+                )
+            }
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, InspectionError>;
@@ -0,0 +1,178 @@
+//! Command line interface of the stable memory inspector.
+
+use std::process::exit;
+
+use motoko_stable_format::upgrade_history::PersistenceMode;
+use motoko_stable_inspector::{
+    export::{export_json, ExportOptions},
+    image::{Checksums, StableImage},
+    statistics::Statistics,
+    InspectionError,
+};
+
+const USAGE: &str = "\
+Usage: motoko-stable-inspector <COMMAND> [OPTIONS] <STABLE_MEMORY_FILE>
+
+Inspects a dump of the stable memory of a canister stabilized by the graph copy.
+
+Commands:
+  stats             Print the metadata, the upgrade history, and the object statistics
+  json              Export the stable variables as JSON
+
+Options:
+  --labels <FILE>   Name the record fields and variant cases by the names in FILE, one per line,
+                    in addition to the field names stored with the stable type
+  --rtti            The program has been compiled with `--experimental-rtti`
+  --untyped         Export the object graph without the stable type
+  --no-verify       Skip the checksum verification
+";
+
+/// Stack size of the export, which recurses along the paths of the object graph.
+const EXPORT_STACK_SIZE: usize = 1024 * 1024 * 1024;
+
+struct Arguments {
+    command: String,
+    file: String,
+    labels: Option<String>,
+    rtti: bool,
+    untyped: bool,
+    verify: bool,
+}
+
+fn parse_arguments() -> Option<Arguments> {
+    let mut arguments = std::env::args().skip(1);
+    let command = arguments.next()?;
+    let mut file = None;
+    let mut labels = None;
+    let (mut rtti, mut untyped, mut verify) = (false, false, true);
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--labels" => labels = Some(arguments.next()?),
+            "--rtti" => rtti = true,
+            "--untyped" => untyped = true,
+            "--no-verify" => verify = false,
+            _ if file.is_none() && !argument.starts_with("--") => file = Some(argument),
+            _ => return None,
+        }
+    }
+    Some(Arguments {
+        command,
+        file: file?,
+        labels,
+        rtti,
+        untyped,
+        verify,
+    })
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("motoko-stable-inspector: {}", message);
+    exit(1)
+}
+
+fn check<T>(result: Result<T, InspectionError>) -> T {
+    result.unwrap_or_else(|error| fail(&error.to_string()))
+}
+
+fn main() {
+    let arguments = parse_arguments().unwrap_or_else(|| {
+        eprint!("{}", USAGE);
+        exit(2)
+    });
+    let memory = std::fs::read(&arguments.file)
+        .unwrap_or_else(|error| fail(&format!("{}: {}", arguments.file, error)));
+    let image = check(StableImage::parse(memory));
+    let checksums = if arguments.verify {
+        Some(check(image.verify_checksums()))
+    } else {
+        None
+    };
+    match arguments.command.as_str() {
+        "stats" => print_statistics(&image, checksums),
+        "json" => print_json(image, &arguments),
+        _ => {
+            eprint!("{}", USAGE);
+            exit(2)
+        }
+    }
+}
+
+fn print_statistics(image: &StableImage, checksums: Option<Checksums>) {
+    println!("Stable memory size: {} bytes", image.size());
+    println!(
+        "Version: {}{}",
+        image.version,
+        if image.has_regions() {
+            " (with regions)"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "Serialized data: {} bytes at offset {}",
+        image.serialized_data_length, image.serialized_data_address
+    );
+    let types = check(image.type_table());
+    println!(
+        "Stable type: {} types at offset {}",
+        types.type_count(),
+        image.type_descriptor_address
+    );
+    let checksums = match checksums {
+        Some(Checksums::Verified) => "verified",
+        Some(Checksums::Absent) => "absent",
+        None => "not verified",
+    };
+    println!("Checksums: {}", checksums);
+    println!(
+        "Stabilization instructions: {}",
+        image.stabilization_instructions
+    );
+    println!();
+    if !image.upgrade_history.is_empty() {
+        println!("Upgrade history (last upgrade first):");
+        for record in &image.upgrade_history {
+            println!(
+                "  time {} ns, {}, stabilization {}, destabilization {}, compatibility check {}, \
+                 GC completion {} instructions, heap {} -> {} bytes",
+                record.timestamp,
+                PersistenceMode::from_raw(record.persistence_mode)
+                    .map_or("unknown", |mode| mode.name()),
+                record.stabilization_instructions,
+                record.destabilization_instructions,
+                record.compatibility_check_instructions,
+                record.gc_completion_instructions,
+                record.heap_size_before,
+                record.heap_size_after
+            );
+        }
+        println!();
+    }
+    print!("{}", check(Statistics::collect(&image.graph())));
+}
+
+fn print_json(image: StableImage, arguments: &Arguments) {
+    let mut options = ExportOptions {
+        rtti: arguments.rtti,
+        ..ExportOptions::default()
+    };
+    options.add_labels(check(image.labels()).into_iter());
+    if let Some(file) = &arguments.labels {
+        let names = std::fs::read_to_string(file)
+            .unwrap_or_else(|error| fail(&format!("{}: {}", file, error)));
+        options.add_labels(names.lines().map(str::trim).filter(|name| !name.is_empty()));
+    }
+    let untyped = arguments.untyped;
+    let export = std::thread::Builder::new()
+        .stack_size(EXPORT_STACK_SIZE)
+        .spawn(move || {
+            let types = if untyped {
+                None
+            } else {
+                Some(image.type_table()?)
+            };
+            export_json(&image.graph(), types.as_ref(), &options)
+        })
+        .unwrap();
+    println!("{}", check(export.join().unwrap()));
+}
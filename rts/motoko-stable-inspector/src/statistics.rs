@@ -0,0 +1,95 @@
+//! Statistics of the serialized object graph.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::{
+    graph::{StableGraph, StableObjectKind},
+    Result,
+};
+
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct KindStatistics {
+    pub count: u64,
+    /// Size in the serialized data, including the tag.
+    pub bytes: u64,
+}
+
+#[derive(Default, Debug)]
+pub struct Statistics {
+    pub kinds: BTreeMap<StableObjectKind, KindStatistics>,
+    pub objects: u64,
+    pub bytes: u64,
+    /// Objects that are referenced more than once, by sharing or by cycles.
+    pub shared_objects: u64,
+    /// Address, size, and kind of the largest object.
+    pub largest_object: Option<(u64, u64, StableObjectKind)>,
+}
+
+impl Statistics {
+    pub fn collect(graph: &StableGraph) -> Result<Statistics> {
+        let mut statistics = Statistics::default();
+        // Bitmaps over the 64-bit words of the serialized data, denoting objects that are
+        // referenced at least once, respectively more than once.
+        let words = (graph.length() / 8) as usize;
+        let mut referenced = vec![0u64; words / 64 + 1];
+        let mut shared = vec![0u64; words / 64 + 1];
+        for object in graph.objects() {
+            let (address, object, size) = object?;
+            let kind = object.kind();
+            let entry = statistics.kinds.entry(kind).or_default();
+            entry.count += 1;
+            entry.bytes += size;
+            statistics.objects += 1;
+            statistics.bytes += size;
+            let largest = statistics.largest_object.map_or(0, |(_, size, _)| size);
+            if size > largest {
+                statistics.largest_object = Some((address, size, kind));
+            }
+            for value in object.values().iter().filter(|value| value.is_pointer()) {
+                let word = (value.address() / 8) as usize;
+                let (index, bit) = (word / 64, 1 << (word % 64));
+                if index >= referenced.len() {
+                    continue;
+                }
+                if referenced[index] & bit != 0 && shared[index] & bit == 0 {
+                    shared[index] |= bit;
+                    statistics.shared_objects += 1;
+                }
+                referenced[index] |= bit;
+            }
+        }
+        Ok(statistics)
+    }
+}
+
+impl Display for Statistics {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        writeln!(formatter, "{:<20} {:>12} {:>16}", "Kind", "Count", "Bytes")?;
+        for (kind, entry) in &self.kinds {
+            writeln!(
+                formatter,
+                "{:<20} {:>12} {:>16}",
+                kind.name(),
+                entry.count,
+                entry.bytes
+            )?;
+        }
+        writeln!(
+            formatter,
+            "{:<20} {:>12} {:>16}",
+            "total", self.objects, self.bytes
+        )?;
+        writeln!(formatter, "Shared objects: {}", self.shared_objects)?;
+        if let Some((address, size, kind)) = self.largest_object {
+            writeln!(
+                formatter,
+                "Largest object: {} of {} bytes at offset {}",
+                kind.name(),
+                size,
+                address
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+//! The stable actor type, stored as the Candid type table of the type descriptor.
+//!
+//! The type table uses the Candid binary format with the Motoko-specific extensions for memory
+//! compatibility checks (see `Serialization.type_desc` in `compile_enhanced.ml`):
+//! * `alias` (1) for mutable values: `var` fields, mutable arrays, and regions.
+//! * `blob` (-129) and `region` (-128) as primitive types.
+//! * `tuple` (-130) with positional fields.
+//!
+//! The type descriptor consists of the Candid data and a sequence of 64-bit offsets into the
+//! Candid data, one per table type, where the first type is the stable actor type.
+
+use std::convert::TryInto;
+
+use crate::{InspectionError, Result};
+
+/// A primitive type or a reference to a type of the table.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Type {
+    Null,
+    Bool,
+    Nat,
+    Int,
+    Nat8,
+    Nat16,
+    /// Also used for `Char`. Characters are therefore exported as their scalar encoding
+    /// interpreted as `Nat32`, not as their code point.
+    Nat32,
+    Nat64,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    Text,
+    Reserved,
+    Empty,
+    Principal,
+    Region,
+    Blob,
+    Table(usize),
+}
+
+/// A composite type of the table.
+#[derive(Clone, PartialEq, Debug)]
+pub enum TypeDefinition {
+    Opt(Type),
+    Vec(Type),
+    /// Fields with their label hashes, sorted by hash.
+    Record(Vec<(u32, Type)>),
+    /// Cases with their label hashes, sorted by hash.
+    Variant(Vec<(u32, Type)>),
+    Tuple(Vec<Type>),
+    Func,
+    Service,
+    /// Mutable value of the type.
+    Alias(Type),
+}
+
+/// The decoded type table.
+pub struct TypeTable {
+    definitions: Vec<TypeDefinition>,
+}
+
+impl TypeTable {
+    pub fn new(candid_data: &[u8], type_offsets: &[u8]) -> Result<TypeTable> {
+        const OFFSET_SIZE: usize = std::mem::size_of::<u64>();
+        if type_offsets.is_empty()
+            || !type_offsets
+                .chunks_exact(OFFSET_SIZE)
+                .remainder()
+                .is_empty()
+        {
+            return Err(InspectionError::InvalidType(0));
+        }
+        let type_count = type_offsets.len() / OFFSET_SIZE;
+        let definitions = type_offsets
+            .chunks_exact(OFFSET_SIZE)
+            .enumerate()
+            .map(|(index, offset)| {
+                let offset = u64::from_le_bytes(offset.try_into().unwrap()) as usize;
+                let mut reader = Reader {
+                    bytes: candid_data.get(offset..).unwrap_or(&[]),
+                    type_count,
+                };
+                reader
+                    .definition()
+                    .ok_or(InspectionError::InvalidType(index))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(TypeTable { definitions })
+    }
+
+    pub fn type_count(&self) -> usize {
+        self.definitions.len()
+    }
+
+    /// The stable actor type.
+    pub fn actor_type(&self) -> Type {
+        Type::Table(0)
+    }
+
+    pub fn definition(&self, index: usize) -> &TypeDefinition {
+        &self.definitions[index]
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    type_count: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(byte)
+    }
+
+    fn skip(&mut self, length: usize) -> Option<()> {
+        self.bytes = self.bytes.get(length..)?;
+        Some(())
+    }
+
+    fn leb128(&mut self) -> Option<u32> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            result |= ((byte & 0b0111_1111) as u64) << shift;
+            if byte & 0b1000_0000 == 0 {
+                return result.try_into().ok();
+            }
+            shift += 7;
+            if shift > 35 {
+                return None;
+            }
+        }
+    }
+
+    fn sleb128(&mut self) -> Option<i32> {
+        let mut result: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            result |= ((byte & 0b0111_1111) as i64) << shift;
+            shift += 7;
+            if byte & 0b1000_0000 == 0 {
+                if byte & 0b0100_0000 != 0 {
+                    result |= -1 << shift;
+                }
+                return result.try_into().ok();
+            }
+            if shift > 35 {
+                return None;
+            }
+        }
+    }
+
+    fn type_reference(&mut self) -> Option<Type> {
+        let reference = self.sleb128()?;
+        let typ = match reference {
+            -1 => Type::Null,
+            -2 => Type::Bool,
+            -3 => Type::Nat,
+            -4 => Type::Int,
+            -5 => Type::Nat8,
+            -6 => Type::Nat16,
+            -7 => Type::Nat32,
+            -8 => Type::Nat64,
+            -9 => Type::Int8,
+            -10 => Type::Int16,
+            -11 => Type::Int32,
+            -12 => Type::Int64,
+            -13 => Type::Float32,
+            -14 => Type::Float64,
+            -15 => Type::Text,
+            -16 => Type::Reserved,
+            -17 => Type::Empty,
+            -24 => Type::Principal,
+            -128 => Type::Region,
+            -129 => Type::Blob,
+            index if index >= 0 && (index as usize) < self.type_count => {
+                Type::Table(index as usize)
+            }
+            _ => return None,
+        };
+        Some(typ)
+    }
+
+    fn fields(&mut self) -> Option<Vec<(u32, Type)>> {
+        (0..self.leb128()?)
+            .map(|_| Some((self.leb128()?, self.type_reference()?)))
+            .collect()
+    }
+
+    fn definition(&mut self) -> Option<TypeDefinition> {
+        let definition = match self.sleb128()? {
+            -18 => TypeDefinition::Opt(self.type_reference()?),
+            -19 => TypeDefinition::Vec(self.type_reference()?),
+            -20 => TypeDefinition::Record(self.fields()?),
+            -21 => TypeDefinition::Variant(self.fields()?),
+            -130 => TypeDefinition::Tuple(self.fields()?.into_iter().map(|(_, t)| t).collect()),
+            -22 => {
+                for _ in 0..2 {
+                    for _ in 0..self.leb128()? {
+                        self.type_reference()?;
+                    }
+                }
+                let annotations = self.leb128()?;
+                self.skip(annotations as usize)?;
+                TypeDefinition::Func
+            }
+            -23 => {
+                for _ in 0..self.leb128()? {
+                    let length = self.leb128()?;
+                    self.skip(length as usize)?;
+                    self.type_reference()?;
+                }
+                TypeDefinition::Service
+            }
+            1 => TypeDefinition::Alias(self.type_reference()?),
+            _ => return None,
+        };
+        Some(definition)
+    }
+}
@@ -1138,10 +1138,19 @@ module RTS = struct
     E.add_func_import env "rts" "memcmp" [I32Type; I32Type; I32Type] [I32Type];
     E.add_func_import env "rts" "version" [] [I32Type];
     E.add_func_import env "rts" "parse_idl_header" [I32Type; I32Type; I32Type; I32Type; I32Type] [];
+    E.add_func_import env "rts" "idl_validate" [I32Type] [I32Type];
+    E.add_func_import env "rts" "get_upgrade_history" [] [I32Type];
     E.add_func_import env "rts" "idl_sub_buf_words" [I32Type; I32Type] [I32Type];
     E.add_func_import env "rts" "idl_sub_buf_init" [I32Type; I32Type; I32Type] [];
     E.add_func_import env "rts" "idl_sub"
       [I32Type; I32Type; I32Type; I32Type; I32Type; I32Type; I32Type; I32Type; I32Type] [I32Type];
+    E.add_func_import env "rts" "idl_budget_reset" [I32Type] [];
+    E.add_func_import env "rts" "idl_budget_enter" [I32Type] [];
+    E.add_func_import env "rts" "idl_budget_exit" [] [];
+    E.add_func_import env "rts" "idl_budget_vec" [I32Type; I32Type; I32Type; I32Type] [];
+    E.add_func_import env "rts" "idl_budget_alloc" [I32Type; I32Type] [];
+    E.add_func_import env "rts" "idl_set_decoding_limit" [I32Type; I64Type] [];
+    E.add_func_import env "rts" "idl_get_decoding_limit" [I32Type] [I64Type];
     E.add_func_import env "rts" "leb128_decode" [I32Type] [I32Type];
     E.add_func_import env "rts" "sleb128_decode" [I32Type] [I32Type];
     E.add_func_import env "rts" "bigint_of_word32" [I32Type] [I32Type];
@@ -1209,7 +1218,7 @@ module RTS = struct
     E.add_func_import env "rts" "text_to_buf" [I32Type; I32Type] [];
     E.add_func_import env "rts" "text_lowercase" [I32Type] [I32Type];
     E.add_func_import env "rts" "text_uppercase" [I32Type] [I32Type];
-    E.add_func_import env "rts" "region_init" [I32Type] [];
+    E.add_func_import env "rts" "region_init" [I32Type; I32Type] [];
     E.add_func_import env "rts" "alloc_region" [I64Type; I32Type; I32Type] [I32Type];
     E.add_func_import env "rts" "init_region" [I32Type; I64Type; I32Type; I32Type] [];
     E.add_func_import env "rts" "region_new" [] [I32Type];
@@ -1218,8 +1227,15 @@ module RTS = struct
     E.add_func_import env "rts" "region_vec_pages" [I32Type] [I32Type];
     E.add_func_import env "rts" "region_size" [I32Type] [I64Type];
     E.add_func_import env "rts" "region_grow" [I32Type; I64Type] [I64Type];
+    E.add_func_import env "rts" "region_shrink" [I32Type; I64Type] [I64Type];
+    E.add_func_import env "rts" "region_free" [I32Type] [];
     E.add_func_import env "rts" "region_load_blob" [I32Type; I64Type; I32Type] [I32Type];
     E.add_func_import env "rts" "region_store_blob" [I32Type; I64Type; I32Type] [];
+    E.add_func_import env "rts" "region_load_bytes" [I32Type; I64Type; I32Type; I32Type] [];
+    E.add_func_import env "rts" "region_store_bytes" [I32Type; I64Type; I32Type; I32Type] [];
+    E.add_func_import env "rts" "region_copy" [I32Type; I64Type; I32Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "region_check" [I32Type; I32Type] [I32Type];
+    E.add_func_import env "rts" "region_stats" [] [I32Type];
     E.add_func_import env "rts" "region_load_word8" [I32Type; I64Type] [I32Type];
     E.add_func_import env "rts" "region_store_word8" [I32Type; I64Type; I32Type] [];
     E.add_func_import env "rts" "region_load_word16" [I32Type; I64Type] [I32Type];
@@ -4508,6 +4524,22 @@ module Region = struct
     E.require_stable_memory env;
     E.call_import env "rts" "region_grow"
 
+  let shrink env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_shrink"
+
+  let free env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_free"
+
+  let check env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_check"
+
+  let stats env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_stats"
+
   let load_blob env =
     E.require_stable_memory env;
     E.call_import env "rts" "region_load_blob"
@@ -4515,6 +4547,17 @@ module Region = struct
     E.require_stable_memory env;
     E.call_import env "rts" "region_store_blob"
 
+  let load_bytes env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_load_bytes"
+  let store_bytes env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_store_bytes"
+
+  let copy env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_copy"
+
   let load_word8 env =
     E.require_stable_memory env;
     E.call_import env "rts" "region_load_word8"
@@ -6711,10 +6754,15 @@ module MakeSerialization (Strm : Stream) = struct
           compile_const_64 (-1L) ^^
           set_value_quota env
         end
-          G.nop
+          G.nop ^^
+
+        (* Reset decoding budget, see budget.rs *)
+        compile_unboxed_const 1l ^^
+        E.call_import env "rts" "idl_budget_reset"
       end
       begin (* Extended candid/ Destabilization *)
-        G.nop
+        compile_unboxed_const 0l ^^
+        E.call_import env "rts" "idl_budget_reset"
       end
 
     let define_idl_limit_check env =
@@ -7403,6 +7451,10 @@ module MakeSerialization (Strm : Stream) = struct
       compile_const_64 1L ^^
       Registers.idl_limit_check env ^^
 
+      (* Check nesting depth of the decoding budget, exited at the end *)
+      get_data_buf ^^
+      E.call_import env "rts" "idl_budget_enter" ^^
+
       (* Check recursion depth (protects against empty record etc.) *)
       (* Factor 2 because at each step, the expected type could go through one
          level of opt that is not present in the value type
@@ -7503,6 +7555,7 @@ module MakeSerialization (Strm : Stream) = struct
         let (set_len, get_len) = new_local env "len" in
         let (set_x, get_x) = new_local env "x" in
         ReadBuf.read_leb128 env get_data_buf ^^ set_len ^^
+        get_data_buf ^^ get_len ^^ E.call_import env "rts" "idl_budget_alloc" ^^
 
         Blob.alloc env Tagged.B get_len ^^ set_x ^^
         get_x ^^ Blob.payload_ptr_unskewed env ^^
@@ -7535,6 +7588,7 @@ module MakeSerialization (Strm : Stream) = struct
         ReadBuf.advance get_data_buf get_len ^^
         (* validate *)
         get_ptr ^^ get_len ^^ E.call_import env "rts" "utf8_validate" ^^
+        get_data_buf ^^ get_len ^^ E.call_import env "rts" "idl_budget_alloc" ^^
         (* copy *)
         get_ptr ^^ get_len ^^ Text.of_ptr_size env
       in
@@ -7916,6 +7970,8 @@ module MakeSerialization (Strm : Stream) = struct
           compile_unboxed_const 0l ^^
           get_len ^^ G.i (Convert (Wasm.Values.I64 I64Op.ExtendUI32)) ^^
           Registers.idl_limit_check env ^^
+          get_data_buf ^^ get_typtbl ^^ get_arg_typ ^^ get_len ^^
+          E.call_import env "rts" "idl_budget_vec" ^^
           Arr.alloc env Tagged.I get_len ^^ set_x ^^
           get_len ^^ from_0_to_n env (fun get_i ->
           get_x ^^ get_i ^^ Arr.unsafe_idx env ^^
@@ -8066,6 +8122,7 @@ module MakeSerialization (Strm : Stream) = struct
         coercion_failed "IDL error: deserializing value of type None"
       | _ -> todo_trap env "deserialize" (Arrange_ir.typ t)
       end ^^
+      E.call_import env "rts" "idl_budget_exit" ^^
       (* Parsed value on the stack, return that, unless the failure flag is set *)
       when_failed (compile_unboxed_const (coercion_error_value env) ^^ G.i Return)
     )
@@ -9007,6 +9064,162 @@ module StackRep = struct
 
 end (* StackRep *)
 
+module RegionArr = struct
+  (* Bulk transfer of fixed-size numbers between regions and immutable arrays,
+     in little-endian order, without an intermediate blob.
+
+     Loads of 32-bit numbers read the elements directly into the payload of the
+     new array, where they are converted in place to their vanilla representation.
+     64-bit numbers exceed the array slots and are loaded chunk by chunk through a
+     buffer on the stack. Likewise, stores convert the elements chunk by chunk into
+     a buffer on the stack, as the array must not be modified.
+     The RTS accesses the region once per block span. *)
+
+  let element_size = function
+    | Type.(Nat32 | Int32) -> 4l
+    | Type.(Nat64 | Int64 | Float) -> 8l
+    | _ -> assert false
+
+  (* Size of the stack buffer, see `for_chunks` *)
+  let chunk_bytes = 8192l
+
+  (* Loads the element at the address on the stack, in its unboxed stack representation *)
+  let load_element = function
+    | Type.(Nat32 | Int32) ->
+      G.i (Load {ty = I32Type; align = 0; offset = 0L; sz = None})
+    | Type.(Nat64 | Int64) ->
+      G.i (Load {ty = I64Type; align = 0; offset = 0L; sz = None})
+    | Type.Float ->
+      G.i (Load {ty = F64Type; align = 0; offset = 0L; sz = None})
+    | _ -> assert false
+
+  (* Stores an element in its unboxed stack representation at the given address *)
+  let store_element = function
+    | Type.(Nat32 | Int32) ->
+      G.i (Store {ty = I32Type; align = 0; offset = 0L; sz = None})
+    | Type.(Nat64 | Int64) ->
+      G.i (Store {ty = I64Type; align = 0; offset = 0L; sz = None})
+    | Type.Float ->
+      G.i (Store {ty = F64Type; align = 0; offset = 0L; sz = None})
+    | _ -> assert false
+
+  (* Unskewed address of the `i`-th element, starting from `get_base` *)
+  let element_address get_base get_i ty =
+    get_base ^^
+    get_i ^^ compile_mul_const (element_size ty) ^^
+    G.i (Binary (Wasm.Values.I32 I32Op.Add))
+
+  (* Region offset of the `i`-th element, starting from `get_offset` *)
+  let element_offset get_offset get_i ty =
+    get_offset ^^
+    get_i ^^ G.i (Convert (Wasm.Values.I64 I64Op.ExtendUI32)) ^^
+    compile_const_64 (Int64.of_int32 (element_size ty)) ^^
+    G.i (Binary (Wasm.Values.I64 I64Op.Mul)) ^^
+    G.i (Binary (Wasm.Values.I64 I64Op.Add))
+
+  let payload_ptr_unskewed env get_array =
+    get_array ^^ compile_unboxed_zero ^^ Arr.unsafe_idx env ^^
+    compile_add_const ptr_unskew
+
+  (* Iterates over chunks of at most `chunk_bytes`, with a stack buffer of that size.
+     Runs at least once, such that the region range is also checked for zero elements. *)
+  let for_chunks env ty get_count mk_body =
+    let chunk_length = Int32.div chunk_bytes (element_size ty) in
+    let (set_start, get_start) = new_local env "start" in
+    let (set_n, get_n) = new_local env "n" in
+    compile_unboxed_zero ^^ set_start ^^
+    Stack.with_words env "buffer" (Int32.div chunk_bytes Heap.word_size) (fun get_buffer ->
+      G.loop0 (
+        get_count ^^ get_start ^^ G.i (Binary (Wasm.Values.I32 I32Op.Sub)) ^^ set_n ^^
+        get_n ^^ compile_unboxed_const chunk_length ^^
+        compile_comparison I32Op.GtU ^^
+        E.if0 (compile_unboxed_const chunk_length ^^ set_n) G.nop ^^
+
+        mk_body get_buffer get_start get_n ^^
+
+        get_start ^^ get_n ^^ G.i (Binary (Wasm.Values.I32 I32Op.Add)) ^^ set_start ^^
+        get_start ^^ get_count ^^ compile_comparison I32Op.LtU ^^
+        E.if0 (G.i (Br (nr 1l))) G.nop
+      )
+    )
+
+  (* Region, offset, and element count on the stack, returns the array *)
+  let load env ty =
+    Func.share_code3 Func.Always env (prim_fun_name ty "RegionArr.load") (("region", I32Type), ("offset", I64Type), ("count", I32Type)) [I32Type]
+      (fun env get_region get_offset get_count ->
+        let (set_r, get_r) = new_local env "r" in
+
+        (* Allocate first, such that the size computation cannot overflow *)
+        Arr.alloc env Tagged.I get_count ^^ set_r ^^
+
+        (if element_size ty <= Heap.word_size then begin
+          let (set_payload, get_payload) = new_local env "payload" in
+          payload_ptr_unskewed env get_r ^^ set_payload ^^
+
+          get_region ^^
+          get_offset ^^
+          get_payload ^^
+          get_count ^^ compile_mul_const (element_size ty) ^^
+          Region.load_bytes env ^^
+
+          get_count ^^ from_0_to_n env (fun get_i ->
+            get_r ^^ get_i ^^ Arr.unsafe_idx env ^^
+            element_address get_payload get_i ty ^^
+            load_element ty ^^
+            StackRep.adjust env (StackRep.of_type (Type.Prim ty)) SR.Vanilla ^^
+            store_ptr
+          )
+        end else
+          for_chunks env ty get_count (fun get_buffer get_start get_n ->
+            get_region ^^
+            element_offset get_offset get_start ty ^^
+            get_buffer ^^
+            get_n ^^ compile_mul_const (element_size ty) ^^
+            Region.load_bytes env ^^
+
+            get_n ^^ from_0_to_n env (fun get_i ->
+              get_r ^^
+              get_start ^^ get_i ^^ G.i (Binary (Wasm.Values.I32 I32Op.Add)) ^^
+              Arr.unsafe_idx env ^^
+              element_address get_buffer get_i ty ^^
+              load_element ty ^^
+              StackRep.adjust env (StackRep.of_type (Type.Prim ty)) SR.Vanilla ^^
+              store_ptr
+            )
+          )
+        ) ^^
+        get_r ^^
+        Tagged.allocation_barrier env
+      )
+
+  (* Region, offset, and array on the stack *)
+  let store env ty =
+    Func.share_code3 Func.Always env (prim_fun_name ty "RegionArr.store") (("region", I32Type), ("offset", I64Type), ("array", I32Type)) []
+      (fun env get_region get_offset get_array ->
+        let (set_len, get_len) = new_local env "len" in
+        get_array ^^ Arr.len env ^^ set_len ^^
+
+        for_chunks env ty get_len (fun get_buffer get_start get_n ->
+          get_n ^^ from_0_to_n env (fun get_i ->
+            element_address get_buffer get_i ty ^^
+            get_array ^^
+            get_start ^^ get_i ^^ G.i (Binary (Wasm.Values.I32 I32Op.Add)) ^^
+            Arr.unsafe_idx env ^^
+            load_ptr ^^
+            StackRep.adjust env SR.Vanilla (StackRep.of_type (Type.Prim ty)) ^^
+            store_element ty
+          ) ^^
+
+          get_region ^^
+          element_offset get_offset get_start ty ^^
+          get_buffer ^^
+          get_n ^^ compile_mul_const (element_size ty) ^^
+          Region.store_bytes env
+        )
+      )
+
+end (* RegionArr *)
+
 module VarEnv = struct
 
   (* A type to record where Motoko names are stored. *)
@@ -11505,6 +11718,11 @@ and compile_prim_invocation (env : E.t) ae p es at =
     SR.Vanilla,
     GC.get_collector_instructions env ^^ BigNum.from_word64 env
 
+  | OtherPrim "rts_upgrade_history", [] ->
+    SR.Vanilla,
+    (* Classical persistence does not record upgrades: returns an empty history. *)
+    E.call_import env "rts" "get_upgrade_history"
+
   | OtherPrim "rts_stable_memory_size", [] ->
     SR.Vanilla,
     StableMem.stable64_size env ^^ BigNum.from_word64 env
@@ -11531,6 +11749,27 @@ and compile_prim_invocation (env : E.t) ae p es at =
     compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
     Region.grow env
 
+  | OtherPrim "regionShrink", [e0; e1] ->
+    SR.UnboxedWord64 Type.Nat64,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    Region.shrink env
+
+  | OtherPrim "regionFree", [e0] ->
+    SR.unit,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    Region.free env
+
+  | OtherPrim "regionCheck", [e0; e1] ->
+    SR.Vanilla,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae SR.bool e1 ^^
+    Region.check env
+
+  | OtherPrim "regionStats", [] ->
+    SR.Vanilla,
+    Region.stats env
+
   | OtherPrim "regionSize", [e0] ->
     SR.UnboxedWord64 Type.Nat64,
     compile_exp_as env ae SR.Vanilla e0 ^^
@@ -11552,6 +11791,15 @@ and compile_prim_invocation (env : E.t) ae p es at =
     compile_exp_as env ae SR.Vanilla e2 ^^
     Region.store_blob env
 
+  | OtherPrim "regionCopy", [e0; e1; e2; e3; e4] ->
+    SR.unit,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    compile_exp_as env ae SR.Vanilla e2 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e3 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e4 ^^
+    Region.copy env
+
   | OtherPrim (("regionLoadNat8" | "regionLoadInt8" as p)), [e0; e1] ->
     let ty = Type.(if p = "regionLoadNat8" then Nat8 else Int8) in
     SR.UnboxedWord32 ty,
@@ -11615,6 +11863,34 @@ and compile_prim_invocation (env : E.t) ae p es at =
       e2 ^^
     Region.store_word64 env
 
+  | OtherPrim (("regionLoadNat32Array" | "regionLoadInt32Array" | "regionLoadNat64Array" | "regionLoadInt64Array" | "regionLoadFloatArray") as p), [e0; e1; e2] ->
+    let ty = Type.(match p with
+      | "regionLoadNat32Array" -> Nat32
+      | "regionLoadInt32Array" -> Int32
+      | "regionLoadNat64Array" -> Nat64
+      | "regionLoadInt64Array" -> Int64
+      | _ -> Float) in
+    SR.Vanilla,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    compile_exp_as env ae SR.Vanilla e2 ^^
+    Blob.lit env Tagged.T "Array size out of bounds" ^^
+    BigNum.to_word32_with env ^^
+    RegionArr.load env ty
+
+  | OtherPrim (("regionStoreNat32Array" | "regionStoreInt32Array" | "regionStoreNat64Array" | "regionStoreInt64Array" | "regionStoreFloatArray") as p), [e0; e1; e2] ->
+    let ty = Type.(match p with
+      | "regionStoreNat32Array" -> Nat32
+      | "regionStoreInt32Array" -> Int32
+      | "regionStoreNat64Array" -> Nat64
+      | "regionStoreInt64Array" -> Int64
+      | _ -> Float) in
+    SR.unit,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    compile_exp_as env ae SR.Vanilla e2 ^^
+    RegionArr.store env ty
+
   | OtherPrim ("regionLoadFloat"), [e0; e1] ->
     SR.UnboxedFloat64,
     compile_exp_as env ae SR.Vanilla e0 ^^
@@ -11955,6 +12231,28 @@ and compile_prim_invocation (env : E.t) ae p es at =
     Serialization.Registers.get_value_bias env ^^
     BoxedSmallWord.box env Type.Nat32
 
+  | OtherPrim "setCandidDecodingLimits", [e1; e2; e3; e4] ->
+    SR.unit,
+    G.concat_mapi (fun i e ->
+      compile_unboxed_const (Int32.of_int i) ^^
+      compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e ^^
+      E.call_import env "rts" "idl_set_decoding_limit"
+    ) [e1; e2; e3; e4]
+
+  | OtherPrim "getCandidDecodingLimits", [] ->
+    SR.UnboxedTuple 4,
+    G.table 4 (fun i ->
+      compile_unboxed_const (Int32.of_int i) ^^
+      E.call_import env "rts" "idl_get_decoding_limit" ^^
+      BoxedWord64.box env Type.Nat64)
+
+  | OtherPrim "candidValidate", [e] ->
+    SR.Vanilla,
+    compile_exp_as env ae SR.Vanilla e ^^
+    (* The value quota only applies to deserialization *)
+    compile_unboxed_const 0l ^^ Serialization.Registers.set_rel_buf_opt env ^^
+    E.call_import env "rts" "idl_validate"
+
   (* Coercions for abstract types *)
   | CastPrim (_,_), [e] ->
     compile_exp env ae e
@@ -12061,6 +12359,7 @@ and compile_prim_invocation (env : E.t) ae p es at =
     SR.Vanilla,
     Stabilization.destabilize env ty (StableMem.set_version env) ^^
     compile_unboxed_const (if !Flags.use_stable_regions then 1l else 0l) ^^
+    compile_unboxed_const (Int32.of_int !Flags.stable_region_block_pages) ^^
     E.call_import env "rts" "region_init"
 
   | ICStableWrite ty, [] ->
@@ -13037,6 +13336,7 @@ and conclude_module env set_serialization_globals start_fi_o =
       motoko = {
         labels = E.get_labs env;
         stable_types = !(env.E.stable_types);
+        stable_type_descriptor = None;
         compiler = metadata "motoko:compiler" (Lib.Option.get Source_id.release Source_id.id)
       };
       enhanced_orthogonal_persistence = None;
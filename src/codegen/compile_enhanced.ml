@@ -464,6 +464,7 @@ module E = struct
     args : (bool * string) option ref;
     service : (bool * string) option ref;
     stable_types : (bool * string) option ref;
+    stable_type_descriptor : (bool * string) option ref;
     labs : LabSet.t ref; (* Used labels (fields and variants),
                             collected for Motoko custom section 0 *)
 
@@ -517,6 +518,7 @@ module E = struct
     args = ref None;
     service = ref None;
     stable_types = ref None;
+    stable_type_descriptor = ref None;
     labs = ref LabSet.empty;
     (* Actually unused outside mk_fun_env: *)
     n_param = 0l;
@@ -1130,7 +1132,9 @@ module RTS = struct
     E.add_func_import env "rts" "write_with_barrier" [I64Type; I64Type] [];
     E.add_func_import env "rts" "allocation_barrier" [I64Type] [I64Type];
     E.add_func_import env "rts" "running_gc" [] [I32Type];
-    E.add_func_import env "rts" "register_stable_type" [I64Type; I64Type] [];
+    E.add_func_import env "rts" "register_stable_type" [I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "check_stable_type_compatibility" [I64Type] [I64Type];
+    E.add_func_import env "rts" "get_stable_type_descriptor" [] [I64Type];
     E.add_func_import env "rts" "load_stable_actor" [] [I64Type];
     E.add_func_import env "rts" "save_stable_actor" [I64Type] [];
     E.add_func_import env "rts" "free_stable_actor" [] [];
@@ -1140,13 +1144,27 @@ module RTS = struct
     E.add_func_import env "rts" "set_static_variable" [I64Type; I64Type] [];
     E.add_func_import env "rts" "set_upgrade_instructions" [I64Type] [];
     E.add_func_import env "rts" "get_upgrade_instructions" [] [I64Type];
+    E.add_func_import env "rts" "record_upgrade" [I64Type; I64Type] [];
+    E.add_func_import env "rts" "get_upgrade_history" [] [I64Type];
     E.add_func_import env "rts" "memcpy" [I64Type; I64Type; I64Type] [I64Type]; (* standard libc memcpy *)
     E.add_func_import env "rts" "memcmp" [I64Type; I64Type; I64Type] [I32Type];
     E.add_func_import env "rts" "version" [] [I64Type];
     E.add_func_import env "rts" "parse_idl_header" [I32Type; I64Type; I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "idl_validate" [I64Type] [I64Type];
+    E.add_func_import env "rts" "idl_decode" [I64Type; I64Type; I64Type; I32Type] [I64Type];
+    E.add_func_import env "rts" "idl_encode" [I64Type; I64Type; I64Type; I64Type] [I64Type];
+    E.add_func_import env "rts" "idl_print" [I64Type; I64Type; I64Type; I64Type] [I64Type];
     E.add_func_import env "rts" "idl_alloc_typtbl" [I64Type; I64Type; I64Type; I64Type; I64Type] [];
     E.add_func_import env "rts" "idl_sub_buf_words" [I64Type; I64Type] [I64Type];
     E.add_func_import env "rts" "idl_sub_buf_init" [I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "idl_sub_cache_lookup" [I64Type; I64Type; I64Type; I64Type; I64Type; I64Type] [I64Type];
+    E.add_func_import env "rts" "idl_budget_reset" [I32Type] [];
+    E.add_func_import env "rts" "idl_budget_enter" [I64Type] [];
+    E.add_func_import env "rts" "idl_budget_exit" [] [];
+    E.add_func_import env "rts" "idl_budget_vec" [I64Type; I64Type; I32Type; I64Type] [];
+    E.add_func_import env "rts" "idl_budget_alloc" [I64Type; I64Type] [];
+    E.add_func_import env "rts" "idl_set_decoding_limit" [I32Type; I64Type] [];
+    E.add_func_import env "rts" "idl_get_decoding_limit" [I32Type] [I64Type];
     E.add_func_import env "rts" "idl_sub"
       [I64Type; I64Type; I64Type; I64Type; I64Type; I64Type; I64Type; I32Type; I32Type] [I32Type];
     E.add_func_import env "rts" "leb128_decode" [I64Type] [I64Type];
@@ -1211,7 +1229,7 @@ module RTS = struct
     E.add_func_import env "rts" "text_to_buf" [I64Type; I64Type] [];
     E.add_func_import env "rts" "text_lowercase" [I64Type] [I64Type];
     E.add_func_import env "rts" "text_uppercase" [I64Type] [I64Type];
-    E.add_func_import env "rts" "region_init" [I64Type] [];
+    E.add_func_import env "rts" "region_init" [I64Type; I64Type] [];
     E.add_func_import env "rts" "alloc_region" [I64Type; I64Type; I64Type] [I64Type];
     E.add_func_import env "rts" "init_region" [I64Type; I64Type; I64Type; I64Type] [];
     E.add_func_import env "rts" "region_new" [] [I64Type];
@@ -1220,8 +1238,15 @@ module RTS = struct
     E.add_func_import env "rts" "region_vec_pages" [I64Type] [I64Type];
     E.add_func_import env "rts" "region_size" [I64Type] [I64Type];
     E.add_func_import env "rts" "region_grow" [I64Type; I64Type] [I64Type];
+    E.add_func_import env "rts" "region_shrink" [I64Type; I64Type] [I64Type];
+    E.add_func_import env "rts" "region_free" [I64Type] [];
     E.add_func_import env "rts" "region_load_blob" [I64Type; I64Type; I64Type] [I64Type];
     E.add_func_import env "rts" "region_store_blob" [I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "region_load_bytes" [I64Type; I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "region_store_bytes" [I64Type; I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "region_copy" [I64Type; I64Type; I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "region_check" [I64Type; I32Type] [I64Type];
+    E.add_func_import env "rts" "region_stats" [] [I64Type];
     E.add_func_import env "rts" "region_load_word8" [I64Type; I64Type] [I32Type];
     E.add_func_import env "rts" "region_store_word8" [I64Type; I64Type; I32Type] [];
     E.add_func_import env "rts" "region_load_word16" [I64Type; I64Type] [I32Type];
@@ -1268,9 +1293,9 @@ module RTS = struct
     E.add_func_import env "rts" "stop_gc_before_stabilization" [] [];
     E.add_func_import env "rts" "start_gc_after_destabilization" [] [];
     E.add_func_import env "rts" "is_graph_stabilization_started" [] [I32Type];
-    E.add_func_import env "rts" "start_graph_stabilization" [I64Type; I64Type; I64Type] [];
+    E.add_func_import env "rts" "start_graph_stabilization" [I64Type; I64Type; I64Type; I64Type] [];
     E.add_func_import env "rts" "graph_stabilization_increment" [] [I32Type];
-    E.add_func_import env "rts" "start_graph_destabilization" [I64Type; I64Type] [];
+    E.add_func_import env "rts" "start_graph_destabilization" [I64Type; I64Type; I64Type] [];
     E.add_func_import env "rts" "graph_destabilization_increment" [] [I32Type];
     E.add_func_import env "rts" "get_graph_destabilized_actor" [] [I64Type];
     E.add_func_import env "rts" "buffer_in_32_bit_range" [] [I64Type];
@@ -4149,6 +4174,22 @@ module Region = struct
     E.require_stable_memory env;
     E.call_import env "rts" "region_grow"
 
+  let shrink env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_shrink"
+
+  let free env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_free"
+
+  let check env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_check"
+
+  let stats env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_stats"
+
   let load_blob env =
     E.require_stable_memory env;
     E.call_import env "rts" "region_load_blob"
@@ -4156,6 +4197,17 @@ module Region = struct
     E.require_stable_memory env;
     E.call_import env "rts" "region_store_blob"
 
+  let load_bytes env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_load_bytes"
+  let store_bytes env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_store_bytes"
+
+  let copy env =
+    E.require_stable_memory env;
+    E.call_import env "rts" "region_copy"
+
   let load_word8 env =
     E.require_stable_memory env;
     E.call_import env "rts" "region_load_word8"
@@ -5505,6 +5557,7 @@ module StableMem = struct
 
   let region_init env =
     compile_unboxed_const (if !Flags.use_stable_regions then 1L else 0L) ^^
+    compile_unboxed_const (Int64.of_int !Flags.stable_region_block_pages) ^^
     E.call_import env "rts" "region_init"
 
   (* stable memory bounds check *)
@@ -5935,15 +5988,29 @@ module UpgradeStatistics = struct
   let set_upgrade_instructions env =
     E.call_import env "rts" "set_upgrade_instructions"
 
+  (* Persistence modes of the upgrade history, see `upgrade_history.rs` in the RTS. *)
+  let enhanced_orthogonal_persistence = 1L
+  let candid_stabilization = 3L
+
   let add_instructions env =
     get_upgrade_instructions env ^^
     GC.instruction_counter env ^^
     G.i (Binary (Wasm_exts.Values.I64 I64Op.Add)) ^^
     set_upgrade_instructions env
 
+  (* Adds the instructions of the destabilization to the upgrade statistics and
+     records the completed upgrade in the upgrade history. *)
+  let record_upgrade env persistence_mode =
+    compile_unboxed_const persistence_mode ^^
+    GC.instruction_counter env ^^
+    E.call_import env "rts" "record_upgrade"
+
   let set_instructions env =
     GC.instruction_counter env ^^
     set_upgrade_instructions env
+
+  let get_upgrade_history env =
+    E.call_import env "rts" "get_upgrade_history"
 end
 
 module RTS_Exports = struct
@@ -5965,6 +6032,75 @@ module RTS_Exports = struct
       edesc = nr (FuncExport (nr int_from_i64_fi))
     });
 
+    (* The conversions of numbers for the Candid decoder and encoder of the RTS are only
+       generated when used, otherwise they trap *)
+    let when_used_else_trap used env code =
+      if used then code () else E.trap_with env "unreachable" in
+
+    (* Tags or boxes a number received by the Candid decoder of the RTS,
+       dispatching on its primitive IDL type, see `idl_decode` *)
+    let value_from_word64_fi = E.add_fun env "value_from_word64" (
+      Func.of_body env ["idltyp", I32Type; "bits", I64Type] [I64Type] (fun env ->
+        when_used_else_trap !Flags.rts_candid_decoder env (fun () ->
+        let get_idltyp = G.i (LocalGet (nr 0l)) in
+        let get_bits = G.i (LocalGet (nr 1l)) in
+        let small_word ty =
+          get_bits ^^ TaggedSmallWord.msb_adjust ty ^^ TaggedSmallWord.tag env ty in
+        List.fold_right (fun (idltyp, convert) continue ->
+          get_idltyp ^^ compile_eq32_const idltyp ^^
+          E.if1 I64Type convert continue)
+          [ (-3l, get_bits ^^ BigNum.from_word64 env); (* Nat *)
+            (-4l, get_bits ^^ BigNum.from_signed_word64 env); (* Int *)
+            (-5l, small_word Type.Nat8);
+            (-6l, small_word Type.Nat16);
+            (-7l, small_word Type.Nat32);
+            (-8l, get_bits ^^ BoxedWord64.box env Type.Nat64);
+            (-9l, small_word Type.Int8);
+            (-10l, small_word Type.Int16);
+            (-11l, small_word Type.Int32);
+            (-12l, get_bits ^^ BoxedWord64.box env Type.Int64);
+            (-14l, get_bits ^^ G.i (Convert (Wasm_exts.Values.F64 F64Op.ReinterpretInt)) ^^ Float.box env); (* Float64 *)
+          ]
+          (E.trap_with env "value_from_word64: unexpected IDL type"))
+      )
+    ) in
+    E.add_export env (nr {
+      name = Lib.Utf8.decode "value_from_word64";
+      edesc = nr (FuncExport (nr value_from_word64_fi))
+    });
+
+    (* Untags or unboxes a number for the Candid encoder of the RTS,
+       dispatching on its primitive IDL type, see `idl_encode`.
+       Also used by the Candid text rendering of the stable variable dump, see `idl_print` *)
+    let value_to_word64_fi = E.add_fun env "value_to_word64" (
+      Func.of_body env ["idltyp", I32Type; "x", I64Type] [I64Type] (fun env ->
+        when_used_else_trap (!Flags.rts_candid_encoder || !Flags.stable_var_dump) env (fun () ->
+        let get_idltyp = G.i (LocalGet (nr 0l)) in
+        let get_x = G.i (LocalGet (nr 1l)) in
+        let small_word ty = get_x ^^ TaggedSmallWord.lsb_adjust ty in
+        List.fold_right (fun (idltyp, convert) continue ->
+          get_idltyp ^^ compile_eq32_const idltyp ^^
+          E.if1 I64Type convert continue)
+          [ (-3l, get_x ^^ BigNum.truncate_to_word64 env); (* Nat *)
+            (-4l, get_x ^^ BigNum.truncate_to_word64 env); (* Int *)
+            (-5l, small_word Type.Nat8);
+            (-6l, small_word Type.Nat16);
+            (-7l, small_word Type.Nat32);
+            (-8l, get_x ^^ BoxedWord64.unbox env Type.Nat64);
+            (-9l, small_word Type.Int8);
+            (-10l, small_word Type.Int16);
+            (-11l, small_word Type.Int32);
+            (-12l, get_x ^^ BoxedWord64.unbox env Type.Int64);
+            (-14l, get_x ^^ Float.unbox env ^^ G.i (Convert (Wasm_exts.Values.I64 I64Op.ReinterpretFloat))); (* Float64 *)
+          ]
+          (E.trap_with env "value_to_word64: unexpected IDL type"))
+      )
+    ) in
+    E.add_export env (nr {
+      name = Lib.Utf8.decode "value_to_word64";
+      edesc = nr (FuncExport (nr value_to_word64_fi))
+    });
+
     (* Traps *)
 
     let bigint_trap_fi = E.add_fun env "bigint_trap" (
@@ -6024,6 +6160,20 @@ module RTS_Exports = struct
       edesc = nr (FuncExport (nr ic0_performance_counter_fi))
     });
 
+    let ic0_time_fi =
+      if E.mode env = Flags.WASIMode then
+        E.add_fun env "ic0_time" (
+            Func.of_body env [] [I64Type]
+              (fun env ->
+                E.trap_with env "ic0_time is not supposed to be called in WASI"
+              )
+          )
+      else E.reuse_import env "ic0" "time" in
+    E.add_export env (nr {
+      name = Lib.Utf8.decode "ic0_time";
+      edesc = nr (FuncExport (nr ic0_time_fi))
+    });
+
     (* Keep a memory reserve when in update or init state. 
     This reserve can be used by queries, composite queries, and (graph-copy) upgrades. *)
     let keep_memory_reserve_fi = E.add_fun env "keep_memory_reserve" (
@@ -6511,10 +6661,15 @@ module Serialization = struct
           compile_unboxed_const (-1L) ^^ (* u64::MAX *)
           set_value_quota env
         end
-          G.nop
+          G.nop ^^
+
+        (* Reset decoding budget, see budget.rs *)
+        Bool.lit_rts_int32 true ^^
+        E.call_import env "rts" "idl_budget_reset"
       end
       begin (* Extended candid/ Destabilization *)
-        G.nop
+        Bool.lit_rts_int32 false ^^
+        E.call_import env "rts" "idl_budget_reset"
       end
 
     let define_idl_limit_check env =
@@ -6606,43 +6761,45 @@ module Serialization = struct
   (* only used for memory compatibility checks *)
   let idl_tuple     = -130l
 
+  (* Type traversal *)
+  (* We do a first traversal to find out the indices of non-primitive types *)
+  let type_table mode ts =
+    let open Type in
+    let typs = ref [] in
+    let idx = ref TM.empty in
+    let rec go t =
+      let t = Type.normalize t in
+      if to_idl_prim mode t <> None then () else
+      if TM.mem t !idx then () else begin
+        idx := TM.add t (Lib.List32.length !typs) !idx;
+        typs := !typs @ [ t ];
+        match t with
+        | Tup ts -> List.iter go ts
+        | Obj (_, fs) ->
+          List.iter (fun f -> go f.typ) fs
+        | Array (Mut t) -> go (Array t)
+        | Array t -> go t
+        | Opt t -> go t
+        | Variant vs -> List.iter (fun f -> go f.typ) vs
+        | Func (s, c, tbs, ts1, ts2) ->
+          List.iter go ts1; List.iter go ts2
+        | Prim Blob -> ()
+        | Mut t -> go t
+        | _ ->
+          Printf.eprintf "type_desc: unexpected type %s\n" (string_of_typ t);
+          assert false
+      end
+    in
+    List.iter go ts;
+    (!typs, !idx)
+
   (* TODO: use record *)
   let type_desc env mode ts :
      string * int list * int32 list  (* type_desc, (relative offsets), indices of ts *)
     =
     let open Type in
 
-    (* Type traversal *)
-    (* We do a first traversal to find out the indices of non-primitive types *)
-    let (typs, idx) =
-      let typs = ref [] in
-      let idx = ref TM.empty in
-      let rec go t =
-        let t = Type.normalize t in
-        if to_idl_prim mode t <> None then () else
-        if TM.mem t !idx then () else begin
-          idx := TM.add t (Lib.List32.length !typs) !idx;
-          typs := !typs @ [ t ];
-          match t with
-          | Tup ts -> List.iter go ts
-          | Obj (_, fs) ->
-            List.iter (fun f -> go f.typ) fs
-          | Array (Mut t) -> go (Array t)
-          | Array t -> go t
-          | Opt t -> go t
-          | Variant vs -> List.iter (fun f -> go f.typ) vs
-          | Func (s, c, tbs, ts1, ts2) ->
-            List.iter go ts1; List.iter go ts2
-          | Prim Blob -> ()
-          | Mut t -> go t
-          | _ ->
-            Printf.eprintf "type_desc: unexpected type %s\n" (string_of_typ t);
-            assert false
-        end
-      in
-      List.iter go ts;
-      (!typs, !idx)
-    in
+    let (typs, idx) = type_table mode ts in
 
     (* buffer utilities *)
     let buf = Buffer.create 16 in
@@ -6770,6 +6927,68 @@ module Serialization = struct
      offsets,
      List.map idx ts)
 
+  (* The Motoko label hashes of the record and variant fields of the persistence
+     type table, in table order and Candid field order, see `idl_decode` in the RTS *)
+  let field_hashes env ts =
+    let buf = Buffer.create 16 in
+    let (typs, _) = type_table Persistence ts in
+    List.iter (function
+      | Type.(Obj ((Object | Memory), fs) | Variant fs) ->
+        List.iter (fun (_, f) ->
+          Buffer.add_int32_le buf (Int64.to_int32 (E.hash env f.Type.lab))
+        ) (sort_by_hash fs)
+      | _ -> ()
+    ) typs;
+    Buffer.contents buf
+
+  (* The Candid names of the same fields, as LEB128-length-prefixed strings,
+     see `idl_print` in the RTS *)
+  let field_names ts =
+    let buf = Buffer.create 16 in
+    let rec add_leb128 i =
+      if i < 0x80 then Buffer.add_char buf (Char.chr i) else begin
+        Buffer.add_char buf (Char.chr ((i land 0x7f) lor 0x80));
+        add_leb128 (i lsr 7)
+      end in
+    let name lab = match Idllib.Escape.unescape lab with
+      | Idllib.Escape.Nat n -> Lib.Uint32.to_string n
+      | Idllib.Escape.Id s when Idllib.Escape.needs_candid_quote s ->
+        "\"" ^ Lib.String.lightweight_escaped s ^ "\""
+      | Idllib.Escape.Id s -> s in
+    let (typs, _) = type_table Persistence ts in
+    List.iter (function
+      | Type.(Obj ((Object | Memory), fs) | Variant fs) ->
+        List.iter (fun (_, f) ->
+          let s = name f.Type.lab in
+          add_leb128 (String.length s);
+          Buffer.add_string buf s
+        ) (sort_by_hash fs)
+      | _ -> ()
+    ) typs;
+    Buffer.contents buf
+
+  (* Whether the RTS can decode and encode values of these types, i.e. whether their
+     persistence type descriptor is unambiguous and free of references.
+     (`Char` shares the IDL type of `Nat32`, and `()` that of `Null`.) *)
+  let native_decodable ts =
+    let open Type in
+    let rec go seen t =
+      let t = normalize t in
+      TM.mem t seen ||
+      let seen = TM.add t () seen in
+      match t with
+      | Prim (Null | Bool | Nat | Nat8 | Nat16 | Nat32 | Nat64 |
+              Int | Int8 | Int16 | Int32 | Int64 | Float | Text | Blob | Principal) -> true
+      | Any -> true
+      | Tup [] -> false
+      | Tup ts -> List.for_all (go seen) ts
+      | Obj ((Object | Memory), fs) | Variant fs ->
+        List.for_all (fun f -> go seen f.typ) fs
+      | Array t | Opt t -> go seen t
+      | _ -> false
+    in
+    List.for_all (go TM.empty) ts
+
   (* See Note [Candid subtype checks] *)
   let reserve_global_type_descriptor (env : E.t) =
     let candid_data_segment = E.add_data_segment env "" in
@@ -7140,16 +7359,16 @@ module Serialization = struct
   let coercion_error_value env = 0xffff_ffff_ffff_fffdL
 
   (* See Note [Candid subtype checks] *)
-  let with_rel_buf_opt env extended get_typtbl_size1 get_typtbl_size2 f =
+  let with_rel_buf_opt env extended (get_typtbl1, get_typtbl_end1, get_typtbl_size1) (get_typtbl2, get_typtbl_end2, get_typtbl_size2) f =
     if extended then
       f (compile_unboxed_const 0L)
     else
-      get_typtbl_size1 ^^ get_typtbl_size2 ^^
-      E.call_import env "rts" "idl_sub_buf_words" ^^
-      Stack.dynamic_with_words env "rel_buf" (fun get_ptr ->
-        get_ptr ^^ get_typtbl_size1 ^^ get_typtbl_size2 ^^
-        E.call_import env "rts" "idl_sub_buf_init" ^^
-        f get_ptr)
+      let (set_rel_buf, get_rel_buf) = new_local env "rel_buf" in
+      get_typtbl1 ^^ get_typtbl_end1 ^^ get_typtbl_size1 ^^
+      get_typtbl2 ^^ get_typtbl_end2 ^^ get_typtbl_size2 ^^
+      E.call_import env "rts" "idl_sub_cache_lookup" ^^
+      set_rel_buf ^^
+      f get_rel_buf
 
   (* See Note [Candid subtype checks] *)
   let idl_sub env t2 =
@@ -7234,6 +7453,10 @@ module Serialization = struct
       compile_unboxed_const 1L ^^
       Registers.idl_limit_check env ^^
 
+      (* Check nesting depth of the decoding budget, exited at the end *)
+      get_data_buf ^^
+      E.call_import env "rts" "idl_budget_enter" ^^
+
       (* Check recursion depth (protects against empty record etc.) *)
       (* Factor 2 because at each step, the expected type could go through one
          level of opt that is not present in the value type
@@ -7334,6 +7557,7 @@ module Serialization = struct
         let (set_len, get_len) = new_local env "len" in
         let (set_x, get_x) = new_local env "x" in
         ReadBuf.read_leb128 env get_data_buf ^^ set_len ^^
+        get_data_buf ^^ get_len ^^ E.call_import env "rts" "idl_budget_alloc" ^^
 
         Blob.alloc env Tagged.B get_len ^^ set_x ^^
         get_x ^^ Blob.payload_ptr_unskewed env ^^
@@ -7366,6 +7590,7 @@ module Serialization = struct
         ReadBuf.advance get_data_buf get_len ^^
         (* validate *)
         get_ptr ^^ get_len ^^ E.call_import env "rts" "utf8_validate" ^^
+        get_data_buf ^^ get_len ^^ E.call_import env "rts" "idl_budget_alloc" ^^
         (* copy *)
         get_ptr ^^ get_len ^^ Text.of_ptr_size env
       in
@@ -7789,6 +8014,10 @@ module Serialization = struct
           Bool.lit_rts_int32 false ^^
           get_len ^^
           Registers.idl_limit_check env ^^
+          get_data_buf ^^ get_typtbl ^^
+          get_arg_typ ^^ G.i (Convert (Wasm_exts.Values.I32 I32Op.WrapI64)) ^^
+          get_len ^^
+          E.call_import env "rts" "idl_budget_vec" ^^
           Arr.alloc env Tagged.I get_len ^^ set_x ^^
           get_len ^^ from_0_to_n env (fun get_i ->
           get_x ^^ get_i ^^ Arr.unsafe_idx env ^^
@@ -7931,11 +8160,48 @@ module Serialization = struct
         coercion_failed "IDL error: deserializing value of type None"
       | _ -> todo_trap env "deserialize" (Arrange_ir.typ t)
       end ^^
+      E.call_import env "rts" "idl_budget_exit" ^^
       (* Parsed value on the stack, return that, unless the failure flag is set *)
       when_failed (compile_unboxed_const (coercion_error_value env) ^^ G.i Return)
     )
 
+  (* Serialization by the generic Candid encoder of the RTS, see `idl_encode`.
+     Same interface as `serialize` below. *)
+  let serialize_natively env ts =
+    let name = Strm.name_for "serialize_natively" ts in
+    Func.share_code1 Func.Always env name ("x", I64Type) [I64Type; I64Type] (fun env get_x ->
+      let (tydesc, _, _) = type_desc env Candid ts in
+      let (value_tydesc, _, _) = type_desc env Persistence ts in
+      let (set_blob, get_blob) = new_local env "blob" in
+      get_x ^^
+      Blob.lit env Tagged.B tydesc ^^
+      Blob.lit env Tagged.B value_tydesc ^^
+      Blob.lit env Tagged.B (field_hashes env ts) ^^
+      E.call_import env "rts" "idl_encode" ^^ set_blob ^^
+      get_blob ^^ Blob.payload_ptr_unskewed env ^^
+      get_blob ^^ Blob.len env
+    )
+
+  (* Renders a value of type `t` in the Candid textual syntax, see `idl_print`.
+     Fields of a top-level object that the RTS cannot print are omitted. *)
+  let candid_text env t =
+    let t = match Type.normalize t with
+      | Type.Obj ((Type.Object | Type.Memory) as s, fs) ->
+        Type.Obj (s, List.filter (fun f -> native_decodable [f.Type.typ]) fs)
+      | t -> t in
+    if not (native_decodable [t])
+    then todo_trap env "candid_text" (Arrange_ir.typ t)
+    else
+    let (value_tydesc, _, _) = type_desc env Persistence [t] in
+    Blob.lit env Tagged.B value_tydesc ^^
+    Blob.lit env Tagged.B (field_hashes env [t]) ^^
+    Blob.lit env Tagged.B (field_names [t]) ^^
+    E.call_import env "rts" "idl_print"
+
   let serialize env ts : G.t =
+    if !Flags.rts_candid_encoder && native_decodable ts
+    then serialize_natively env ts
+    else
     let name = Strm.name_for "serialize" ts in
     (* returns data/length pointers (will be GC’ed next time!) *)
     Func.share_code1 Func.Always env name ("x", I64Type) [I64Type; I64Type] (fun env get_x ->
@@ -7986,12 +8252,48 @@ module Serialization = struct
     )
 
 
+  (* Deserialization by the generic Candid decoder of the RTS, see `idl_decode`.
+     Same interface as `deserialize_from_blob` below. *)
+  let deserialize_natively env ts =
+    let ts_name = typ_seq_hash ts in
+    let name = "@deserialize_natively<" ^ ts_name ^ ">" in
+    Func.share_code2 Func.Always env name (("blob", I64Type), ("can_recover", I64Type)) (List.map (fun _ -> I64Type) ts) (fun env get_blob get_can_recover ->
+      let (tydesc, _, _) = type_desc env Persistence ts in
+      let (set_args, get_args) = new_local env "args" in
+
+      (* No subtype memo table is needed, as references are not decoded natively.
+         However, a non-null `rel_buf_opt` enables the value quota of `idl_limit_check` *)
+      Stack.with_words env "rel_buf" 1L (fun get_rel_buf ->
+        get_rel_buf ^^ Registers.set_rel_buf_opt env ^^
+        Registers.reset_value_limit env get_blob get_rel_buf ^^
+
+        get_blob ^^
+        Blob.lit env Tagged.B tydesc ^^
+        Blob.lit env Tagged.B (field_hashes env ts) ^^
+        get_can_recover ^^ Bool.to_rts_int32 ^^
+        E.call_import env "rts" "idl_decode" ^^ set_args ^^
+
+        (* Safety guard: The temporary pointers in the registers must no longer be used when a GC increment runs. *)
+        Registers.clear_registers env
+      ) ^^
+
+      get_args ^^ compile_eq_const (coercion_error_value env) ^^
+      E.if_ env (List.map (fun _ -> I64Type) ts)
+        (G.concat_map (fun _ -> compile_unboxed_const (coercion_error_value env)) ts)
+        (G.concat_mapi (fun i _ ->
+          get_args ^^ compile_unboxed_const (Int64.of_int i) ^^ Arr.unsafe_idx env ^^ load_ptr
+        ) ts)
+    )
+
   let deserialize_from_blob extended env ts =
+    if not extended && !Flags.rts_candid_decoder && native_decodable ts
+    then deserialize_natively env ts
+    else
     let ts_name = typ_seq_hash ts in
     let name =
       (* TODO(#3185): this specialization on `extended` seems redundant,
          removing it might simplify things *and* share more code in binaries.
-         The only tricky bit might be the conditional Stack.dynamic_with_words bit... *)
+         The only tricky bit might be the conditional memo table lookup... *)
       if extended
       then "@deserialize_extended<" ^ ts_name ^ ">"
       else "@deserialize<" ^ ts_name ^ ">" in
@@ -8041,10 +8343,14 @@ module Serialization = struct
          E.call_import env "rts" "idl_alloc_typtbl"
       end) ^^
 
-      (* Allocate memo table, if necessary *)
+      (* Look up memo table, if necessary *)
       with_rel_buf_opt env extended
-        (get_typtbl_size_ptr ^^ load_unskewed_ptr)
-        (get_global_typtbl_size_ptr ^^ load_unskewed_ptr)
+        (get_typtbl_ptr ^^ load_unskewed_ptr,
+         get_maintyps_ptr ^^ load_unskewed_ptr,
+         get_typtbl_size_ptr ^^ load_unskewed_ptr)
+        (get_global_typtbl_ptr ^^ load_unskewed_ptr,
+         get_global_typtbl_end_ptr ^^ load_unskewed_ptr,
+         get_global_typtbl_size_ptr ^^ load_unskewed_ptr)
         (fun get_rel_buf_opt ->
       begin
         (* set up invariant register arguments *)
@@ -8273,17 +8579,28 @@ stored in passive data segments referenced (by way of segment indices)
 from dedicated wasm globals so that we can generate code that 
 references the globals before their final definitions are known.
 
-Deserializing a proper (not extended) Candid value stack allocates a
-mutable word buffer, of size determined by `idl_sub_buf_words`.
-The word buffer is used to initialize and provide storage for a
-Rust memo table (see bitrel.rs) memoizing the result of sub and
-super type tests performed during deserialization of a given Candid
-value sequence. The memo table is initialized once, using `idl_sub_buf_init`,
-then shared between recursive calls to deserialize, by threading the (possibly
-null) wasm address of the word buffer as an optional argument. The
-word buffer is stack allocated in generated code, not Rust, because
-it's size is dynamic and Rust doesn't seem to support dynamically-sized
-stack allocation.
+Deserializing a proper (not extended) Candid value obtains a
+mutable word buffer from the RTS function `idl_sub_cache_lookup`.
+The word buffer provides storage for a Rust memo table (see bitrel.rs)
+memoizing the result of sub and super type tests performed during
+deserialization of a given Candid value sequence. The memo table is
+shared between recursive calls to deserialize, by threading the (possibly
+null) wasm address of the word buffer as an optional argument.
+
+The memo tables are cached in the heap across calls (see idl_sub/cache.rs),
+keyed by a hash of the dynamic and the static type table. Repeated calls
+with the same argument types thus reuse the subtype results of prior calls,
+instead of initializing a fresh table with `idl_sub_buf_init` on each call.
+The cache is bounded in its number of entries and the size of each entry.
+It is referenced by a GC root and reset on upgrades, together with the
+static type table.
+
+The memo table assumes pairs to be related while checking them. If such a
+check fails, pairs that were proven under the failed assumption remain set.
+Within a message, this is tolerated, as before the cache. To not make the
+results of later messages depend on earlier messages, the cache only carries
+over definite results: When a cached table contains a disproven pair, its
+proven pairs are cleared on the next lookup, retaining the disproven ones.
 
 Currently, we only perform Candid subtype checks when decoding proper
 (not extended) Candid values. Extended values are required for
@@ -8510,7 +8827,7 @@ module OldStabilization = struct
 
   let load env actor_type upgrade_version =
     candid_destabilize env actor_type upgrade_version ^^
-    UpgradeStatistics.add_instructions env
+    UpgradeStatistics.(record_upgrade env candid_stabilization)
 end
 
 (* New stable memory layout with dedicated version for enhanced orthogonal persistence.
@@ -8682,10 +8999,35 @@ module EnhancedOrthogonalPersistence = struct
     Blob.lit env Tagged.B candid_type_desc ^^
     Blob.lit env Tagged.B serialized_offsets
 
+  (* The field names of the stable type, to report memory-incompatible upgrades *)
+  let field_names env actor_type =
+    Blob.lit env Tagged.B (Serialization.field_names [actor_type])
+
   let register_stable_type env actor_type =
     create_type_descriptor env actor_type ^^
+    field_names env actor_type ^^
     E.call_import env "rts" "register_stable_type"
 
+  (* The type descriptor as a single blob: the Candid type table, the type offsets and
+     the field names, each prefixed by its 64-bit byte length, see `EncodedTypeDescriptor`
+     in the RTS *)
+  let encode_type_descriptor env actor_type =
+    let (candid_type_desc, type_offsets, type_indices) = Serialization.(type_desc env Persistence [actor_type]) in
+    let serialized_offsets = StaticBytes.(as_bytes [i64s (List.map Int64.of_int type_offsets)]) in
+    assert (type_indices = [0l]);
+    let part data = StaticBytes.[I64 (Int64.of_int (String.length data)); Bytes data] in
+    StaticBytes.as_bytes
+      (part candid_type_desc @ part serialized_offsets @ part (Serialization.field_names [actor_type]))
+
+  (* The registered stable type, as encoded by `encode_type_descriptor` *)
+  let get_stable_type_descriptor env =
+    E.call_import env "rts" "get_stable_type_descriptor"
+
+  (* Dry run of the compatibility check with an encoded type descriptor of a new
+     program version, returns null or the reason of the incompatibility *)
+  let check_stable_type_compatibility env =
+    E.call_import env "rts" "check_stable_type_compatibility"
+
   let load_old_field env field get_old_actor =
     if field.Type.typ = Type.(Opt Any) then
       (* A stable variable may have been promoted to type `Any`: Therefore, drop its former content. *)
@@ -8731,15 +9073,22 @@ module EnhancedOrthogonalPersistence = struct
     UpgradeStatistics.set_instructions env
 
   let load env actor_type =
+    let set_is_install, get_is_install = new_local env "is_install" in
     register_stable_type env actor_type ^^
     load_stable_actor env ^^
     compile_test I64Op.Eqz ^^
+    set_is_install ^^
+    get_is_install ^^
     (E.if1 I64Type
       (empty_actor env actor_type)
       (recover_actor env actor_type)
     ) ^^
     NewStableMemory.restore env ^^
-    UpgradeStatistics.add_instructions env
+    (* Only an upgrade is recorded in the upgrade history, not the initial installation. *)
+    get_is_install ^^
+    E.if0
+      (UpgradeStatistics.add_instructions env)
+      UpgradeStatistics.(record_upgrade env enhanced_orthogonal_persistence)
 
   let initialize env actor_type =
     register_stable_type env actor_type
@@ -8752,6 +9101,7 @@ module GraphCopyStabilization = struct
 
   let start_graph_stabilization env actor_type =
     EnhancedOrthogonalPersistence.create_type_descriptor env actor_type ^^
+    EnhancedOrthogonalPersistence.field_names env actor_type ^^
     E.call_import env "rts" "start_graph_stabilization"
 
   let graph_stabilization_increment env =
@@ -8759,6 +9109,7 @@ module GraphCopyStabilization = struct
 
   let start_graph_destabilization env actor_type =
     EnhancedOrthogonalPersistence.create_type_descriptor env actor_type ^^
+    EnhancedOrthogonalPersistence.field_names env actor_type ^^
     E.call_import env "rts" "start_graph_destabilization"
 
   let graph_destabilization_increment env =
@@ -8934,6 +9285,138 @@ module StackRep = struct
 
 end (* StackRep *)
 
+module RegionArr = struct
+  (* Bulk transfer of fixed-size numbers between regions and immutable arrays,
+     in little-endian order, without an intermediate blob.
+
+     Loads read the elements directly into the payload of the new array,
+     where they are converted in place to their vanilla representation.
+     Stores convert the elements chunk by chunk into a buffer on the stack,
+     as the array must not be modified.
+     The RTS accesses the region once per block span. *)
+
+  let element_size = function
+    | Type.(Nat32 | Int32) -> 4L
+    | Type.(Nat64 | Int64 | Float) -> 8L
+    | _ -> assert false
+
+  (* Size of the stack buffer of the stores, see `for_chunks` *)
+  let chunk_bytes = 8192L
+
+  (* Loads the element at the address on the stack, in its unboxed stack representation *)
+  let load_element ty = match ty with
+    | Type.(Nat32 | Int32) ->
+      G.i (Load {ty = I64Type; align = 0; offset = 0L; sz = Some Wasm_exts.Types.(Pack32, if ty = Type.Nat32 then ZX else SX)}) ^^
+      TaggedSmallWord.msb_adjust ty
+    | Type.(Nat64 | Int64) ->
+      G.i (Load {ty = I64Type; align = 0; offset = 0L; sz = None})
+    | Type.Float ->
+      G.i (Load {ty = F64Type; align = 0; offset = 0L; sz = None})
+    | _ -> assert false
+
+  (* Stores an element in its unboxed stack representation at the given address *)
+  let store_element ty = match ty with
+    | Type.(Nat32 | Int32) ->
+      TaggedSmallWord.lsb_adjust ty ^^
+      G.i (Store {ty = I64Type; align = 0; offset = 0L; sz = Some Wasm_exts.Types.Pack32})
+    | Type.(Nat64 | Int64) ->
+      G.i (Store {ty = I64Type; align = 0; offset = 0L; sz = None})
+    | Type.Float ->
+      G.i (Store {ty = F64Type; align = 0; offset = 0L; sz = None})
+    | _ -> assert false
+
+  (* Unskewed address or region offset of the `i`-th element, starting from `get_base` *)
+  let element_address get_base get_i ty =
+    get_base ^^
+    get_i ^^ compile_mul_const (element_size ty) ^^
+    G.i (Binary (Wasm_exts.Values.I64 I64Op.Add))
+
+  let payload_ptr_unskewed env get_array =
+    get_array ^^ compile_unboxed_zero ^^ Arr.unsafe_idx env ^^
+    compile_add_const ptr_unskew
+
+  (* Region, offset, and element count on the stack, returns the array *)
+  let load env ty =
+    Func.share_code3 Func.Always env (prim_fun_name ty "RegionArr.load") (("region", I64Type), ("offset", I64Type), ("count", I64Type)) [I64Type]
+      (fun env get_region get_offset get_count ->
+        let (set_r, get_r) = new_local env "r" in
+        let (set_payload, get_payload) = new_local env "payload" in
+        let (set_j, get_j) = new_local env "j" in
+
+        (* Allocate first, such that the size computation cannot overflow *)
+        Arr.alloc env Tagged.I get_count ^^ set_r ^^
+        payload_ptr_unskewed env get_r ^^ set_payload ^^
+
+        get_region ^^
+        get_offset ^^
+        get_payload ^^
+        get_count ^^ compile_mul_const (element_size ty) ^^
+        Region.load_bytes env ^^
+
+        (* Convert from the last element on, as the elements may be smaller than the array slots *)
+        get_count ^^ from_0_to_n env (fun get_i ->
+          get_count ^^ get_i ^^ G.i (Binary (Wasm_exts.Values.I64 I64Op.Sub)) ^^
+          compile_sub_const 1L ^^ set_j ^^
+          get_r ^^ get_j ^^ Arr.unsafe_idx env ^^
+          element_address get_payload get_j ty ^^
+          load_element ty ^^
+          StackRep.adjust env (StackRep.of_type (Type.Prim ty)) SR.Vanilla ^^
+          store_ptr
+        ) ^^
+        get_r ^^
+        Tagged.allocation_barrier env
+      )
+
+  (* Iterates over chunks of at most `chunk_bytes`, with a stack buffer of that size.
+     Runs at least once, such that the region range is also checked for zero elements. *)
+  let for_chunks env ty get_count mk_body =
+    let chunk_length = Int64.div chunk_bytes (element_size ty) in
+    let (set_start, get_start) = new_local env "start" in
+    let (set_n, get_n) = new_local env "n" in
+    compile_unboxed_zero ^^ set_start ^^
+    Stack.with_words env "buffer" (Int64.div chunk_bytes Heap.word_size) (fun get_buffer ->
+      G.loop0 (
+        get_count ^^ get_start ^^ G.i (Binary (Wasm_exts.Values.I64 I64Op.Sub)) ^^ set_n ^^
+        get_n ^^ compile_unboxed_const chunk_length ^^
+        compile_comparison I64Op.GtU ^^
+        E.if0 (compile_unboxed_const chunk_length ^^ set_n) G.nop ^^
+
+        mk_body get_buffer get_start get_n ^^
+
+        get_start ^^ get_n ^^ G.i (Binary (Wasm_exts.Values.I64 I64Op.Add)) ^^ set_start ^^
+        get_start ^^ get_count ^^ compile_comparison I64Op.LtU ^^
+        E.if0 (G.i (Br (nr 1l))) G.nop
+      )
+    )
+
+  (* Region, offset, and array on the stack *)
+  let store env ty =
+    Func.share_code3 Func.Always env (prim_fun_name ty "RegionArr.store") (("region", I64Type), ("offset", I64Type), ("array", I64Type)) []
+      (fun env get_region get_offset get_array ->
+        let (set_len, get_len) = new_local env "len" in
+        get_array ^^ Arr.len env ^^ set_len ^^
+
+        for_chunks env ty get_len (fun get_buffer get_start get_n ->
+          get_n ^^ from_0_to_n env (fun get_i ->
+            element_address get_buffer get_i ty ^^
+            get_array ^^
+            get_start ^^ get_i ^^ G.i (Binary (Wasm_exts.Values.I64 I64Op.Add)) ^^
+            Arr.unsafe_idx env ^^
+            load_ptr ^^
+            StackRep.adjust env SR.Vanilla (StackRep.of_type (Type.Prim ty)) ^^
+            store_element ty
+          ) ^^
+
+          get_region ^^
+          element_address get_offset get_start ty ^^
+          get_buffer ^^
+          get_n ^^ compile_mul_const (element_size ty) ^^
+          Region.store_bytes env
+        )
+      )
+
+end (* RegionArr *)
+
 module VarEnv = struct
 
   (* A type to record where Motoko names are stored. *)
@@ -9761,7 +10244,17 @@ module FuncDec = struct
     E.add_export env (nr {
       name = Lib.Utf8.decode "moc_stable_memory_access_limit";
       edesc = nr (FuncExport (nr moc_stable_memory_access_limit_fi))
-    }) 
+    });
+    let moc_stabilization_deduplication_fi =
+      E.add_fun env "moc_stabilization_deduplication" (
+        Func.of_body env [] [I32Type] (fun env ->
+          compile_const_32 (if !Flags.stabilization_deduplication then 1l else 0l)
+        )
+      ) in
+    E.add_export env (nr {
+      name = Lib.Utf8.decode "moc_stabilization_deduplication";
+      edesc = nr (FuncExport (nr moc_stabilization_deduplication_fi))
+    })
 
 end (* FuncDec *)
 
@@ -11381,6 +11874,11 @@ and compile_prim_invocation (env : E.t) ae p es at =
     Serialization.serialize env ts ^^
     Blob.of_ptr_size env
 
+  | OtherPrim "candid_text", [e] ->
+    SR.Vanilla,
+    compile_exp_vanilla env ae e ^^
+    Serialization.candid_text env e.note.Note.typ
+
   | DeserializePrim ts, [e] ->
     StackRep.of_arity (List.length ts),
     compile_exp_vanilla env ae e ^^
@@ -11662,6 +12160,19 @@ and compile_prim_invocation (env : E.t) ae p es at =
     SR.Vanilla,
     UpgradeStatistics.get_upgrade_instructions env ^^ BigNum.from_word64 env
 
+  | OtherPrim "rts_upgrade_history", [] ->
+    SR.Vanilla,
+    UpgradeStatistics.get_upgrade_history env
+
+  | OtherPrim "rts_stable_type_descriptor", [] ->
+    SR.Vanilla,
+    EnhancedOrthogonalPersistence.get_stable_type_descriptor env
+
+  | OtherPrim "rts_check_stable_type_compatibility", [e] ->
+    SR.Vanilla,
+    compile_exp_vanilla env ae e ^^
+    EnhancedOrthogonalPersistence.check_stable_type_compatibility env
+
   | OtherPrim "rts_stable_memory_size", [] ->
     SR.Vanilla,
     StableMem.stable64_size env ^^ BigNum.from_word64 env
@@ -11688,6 +12199,28 @@ and compile_prim_invocation (env : E.t) ae p es at =
     compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
     Region.grow env
 
+  | OtherPrim "regionShrink", [e0; e1] ->
+    SR.UnboxedWord64 Type.Nat64,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    Region.shrink env
+
+  | OtherPrim "regionFree", [e0] ->
+    SR.unit,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    Region.free env
+
+  | OtherPrim "regionCheck", [e0; e1] ->
+    SR.Vanilla,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae SR.bool e1 ^^
+    Bool.to_rts_int32 ^^
+    Region.check env
+
+  | OtherPrim "regionStats", [] ->
+    SR.Vanilla,
+    Region.stats env
+
   | OtherPrim "regionSize", [e0] ->
     SR.UnboxedWord64 Type.Nat64,
     compile_exp_as env ae SR.Vanilla e0 ^^
@@ -11708,6 +12241,15 @@ and compile_prim_invocation (env : E.t) ae p es at =
     compile_exp_as env ae SR.Vanilla e2 ^^
     Region.store_blob env
 
+  | OtherPrim "regionCopy", [e0; e1; e2; e3; e4] ->
+    SR.unit,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    compile_exp_as env ae SR.Vanilla e2 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e3 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e4 ^^
+    Region.copy env
+
   | OtherPrim (("regionLoadNat8" | "regionLoadInt8" as p)), [e0; e1] ->
     let ty = Type.(if p = "regionLoadNat8" then Nat8 else Int8) in
     SR.UnboxedWord64 ty,
@@ -11780,6 +12322,33 @@ and compile_prim_invocation (env : E.t) ae p es at =
     compile_exp_as env ae (SR.UnboxedWord64 ty) e2 ^^
     Region.store_word64 env
 
+  | OtherPrim (("regionLoadNat32Array" | "regionLoadInt32Array" | "regionLoadNat64Array" | "regionLoadInt64Array" | "regionLoadFloatArray") as p), [e0; e1; e2] ->
+    let ty = Type.(match p with
+      | "regionLoadNat32Array" -> Nat32
+      | "regionLoadInt32Array" -> Int32
+      | "regionLoadNat64Array" -> Nat64
+      | "regionLoadInt64Array" -> Int64
+      | _ -> Float) in
+    SR.Vanilla,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    compile_exp_as env ae SR.Vanilla e2 ^^
+    BigNum.to_word64_with env (Blob.lit env Tagged.T "Array size out of bounds") ^^
+    RegionArr.load env ty
+
+  | OtherPrim (("regionStoreNat32Array" | "regionStoreInt32Array" | "regionStoreNat64Array" | "regionStoreInt64Array" | "regionStoreFloatArray") as p), [e0; e1; e2] ->
+    let ty = Type.(match p with
+      | "regionStoreNat32Array" -> Nat32
+      | "regionStoreInt32Array" -> Int32
+      | "regionStoreNat64Array" -> Nat64
+      | "regionStoreInt64Array" -> Int64
+      | _ -> Float) in
+    SR.unit,
+    compile_exp_as env ae SR.Vanilla e0 ^^
+    compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e1 ^^
+    compile_exp_as env ae SR.Vanilla e2 ^^
+    RegionArr.store env ty
+
   | OtherPrim ("regionLoadFloat"), [e0; e1] ->
     SR.UnboxedFloat64,
     compile_exp_as env ae SR.Vanilla e0 ^^
@@ -12145,6 +12714,28 @@ and compile_prim_invocation (env : E.t) ae p es at =
     TaggedSmallWord.msb_adjust Type.Nat32 ^^
     TaggedSmallWord.tag env Type.Nat32
 
+  | OtherPrim "setCandidDecodingLimits", [e1; e2; e3; e4] ->
+    SR.unit,
+    G.concat_mapi (fun i e ->
+      compile_const_32 (Int32.of_int i) ^^
+      compile_exp_as env ae (SR.UnboxedWord64 Type.Nat64) e ^^
+      E.call_import env "rts" "idl_set_decoding_limit"
+    ) [e1; e2; e3; e4]
+
+  | OtherPrim "getCandidDecodingLimits", [] ->
+    SR.UnboxedTuple 4,
+    G.table 4 (fun i ->
+      compile_const_32 (Int32.of_int i) ^^
+      E.call_import env "rts" "idl_get_decoding_limit" ^^
+      BoxedWord64.box env Type.Nat64)
+
+  | OtherPrim "candidValidate", [e] ->
+    SR.Vanilla,
+    compile_exp_as env ae SR.Vanilla e ^^
+    (* The value quota only applies to deserialization *)
+    compile_unboxed_const 0L ^^ Serialization.Registers.set_rel_buf_opt env ^^
+    E.call_import env "rts" "idl_validate"
+
   (* Coercions for abstract types *)
   | CastPrim (_,_), [e] ->
     compile_exp env ae e
@@ -13035,6 +13626,9 @@ and main_actor as_opt mod_env ds fs up =
 
   (* Export metadata *)
   mod_env.E.stable_types := metadata "motoko:stable-types" up.meta.sig_;
+  if !Flags.stable_type_descriptor then
+    mod_env.E.stable_type_descriptor := metadata "motoko:stable-type-descriptor"
+      (EnhancedOrthogonalPersistence.encode_type_descriptor mod_env stable_actor_type);
   mod_env.E.service := metadata "candid:service" up.meta.candid.service;
   mod_env.E.args := metadata "candid:args" up.meta.candid.args;
 
@@ -13235,6 +13829,7 @@ and conclude_module env set_serialization_globals start_fi_o =
       motoko = {
         labels = E.get_labs env;
         stable_types = !(env.E.stable_types);
+        stable_type_descriptor = !(env.E.stable_type_descriptor);
         compiler = metadata "motoko:compiler" (Lib.Option.get Source_id.release Source_id.id);
       };
       enhanced_orthogonal_persistence = Some (false, "64-bit, layout version 1");
@@ -34,6 +34,7 @@ let valid_metadata_names =
     ["candid:args";
      "candid:service";
      "motoko:stable-types";
+     "motoko:stable-type-descriptor";
      "motoko:compiler"]
 
 let argspec = [
@@ -143,11 +144,24 @@ let argspec = [
     set_mode Compile ()), (* similar to --idl *)
       " compile and emit signature of stable types to `.most` file";
 
+  "--stable-type-descriptor",
+  Arg.Unit (fun () ->
+    Flags.stable_type_descriptor := true;
+    set_mode Compile ()),
+      " compile and emit the stable type descriptor to `.stable-type-descriptor` file, for `rts_check_stable_type_compatibility` (only with enhanced orthogonal persistence)";
+
   "--stable-regions",
   Arg.Unit (fun () ->
     Flags.use_stable_regions := true),
       " force eager initialization of stable regions metadata (for testing purposes); consumes between 386KiB or 8MiB of additional physical stable memory, depending on current use of ExperimentalStableMemory library";
 
+  "--stable-region-block-pages",
+  Arg.Int (fun pages ->
+    if pages < 1 || pages > Flags.stable_region_block_pages_default || pages land (pages - 1) <> 0 then
+      raise (Arg.Bad "--stable-region-block-pages must be a power of two between 1 and 128");
+    Flags.stable_region_block_pages := pages),
+  "<n>  set the block size in pages for a new stable region layout; fixed once the layout is initialized (default " ^ (Int.to_string Flags.stable_region_block_pages_default) ^ ")";
+
   "--generational-gc",
   Arg.Unit (fun () -> Flags.gc_strategy := Mo_config.Flags.Generational),
   " use generational GC (only available with classical persistence)";
@@ -197,6 +211,18 @@ let argspec = [
   Arg.Unit (fun () -> Flags.enhanced_orthogonal_persistence := true),
   " Use enhanced orthogonal persistence (experimental): Scalable and fast upgrades using a persistent 64-bit main memory.";
 
+  "--rts-candid-decoder",
+  Arg.Unit (fun () -> Flags.rts_candid_decoder := true),
+  " decode Candid arguments with the generic decoder of the runtime system instead of generated code, where possible (only with enhanced orthogonal persistence): smaller code size";
+
+  "--rts-candid-encoder",
+  Arg.Unit (fun () -> Flags.rts_candid_encoder := true),
+  " encode Candid arguments and replies with the generic encoder of the runtime system instead of generated code, where possible (only with enhanced orthogonal persistence)";
+
+  "--stable-var-dump",
+  Arg.Unit (fun () -> Flags.stable_var_dump := true),
+  " export the controller-only query `__motoko_stable_var_dump` that renders the stable variables in the Candid textual syntax (only with enhanced orthogonal persistence)";
+
   "--stabilization-instruction-limit",
   Arg.Int (fun limit -> Flags.(stabilization_instruction_limit := {
     upgrade = limit; 
@@ -211,6 +237,10 @@ let argspec = [
   })),
   "<n>  set stable memory access limit for incremental graph-copy-based stabilization and destabilization (for testing)";
 
+  "--stabilization-deduplication",
+  Arg.Unit (fun () -> Flags.stabilization_deduplication := true),
+  " share identical blobs and texts in the stable memory image of graph-copy-based stabilization (only with enhanced orthogonal persistence)";
+
   (* optimizations *)
   "-fno-shared-code",
   Arg.Unit (fun () -> Flags.share_code := false),
@@ -301,6 +331,17 @@ let process_files files : unit =
           let oc_ = open_out sig_file in
           output_string oc_ txt; close_out oc_
         | _ -> ())
+    end;
+
+    if !Flags.stable_type_descriptor then begin
+      let descriptor_file = Filename.remove_extension !out_file ^ ".stable-type-descriptor"
+      in
+      CustomModule.(
+        match module_.motoko.stable_type_descriptor with
+        | Some (_, bytes) ->
+          let oc_ = open_out_bin descriptor_file in
+          output_string oc_ bytes; close_out oc_
+        | _ -> ())
     end
 
   | PrintDeps -> begin
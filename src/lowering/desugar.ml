@@ -529,6 +529,44 @@ and export_runtime_information self_id =
   )],
   [{ it = I.{ name = lab; var = v }; at = no_region; note = typ }])
 
+and export_stable_var_dump self_id ids =
+  let open T in
+  let {lab;typ;_} = motoko_stable_var_dump_fld in
+  let v = "$"^lab in
+  let scope_con1 = Cons.fresh "T1" (Abs ([], scope_bound)) in
+  let scope_con2 = Cons.fresh "T2" (Abs ([], Any)) in
+  let bind1  = typ_arg scope_con1 Scope scope_bound in
+  let bind2 = typ_arg scope_con2 Scope scope_bound in
+  (* The current values, without running the pre-upgrade hook *)
+  let fields = List.map (fun (i, t) -> {lab = i; typ = as_immut t; src = empty_src}) ids in
+  let record_typ = Obj (Object, List.sort compare_field fields) in
+  let xs = fresh_vars "v" (List.map (fun f -> f.typ) fields) in
+  let caller = fresh_var "caller" caller in
+  ([ letD (var v typ) (
+       funcE v (Shared Query) Promises [bind1] [] [text] (
+           (asyncE T.Fut bind2
+              (blockE ([
+                  letD caller (primE I.ICCallerPrim []);
+                  expD (ifE (orE
+                      (primE (I.RelPrim (principal, Operator.EqOp)) [varE caller; selfRefE principal])
+                      (primE (I.OtherPrim "is_controller") [varE caller]))
+                    (unitE())
+                    (primE (Ir.OtherPrim "trap")
+                      [textE "Unauthorized call of __motoko_stable_var_dump"]))
+                  ] @
+                  (List.map2 (fun x (i, t) -> letD x (varE (var i t))) xs ids))
+                (primE (I.OtherPrim "candid_text") [
+                  newObjE T.Object
+                    (List.map2 (fun f x ->
+                         { it = I.{name = f.lab; var = id_of_var x};
+                           at = no_region;
+                           note = f.typ }
+                       ) fields xs)
+                    record_typ]))
+              (Con (scope_con1, []))))
+  )],
+  [{ it = I.{ name = lab; var = v }; at = no_region; note = typ }])
+
 and build_actor at ts self_id es obj_typ =
   let candid = build_candid ts obj_typ in
   let fs = build_fields obj_typ in
@@ -585,7 +623,11 @@ and build_actor at ts self_id es obj_typ =
             ty)) in
   let footprint_d, footprint_f = export_footprint self_id (with_stable_vars Fun.id) in
   let runtime_info_d, runtime_info_f = export_runtime_information self_id in
-  I.(ActorE (footprint_d @ runtime_info_d @ ds', footprint_f @ runtime_info_f @ fs,
+  let dump_d, dump_f =
+    if !Mo_config.Flags.enhanced_orthogonal_persistence && !Mo_config.Flags.stable_var_dump
+    then export_stable_var_dump self_id ids
+    else [], [] in
+  I.(ActorE (footprint_d @ runtime_info_d @ dump_d @ ds', footprint_f @ runtime_info_f @ dump_f @ fs,
      { meta;
        preupgrade = (primE (I.ICStableWrite ty) []);
        postupgrade =
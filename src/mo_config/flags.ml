@@ -56,7 +56,13 @@ let rts_stack_pages : int option ref = ref None
 let rtti = ref false
 let trap_on_call_error = ref false
 let use_stable_regions = ref false
+let stable_region_block_pages_default = 128 (* 8MB *)
+let stable_region_block_pages = ref stable_region_block_pages_default
 let enhanced_orthogonal_persistence = ref false
+let rts_candid_decoder = ref false
+let rts_candid_encoder = ref false
+let stable_var_dump = ref false
+let stable_type_descriptor = ref false
 let share_code = ref false
 let stabilization_instruction_limit_default = {
   upgrade = 180_000_000_000; (* 200 billion limit with 10% reserve *)
@@ -69,5 +75,6 @@ let stable_memory_access_limit_default =
   update_call = 1 * gigabyte; (* 2 GB limit with 1 GB reserve *)
 }
 let stable_memory_access_limit = ref stable_memory_access_limit_default
+let stabilization_deduplication = ref false
 let experimental_stable_memory_default = 0 (* _ < 0: error; _ = 0: warn, _ > 0: allow *)
 let experimental_stable_memory = ref experimental_stable_memory_default
@@ -1384,6 +1384,12 @@ let motoko_runtime_information_fld =
     src = empty_src;
   }
 
+let motoko_stable_var_dump_fld =
+  { lab = "__motoko_stable_var_dump";
+    typ = Func(Shared Query, Promises, [scope_bind], [], [text]);
+    src = empty_src;
+  }
+
 let well_known_actor_fields = [
     motoko_async_helper_fld;
     motoko_stable_var_info_fld;
@@ -271,6 +271,7 @@ val motoko_async_helper_fld : field
 val motoko_stable_var_info_fld : field
 val motoko_gc_trigger_fld : field
 val motoko_runtime_information_fld : field
+val motoko_stable_var_dump_fld : field
 
 val well_known_actor_fields : field list
 val decode_msg_typ : field list -> typ
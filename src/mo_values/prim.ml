@@ -396,4 +396,11 @@ let prim trap =
       fun _ v k -> k (Tup [
         Nat32 Numerics.Nat32.zero; Nat32 Numerics.Nat32.zero; Nat32 Numerics.Nat32.zero])
 
+  | "setCandidDecodingLimits" ->
+      fun _ v k -> k unit
+  | "getCandidDecodingLimits" ->
+      fun _ v k -> k (Tup [
+        Nat64 Numerics.Nat64.zero; Nat64 Numerics.Nat64.zero;
+        Nat64 Numerics.Nat64.zero; Nat64 Numerics.Nat64.zero])
+
   | s -> trap.trap ("Value.prim: " ^ s)
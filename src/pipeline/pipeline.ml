@@ -716,7 +716,17 @@ let adjust_flags () =
       (if !Flags.stabilization_instruction_limit <> Flags.stabilization_instruction_limit_default then
         invalid_flag "--stabilization-instruction-limit is only supported with --enhanced-orthogonal-persistence");
       (if !Flags.stable_memory_access_limit <> Flags.stable_memory_access_limit_default then
-        invalid_flag "--stable-memory-access-limit is only supported with --enhanced-orthogonal-persistence")
+        invalid_flag "--stable-memory-access-limit is only supported with --enhanced-orthogonal-persistence");
+      (if !Flags.rts_candid_decoder then
+        invalid_flag "--rts-candid-decoder is only supported with --enhanced-orthogonal-persistence");
+      (if !Flags.rts_candid_encoder then
+        invalid_flag "--rts-candid-encoder is only supported with --enhanced-orthogonal-persistence");
+      (if !Flags.stable_var_dump then
+        invalid_flag "--stable-var-dump is only supported with --enhanced-orthogonal-persistence");
+      (if !Flags.stable_type_descriptor then
+        invalid_flag "--stable-type-descriptor is only supported with --enhanced-orthogonal-persistence");
+      (if !Flags.stabilization_deduplication then
+        invalid_flag "--stabilization-deduplication is only supported with --enhanced-orthogonal-persistence")
     end
 
 (* This transforms the flat list of libs (some of which are classes)
@@ -41,6 +41,7 @@ type dylink_section = {
 type motoko_sections = {
   labels : string list;
   stable_types : (bool * string) option;
+  stable_type_descriptor : (bool * string) option;
   compiler : (bool * string) option;
 }
 
@@ -52,6 +53,7 @@ type candid_sections = {
 let empty_motoko_sections = {
   labels = [];
   stable_types = None;
+  stable_type_descriptor = None;
   compiler = None;
 }
 
@@ -871,6 +871,9 @@ let motoko_section_content p_end s =
 
 let is_motoko n = (n = Utf8.decode "motoko")
 
+let binary sec_end s =
+  get_string (sec_end - pos s) s
+
 let utf8 sec_end s =
   let pos = pos s in
   let bytes = get_string (sec_end - pos) s in
@@ -882,8 +885,10 @@ let utf8 sec_end s =
 
 let motoko_sections s =
   let stable_types = icp_custom_section "motoko:stable-types" utf8 None s in
+  let stable_type_descriptor = icp_custom_section "motoko:stable-type-descriptor" binary None s in
   let compiler = icp_custom_section "motoko:compiler" utf8 None s in
-  custom_section is_motoko motoko_section_content { empty_motoko_sections with stable_types; compiler; } s
+  custom_section is_motoko motoko_section_content
+    { empty_motoko_sections with stable_types; stable_type_descriptor; compiler; } s
 
 (* Enhanced orthogonal persistence section *)
 let enhanced_orthogonal_persistence_section s =
@@ -901,6 +906,7 @@ let candid_sections s =
 let candid_service_name = icp_name "candid:service"
 let candid_args_name = icp_name "candid:args"
 let motoko_stable_types_name = icp_name "motoko:stable-types"
+let motoko_stable_type_descriptor_name = icp_name "motoko:stable-type-descriptor"
 
 let is_icp icp_name n = icp_name n <> None
 
@@ -916,6 +922,7 @@ let is_unknown n = not (
   is_icp candid_service_name n ||
   is_icp candid_args_name n ||
   is_icp motoko_stable_types_name n ||
+  is_icp motoko_stable_type_descriptor_name n ||
   is_wasm_features n)
 
 let skip_custom sec_end s =